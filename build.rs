@@ -0,0 +1,12 @@
+//! Generates the gRPC service of the `grpc` feature from `proto/ping.proto`,
+//! with a Protobuf compiler written in Rust so that no `protoc` is needed.
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    println!("cargo:rerun-if-changed=proto");
+    #[cfg(feature = "grpc")]
+    {
+        let files = protox::compile(["proto/ping.proto"], ["proto"])?;
+        tonic_build::configure().build_client(false).compile_fds(files)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,90 @@
+/*
+ * C bindings of the libp2p ping node, built with
+ * `cargo build --release --features ffi` as liblibp2p_ping_tut.so (.dylib,
+ * libp2p_ping_tut.dll), and linked with `-llibp2p_ping_tut`.
+ *
+ * A node dials peers and pings them over every connection; its events are
+ * handed out by ping_node_poll_event, which has to be called regularly, e.g.
+ * in a loop on a thread of its own. A node must not be used from two threads
+ * at once.
+ *
+ *     ping_node_t *node = ping_node_new();
+ *     ping_node_dial(node, "/ip4/192.0.2.1/tcp/4001");
+ *     ping_node_event event;
+ *     for (;;) {
+ *         if (ping_node_poll_event(node, &event, 1000) == 1 && event.kind == PING_NODE_EVENT_PING_SUCCESS)
+ *             printf("%s: %llu us\n", event.peer_id, (unsigned long long)event.rtt_us);
+ *     }
+ *     ping_node_free(node);
+ */
+
+#ifndef PING_NODE_H
+#define PING_NODE_H
+
+#include <stdbool.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* A ping node. */
+typedef struct ping_node ping_node_t;
+
+/* What an event reports. */
+typedef enum ping_node_event_kind {
+    /* The node listens on `address`. */
+    PING_NODE_EVENT_LISTEN_ADDR = 1,
+    /* `peer_id` was discovered at `address` on the local network or at the
+     * rendezvous point. */
+    PING_NODE_EVENT_PEER_DISCOVERED = 2,
+    /* A connection to `peer_id` at `address` was established, through a
+     * relay if `relayed`. */
+    PING_NODE_EVENT_CONNECTED = 3,
+    /* `peer_id` answered a ping after `rtt_us` microseconds. */
+    PING_NODE_EVENT_PING_SUCCESS = 4,
+    /* `peer_id` didn't answer a ping in time. */
+    PING_NODE_EVENT_PING_TIMEOUT = 5,
+    /* A ping of `peer_id` failed with `error`. */
+    PING_NODE_EVENT_PING_FAILED = 6,
+    /* A connection to `peer_id` was closed, because of `error` unless it is
+     * empty. */
+    PING_NODE_EVENT_DISCONNECTED = 7,
+} ping_node_event_kind;
+
+/* An event of a node. Fields the kind doesn't mention are zero or empty;
+ * longer strings are cut off. */
+typedef struct ping_node_event {
+    ping_node_event_kind kind;
+    uint64_t rtt_us;
+    bool relayed;
+    char peer_id[64];
+    char address[256];
+    char error[256];
+} ping_node_event;
+
+/* Creates a node with a random identity and the default settings, or returns
+ * NULL if that fails. */
+ping_node_t *ping_node_new(void);
+
+/* Dials the peer at `multiaddr` and pings it once connected. Returns 0, or -1
+ * if the address is invalid or can't be dialed. */
+int ping_node_dial(ping_node_t *node, const char *multiaddr);
+
+/* Waits up to `timeout_ms` for the next event and writes it to `event`.
+ * Returns 1 if it did, or 0 if the time ran out. */
+int ping_node_poll_event(ping_node_t *node, ping_node_event *event, uint32_t timeout_ms);
+
+/* Returns the error of the last call on `node` that returned -1; valid until
+ * the next call on `node`. */
+const char *ping_node_last_error(const ping_node_t *node);
+
+/* Closes the connections of `node`, waiting up to a second for them, and
+ * frees it. NULL is ignored. */
+void ping_node_free(ping_node_t *node);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* PING_NODE_H */
@@ -0,0 +1,155 @@
+//! Ping intervals that lengthen while a peer is stable and shorten as soon as
+//! its RTTs vary more or its pings fail, see
+//! [`NodeConfig::adaptive_interval`](crate::NodeConfig::adaptive_interval).
+
+use libp2p::ping;
+use std::time::Duration;
+
+/// Bounds of the interval between the pings of a connection in adaptive mode.
+#[derive(Debug, Clone, Copy)]
+pub struct AdaptiveInterval {
+    /// Interval right after connecting and after a failure or a jump of the
+    /// RTT.
+    pub min: Duration,
+    /// Interval reached after enough stable pings.
+    pub max: Duration,
+}
+
+/// Factor the interval grows by after each stable ping.
+const GROWTH: f64 = 1.5;
+
+/// Picks the interval before the next ping of a connection from the results
+/// so far.
+///
+/// RTTs are smoothed like TCP does for its retransmission timeout (RFC 6298):
+/// an RTT further from the smoothed RTT than twice its mean deviation, or
+/// a tenth of it for very steady peers, counts as a change and halves the
+/// interval; a failure resets it to the minimum.
+#[derive(Debug, Clone)]
+pub(crate) struct Pacer {
+    bounds: AdaptiveInterval,
+    interval: Duration,
+    srtt: Option<Duration>,
+    rttvar: Duration,
+}
+
+impl Pacer {
+    pub(crate) fn new(bounds: AdaptiveInterval) -> Self {
+        Self { bounds, interval: bounds.min, srtt: None, rttvar: Duration::ZERO }
+    }
+
+    pub(crate) fn min(&self) -> Duration {
+        self.bounds.min
+    }
+
+    /// Returns how long to wait after `result` before the next ping.
+    pub(crate) fn next(&mut self, result: &Result<Duration, ping::Failure>) -> Duration {
+        let rtt = match result {
+            Ok(rtt) => *rtt,
+            Err(_) => {
+                self.interval = self.bounds.min;
+                return self.interval;
+            }
+        };
+        let Some(srtt) = self.srtt else {
+            self.srtt = Some(rtt);
+            self.rttvar = rtt / 2;
+            return self.interval;
+        };
+        let deviation = srtt.abs_diff(rtt);
+        self.interval = if deviation > (self.rttvar * 2).max(srtt / 10) {
+            self.interval / 2
+        } else {
+            self.interval.mul_f64(GROWTH)
+        }
+        .min(self.bounds.max)
+        .max(self.bounds.min);
+        self.rttvar = (self.rttvar * 3 + deviation) / 4;
+        self.srtt = Some((srtt * 7 + rtt) / 8);
+        self.interval
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    fn pacer(min: u64, max: u64) -> Pacer {
+        Pacer::new(AdaptiveInterval { min: Duration::from_secs(min), max: Duration::from_secs(max) })
+    }
+
+    #[test]
+    fn first_rtt_starts_the_smoothing() {
+        let mut pacer = pacer(1, 60);
+        assert_eq!(pacer.next(&Ok(ms(100))), Duration::from_secs(1));
+        assert_eq!(pacer.srtt, Some(ms(100)));
+        assert_eq!(pacer.rttvar, ms(50));
+    }
+
+    #[test]
+    fn rtts_are_smoothed() {
+        let mut pacer = pacer(1, 60);
+        pacer.next(&Ok(ms(100)));
+        pacer.next(&Ok(ms(120)));
+        assert_eq!(pacer.srtt, Some(Duration::from_micros(102_500)));
+        assert_eq!(pacer.rttvar, Duration::from_micros(42_500));
+    }
+
+    #[test]
+    fn stable_rtts_grow_the_interval() {
+        let mut pacer = pacer(1, 60);
+        pacer.next(&Ok(ms(100)));
+        assert_eq!(pacer.next(&Ok(ms(100))), ms(1500));
+        assert_eq!(pacer.next(&Ok(ms(110))), ms(2250));
+    }
+
+    #[test]
+    fn rtts_beyond_twice_the_deviation_halve_the_interval() {
+        let mut pacer = pacer(1, 60);
+        pacer.next(&Ok(ms(100)));
+        pacer.next(&Ok(ms(100)));
+        pacer.next(&Ok(ms(100)));
+        // Twice the deviation is 56.25ms by now, well above a tenth of the
+        // smoothed RTT.
+        assert_eq!(pacer.next(&Ok(ms(150))), ms(3375), "within twice the deviation");
+        // And 67.1875ms from a smoothed RTT of 106.25ms.
+        assert_eq!(pacer.next(&Ok(ms(200))), Duration::from_micros(1_687_500), "beyond twice the deviation");
+    }
+
+    #[test]
+    fn steady_peers_change_at_a_tenth_of_the_rtt() {
+        let mut pacer = pacer(1, 60);
+        for _ in 0..30 {
+            pacer.next(&Ok(ms(100)));
+        }
+        assert!(pacer.rttvar * 2 < ms(1));
+        assert_eq!(pacer.next(&Ok(ms(109))), Duration::from_secs(60), "within a tenth");
+        assert_eq!(pacer.next(&Ok(ms(115))), Duration::from_secs(30), "beyond a tenth");
+    }
+
+    #[test]
+    fn interval_stays_within_the_bounds() {
+        let mut pacer = pacer(1, 2);
+        pacer.next(&Ok(ms(100)));
+        assert_eq!(pacer.next(&Ok(ms(100))), ms(1500));
+        assert_eq!(pacer.next(&Ok(ms(100))), Duration::from_secs(2), "capped at the max");
+        assert_eq!(pacer.next(&Ok(ms(100))), Duration::from_secs(2));
+        assert_eq!(pacer.next(&Ok(ms(500))), Duration::from_secs(1));
+        assert_eq!(pacer.next(&Ok(ms(5000))), Duration::from_secs(1), "floored at the min");
+    }
+
+    #[test]
+    fn failures_reset_the_interval() {
+        let mut pacer = pacer(1, 60);
+        for _ in 0..5 {
+            pacer.next(&Ok(ms(100)));
+        }
+        assert_eq!(pacer.next(&Err(ping::Failure::Timeout)), Duration::from_secs(1));
+        assert_eq!(pacer.srtt, Some(ms(100)), "the smoothed RTT is kept");
+        assert_eq!(pacer.next(&Ok(ms(100))), ms(1500));
+    }
+}
@@ -0,0 +1,112 @@
+//! Peers met so far, kept in SQLite (`--address-book`) so that a restarted
+//! node pings them again without being told their addresses.
+
+use libp2p::multiaddr::Protocol;
+use libp2p::{Multiaddr, PeerId};
+use rusqlite::{params, Connection};
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Tables, created when an address book is first opened.
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS peers (
+    peer_id TEXT PRIMARY KEY,
+    address TEXT NOT NULL,          -- where the peer was dialed last
+    name TEXT,
+    last_seen_us INTEGER,           -- last answered ping, microseconds since the Unix epoch
+    answered INTEGER NOT NULL DEFAULT 0,
+    rtt_total_us INTEGER NOT NULL DEFAULT 0
+);
+";
+
+/// A peer from the address book.
+#[derive(Debug)]
+pub struct Entry {
+    pub peer_id: PeerId,
+    /// The address to dial, ending with `/p2p/<peer id>` unless given without.
+    pub address: Multiaddr,
+    pub name: Option<String>,
+    /// When the peer last answered a ping.
+    pub last_seen: Option<SystemTime>,
+    /// Average RTT of all pings it ever answered.
+    pub avg_rtt: Option<Duration>,
+}
+
+/// A SQLite database of the peers dialed so far.
+pub struct AddressBook {
+    conn: Connection,
+    path: PathBuf,
+}
+
+impl AddressBook {
+    /// Opens the address book at `path`, creating it if it doesn't exist.
+    pub fn open(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let conn = Connection::open(path).map_err(|e| format!("{}: {e}", path.display()))?;
+        conn.execute_batch(SCHEMA)?;
+        Ok(Self { conn, path: path.to_owned() })
+    }
+
+    /// The file the address book is kept in.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns every peer in the address book, most recently seen first.
+    pub fn entries(&self) -> Result<Vec<Entry>, Box<dyn Error>> {
+        let mut statement = self.conn.prepare(
+            "SELECT peer_id, address, name, last_seen_us, answered, rtt_total_us FROM peers
+             ORDER BY last_seen_us IS NULL, last_seen_us DESC",
+        )?;
+        let mut rows = statement.query([])?;
+        let mut entries = Vec::new();
+        while let Some(row) = rows.next()? {
+            let peer_id: String = row.get(0)?;
+            let address: String = row.get(1)?;
+            let last_seen_us: Option<i64> = row.get(3)?;
+            let answered: i64 = row.get(4)?;
+            let rtt_total_us: i64 = row.get(5)?;
+            entries.push(Entry {
+                peer_id: peer_id.parse().map_err(|e| format!("address book peer {peer_id}: {e}"))?,
+                address: address.parse().map_err(|e| format!("address book address {address}: {e}"))?,
+                name: row.get(2)?,
+                last_seen: last_seen_us.map(|us| UNIX_EPOCH + Duration::from_micros(us as u64)),
+                avg_rtt: (answered > 0).then(|| Duration::from_micros((rtt_total_us / answered) as u64)),
+            });
+        }
+        Ok(entries)
+    }
+
+    /// Notes that `peer_id` was dialed at `address`, named `name` for the
+    /// output if given.
+    pub fn dialed(&self, peer_id: &PeerId, address: &Multiaddr, name: Option<&str>) -> rusqlite::Result<()> {
+        self.conn.execute(
+            "INSERT INTO peers (peer_id, address, name) VALUES (?1, ?2, ?3)
+             ON CONFLICT (peer_id) DO UPDATE SET address = excluded.address, name = COALESCE(excluded.name, name)",
+            params![peer_id.to_string(), address.to_string(), name],
+        )?;
+        Ok(())
+    }
+
+    /// Adds an answered ping of `peer_id`, if the peer is in the address book.
+    pub fn answered(&self, peer_id: &PeerId, rtt: Duration) -> rusqlite::Result<()> {
+        self.conn.execute(
+            "UPDATE peers SET last_seen_us = ?2, answered = answered + 1, rtt_total_us = rtt_total_us + ?3
+             WHERE peer_id = ?1",
+            params![peer_id.to_string(), micros_since_epoch(SystemTime::now()), rtt.as_micros() as i64],
+        )?;
+        Ok(())
+    }
+}
+
+/// Returns `address` ending with `/p2p/<peer_id>`, adding it if missing.
+pub fn with_peer_id(address: &Multiaddr, peer_id: PeerId) -> Multiaddr {
+    match address.iter().last() {
+        Some(Protocol::P2p(_)) => address.clone(),
+        _ => address.clone().with(Protocol::P2p(peer_id)),
+    }
+}
+
+fn micros_since_epoch(time: SystemTime) -> i64 {
+    time.duration_since(UNIX_EPOCH).unwrap_or_default().as_micros() as i64
+}
@@ -0,0 +1,104 @@
+//! Exponential backoff with jitter for re-dialing peers.
+
+use rand::Rng;
+use std::time::Duration;
+
+/// Computes increasing, randomized delays between reconnection attempts.
+///
+/// Each attempt doubles the base delay up to `max`; the returned delay is
+/// picked uniformly from the upper half of the base delay so that many nodes
+/// losing the same peer don't all re-dial at the same instant.
+#[derive(Debug, Clone)]
+pub struct Backoff {
+    initial: Duration,
+    max: Duration,
+    current: Duration,
+    attempts: u32,
+}
+
+impl Backoff {
+    /// Creates a backoff starting at `initial` and capped at `max`.
+    pub fn new(initial: Duration, max: Duration) -> Self {
+        Self {
+            initial,
+            max,
+            current: initial.min(max),
+            attempts: 0,
+        }
+    }
+
+    /// Number of delays handed out since the last [`reset`](Self::reset).
+    pub fn attempts(&self) -> u32 {
+        self.attempts
+    }
+
+    /// Returns the delay before the next attempt and advances the backoff.
+    pub fn next_delay(&mut self) -> Duration {
+        let base = self.current;
+        self.current = self.current.saturating_mul(2).min(self.max);
+        self.attempts = self.attempts.saturating_add(1);
+
+        let half = base / 2;
+        half + rand::thread_rng().gen_range(Duration::ZERO..=half)
+    }
+
+    /// Starts over from the initial delay, e.g. after a successful connection.
+    pub fn reset(&mut self) {
+        self.current = self.initial.min(self.max);
+        self.attempts = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn secs(secs: u64) -> Duration {
+        Duration::from_secs(secs)
+    }
+
+    #[test]
+    fn delays_are_within_the_upper_half_of_the_base() {
+        let mut backoff = Backoff::new(secs(8), secs(8));
+        for _ in 0..1000 {
+            let delay = backoff.next_delay();
+            assert!((secs(4)..=secs(8)).contains(&delay), "{delay:?}");
+        }
+    }
+
+    #[test]
+    fn base_doubles_up_to_the_cap() {
+        let mut backoff = Backoff::new(secs(1), secs(5));
+        for base in [1, 2, 4, 5, 5] {
+            let delay = backoff.next_delay();
+            assert!((secs(base) / 2..=secs(base)).contains(&delay), "{delay:?} for a base of {base}s");
+        }
+        assert_eq!(backoff.attempts(), 5);
+    }
+
+    #[test]
+    fn initial_delay_is_capped() {
+        let mut backoff = Backoff::new(secs(10), secs(2));
+        assert!(backoff.next_delay() <= secs(2));
+    }
+
+    #[test]
+    fn doubling_saturates() {
+        let mut backoff = Backoff::new(Duration::MAX / 2 + secs(1), Duration::MAX);
+        backoff.next_delay();
+        let delay = backoff.next_delay();
+        assert!(delay >= Duration::MAX / 2, "{delay:?}");
+    }
+
+    #[test]
+    fn reset_starts_over() {
+        let mut backoff = Backoff::new(secs(1), secs(60));
+        for _ in 0..5 {
+            backoff.next_delay();
+        }
+        backoff.reset();
+        assert_eq!(backoff.attempts(), 0);
+        let delay = backoff.next_delay();
+        assert!((Duration::from_millis(500)..=secs(1)).contains(&delay), "{delay:?}");
+    }
+}
@@ -0,0 +1,180 @@
+//! Bytes sent and received over the streams of each peer's connections.
+//!
+//! The muxer of every connection is wrapped to count what its streams read
+//! and write, so the counts leave out the overhead of the security protocol
+//! and the multiplexer. Relayed connections count towards the peer at the end
+//! of the circuit, and again towards the relay.
+
+use futures::{AsyncRead, AsyncWrite};
+use libp2p::core::muxing::{StreamMuxer, StreamMuxerBox, StreamMuxerEvent, SubstreamBox};
+use libp2p::PeerId;
+use prometheus_client::encoding::EncodeLabelSet;
+use prometheus_client::metrics::counter::Counter;
+use prometheus_client::metrics::family::Family;
+use std::io;
+use std::ops::Sub;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+/// Bytes sent to and received from a peer, or all peers, as returned by
+/// [`PingNode::traffic`](crate::PingNode::traffic).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Traffic {
+    pub sent: u64,
+    pub received: u64,
+}
+
+impl Sub for Traffic {
+    type Output = Traffic;
+
+    /// Returns the traffic since an earlier reading of the same counters.
+    fn sub(self, earlier: Traffic) -> Traffic {
+        Traffic {
+            sent: self.sent.saturating_sub(earlier.sent),
+            received: self.received.saturating_sub(earlier.received),
+        }
+    }
+}
+
+/// Labels of the per-peer byte counters.
+#[derive(Debug, Clone, Hash, PartialEq, Eq, EncodeLabelSet)]
+pub(crate) struct PeerLabels {
+    peer_id: String,
+}
+
+/// Byte counters of every peer and of all of them, shared with the muxers of
+/// the connections.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Bandwidth {
+    pub(crate) sent: Family<PeerLabels, Counter>,
+    pub(crate) received: Family<PeerLabels, Counter>,
+    total_sent: Counter,
+    total_received: Counter,
+}
+
+impl Bandwidth {
+    /// Returns the traffic with `peer_id` so far.
+    pub(crate) fn peer(&self, peer_id: &PeerId) -> Traffic {
+        let labels = PeerLabels { peer_id: peer_id.to_string() };
+        Traffic { sent: self.sent.get_or_create(&labels).get(), received: self.received.get_or_create(&labels).get() }
+    }
+
+    /// Returns the traffic with all peers so far.
+    pub(crate) fn total(&self) -> Traffic {
+        Traffic { sent: self.total_sent.get(), received: self.total_received.get() }
+    }
+
+    /// Wraps the muxer of a connection with `peer_id` to count its bytes.
+    pub(crate) fn meter(&self, peer_id: &PeerId, muxer: StreamMuxerBox) -> StreamMuxerBox {
+        let labels = PeerLabels { peer_id: peer_id.to_string() };
+        let meter = Meter {
+            sent: [self.sent.get_or_create(&labels).clone(), self.total_sent.clone()],
+            received: [self.received.get_or_create(&labels).clone(), self.total_received.clone()],
+        };
+        StreamMuxerBox::new(Metered { inner: muxer, meter })
+    }
+}
+
+/// The counters of a peer and the totals, increased together.
+#[derive(Debug, Clone)]
+struct Meter {
+    sent: [Counter; 2],
+    received: [Counter; 2],
+}
+
+impl Meter {
+    fn sent(&self, bytes: usize) {
+        self.sent.iter().for_each(|counter| {
+            counter.inc_by(bytes as u64);
+        });
+    }
+
+    fn received(&self, bytes: usize) {
+        self.received.iter().for_each(|counter| {
+            counter.inc_by(bytes as u64);
+        });
+    }
+}
+
+/// A muxer whose streams count the bytes they read and write.
+struct Metered {
+    inner: StreamMuxerBox,
+    meter: Meter,
+}
+
+impl StreamMuxer for Metered {
+    type Substream = MeteredStream;
+    type Error = io::Error;
+
+    fn poll_inbound(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<Self::Substream, Self::Error>> {
+        let meter = self.meter.clone();
+        Pin::new(&mut self.inner).poll_inbound(cx).map_ok(|inner| MeteredStream { inner, meter })
+    }
+
+    fn poll_outbound(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<Self::Substream, Self::Error>> {
+        let meter = self.meter.clone();
+        Pin::new(&mut self.inner).poll_outbound(cx).map_ok(|inner| MeteredStream { inner, meter })
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut self.inner).poll_close(cx)
+    }
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<StreamMuxerEvent, Self::Error>> {
+        Pin::new(&mut self.inner).poll(cx)
+    }
+}
+
+/// A stream of a [`Metered`] muxer.
+struct MeteredStream {
+    inner: SubstreamBox,
+    meter: Meter,
+}
+
+impl AsyncRead for MeteredStream {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+        let read = Pin::new(&mut self.inner).poll_read(cx, buf);
+        if let Poll::Ready(Ok(bytes)) = read {
+            self.meter.received(bytes);
+        }
+        read
+    }
+
+    fn poll_read_vectored(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &mut [io::IoSliceMut<'_>],
+    ) -> Poll<io::Result<usize>> {
+        let read = Pin::new(&mut self.inner).poll_read_vectored(cx, bufs);
+        if let Poll::Ready(Ok(bytes)) = read {
+            self.meter.received(bytes);
+        }
+        read
+    }
+}
+
+impl AsyncWrite for MeteredStream {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let written = Pin::new(&mut self.inner).poll_write(cx, buf);
+        if let Poll::Ready(Ok(bytes)) = written {
+            self.meter.sent(bytes);
+        }
+        written
+    }
+
+    fn poll_write_vectored(mut self: Pin<&mut Self>, cx: &mut Context<'_>, bufs: &[io::IoSlice<'_>]) -> Poll<io::Result<usize>> {
+        let written = Pin::new(&mut self.inner).poll_write_vectored(cx, bufs);
+        if let Poll::Ready(Ok(bytes)) = written {
+            self.meter.sent(bytes);
+        }
+        written
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_close(cx)
+    }
+}
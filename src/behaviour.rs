@@ -0,0 +1,171 @@
+//! The network behaviour composed from ping, identify and optional protocols.
+
+use libp2p::allow_block_list::{self, AllowedPeers, BlockedPeers};
+use libp2p::identity::Keypair;
+use libp2p::kad::store::MemoryStore;
+use libp2p::swarm::behaviour::toggle::Toggle;
+use libp2p::swarm::NetworkBehaviour;
+use libp2p::{autonat, connection_limits, dcutr, gossipsub, identify, kad, mdns, relay, rendezvous, upnp};
+use std::error::Error;
+use std::time::Duration;
+
+use crate::{bench, clock, echo, exchange, labels, mesh, ping_limit, NodeConfig};
+
+/// Protocol family advertised via identify.
+const PROTOCOL_VERSION: &str = "/ipfs/0.1.0";
+
+/// Agent version advertised via identify, followed by the labels of the node.
+const AGENT_VERSION: &str = concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"));
+
+/// All protocols run by a [`PingNode`](crate::PingNode).
+///
+/// Optional protocols are wrapped in [`Toggle`] and stay inert unless enabled
+/// in the [`NodeConfig`].
+#[derive(NetworkBehaviour)]
+pub struct Behaviour {
+    /// Denies connections with peers missing from the allow list, if one is set.
+    ///
+    /// The gates come first so that a denied connection never reaches the
+    /// other protocols.
+    pub allowed_peers: Toggle<allow_block_list::Behaviour<AllowedPeers>>,
+    /// Denies connections with peers on the deny list.
+    pub denied_peers: allow_block_list::Behaviour<BlockedPeers>,
+    /// Denies connections beyond the configured limits.
+    pub limits: connection_limits::Behaviour,
+    /// Measures round-trip times on every connection, and answers the pings of
+    /// each peer up to the inbound ping limit.
+    pub ping: ping_limit::Behaviour,
+    /// Answers echo requests, and measures round-trip times with payloads of
+    /// the configured size.
+    pub echo: echo::Behaviour,
+    /// Answers clock requests, and estimates the clock offset to every peer if
+    /// enabled.
+    pub clock: clock::Behaviour,
+    /// Takes part in throughput benchmarks.
+    pub bench: bench::Behaviour,
+    /// Shares the peers of the node with those asking, and asks peers for
+    /// theirs.
+    pub exchange: exchange::Behaviour,
+    /// Exchanges agent version, supported protocols and observed addresses.
+    pub identify: identify::Behaviour,
+    /// Discovers peers on the local network.
+    pub mdns: Toggle<mdns::tokio::Behaviour>,
+    /// Dials and listens on `/p2p-circuit` addresses via relays.
+    pub relay_client: relay::client::Behaviour,
+    /// Relays circuits between other peers.
+    pub relay_server: Toggle<relay::Behaviour>,
+    /// Upgrades relayed connections to direct ones by hole punching.
+    pub dcutr: dcutr::Behaviour,
+    /// Asks connected peers to dial us back to learn whether we are reachable.
+    pub autonat: autonat::Behaviour,
+    /// Maps the listening ports on the router of the local network via UPnP.
+    pub upnp: Toggle<upnp::tokio::Behaviour>,
+    /// Finds the addresses of peers known only by their [`PeerId`](libp2p::PeerId).
+    pub kademlia: Toggle<kad::Behaviour<MemoryStore>>,
+    /// Shares measured RTTs with the rest of the latency mesh.
+    pub gossipsub: Toggle<gossipsub::Behaviour>,
+    /// Registers at a rendezvous point and discovers the peers registered
+    /// there under the same namespace.
+    pub rendezvous_client: Toggle<rendezvous::client::Behaviour>,
+    /// Keeps the registrations of other peers as a rendezvous point.
+    pub rendezvous_server: Toggle<rendezvous::server::Behaviour>,
+}
+
+impl Behaviour {
+    /// Builds the behaviour for the node identified by `keypair`, pinging
+    /// every `ping_interval` at most and driving the relay client transport
+    /// paired with `relay_client`.
+    pub(crate) fn new(
+        keypair: &Keypair,
+        config: &NodeConfig,
+        ping_interval: Duration,
+        relay_client: relay::client::Behaviour,
+    ) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let mdns = config
+            .mdns
+            .then(|| mdns::tokio::Behaviour::new(mdns::Config::default(), keypair.public().to_peer_id()))
+            .transpose()?;
+
+        let relay_server = config
+            .relay_server
+            .as_ref()
+            .map(|limits| relay::Behaviour::new(keypair.public().to_peer_id(), limits.into()));
+
+        let local_peer_id = keypair.public().to_peer_id();
+        let kademlia = config
+            .kademlia
+            .then(|| kad::Behaviour::new(local_peer_id, MemoryStore::new(local_peer_id)));
+
+        let gossipsub = config
+            .mesh
+            .then(|| -> Result<_, Box<dyn Error + Send + Sync>> {
+                let mut gossipsub = gossipsub::Behaviour::new(
+                    gossipsub::MessageAuthenticity::Signed(keypair.clone()),
+                    gossipsub::Config::default(),
+                )?;
+                gossipsub.subscribe(&mesh::topic())?;
+                Ok(gossipsub)
+            })
+            .transpose()?;
+
+        let allowed_peers = (!config.allow_peers.is_empty()).then(|| {
+            let mut allowed = allow_block_list::Behaviour::<AllowedPeers>::default();
+            for peer_id in &config.allow_peers {
+                allowed.allow_peer(*peer_id);
+            }
+            allowed
+        });
+        let mut denied_peers = allow_block_list::Behaviour::<BlockedPeers>::default();
+        for peer_id in &config.deny_peers {
+            denied_peers.block_peer(*peer_id);
+        }
+
+        let identify_config = identify::Config::new(PROTOCOL_VERSION.to_owned(), keypair.public())
+            .with_agent_version(labels::agent_version(AGENT_VERSION, &config.labels));
+
+        let ping = ping_limit::Behaviour::new(
+            ping_interval,
+            config.ping_timeout,
+            config.inbound_ping_limit,
+            config.impairment,
+            config.adaptive_interval,
+            config.min_peer_interval.filter(|min| *min < config.ping_interval).map(|_| config.ping_interval),
+            config.keep_alive,
+        );
+        // Echo and clock requests are answered from the same budget as pings,
+        // so that a peer can't get around the limit by switching protocols.
+        let mut echo = echo::Behaviour::new(config.echo_size, config.ping_interval, config.ping_timeout);
+        echo.set_limit(ping.buckets());
+        let mut clock = clock::Behaviour::new(config.clock_probe, config.ping_interval, config.ping_timeout);
+        clock.set_limit(ping.buckets());
+
+        Ok(Self {
+            allowed_peers: allowed_peers.into(),
+            denied_peers,
+            limits: connection_limits::Behaviour::new((&config.connection_limits).into()),
+            ping,
+            echo,
+            clock,
+            bench: bench::Behaviour::new(config.bench_server),
+            exchange: exchange::Behaviour::new(),
+            identify: identify::Behaviour::new(identify_config),
+            mdns: mdns.into(),
+            relay_client,
+            relay_server: relay_server.into(),
+            dcutr: dcutr::Behaviour::new(keypair.public().to_peer_id()),
+            autonat: autonat::Behaviour::new(keypair.public().to_peer_id(), crate::node::autonat_config(config)),
+            upnp: config.upnp.then(upnp::tokio::Behaviour::default).into(),
+            kademlia: kademlia.into(),
+            gossipsub: gossipsub.into(),
+            rendezvous_client: config
+                .rendezvous
+                .as_ref()
+                .map(|_| rendezvous::client::Behaviour::new(keypair.clone()))
+                .into(),
+            rendezvous_server: config
+                .rendezvous_server
+                .then(|| rendezvous::server::Behaviour::new(rendezvous::server::Config::default()))
+                .into(),
+        })
+    }
+}
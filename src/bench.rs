@@ -0,0 +1,229 @@
+//! Throughput benchmark protocol measuring how fast a connection carries data.
+//!
+//! The requesting side picks a direction and a duration. For an upload it
+//! sends data for that long and the remote answers with the number of bytes it
+//! received; for a download the remote sends data for that long instead.
+//!
+//! A transfer keeps the remote busy for up to [`MAX_DURATION`], so nodes only
+//! take part in those of other peers when serving is enabled.
+
+use async_trait::async_trait;
+use futures::prelude::*;
+use libp2p::request_response::{self, OutboundFailure, OutboundRequestId, ProtocolSupport};
+use libp2p::{PeerId, StreamProtocol};
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::time::{Duration, Instant};
+
+use crate::forward::request_response_behaviour;
+
+/// Protocol name of the benchmark protocol.
+pub const PROTOCOL_NAME: StreamProtocol = StreamProtocol::new("/libp2p-ping-tut/bench/1.0.0");
+
+/// Longest transfer a peer takes part in; longer ones are cut short.
+pub const MAX_DURATION: Duration = Duration::from_secs(60);
+
+/// Size of the writes making up a transfer.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Which way the data of a transfer flows, seen from the requesting side.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Upload,
+    Download,
+}
+
+impl fmt::Display for Direction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Direction::Upload => write!(f, "upload"),
+            Direction::Download => write!(f, "download"),
+        }
+    }
+}
+
+/// How much data a transfer carried in how much time.
+#[derive(Debug, Clone, Copy)]
+pub struct Throughput {
+    pub bytes: u64,
+    /// Time from sending the request to receiving the answer.
+    pub elapsed: Duration,
+}
+
+impl Throughput {
+    /// Returns the throughput in bytes per second.
+    pub fn bytes_per_second(&self) -> f64 {
+        self.bytes as f64 / self.elapsed.as_secs_f64()
+    }
+}
+
+/// The result of a transfer requested with [`Behaviour::start`].
+#[derive(Debug)]
+pub struct Event {
+    pub peer: PeerId,
+    pub direction: Direction,
+    pub result: Result<Throughput, OutboundFailure>,
+}
+
+/// Runs the transfers started with [`Self::start`], and takes part in those
+/// requested by every peer if serving.
+pub struct Behaviour {
+    inner: request_response::Behaviour<Codec>,
+    /// Direction and start of each pending transfer.
+    started: HashMap<OutboundRequestId, (Direction, Instant)>,
+}
+
+impl Behaviour {
+    /// Takes part in the transfers of other peers if `serve` is set; without,
+    /// their requests are refused as unsupported.
+    pub fn new(serve: bool) -> Self {
+        // Leave time for the request and answer around the longest transfer.
+        let config = request_response::Config::default().with_request_timeout(MAX_DURATION * 2);
+        let support = if serve { ProtocolSupport::Full } else { ProtocolSupport::Outbound };
+        Self {
+            inner: request_response::Behaviour::with_codec(Codec, [(PROTOCOL_NAME, support)], config),
+            started: HashMap::new(),
+        }
+    }
+
+    /// Transfers data to or from a connected peer for `duration`, at most
+    /// [`MAX_DURATION`]; the result is reported in an [`Event`].
+    pub fn start(&mut self, peer: PeerId, direction: Direction, duration: Duration) {
+        let transfer = Transfer { direction, duration: duration.min(MAX_DURATION), bytes: 0 };
+        let request_id = self.inner.send_request(&peer, transfer);
+        self.started.insert(request_id, (direction, Instant::now()));
+    }
+
+    /// Answers requests and turns answers into events.
+    fn on_inner_event(&mut self, event: request_response::Event<Transfer, Transfer>) -> Option<Event> {
+        match event {
+            request_response::Event::Message { peer, message } => match message {
+                request_response::Message::Request { request, channel, .. } => {
+                    // Fails only if the connection closed in the meantime.
+                    let _ = self.inner.send_response(channel, request);
+                    None
+                }
+                request_response::Message::Response { request_id, response } => {
+                    let (direction, started) = self.started.remove(&request_id)?;
+                    let throughput = Throughput { bytes: response.bytes, elapsed: started.elapsed() };
+                    Some(Event { peer, direction, result: Ok(throughput) })
+                }
+            },
+            request_response::Event::OutboundFailure { peer, request_id, error } => {
+                let (direction, _) = self.started.remove(&request_id)?;
+                Some(Event { peer, direction, result: Err(error) })
+            }
+            _ => None,
+        }
+    }
+}
+
+request_response_behaviour!(Behaviour, Codec, Event);
+
+/// A transfer request, or the answer to one.
+///
+/// On the wire both start with the direction and the duration in
+/// milliseconds. The side sending the data follows with it until the duration
+/// has passed and then closes the stream; the answer to an upload carries the
+/// number of bytes received instead.
+#[derive(Debug, Clone, Copy)]
+pub struct Transfer {
+    direction: Direction,
+    duration: Duration,
+    /// Bytes received, once read.
+    bytes: u64,
+}
+
+/// Reads and writes [`Transfer`]s.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Codec;
+
+#[async_trait]
+impl request_response::Codec for Codec {
+    type Protocol = StreamProtocol;
+    type Request = Transfer;
+    type Response = Transfer;
+
+    async fn read_request<T>(&mut self, _: &StreamProtocol, io: &mut T) -> io::Result<Transfer>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        let mut transfer = read_header(io).await?;
+        if transfer.direction == Direction::Upload {
+            transfer.bytes = futures::io::copy(io, &mut futures::io::sink()).await?;
+        }
+        Ok(transfer)
+    }
+
+    async fn read_response<T>(&mut self, _: &StreamProtocol, io: &mut T) -> io::Result<Transfer>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        let mut transfer = read_header(io).await?;
+        transfer.bytes = match transfer.direction {
+            Direction::Upload => {
+                let mut bytes = [0; 8];
+                io.read_exact(&mut bytes).await?;
+                u64::from_be_bytes(bytes)
+            }
+            Direction::Download => futures::io::copy(io, &mut futures::io::sink()).await?,
+        };
+        Ok(transfer)
+    }
+
+    async fn write_request<T>(&mut self, _: &StreamProtocol, io: &mut T, transfer: Transfer) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        write_header(io, &transfer).await?;
+        if transfer.direction == Direction::Upload {
+            write_for(io, transfer.duration).await?;
+        }
+        io.close().await
+    }
+
+    async fn write_response<T>(&mut self, _: &StreamProtocol, io: &mut T, transfer: Transfer) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        write_header(io, &transfer).await?;
+        match transfer.direction {
+            Direction::Upload => io.write_all(&transfer.bytes.to_be_bytes()).await?,
+            Direction::Download => write_for(io, transfer.duration.min(MAX_DURATION)).await?,
+        }
+        io.close().await
+    }
+}
+
+async fn read_header<T: AsyncRead + Unpin + Send>(io: &mut T) -> io::Result<Transfer> {
+    let mut header = [0; 9];
+    io.read_exact(&mut header).await?;
+    let direction = match header[0] {
+        0 => Direction::Upload,
+        1 => Direction::Download,
+        _ => return Err(io::Error::new(io::ErrorKind::InvalidData, "unknown transfer direction")),
+    };
+    let millis = u64::from_be_bytes(header[1..].try_into().expect("header holds 8 duration bytes"));
+    Ok(Transfer { direction, duration: Duration::from_millis(millis), bytes: 0 })
+}
+
+async fn write_header<T: AsyncWrite + Unpin + Send>(io: &mut T, transfer: &Transfer) -> io::Result<()> {
+    let mut header = [0; 9];
+    header[0] = match transfer.direction {
+        Direction::Upload => 0,
+        Direction::Download => 1,
+    };
+    header[1..].copy_from_slice(&(transfer.duration.as_millis() as u64).to_be_bytes());
+    io.write_all(&header).await
+}
+
+/// Writes zeros until `duration` has passed.
+async fn write_for<T: AsyncWrite + Unpin + Send>(io: &mut T, duration: Duration) -> io::Result<()> {
+    let chunk = vec![0; CHUNK_SIZE];
+    let deadline = Instant::now() + duration;
+    while Instant::now() < deadline {
+        io.write_all(&chunk).await?;
+    }
+    Ok(())
+}
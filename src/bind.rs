@@ -0,0 +1,315 @@
+//! Binding the TCP dials of a node to a local IP or network interface, so that
+//! a host with several uplinks pings over a chosen one, and the socket options
+//! of its TCP connections.
+
+use futures::future::BoxFuture;
+use futures::{FutureExt, TryFutureExt};
+use libp2p::core::transport::{ListenerId, TransportError, TransportEvent};
+use libp2p::multiaddr::Protocol;
+use libp2p::{tcp, Multiaddr, Transport};
+use socket2::{SockRef, TcpKeepalive};
+use std::collections::HashMap;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::net::TcpSocket;
+
+use crate::socks::{self, Destination, Socks5Proxy};
+use crate::NodeConfig;
+
+/// The local addresses and interface outgoing connections use; the default
+/// leaves both to the OS.
+///
+/// Only TCP and WebSocket dials are bound; QUIC dials go out through the socket
+/// of a listener of the same IP family, so listen on the bound addresses to
+/// have them use those too.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LocalBinding {
+    /// Source IPs of dials, at most one of each family; dials to a family
+    /// without one use whichever the OS picks.
+    pub addresses: Vec<IpAddr>,
+    /// Network interface every dial is bound to, so that it leaves through
+    /// that interface whatever the routing table says. Only supported on
+    /// Linux, elsewhere it is ignored.
+    pub interface: Option<String>,
+}
+
+impl LocalBinding {
+    /// Binds to the interface `name` and to its first IPv4 and first IPv6
+    /// address that isn't link-local.
+    pub fn interface(name: &str) -> Result<Self, String> {
+        let interfaces = if_addrs::get_if_addrs().map_err(|e| format!("listing network interfaces: {e}"))?;
+        let mut addresses: Vec<IpAddr> = Vec::new();
+        let mut found = false;
+        for interface in interfaces.iter().filter(|interface| interface.name == name) {
+            found = true;
+            let ip = interface.ip();
+            if !interface.is_link_local() && !addresses.iter().any(|bound| bound.is_ipv4() == ip.is_ipv4()) {
+                addresses.push(ip);
+            }
+        }
+        if !found {
+            return Err(format!("no network interface named `{name}`"));
+        }
+        if addresses.is_empty() {
+            return Err(format!("network interface `{name}` has no usable address"));
+        }
+        Ok(Self { addresses, interface: Some(name.to_owned()) })
+    }
+
+    /// The source IP of dials to `remote`.
+    fn source_for(&self, remote: IpAddr) -> Option<IpAddr> {
+        self.addresses.iter().copied().find(|ip| ip.is_ipv4() == remote.is_ipv4())
+    }
+}
+
+/// Socket options of the TCP connections of a node, both dialed and accepted,
+/// including those carrying WebSocket.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TcpOptions {
+    /// Send small writes such as pings right away instead of holding them back
+    /// to coalesce them (Nagle's algorithm), which would add to their RTTs.
+    pub nodelay: bool,
+    /// Probe connections idle this long with TCP keepalives, so that dead
+    /// peers and NAT mappings about to expire are noticed; `None` leaves it
+    /// to the OS, which usually doesn't probe.
+    pub keepalive: Option<Duration>,
+    /// Time to live (hop limit over IPv6) of the packets sent.
+    pub ttl: Option<u32>,
+    /// Differentiated services code point marking the packets sent for a QoS
+    /// class, from 0 to 63, e.g. 46 for expedited forwarding. Setting it over
+    /// IPv6 is only supported on Linux.
+    pub dscp: Option<u8>,
+    /// Dial from the port of a listener of the same IP family, so that peers
+    /// observe the address we listen on, which NATs then map the same way for
+    /// both directions and which hole punching needs to succeed.
+    pub port_reuse: bool,
+}
+
+impl Default for TcpOptions {
+    fn default() -> Self {
+        Self { nodelay: true, keepalive: None, ttl: None, dscp: None, port_reuse: false }
+    }
+}
+
+impl TcpOptions {
+    /// Sets the options on a socket of the family of `ip`.
+    fn apply(&self, socket: SockRef<'_>, ip: IpAddr) -> io::Result<()> {
+        socket.set_nodelay(self.nodelay)?;
+        if let Some(idle) = self.keepalive {
+            socket.set_tcp_keepalive(&TcpKeepalive::new().with_time(idle))?;
+        }
+        match (ip, self.ttl) {
+            (IpAddr::V4(_), Some(ttl)) => socket.set_ttl(ttl)?,
+            (IpAddr::V6(_), Some(hops)) => socket.set_unicast_hops_v6(hops)?,
+            (_, None) => {}
+        }
+        // DSCP is the upper six bits of the former type of service byte.
+        match (ip, self.dscp) {
+            (IpAddr::V4(_), Some(dscp)) => socket.set_tos(u32::from(dscp) << 2)?,
+            #[cfg(target_os = "linux")]
+            (IpAddr::V6(_), Some(dscp)) => socket.set_tclass_v6(u32::from(dscp) << 2)?,
+            #[cfg(not(target_os = "linux"))]
+            (IpAddr::V6(_), Some(_)) => {
+                return Err(io::Error::new(io::ErrorKind::Unsupported, "DSCP marking over IPv6 is only supported on Linux"))
+            }
+            (_, None) => {}
+        }
+        Ok(())
+    }
+}
+
+/// The TCP transport, dialing from the addresses and interface of a
+/// [`LocalBinding`], through the proxy if there is one, and setting the
+/// [`TcpOptions`] on every connection; listening is left to the inner
+/// transport.
+pub(crate) struct BoundTcp {
+    inner: tcp::tokio::Transport,
+    binding: LocalBinding,
+    options: TcpOptions,
+    proxy: Option<Socks5Proxy>,
+    /// Addresses listened on, for dialing from their ports with
+    /// [`TcpOptions::port_reuse`].
+    listen_addrs: HashMap<ListenerId, SocketAddr>,
+}
+
+impl BoundTcp {
+    /// Dials as set in [`NodeConfig::local_binding`], [`NodeConfig::tcp_options`]
+    /// and [`NodeConfig::proxy`].
+    pub(crate) fn new(node: &NodeConfig) -> Self {
+        let (binding, options) = (node.local_binding.clone(), node.tcp_options);
+        // Listening sockets get the options as well, as some of them carry
+        // over to the connections accepted, and with port reuse share their
+        // ports with dials.
+        let mut config = tcp::Config::default().nodelay(options.nodelay).port_reuse(options.port_reuse);
+        if let Some(ttl) = options.ttl {
+            config = config.ttl(ttl);
+        }
+        Self {
+            inner: tcp::tokio::Transport::new(config),
+            binding,
+            options,
+            proxy: node.proxy.clone(),
+            listen_addrs: HashMap::new(),
+        }
+    }
+
+    /// The local address of dials to `remote`: the bound source IP and, with
+    /// port reuse, the port of a listener of the same family on all addresses
+    /// or on one that is loopback exactly if `remote` is, as libp2p picks them.
+    fn local_addr(&self, remote: IpAddr, reuse_port: bool) -> Option<SocketAddr> {
+        let source = self.binding.source_for(remote);
+        let port = self
+            .listen_addrs
+            .values()
+            .filter(|_| reuse_port)
+            .find(|addr| {
+                addr.is_ipv4() == remote.is_ipv4()
+                    && (addr.ip().is_unspecified() || addr.ip().is_loopback() == remote.is_loopback())
+            })
+            .map(SocketAddr::port);
+        if source.is_none() && port.is_none() {
+            return None;
+        }
+        let unspecified = if remote.is_ipv4() { IpAddr::from(Ipv4Addr::UNSPECIFIED) } else { Ipv6Addr::UNSPECIFIED.into() };
+        Some(SocketAddr::new(source.unwrap_or(unspecified), port.unwrap_or(0)))
+    }
+
+    /// Creates a socket for dialing `remote`, bound and set up as configured
+    /// so that even the handshake goes out as it should.
+    fn socket(&self, remote: SocketAddr, reuse_port: bool) -> io::Result<TcpSocket> {
+        let socket = if remote.is_ipv4() { TcpSocket::new_v4()? } else { TcpSocket::new_v6()? };
+        if let Some(local) = self.local_addr(remote.ip(), reuse_port) {
+            if local.port() != 0 {
+                socket.set_reuseaddr(true)?;
+                #[cfg(unix)]
+                socket.set_reuseport(true)?;
+            }
+            socket.bind(local)?;
+        }
+        #[cfg(target_os = "linux")]
+        if let Some(interface) = &self.binding.interface {
+            socket.bind_device(Some(interface.as_bytes()))?;
+        }
+        self.options.apply(SockRef::from(&socket), remote.ip())?;
+        Ok(socket)
+    }
+}
+
+impl Transport for BoundTcp {
+    type Output = tcp::tokio::TcpStream;
+    type Error = io::Error;
+    type ListenerUpgrade = BoxFuture<'static, io::Result<tcp::tokio::TcpStream>>;
+    type Dial = BoxFuture<'static, io::Result<tcp::tokio::TcpStream>>;
+
+    fn listen_on(&mut self, id: ListenerId, mut addr: Multiaddr) -> Result<(), TransportError<Self::Error>> {
+        let local = socket_addr(&addr).filter(|_| self.options.port_reuse);
+        if let Some(mut local) = local {
+            // Dials right after listening need the port, which the listener
+            // only reports once polled, so pick a free one up front.
+            if local.port() == 0 {
+                let probe = std::net::TcpListener::bind(local).and_then(|probe| probe.local_addr());
+                local.set_port(probe.map_err(TransportError::Other)?.port());
+                addr = addr.replace(1, |_| Some(Protocol::Tcp(local.port()))).expect("the second protocol is TCP");
+            }
+            self.inner.listen_on(id, addr)?;
+            self.listen_addrs.insert(id, local);
+            return Ok(());
+        }
+        self.inner.listen_on(id, addr)
+    }
+
+    fn remove_listener(&mut self, id: ListenerId) -> bool {
+        self.listen_addrs.remove(&id);
+        self.inner.remove_listener(id)
+    }
+
+    fn dial(&mut self, addr: Multiaddr) -> Result<Self::Dial, TransportError<Self::Error>> {
+        if let Some(proxy) = self.proxy.clone() {
+            let Some(destination) = destination(&addr, proxy.remote_dns) else {
+                return Err(TransportError::MultiaddrNotSupported(addr));
+            };
+            tracing::debug!(address = %addr, proxy = %proxy.addr, "dialing through the proxy");
+            // Dials to the proxy can't share a port, as they all go to the same
+            // address.
+            let socket = self.socket(proxy.addr, false).map_err(TransportError::Other)?;
+            return Ok(async move {
+                let mut stream = socket.connect(proxy.addr).await?;
+                socks::connect(&mut stream, &proxy, &destination).await?;
+                Ok(tcp::tokio::TcpStream(stream))
+            }
+            .boxed());
+        }
+        let Some(remote) = socket_addr(&addr).filter(|remote| remote.port() != 0 && !remote.ip().is_unspecified()) else {
+            return Err(TransportError::MultiaddrNotSupported(addr));
+        };
+        tracing::debug!(address = %remote, binding = ?self.binding, "dialing address");
+        let socket = self.socket(remote, self.options.port_reuse).map_err(TransportError::Other)?;
+        Ok(async move { Ok(tcp::tokio::TcpStream(socket.connect(remote).await?)) }.boxed())
+    }
+
+    fn dial_as_listener(&mut self, addr: Multiaddr) -> Result<Self::Dial, TransportError<Self::Error>> {
+        // Dials reuse the listening ports either way, if allowed to.
+        self.dial(addr)
+    }
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<TransportEvent<Self::ListenerUpgrade, Self::Error>> {
+        let options = self.options;
+        let event = Pin::new(&mut self.inner).poll(cx);
+        if let Poll::Ready(TransportEvent::ListenerClosed { listener_id, .. }) = &event {
+            self.listen_addrs.remove(listener_id);
+        }
+        event.map(|event| {
+            event.map_upgrade(move |upgrade| {
+                upgrade
+                    .and_then(move |stream| async move {
+                        let ip = stream.0.peer_addr()?.ip();
+                        options.apply(SockRef::from(&stream.0), ip)?;
+                        Ok(stream)
+                    })
+                    .boxed()
+            })
+        })
+    }
+
+    fn address_translation(&self, listen: &Multiaddr, observed: &Multiaddr) -> Option<Multiaddr> {
+        self.inner.address_translation(listen, observed)
+    }
+}
+
+/// Returns where a proxy connects to for an address that [`socket_addr`] takes,
+/// or for `/dns|dns4|dns6/<name>/tcp/<port>` if the proxy resolves names.
+fn destination(addr: &Multiaddr, names: bool) -> Option<Destination> {
+    let mut protocols = addr.iter();
+    let name = match protocols.next()? {
+        Protocol::Dns(name) | Protocol::Dns4(name) | Protocol::Dns6(name) if names => name,
+        _ => return socket_addr(addr).filter(|addr| addr.port() != 0).map(Destination::Addr),
+    };
+    let Protocol::Tcp(port) = protocols.next()? else {
+        return None;
+    };
+    match protocols.next() {
+        None | Some(Protocol::P2p(_)) => Some(Destination::Name(name.into_owned(), port)),
+        _ => None,
+    }
+}
+
+/// Returns the socket address of `/ip4|ip6/<ip>/tcp/<port>` addresses,
+/// optionally ending with `/p2p/<peer id>`.
+fn socket_addr(addr: &Multiaddr) -> Option<SocketAddr> {
+    let mut protocols = addr.iter();
+    let ip = match protocols.next()? {
+        Protocol::Ip4(ip) => IpAddr::from(ip),
+        Protocol::Ip6(ip) => IpAddr::from(ip),
+        _ => return None,
+    };
+    let Protocol::Tcp(port) = protocols.next()? else {
+        return None;
+    };
+    match protocols.next() {
+        None | Some(Protocol::P2p(_)) => Some(SocketAddr::new(ip, port)),
+        _ => None,
+    }
+}
@@ -0,0 +1,147 @@
+//! Step-by-step construction of a [`PingNode`].
+
+use libp2p::identity::Keypair;
+use libp2p::multiaddr::Protocol;
+use libp2p::relay;
+use std::time::Duration;
+
+use crate::bandwidth::Bandwidth;
+use crate::race::Races;
+use crate::rendezvous::Meeting;
+use crate::timing::DialTimer;
+use crate::{transport, Behaviour, NodeConfig, PingError, PingNode, SecurityChoice, TransportChoice};
+
+/// Builds a [`PingNode`], overriding parts of the default setup.
+///
+/// Anything not set falls back to [`NodeConfig::default`] and a freshly
+/// generated identity.
+///
+/// ```no_run
+/// use libp2p_ping_tut::{PingNode, TransportChoice};
+/// use std::time::Duration;
+///
+/// # fn build() -> Result<(), Box<dyn std::error::Error>> {
+/// let node = PingNode::builder()
+///     .with_transport(TransportChoice::Quic)
+///     .with_ping_interval(Duration::from_secs(1))
+///     .with_idle_timeout(Duration::from_secs(60))
+///     .build()?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Default)]
+pub struct PingNodeBuilder {
+    keypair: Option<Keypair>,
+    config: NodeConfig,
+    /// Transports enabled with [`Self::with_transport`]; empty keeps those of
+    /// `config`.
+    transports: Vec<TransportChoice>,
+}
+
+impl PingNodeBuilder {
+    /// Starts from the default settings.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Uses an existing identity, e.g. one loaded with
+    /// [`keyfile::load_or_generate`](crate::keyfile::load_or_generate).
+    pub fn with_identity(mut self, keypair: Keypair) -> Self {
+        self.keypair = Some(keypair);
+        self
+    }
+
+    /// Replaces all settings at once; options set afterwards override it.
+    pub fn with_config(mut self, config: NodeConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Enables a transport. The first call replaces those of the settings, so
+    /// that e.g. QUIC can be used without TCP; call again to enable more.
+    pub fn with_transport(mut self, transport: TransportChoice) -> Self {
+        if !self.transports.contains(&transport) {
+            self.transports.push(transport);
+        }
+        self
+    }
+
+    /// Sets the security handshake(s) offered on TCP and WebSocket connections.
+    pub fn with_security(mut self, security: SecurityChoice) -> Self {
+        self.config.security = security;
+        self
+    }
+
+    /// Sets how often every peer is pinged; adaptive intervals only lengthen
+    /// it.
+    pub fn with_ping_interval(mut self, interval: Duration) -> Self {
+        self.config.ping_interval = interval;
+        self
+    }
+
+    /// Sets how long to wait for the answer to a ping.
+    pub fn with_ping_timeout(mut self, timeout: Duration) -> Self {
+        self.config.ping_timeout = timeout;
+        self
+    }
+
+    /// Sets how long a connection without active streams is kept open.
+    pub fn with_idle_timeout(mut self, idle_timeout: Duration) -> Self {
+        self.config.idle_timeout = idle_timeout;
+        self
+    }
+
+    /// Keeps every connection open however long it is idle, or not.
+    pub fn with_keep_alive(mut self, keep_alive: bool) -> Self {
+        self.config.keep_alive = keep_alive;
+        self
+    }
+
+    /// Builds the swarm and returns the node.
+    pub fn build(self) -> Result<PingNode, PingError> {
+        let mut config = self.config;
+        if !self.transports.is_empty() {
+            config.transports = self.transports;
+        }
+        let interval = config.adaptive_interval.map_or(config.ping_interval, |adaptive| adaptive.min);
+        // Pings may go out as often as the shortest interval of a peer.
+        let interval = config.min_peer_interval.map_or(interval, |min| min.min(interval));
+        let keypair = self.keypair.unwrap_or_else(Keypair::generate_ed25519);
+        let timer = DialTimer::default();
+        let races = Races::default();
+        let bandwidth = Bandwidth::default();
+
+        let (relay_transport, relay_client) = relay::client::new(keypair.public().to_peer_id());
+        let mut swarm = libp2p::SwarmBuilder::with_existing_identity(keypair)
+            .with_tokio() // Specify tokio as the executor for async operations.
+            .with_other_transport(|key| transport::build(key, &config, relay_transport, timer.clone(), races.clone(), bandwidth.clone())) // Add the selected transports.
+            .map_err(|e| PingError::Build(e.into()))?
+            .with_behaviour(|key| Behaviour::new(key, &config, interval, relay_client)) // Add ping and the optional protocols.
+            .map_err(|e| PingError::Build(e.into()))?
+            .with_swarm_config(|cfg| cfg.with_idle_connection_timeout(config.idle_timeout)) // Set idle connection timeout.
+            .build(); // Finalize building the swarm.
+
+        if !config.bootstrap.is_empty() {
+            let kademlia = swarm
+                .behaviour_mut()
+                .kademlia
+                .as_mut()
+                .ok_or_else(|| PingError::InvalidConfig("bootstrap nodes require Kademlia to be enabled".to_owned()))?;
+            for addr in &config.bootstrap {
+                let Some(Protocol::P2p(peer_id)) = addr.iter().last() else {
+                    let reason = "a bootstrap address must end with /p2p/<peer id>".to_owned();
+                    return Err(PingError::InvalidMultiaddr { addr: addr.to_string(), reason });
+                };
+                kademlia.add_address(&peer_id, addr.clone());
+            }
+            kademlia.bootstrap().map_err(|e| PingError::Build(e.into()))?;
+        }
+
+        let meeting = config.rendezvous.as_ref().map(Meeting::new).transpose()?;
+        if let Some(meeting) = &meeting {
+            meeting.start(&mut swarm)?;
+        }
+
+        Ok(PingNode::from_swarm(swarm, &config, timer, races, meeting, bandwidth))
+    }
+}
@@ -0,0 +1,916 @@
+//! Command-line interface definition.
+//!
+//! Options that can also be set in the configuration file have no clap
+//! defaults, so that [`Settings`](crate::config::Settings) can tell whether
+//! they were given on the command line.
+
+use clap::{Parser, Subcommand};
+use libp2p::{Multiaddr, PeerId};
+use libp2p_ping_tut::ping_limit::PingLimitAction;
+use libp2p_ping_tut::{echo, labels, SecurityChoice, Socks5Proxy, TransportChoice};
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime};
+
+use crate::logging::{LogFormat, Rotation};
+use crate::output::Format;
+use crate::schedule::Schedule;
+use crate::targets::Overrides;
+
+/// Libp2p ping tool.
+#[derive(Debug, Parser)]
+#[command(version, about)]
+pub struct Cli {
+    /// TOML configuration file; options given on the command line take precedence.
+    #[arg(long, global = true, value_name = "PATH")]
+    pub config: Option<PathBuf>,
+
+    /// Time between outbound pings on each connection, e.g. `100ms` or `60s`
+    /// [default: 15s].
+    #[arg(long, global = true, value_parser = parse_duration)]
+    pub interval: Option<Duration>,
+
+    /// Lengthen the interval between the pings of stable peers up to
+    /// `--max-interval`, back towards `--interval` as soon as their RTTs vary
+    /// more or pings fail.
+    #[arg(long, global = true)]
+    pub adaptive: bool,
+
+    /// Longest interval between pings with `--adaptive` [default: 5m].
+    #[arg(long, global = true, value_parser = parse_duration, value_name = "DURATION", requires = "adaptive")]
+    pub max_interval: Option<Duration>,
+
+    /// Time to wait for a ping response before counting it as failed [default: 20s].
+    #[arg(long, global = true, value_parser = parse_duration)]
+    pub timeout: Option<Duration>,
+
+    /// Close connections without open streams after this long; the streams of
+    /// our own pings don't count, so keep it above `--interval` or pass
+    /// `--keep-alive` [default: 30s].
+    #[arg(long, global = true, value_parser = parse_duration, value_name = "DURATION")]
+    pub idle_timeout: Option<Duration>,
+
+    /// Keep connections open however long they are idle, so that slow ping
+    /// intervals don't re-handshake every time.
+    #[arg(long, global = true, conflicts_with = "idle_timeout")]
+    pub keep_alive: bool,
+
+    /// Time a dial, including the handshakes, may take before it fails
+    /// [default: 30s].
+    #[arg(long, global = true, value_parser = parse_duration, value_name = "DURATION")]
+    pub dial_timeout: Option<Duration>,
+
+    /// Head start of each address of a peer over the next one when dialing
+    /// several, e.g. those its DNS name resolves to; the first to connect wins.
+    /// `0` dials them all at once [default: 250ms].
+    #[arg(long, global = true, value_parser = parse_delay, value_name = "DURATION")]
+    pub race_delay: Option<Duration>,
+
+    /// Dial at most this many peers at once; further dials wait for a free slot.
+    #[arg(long, global = true, value_parser = clap::value_parser!(u64).range(1..), value_name = "N")]
+    pub max_concurrent_dials: Option<u64>,
+
+    /// Multi-address to listen on; may be repeated, e.g. to add an IPv6 address
+    /// or pin a fixed port.
+    ///
+    /// Defaults to a random port on all IPv4 and IPv6 interfaces for each
+    /// enabled transport.
+    #[arg(long = "listen", alias = "listen-addr", global = true, value_name = "MULTIADDR")]
+    pub listen: Vec<Multiaddr>,
+
+    /// Don't listen on IPv4 by default.
+    #[arg(long, global = true, conflicts_with = "no_ipv6")]
+    pub no_ipv4: bool,
+
+    /// Don't listen on IPv6 by default.
+    #[arg(long, global = true)]
+    pub no_ipv6: bool,
+
+    /// Local IP that TCP and WebSocket dials go out from, and that the node
+    /// listens on by default; may be given once per IP family.
+    ///
+    /// QUIC dials use the listener of their family, so they go out from it as
+    /// long as the node listens there.
+    #[arg(long, global = true, value_name = "IP")]
+    pub bind_address: Vec<IpAddr>,
+
+    /// Network interface that TCP and WebSocket dials are bound to (on Linux),
+    /// listening by default on its first IPv4 and IPv6 address, which dials use
+    /// as their source unless `--bind-address` picks others.
+    #[arg(long, global = true, value_name = "NAME")]
+    pub interface: Option<String>,
+
+    /// Whether TCP sends small writes such as pings right away rather than
+    /// coalescing them [default: true].
+    #[arg(long, global = true, value_name = "BOOL")]
+    pub tcp_nodelay: Option<bool>,
+
+    /// Send TCP keepalive probes on connections idle this long.
+    #[arg(long, global = true, value_parser = parse_duration, value_name = "DURATION")]
+    pub tcp_keepalive: Option<Duration>,
+
+    /// Time to live (hop limit over IPv6) of TCP packets.
+    #[arg(long, global = true, value_parser = clap::value_parser!(u32).range(1..=255), value_name = "HOPS")]
+    pub ttl: Option<u32>,
+
+    /// Mark TCP packets for a QoS class with this DSCP, from 0 to 63 or a name
+    /// such as `ef`, `af41` or `cs1`, to measure the queue they land in.
+    #[arg(long, global = true, value_parser = parse_dscp, value_name = "CLASS")]
+    pub dscp: Option<u8>,
+
+    /// SOCKS5 proxy to tunnel TCP and WebSocket dials through, as
+    /// `socks5://[user:password@]host:port`; `socks5h://` has the proxy resolve
+    /// DNS names, as needed for Tor. Not supported with QUIC.
+    #[arg(long, global = true, value_name = "URL")]
+    pub proxy: Option<Socks5Proxy>,
+
+    /// Dial over TCP from the port listened on, so that peers observe it and
+    /// hole punching through NATs succeeds more often.
+    #[arg(long, global = true)]
+    pub port_reuse: bool,
+
+    /// Comma-separated transports to enable: `tcp`, `quic`, `ws` and/or
+    /// `webtransport`, e.g. `tcp,ws` [default: tcp]. `webtransport` is
+    /// experimental: it hasn't been tried with browsers running js-libp2p.
+    #[arg(long, global = true, value_delimiter = ',')]
+    pub transport: Vec<TransportChoice>,
+
+    /// PEM certificate chain for listening on `/wss` addresses.
+    #[arg(long, global = true, value_name = "PATH", requires = "wss_key")]
+    pub wss_cert: Option<PathBuf>,
+
+    /// PEM private key for listening on `/wss` addresses.
+    #[arg(long, global = true, value_name = "PATH", requires = "wss_cert")]
+    pub wss_key: Option<PathBuf>,
+
+    /// Security handshake for TCP and WebSocket connections: `tls`, `noise`, or
+    /// `both` [default: tls].
+    #[arg(long, global = true)]
+    pub security: Option<SecurityChoice>,
+
+    /// Keypair file holding the node identity; created on first use.
+    ///
+    /// Without this option a fresh identity is generated on every run.
+    #[arg(long, global = true)]
+    pub identity: Option<PathBuf>,
+
+    /// Join the private network whose pre-shared key is in this `swarm.key`
+    /// file; only nodes holding the same key can connect. Not supported with QUIC.
+    #[arg(long, global = true, value_name = "PATH")]
+    pub psk: Option<PathBuf>,
+
+    /// Discover peers on the local network via mDNS and ping them automatically.
+    #[arg(long, global = true)]
+    pub mdns: bool,
+
+    /// Map the listening ports on the router via UPnP, so that peers outside
+    /// the local network can connect.
+    #[arg(long, global = true)]
+    pub upnp: bool,
+
+    /// Act as a circuit relay for peers that can't be reached directly.
+    #[arg(long, global = true)]
+    pub relay_server: bool,
+
+    /// Maximum number of peers a relay server holds reservations for [default: 128].
+    #[arg(long, global = true, value_name = "N")]
+    pub relay_max_reservations: Option<usize>,
+
+    /// Maximum number of circuits a relay server relays at once [default: 16].
+    #[arg(long, global = true, value_name = "N")]
+    pub relay_max_circuits: Option<usize>,
+
+    /// Relayed circuits are closed after this long [default: 2m].
+    #[arg(long, global = true, value_parser = parse_duration, value_name = "DURATION")]
+    pub relay_max_circuit_duration: Option<Duration>,
+
+    /// Relayed circuits are closed after this many bytes in each direction [default: 131072].
+    #[arg(long, global = true, value_name = "BYTES")]
+    pub relay_max_circuit_bytes: Option<u64>,
+
+    /// Take part in the throughput benchmarks other peers start with `bench`,
+    /// each of which loads the connection for up to a minute.
+    #[arg(long, global = true)]
+    pub bench_server: bool,
+
+    /// Dial back peers asking via AutoNAT whether they are reachable; only
+    /// useful on a publicly reachable node.
+    #[arg(long, global = true)]
+    pub autonat_server: bool,
+
+    /// Maximum number of AutoNAT dial-backs per period [default: 30].
+    #[arg(long, global = true, value_name = "N")]
+    pub autonat_max_dial_backs: Option<usize>,
+
+    /// Maximum number of AutoNAT dial-backs for any one peer per period [default: 3].
+    #[arg(long, global = true, value_name = "N")]
+    pub autonat_max_dial_backs_per_peer: Option<usize>,
+
+    /// Period the AutoNAT dial-back limits apply to [default: 1s].
+    #[arg(long, global = true, value_parser = parse_duration, value_name = "DURATION")]
+    pub autonat_period: Option<Duration>,
+
+    /// Also ask, and as an AutoNAT server dial back, peers at private
+    /// addresses, for a fleet on a private network.
+    #[arg(long, global = true)]
+    pub autonat_allow_private: bool,
+
+    /// Maximum number of incoming connections doing their handshakes at once.
+    #[arg(long, global = true, value_name = "N")]
+    pub max_pending_incoming: Option<u32>,
+
+    /// Maximum number of established incoming connections.
+    #[arg(long, global = true, value_name = "N")]
+    pub max_incoming_connections: Option<u32>,
+
+    /// Maximum number of established outgoing connections.
+    #[arg(long, global = true, value_name = "N")]
+    pub max_outgoing_connections: Option<u32>,
+
+    /// Maximum number of established connections with any one peer.
+    #[arg(long, global = true, value_name = "N")]
+    pub max_connections_per_peer: Option<u32>,
+
+    /// Answer at most this many pings per second from any one peer, across its
+    /// connections.
+    #[arg(long, global = true, value_name = "N")]
+    pub max_inbound_pings: Option<u32>,
+
+    /// What to do with peers pinging faster than `--max-inbound-pings`:
+    /// `throttle` their pings or `disconnect` them [default: throttle].
+    #[arg(long, global = true, value_name = "ACTION", requires = "max_inbound_pings")]
+    pub ping_limit_action: Option<PingLimitAction>,
+
+    /// Label to advertise to peers, e.g. `region=eu-west`; may be repeated.
+    ///
+    /// The labels of peers running this tool are shown next to their results.
+    #[arg(long = "label", global = true, value_name = "KEY=VALUE", value_parser = parse_label)]
+    pub labels: Vec<(String, String)>,
+
+    /// Publicly reachable address of this node; may be repeated.
+    ///
+    /// Relay servers hand these out to peers making a reservation and
+    /// advertise their listen addresses if none are given.
+    #[arg(long = "external-addr", global = true, value_name = "MULTIADDR")]
+    pub external_addrs: Vec<Multiaddr>,
+
+    /// Join the Kademlia DHT, so that this node can find and be found by other
+    /// peers by PeerId alone.
+    #[arg(long, global = true)]
+    pub kademlia: bool,
+
+    /// DHT node to join through, e.g. `/ip4/198.51.100.1/tcp/4001/p2p/12D3Koo...`;
+    /// may be repeated. Implies `--kademlia`.
+    #[arg(long, global = true, value_name = "MULTIADDR")]
+    pub bootstrap: Vec<Multiaddr>,
+
+    /// Join the latency mesh: share measured RTTs with other members over
+    /// gossipsub and print the latency matrix of the whole mesh.
+    #[arg(long, global = true)]
+    pub mesh: bool,
+
+    /// Time between latency reports published to the mesh [default: 30s].
+    #[arg(long, global = true, value_parser = parse_duration, value_name = "DURATION")]
+    pub mesh_interval: Option<Duration>,
+
+    /// Ask connected peers for the peers they are connected to, and ping
+    /// those as well.
+    #[arg(long, global = true)]
+    pub peer_exchange: bool,
+
+    /// How many hops beyond the peers pinged directly peer exchange reaches
+    /// [default: 1].
+    #[arg(long, global = true, value_name = "N", value_parser = clap::value_parser!(u32).range(1..))]
+    pub exchange_depth: Option<u32>,
+
+    /// Most peers dialed from the answer of each peer [default: 16].
+    #[arg(long, global = true, value_name = "N", value_parser = clap::value_parser!(u64).range(1..))]
+    pub exchange_fan_out: Option<u64>,
+
+    /// Meet other peers registered under this namespace at the
+    /// `--rendezvous-point`, e.g. `ns=myproject`, and ping them as they show
+    /// up. Registering needs an external address.
+    #[arg(long, global = true, value_name = "ns=NAMESPACE", value_parser = parse_namespace)]
+    pub rendezvous: Option<String>,
+
+    /// Rendezvous point to meet other peers at, e.g.
+    /// `/ip4/198.51.100.1/tcp/4001/p2p/12D3Koo...`.
+    #[arg(long, global = true, value_name = "MULTIADDR")]
+    pub rendezvous_point: Option<Multiaddr>,
+
+    /// Act as a rendezvous point, keeping the registrations of other peers.
+    #[arg(long, global = true)]
+    pub rendezvous_server: bool,
+
+    /// Only let this peer connect, in either direction; may be repeated.
+    ///
+    /// Remember to allow relays and bootstrap nodes as well.
+    #[arg(long = "allow-peer", global = true, value_name = "PEER_ID")]
+    pub allow_peers: Vec<PeerId>,
+
+    /// Never let this peer connect, in either direction; may be repeated.
+    #[arg(long = "deny-peer", global = true, value_name = "PEER_ID")]
+    pub deny_peers: Vec<PeerId>,
+
+    /// Serve Prometheus metrics at `http://<ADDR>/metrics`, e.g. `127.0.0.1:9090`.
+    #[arg(long, global = true, value_name = "ADDR")]
+    pub metrics: Option<SocketAddr>,
+
+    /// Serve a REST API to list, add and remove peers at `http://<ADDR>/peers`,
+    /// live events over a WebSocket at `/events`, and a web dashboard at `/`,
+    /// e.g. `127.0.0.1:8080`.
+    #[arg(long, global = true, value_name = "ADDR")]
+    pub api: Option<SocketAddr>,
+
+    /// Serve the gRPC control API of `proto/ping.proto` at this address, e.g.
+    /// `127.0.0.1:50051`; needs a build with the `grpc` feature.
+    #[arg(long, global = true, value_name = "ADDR")]
+    pub grpc: Option<SocketAddr>,
+
+    /// Show a live dashboard of peers and round-trip times instead of event
+    /// lines; the final statistics are printed on exit.
+    #[arg(long, global = true)]
+    pub tui: bool,
+
+    /// Like `--tui`, with a scrolling plot of the RTTs of each peer over the
+    /// last two minutes above the table.
+    #[arg(long, global = true)]
+    pub graph: bool,
+
+    /// POST a JSON notification to this URL whenever a peer goes up, degraded
+    /// or down, or starts violating `--fail-under` or `--max-rtt`.
+    #[arg(long, global = true, value_name = "URL")]
+    pub webhook: Option<String>,
+
+    /// Leave out the line of each ping and echo; connection changes and the
+    /// final statistics are still printed.
+    #[arg(short, long, global = true)]
+    pub quiet: bool,
+
+    /// Print the statistics of each peer over the last interval this often,
+    /// e.g. `60s`.
+    #[arg(long, global = true, value_parser = parse_duration, value_name = "DURATION")]
+    pub summary_interval: Option<Duration>,
+
+    /// SQLite database to store every ping result in, and to read for `report`;
+    /// created on first use.
+    #[arg(long, global = true, value_name = "PATH")]
+    pub store: Option<PathBuf>,
+
+    /// SQLite database of the peers dialed so far, with when each last
+    /// answered and its average RTT; they are pinged again on the next start.
+    #[arg(long, global = true, value_name = "PATH")]
+    pub address_book: Option<PathBuf>,
+
+    /// Export RTTs and connection spans to this OTLP/gRPC collector, e.g.
+    /// `http://localhost:4317`.
+    #[arg(long, global = true, value_name = "ENDPOINT")]
+    pub otlp: Option<String>,
+
+    /// Format of the logs on the console, whose level is set with RUST_LOG.
+    #[arg(long, global = true, value_enum, default_value_t = LogFormat::Text)]
+    pub log_format: LogFormat,
+
+    /// Also write the logs to this file, appending to it, with their own
+    /// level and format.
+    #[arg(long, global = true, value_name = "PATH")]
+    pub log_file: Option<PathBuf>,
+
+    /// Which logs go to the `--log-file`, in the syntax of RUST_LOG, e.g.
+    /// `info,libp2p_ping_tut=debug`.
+    #[arg(long, global = true, requires = "log_file", default_value = "info", value_name = "FILTER")]
+    pub log_file_level: String,
+
+    /// Format of the `--log-file`.
+    #[arg(long, global = true, requires = "log_file", value_enum, default_value_t = LogFormat::Text)]
+    pub log_file_format: LogFormat,
+
+    /// Rotate the `--log-file` once it would grow beyond a size such as
+    /// `100MB`, `hourly`, `daily` or `never`.
+    #[arg(long, global = true, requires = "log_file", default_value = "100MB", value_name = "WHEN")]
+    pub log_rotate: Rotation,
+
+    /// How many rotated log files to keep, as `<log-file>.1` etc.
+    #[arg(long, global = true, requires = "log_file", default_value_t = 5, value_name = "N")]
+    pub log_keep: usize,
+
+    /// Keep running until told to stop through the control socket, taking
+    /// `ctl` commands to add and remove peers.
+    #[arg(long, global = true)]
+    pub daemon: bool,
+
+    /// Unix socket of the `--daemon` control interface, for the daemon and
+    /// `ctl` [default: libp2p-ping-tut.sock in $XDG_RUNTIME_DIR or the temp directory].
+    #[arg(long, global = true, value_name = "PATH")]
+    pub control_socket: Option<PathBuf>,
+
+    /// Output format for events and statistics.
+    #[arg(long, global = true, value_enum, default_value_t = Format::Text)]
+    pub output: Format,
+
+    /// Write the output to this file instead of stdout, replacing it if it
+    /// exists.
+    #[arg(long, global = true, value_name = "PATH")]
+    pub out_file: Option<PathBuf>,
+
+    /// Write a report of the run to this JSON file on exit, whatever the
+    /// `--output` format: versions, the configuration used, the statistics
+    /// of each peer and its health changes.
+    #[arg(long, global = true, value_name = "PATH")]
+    pub report_file: Option<PathBuf>,
+
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+/// Available subcommands.
+// Parsed once per run, so the size of `Ping` doesn't matter.
+#[allow(clippy::large_enum_variant)]
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Dial one or more peers and ping them.
+    Ping {
+        /// Multi-addresses of the peers to ping, e.g. `/ip4/127.0.0.1/tcp/12345`.
+        ///
+        /// Replaces the `peers` of the configuration file if given.
+        addrs: Vec<Multiaddr>,
+
+        /// Additional peer to ping, optionally named for the output as in
+        /// `berlin-edge-1=/ip4/192.0.2.1/tcp/4001`; may be repeated.
+        #[arg(long = "peer", value_name = "[NAME=]MULTIADDR")]
+        peers: Vec<NamedPeer>,
+
+        /// Peer to ping after looking up its addresses in the DHT; may be
+        /// repeated. Requires `--bootstrap`.
+        #[arg(long = "peer-id", value_name = "PEER_ID")]
+        peer_ids: Vec<PeerId>,
+
+        /// Only ping the peer if it authenticates as this PeerId, like giving
+        /// its address with a `/p2p/<PEER_ID>` suffix; needs exactly one
+        /// address.
+        #[arg(long, value_name = "PEER_ID")]
+        expect_peer: Option<PeerId>,
+
+        /// File listing more peers to ping, one `[NAME=]MULTIADDR` per line;
+        /// peers added to or removed from it while running are dialed or
+        /// dropped.
+        #[arg(long, value_name = "PATH")]
+        peers_file: Option<PathBuf>,
+
+        /// Stop once every peer has answered this many pings instead of running forever.
+        #[arg(short, long, value_parser = clap::value_parser!(u64).range(1..))]
+        count: Option<u64>,
+
+        /// Wait for a single answered ping from each peer, print its RTT and
+        /// exit, with a failure unless all answered within `--deadline`
+        /// [default: the dial timeout plus the ping timeout]; e.g. for health
+        /// checks.
+        #[arg(long, conflicts_with_all = ["count", "peers_file", "peer_ids", "warmup", "via_relay"])]
+        oneshot: bool,
+
+        /// Leave the RTTs of the first N pings of each peer out of the
+        /// statistics, as the handshakes slow them down; they still count as
+        /// transmitted or lost, are printed and stored, but don't count
+        /// towards `--count`.
+        #[arg(long, value_name = "N")]
+        warmup: Option<u64>,
+
+        /// Only ping during the windows opened at the minutes this cron
+        /// expression matches, in UTC, e.g. `"0 2 * * *"` for 02:00 every
+        /// night; the node keeps listening and answering pings in between.
+        #[arg(long, value_name = "CRON")]
+        schedule: Option<Schedule>,
+
+        /// How long each window of `--schedule` stays open [default: 1m];
+        /// consecutive matching minutes form one window.
+        #[arg(long, value_parser = parse_duration, value_name = "DURATION", requires = "schedule")]
+        window: Option<Duration>,
+
+        /// Stop after this long, e.g. `30s`, however many pings were answered
+        /// (like `ping -w`).
+        #[arg(short = 'w', long, value_parser = parse_duration, value_name = "DURATION")]
+        deadline: Option<Duration>,
+
+        /// Exit with a failure if any peer answered less than this percentage
+        /// of pings, e.g. `95`.
+        #[arg(long, value_parser = parse_percent, value_name = "PERCENT")]
+        fail_under: Option<f64>,
+
+        /// Exit with a failure if the average RTT of any peer exceeds this,
+        /// e.g. `200ms`.
+        #[arg(long, value_parser = parse_duration, value_name = "DURATION")]
+        max_rtt: Option<Duration>,
+
+        /// Also echo payloads of this many bytes, e.g. `1400`, at the ping
+        /// interval to measure the RTT of realistic packet sizes.
+        #[arg(long, value_parser = clap::value_parser!(u64).range(1..=echo::MAX_SIZE as u64), value_name = "BYTES")]
+        size: Option<u64>,
+
+        /// Also exchange timestamps with every peer at the ping interval to
+        /// estimate its clock offset and the one-way delay each way.
+        #[arg(long)]
+        clock: bool,
+
+        /// Give up on a peer after this many consecutive failed re-dials.
+        ///
+        /// Lost peers are re-dialed forever if unset.
+        #[arg(long)]
+        max_retries: Option<u32>,
+
+        /// Upper bound for the exponential backoff between re-dials [default: 60s].
+        #[arg(long, value_parser = parse_duration)]
+        backoff_max: Option<Duration>,
+
+        /// Mark a peer dead after this many failed pings in a row and close
+        /// its connections; it is re-dialed like a lost peer unless `--evict`.
+        #[arg(long, value_parser = clap::value_parser!(u32).range(1..), value_name = "N")]
+        max_failures: Option<u32>,
+
+        /// Stop re-dialing peers marked dead by `--max-failures`.
+        #[arg(long, requires = "max_failures")]
+        evict: bool,
+
+        /// Also ping each peer given with its PeerId through this relay, e.g.
+        /// `/ip4/198.51.100.1/tcp/4001/p2p/<relay id>`, keeping both paths
+        /// open and reporting how much longer the relayed RTTs are.
+        #[arg(long, value_name = "RELAY_MULTIADDR")]
+        via_relay: Option<Multiaddr>,
+    },
+    /// Listen for incoming connections and answer pings.
+    Listen,
+    /// Measure the upload and download throughput to a peer, and the RTT of
+    /// pings sent meanwhile; the peer must run with `--bench-server`.
+    Bench {
+        /// Multi-address of the peer, e.g. `/ip4/127.0.0.1/tcp/12345`.
+        addr: Multiaddr,
+
+        /// How long to transfer data in each direction, at most 60s.
+        #[arg(short = 't', long, value_parser = parse_duration, default_value = "10s")]
+        duration: Duration,
+    },
+    /// Connect to a peer over every transport and security combination in
+    /// turn, and compare their handshake times and RTTs.
+    Compare {
+        /// Multi-addresses of the peer, e.g. one for TCP and one for QUIC;
+        /// those of other transports are learned from the peer if possible.
+        #[arg(required = true)]
+        addrs: Vec<Multiaddr>,
+
+        /// Pings to send over each combination, after a first one that is not counted.
+        #[arg(short, long, value_parser = clap::value_parser!(u64).range(1..), default_value_t = 5)]
+        count: u64,
+    },
+    /// Diagnose why a peer can't be reached: resolve its address, dial it,
+    /// check the handshakes, identify, a ping and whether it can dial us
+    /// back, and through a relay whether a hole punch succeeds, printing a
+    /// checklist with the time of each step.
+    ///
+    /// Takes about 15 seconds, for which AutoNAT waits before its first probe.
+    Doctor {
+        /// Multi-address of the peer, e.g. `/ip4/192.0.2.1/tcp/4001/p2p/<peer id>`;
+        /// a relayed address checks only the path through its relay.
+        addr: Multiaddr,
+
+        /// Also reach the peer through this relay, e.g.
+        /// `/ip4/198.51.100.1/tcp/4001/p2p/<relay id>`, and wait for it to
+        /// hole punch a direct connection.
+        #[arg(long, value_name = "RELAY_MULTIADDR")]
+        via_relay: Option<Multiaddr>,
+    },
+    /// Connect to a peer and list the security protocols it accepts, the
+    /// multiplexer, its agent version and protocols, flagging what keeps it
+    /// and us from working together and how to fix it.
+    Probe {
+        /// Multi-address of the peer, e.g. `/ip4/192.0.2.1/tcp/4001/p2p/<peer id>`.
+        addr: Multiaddr,
+    },
+    /// Look up the peers closest to our PeerId, or to `--key`, in the DHT of
+    /// the `--bootstrap` nodes, ping each of them and rank them by RTT.
+    Sweep {
+        /// Key whose closest peers to ping [default: our PeerId].
+        #[arg(long, value_name = "PEER_ID")]
+        key: Option<PeerId>,
+
+        /// Pings to send to each peer.
+        #[arg(short, long, value_parser = clap::value_parser!(u64).range(1..), default_value_t = 3)]
+        count: u64,
+    },
+    /// Ping virtual peers within this process that answer with artificial
+    /// latency, jitter and loss, and check that the statistics report them.
+    ///
+    /// Pings every 50ms unless `--interval` is given.
+    Simulate {
+        /// Number of virtual peers.
+        #[arg(long, value_parser = clap::value_parser!(u64).range(1..), default_value_t = 3)]
+        peers: u64,
+
+        /// Pings to send to each virtual peer.
+        #[arg(short, long, value_parser = clap::value_parser!(u64).range(1..), default_value_t = 100)]
+        count: u64,
+
+        /// Delay before the virtual peers answer a ping.
+        #[arg(long, value_parser = parse_duration, default_value = "20ms")]
+        latency: Duration,
+
+        /// Each delay differs from `--latency` by up to this much, uniformly
+        /// distributed [default: none].
+        #[arg(long, value_parser = parse_duration, value_name = "DURATION")]
+        jitter: Option<Duration>,
+
+        /// Percentage of pings the virtual peers leave unanswered.
+        #[arg(long, value_parser = parse_percent, value_name = "PERCENT", default_value_t = 0.0)]
+        loss: f64,
+
+        /// Seed of the delays and losses; runs with the same seed draw the
+        /// same ones.
+        #[arg(long, default_value_t = 0)]
+        seed: u64,
+    },
+    /// Check that peers answer a ping, for container health checks: prints
+    /// nothing when healthy and exits with 0 if all peers answered, 1 if any
+    /// didn't and 2 if the configuration is invalid.
+    Healthcheck {
+        /// Multi-addresses of the peers to check.
+        addrs: Vec<Multiaddr>,
+
+        /// Also check the node of the `--config` file, at its `listen`
+        /// addresses on the loopback interface and as the PeerId of its
+        /// `identity`, if any.
+        #[arg(long = "self")]
+        local: bool,
+
+        /// Time the peers have to answer.
+        #[arg(long, value_parser = parse_duration, value_name = "DURATION", default_value = "10s")]
+        within: Duration,
+    },
+    /// Summarize the availability and RTT percentiles of each peer from the
+    /// results in `--store`.
+    Report {
+        /// Only include results from this time on: an RFC 3339 timestamp such
+        /// as `2024-05-01T00:00:00Z`, or a duration ago such as `24h`.
+        #[arg(long, value_parser = parse_time, value_name = "TIME")]
+        from: Option<SystemTime>,
+
+        /// Only include results up to this time, given like `--from`.
+        #[arg(long, value_parser = parse_time, value_name = "TIME")]
+        to: Option<SystemTime>,
+
+        /// Report the uptime of each peer instead: its availability over
+        /// time, its mean time to repair and its longest outage.
+        #[arg(long)]
+        sla: bool,
+    },
+    /// Control a node running with `--daemon`.
+    Ctl {
+        #[command(subcommand)]
+        command: CtlCommand,
+    },
+    /// Generate a new Ed25519 identity and print its PeerId.
+    Keygen {
+        /// Write the keypair to this file, for use with `--identity`.
+        #[arg(short, long, value_name = "PATH")]
+        out: Option<PathBuf>,
+
+        /// Derive the keypair from this 32-byte secret key in hex instead of
+        /// generating a random one. Only use this for test identities.
+        #[arg(long, value_parser = parse_seed, value_name = "HEX")]
+        seed: Option<[u8; 32]>,
+    },
+}
+
+/// Commands understood by a `--daemon` node.
+#[derive(Debug, Subcommand)]
+pub enum CtlCommand {
+    /// Start pinging a peer, optionally named as in `berlin-edge-1=/ip4/192.0.2.1/tcp/4001`.
+    AddPeer {
+        #[arg(value_name = "[NAME=]MULTIADDR")]
+        peer: NamedPeer,
+    },
+    /// Stop pinging a peer and disconnect from it.
+    RemovePeer {
+        /// Name, multi-address or PeerId of the peer.
+        peer: String,
+    },
+    /// Stop pinging a peer, or every peer, until resumed, keeping the
+    /// connections and statistics.
+    Pause {
+        /// Name, multi-address or PeerId of the peer; all peers if left out.
+        peer: Option<String>,
+    },
+    /// Resume pinging a paused peer, or every peer.
+    Resume {
+        /// Name, multi-address or PeerId of the peer; all peers if left out.
+        peer: Option<String>,
+    },
+    /// Print the ping statistics of every peer.
+    Stats,
+    /// Stop the daemon, printing its final statistics.
+    Shutdown,
+}
+
+/// A peer address with an optional human-readable name, and the settings it
+/// is pinged with instead of the global ones, if given in the configuration
+/// file.
+#[derive(Debug, Clone)]
+pub struct NamedPeer {
+    pub name: Option<String>,
+    pub addr: Multiaddr,
+    pub overrides: Overrides,
+}
+
+impl From<Multiaddr> for NamedPeer {
+    fn from(addr: Multiaddr) -> Self {
+        Self { name: None, addr, overrides: Overrides::default() }
+    }
+}
+
+impl fmt::Display for NamedPeer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.name {
+            Some(name) => write!(f, "{name}={}", self.addr),
+            None => write!(f, "{}", self.addr),
+        }
+    }
+}
+
+impl FromStr for NamedPeer {
+    type Err = String;
+
+    /// Parses `NAME=MULTIADDR` or just `MULTIADDR`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, addr) = match s.split_once('=') {
+            Some((name, addr)) if !name.starts_with('/') => (Some(name), addr),
+            _ => (None, s),
+        };
+        if name.is_some_and(str::is_empty) {
+            return Err("peer name must not be empty".into());
+        }
+        let addr: Multiaddr = addr.parse().map_err(|e| format!("{e}"))?;
+        if addr.is_empty() {
+            return Err("peer address must not be empty".into());
+        }
+        Ok(Self { name: name.map(str::to_owned), addr, overrides: Overrides::default() })
+    }
+}
+
+/// Parses an RFC 3339 timestamp, or a duration ago such as `24h`.
+pub fn parse_time(s: &str) -> Result<SystemTime, String> {
+    if let Ok(ago) = humantime::parse_duration(s) {
+        return SystemTime::now().checked_sub(ago).ok_or_else(|| "duration reaches too far back".to_owned());
+    }
+    humantime::parse_rfc3339_weak(s).map_err(|_| format!("`{s}` is neither an RFC 3339 timestamp nor a duration"))
+}
+
+/// Parses 32 bytes given as 64 hex digits.
+pub fn parse_seed(s: &str) -> Result<[u8; 32], String> {
+    // Checked up front, as `from_str_radix` would also take a sign.
+    if s.len() != 64 || !s.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err("seed must be 64 hex digits".into());
+    }
+    let mut seed = [0; 32];
+    for (byte, digits) in seed.iter_mut().zip(s.as_bytes().chunks(2)) {
+        let digits = std::str::from_utf8(digits).expect("hex digits are ASCII");
+        *byte = u8::from_str_radix(digits, 16).expect("two hex digits fit a byte");
+    }
+    Ok(seed)
+}
+
+/// Parses a percentage between 0 and 100.
+pub fn parse_percent(s: &str) -> Result<f64, String> {
+    let percent: f64 = s.parse().map_err(|e| format!("{e}"))?;
+    if !(0.0..=100.0).contains(&percent) {
+        return Err("percentage must be between 0 and 100".into());
+    }
+    Ok(percent)
+}
+
+/// Parses a DSCP given as a number from 0 to 63 or by name: `be` (0), `ef`
+/// (46), `csN` (class selectors 0 to 7) or `afXY` (assured forwarding classes 1
+/// to 4 with drop precedences 1 to 3).
+pub fn parse_dscp(s: &str) -> Result<u8, String> {
+    let name = s.to_ascii_lowercase();
+    let digit = |c: Option<char>, range: std::ops::RangeInclusive<u8>| {
+        c.and_then(|c| c.to_digit(10)).map(|d| d as u8).filter(|d| range.contains(d))
+    };
+    let dscp = match name.as_str() {
+        "be" | "default" => Some(0),
+        "ef" => Some(46),
+        _ if name.starts_with("cs") && name.len() == 3 => digit(name.chars().nth(2), 0..=7).map(|class| class * 8),
+        _ if name.starts_with("af") && name.len() == 4 => {
+            let class = digit(name.chars().nth(2), 1..=4);
+            let drop = digit(name.chars().nth(3), 1..=3);
+            class.zip(drop).map(|(class, drop)| class * 8 + drop * 2)
+        }
+        _ => name.parse().ok().filter(|dscp| *dscp <= 63),
+    };
+    dscp.ok_or_else(|| format!("`{s}` is neither a DSCP from 0 to 63 nor a name such as `ef`, `af41` or `cs1`"))
+}
+
+/// Parses a `KEY=VALUE` label.
+fn parse_label(s: &str) -> Result<(String, String), String> {
+    let (key, value) = s.split_once('=').ok_or("expected KEY=VALUE")?;
+    labels::check(key, value)?;
+    Ok((key.to_owned(), value.to_owned()))
+}
+
+/// Parses a rendezvous namespace given as `ns=NAMESPACE`, or just `NAMESPACE`.
+fn parse_namespace(s: &str) -> Result<String, String> {
+    let namespace = s.strip_prefix("ns=").unwrap_or(s);
+    if namespace.is_empty() {
+        return Err("namespace must not be empty".into());
+    }
+    Ok(namespace.to_owned())
+}
+
+/// Parses a human-readable, non-zero duration such as `250ms`, `5s` or `1m`.
+pub fn parse_duration(s: &str) -> Result<Duration, String> {
+    let duration = humantime::parse_duration(s).map_err(|e| e.to_string())?;
+    if duration.is_zero() {
+        return Err("duration must be greater than zero".into());
+    }
+    Ok(duration)
+}
+
+/// Parses a human-readable duration that may be zero, such as `0` or `250ms`.
+pub fn parse_delay(s: &str) -> Result<Duration, String> {
+    humantime::parse_duration(s).map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dscp_numbers() {
+        assert_eq!(parse_dscp("0"), Ok(0));
+        assert_eq!(parse_dscp("46"), Ok(46));
+        assert_eq!(parse_dscp("63"), Ok(63));
+        assert!(parse_dscp("64").is_err());
+        assert!(parse_dscp("-1").is_err());
+        assert!(parse_dscp("").is_err());
+    }
+
+    #[test]
+    fn dscp_names() {
+        assert_eq!(parse_dscp("be"), Ok(0));
+        assert_eq!(parse_dscp("default"), Ok(0));
+        assert_eq!(parse_dscp("EF"), Ok(46));
+        assert_eq!(parse_dscp("cs0"), Ok(0));
+        assert_eq!(parse_dscp("cs1"), Ok(8));
+        assert_eq!(parse_dscp("CS7"), Ok(56));
+        assert_eq!(parse_dscp("af11"), Ok(10));
+        assert_eq!(parse_dscp("af23"), Ok(22));
+        assert_eq!(parse_dscp("AF41"), Ok(34));
+        assert_eq!(parse_dscp("af43"), Ok(38));
+        for invalid in ["cs8", "cs", "cs10", "af01", "af51", "af10", "af14", "af4", "af411", "ef1", "best-effort"] {
+            let error = parse_dscp(invalid).unwrap_err();
+            assert!(error.contains(&format!("`{invalid}`")), "{invalid}: {error}");
+        }
+    }
+
+    #[test]
+    fn named_peers() {
+        let peer: NamedPeer = "berlin-edge-1=/ip4/192.0.2.1/tcp/4001".parse().unwrap();
+        assert_eq!(peer.name.as_deref(), Some("berlin-edge-1"));
+        assert_eq!(peer.addr, "/ip4/192.0.2.1/tcp/4001".parse::<Multiaddr>().unwrap());
+        assert_eq!(peer.overrides, Overrides::default());
+        assert_eq!(peer.to_string(), "berlin-edge-1=/ip4/192.0.2.1/tcp/4001");
+    }
+
+    #[test]
+    fn unnamed_peers() {
+        let peer: NamedPeer = "/ip4/192.0.2.1/udp/4001/quic-v1".parse().unwrap();
+        assert_eq!(peer.name, None);
+        assert_eq!(peer.to_string(), "/ip4/192.0.2.1/udp/4001/quic-v1");
+        // An `=` within the address doesn't make a name.
+        let peer: NamedPeer = "/dns/a=b.example/tcp/4001".parse().unwrap();
+        assert_eq!((peer.name, peer.addr.to_string()), (None, "/dns/a=b.example/tcp/4001".to_owned()));
+    }
+
+    #[test]
+    fn invalid_named_peers() {
+        assert_eq!("=/ip4/192.0.2.1/tcp/4001".parse::<NamedPeer>().unwrap_err(), "peer name must not be empty");
+        assert_eq!("berlin=".parse::<NamedPeer>().unwrap_err(), "peer address must not be empty");
+        assert_eq!("".parse::<NamedPeer>().unwrap_err(), "peer address must not be empty");
+        assert!("berlin=192.0.2.1:4001".parse::<NamedPeer>().is_err());
+        assert!("192.0.2.1:4001".parse::<NamedPeer>().is_err());
+    }
+
+    #[test]
+    fn seeds() {
+        assert_eq!(parse_seed(&"0".repeat(64)), Ok([0; 32]));
+        let mut expected = [0xab; 32];
+        expected[0] = 0x01;
+        expected[31] = 0xff;
+        assert_eq!(parse_seed(&format!("01{}fF", "aB".repeat(30))), Ok(expected));
+    }
+
+    #[test]
+    fn seeds_must_be_64_hex_digits() {
+        for invalid in ["0".repeat(62), "0".repeat(66), format!("{}0g", "0".repeat(62)), "+f".repeat(32), format!("{}é", "0".repeat(62))] {
+            assert_eq!(parse_seed(&invalid), Err("seed must be 64 hex digits".to_owned()), "{invalid}");
+        }
+    }
+}
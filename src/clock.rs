@@ -0,0 +1,330 @@
+//! Clock protocol estimating the clock offset and the one-way delays to a
+//! peer from exchanged timestamps, as NTP does.
+//!
+//! The requesting side sends the time it sent the request; the remote answers
+//! with it, the time the request arrived and the time it sent the answer. The
+//! requesting side keeps the time it sent each request itself and refuses
+//! answers that don't echo it, so the remote only has a say in its own two
+//! timestamps. RTT alone can't tell a fast outbound path from a fast return
+//! path: the one-way delays can, to the degree the two clocks agree, which the
+//! offset shows.
+
+use async_trait::async_trait;
+use futures::prelude::*;
+use libp2p::request_response::{self, OutboundFailure, OutboundRequestId, ProtocolSupport};
+use libp2p::swarm::FromSwarm;
+use libp2p::{PeerId, StreamProtocol};
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::sync::{Arc, OnceLock};
+use std::task::Context;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::forward::request_response_behaviour;
+use crate::ping_limit::Buckets;
+
+/// Protocol name of the clock protocol.
+pub const PROTOCOL_NAME: StreamProtocol = StreamProtocol::new("/libp2p-ping-tut/clock/1.0.0");
+
+/// What one exchange of timestamps with a peer showed, in microseconds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Sample {
+    /// How far the clock of the peer is ahead of ours, assuming both paths
+    /// take equally long.
+    pub offset_us: i64,
+    /// Round trip, without the time the peer held the request.
+    pub delay_us: i64,
+    /// Time from us to the peer, by the two clocks.
+    pub outbound_us: i64,
+    /// Time from the peer back to us, by the two clocks.
+    pub inbound_us: i64,
+}
+
+impl Sample {
+    /// Works out a sample from the time the request was sent (`t1`) and
+    /// arrived (`t2`), and the time the answer was sent (`t3`) and arrived
+    /// (`t4`), or `None` if the timestamps of the peer are too far off to
+    /// work with.
+    fn new(t1: i64, t2: i64, t3: i64, t4: i64) -> Option<Self> {
+        let outbound_us = t2.checked_sub(t1)?;
+        let inbound_us = t4.checked_sub(t3)?;
+        Some(Self {
+            offset_us: outbound_us.checked_sub(inbound_us)? / 2,
+            delay_us: outbound_us.checked_add(inbound_us)?,
+            outbound_us,
+            inbound_us,
+        })
+    }
+
+    /// How much longer the outbound path takes than the return path, if the
+    /// clocks agree; a clock offset adds twice itself to this.
+    pub fn asymmetry_us(&self) -> i64 {
+        // Checked to fit when the sample was worked out.
+        self.outbound_us - self.inbound_us
+    }
+}
+
+/// The result of an exchange of timestamps with a peer.
+#[derive(Debug)]
+pub struct Event {
+    pub peer: PeerId,
+    pub result: Result<Sample, OutboundFailure>,
+}
+
+/// Answers the clock requests of every peer and, if enabled, sends one to each
+/// connected peer every interval.
+pub struct Behaviour {
+    inner: request_response::Behaviour<Codec>,
+    enabled: bool,
+    interval: tokio::time::Interval,
+    /// Connected peers that haven't refused the protocol.
+    peers: HashSet<PeerId>,
+    /// The time each pending request was sent, once it was written.
+    sent: HashMap<OutboundRequestId, Arc<OnceLock<i64>>>,
+    /// Whether sending is paused; requests are answered either way.
+    paused: bool,
+    /// Peers for which sending is paused.
+    paused_peers: HashSet<PeerId>,
+    /// Buckets of the inbound ping limit, if any; requests over it are refused.
+    limit: Option<Buckets>,
+}
+
+impl Behaviour {
+    /// Exchanges timestamps with every peer every `interval` if `enabled`, and
+    /// waits up to `timeout` for each answer.
+    pub fn new(enabled: bool, interval: Duration, timeout: Duration) -> Self {
+        let config = request_response::Config::default().with_request_timeout(timeout);
+        Self {
+            inner: request_response::Behaviour::with_codec(Codec, [(PROTOCOL_NAME, ProtocolSupport::Full)], config),
+            enabled,
+            interval: tokio::time::interval(interval),
+            peers: HashSet::new(),
+            sent: HashMap::new(),
+            paused: false,
+            paused_peers: HashSet::new(),
+            limit: None,
+        }
+    }
+
+    /// Answers the requests of each peer only while it is within the inbound
+    /// ping limit of `buckets`.
+    pub(crate) fn set_limit(&mut self, buckets: Option<Buckets>) {
+        self.limit = buckets;
+    }
+
+    /// Stops exchanging timestamps, or starts again.
+    pub fn set_paused(&mut self, paused: bool) {
+        self.paused = paused;
+    }
+
+    /// Stops exchanging timestamps with `peer`, or starts again.
+    pub fn set_peer_paused(&mut self, peer: PeerId, paused: bool) {
+        if paused {
+            self.paused_peers.insert(peer);
+        } else {
+            self.paused_peers.remove(&peer);
+        }
+    }
+
+    fn send(&mut self, peer: PeerId) {
+        if self.enabled && !self.paused && !self.paused_peers.contains(&peer) {
+            let request = Request::default();
+            let sent = request.sent.clone();
+            self.sent.insert(self.inner.send_request(&peer, request), sent);
+        }
+    }
+
+    /// Answers requests and turns answers into events.
+    fn on_inner_event(&mut self, event: request_response::Event<Request, Timestamps>) -> Option<Event> {
+        match event {
+            request_response::Event::Message { peer, message } => match message {
+                request_response::Message::Request { request, channel, .. } => {
+                    if self.limit.as_ref().is_some_and(|limit| !limit.try_take(peer)) {
+                        // Dropping the channel refuses the request.
+                        return None;
+                    }
+                    // Both were set when the request was read.
+                    let sent = request.sent.get().copied().unwrap_or_default();
+                    let answer = Timestamps { sent, received: request.received, answered: now_us() };
+                    // Fails only if the connection closed in the meantime.
+                    let _ = self.inner.send_response(channel, answer);
+                    None
+                }
+                request_response::Message::Response { request_id, response } => {
+                    let sent = self.sent.remove(&request_id)?.get().copied();
+                    let sample = sent
+                        .filter(|sent| *sent == response.sent)
+                        .and_then(|sent| Sample::new(sent, response.received, response.answered, now_us()));
+                    let result = sample.ok_or_else(|| {
+                        let e = io::Error::new(io::ErrorKind::InvalidData, "answer with timestamps that don't fit");
+                        OutboundFailure::Io(e)
+                    });
+                    Some(Event { peer, result })
+                }
+            },
+            request_response::Event::OutboundFailure { peer, request_id, error } => {
+                self.sent.remove(&request_id)?;
+                if matches!(error, OutboundFailure::UnsupportedProtocols) {
+                    self.peers.remove(&peer);
+                }
+                Some(Event { peer, result: Err(error) })
+            }
+            _ => None,
+        }
+    }
+
+    /// Tracks the peers to send requests to, probing new ones right away rather
+    /// than at the next interval.
+    fn track_peers(&mut self, event: FromSwarm) {
+        match event {
+            FromSwarm::ConnectionEstablished(established) if established.other_established == 0 => {
+                self.peers.insert(established.peer_id);
+                self.send(established.peer_id);
+            }
+            FromSwarm::ConnectionClosed(closed) if closed.remaining_established == 0 => {
+                self.peers.remove(&closed.peer_id);
+            }
+            _ => {}
+        }
+    }
+
+    /// Sends a request to every peer each interval.
+    fn send_due(&mut self, cx: &mut Context<'_>) {
+        if self.enabled && self.interval.poll_tick(cx).is_ready() {
+            let peers: Vec<PeerId> = self.peers.iter().copied().collect();
+            for peer in peers {
+                self.send(peer);
+            }
+        }
+    }
+}
+
+request_response_behaviour!(Behaviour, Codec, Event, on_swarm_event: track_peers, poll: send_due);
+
+/// A clock request, with its timestamps in microseconds since the Unix epoch.
+///
+/// On the wire a request is the time it was sent, as 8 big-endian bytes.
+#[derive(Debug, Clone, Default)]
+pub struct Request {
+    /// Set as the request is written, by the clock of the side sending it,
+    /// or as it is read.
+    sent: Arc<OnceLock<i64>>,
+    /// The time the request arrived, once read.
+    received: i64,
+}
+
+/// The timestamps of an answer, in microseconds since the Unix epoch by the
+/// clock of the side taking each.
+///
+/// On the wire an answer is the time the request was sent, the time it
+/// arrived and the time the answer was sent, each as 8 big-endian bytes.
+#[derive(Debug, Clone, Copy)]
+pub struct Timestamps {
+    sent: i64,
+    received: i64,
+    answered: i64,
+}
+
+/// Reads and writes [`Request`]s and [`Timestamps`], taking the time a request
+/// is sent as it is written and the time it arrives as soon as it is read.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Codec;
+
+#[async_trait]
+impl request_response::Codec for Codec {
+    type Protocol = StreamProtocol;
+    type Request = Request;
+    type Response = Timestamps;
+
+    async fn read_request<T>(&mut self, _: &StreamProtocol, io: &mut T) -> io::Result<Request>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        let [sent] = read_times(io).await?;
+        Ok(Request { sent: Arc::new(OnceLock::from(sent)), received: now_us() })
+    }
+
+    async fn read_response<T>(&mut self, _: &StreamProtocol, io: &mut T) -> io::Result<Timestamps>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        let [sent, received, answered] = read_times(io).await?;
+        Ok(Timestamps { sent, received, answered })
+    }
+
+    async fn write_request<T>(&mut self, _: &StreamProtocol, io: &mut T, request: Request) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        // Taken only now that the stream is negotiated, which would otherwise
+        // count towards the outbound delay.
+        let sent = *request.sent.get_or_init(now_us);
+        io.write_all(&sent.to_be_bytes()).await?;
+        io.close().await
+    }
+
+    async fn write_response<T>(&mut self, _: &StreamProtocol, io: &mut T, answer: Timestamps) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        let mut bytes = [0; 24];
+        for (chunk, time) in bytes.chunks_exact_mut(8).zip([answer.sent, answer.received, answer.answered]) {
+            chunk.copy_from_slice(&time.to_be_bytes());
+        }
+        io.write_all(&bytes).await?;
+        io.close().await
+    }
+}
+
+async fn read_times<T: AsyncRead + Unpin + Send, const N: usize>(io: &mut T) -> io::Result<[i64; N]> {
+    let mut times = [0; N];
+    for time in &mut times {
+        let mut bytes = [0; 8];
+        io.read_exact(&mut bytes).await?;
+        *time = i64::from_be_bytes(bytes);
+    }
+    Ok(times)
+}
+
+/// Returns the time in microseconds since the Unix epoch.
+fn now_us() -> i64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_micros() as i64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn symmetric_paths() {
+        // The peer's clock is 1000µs ahead; 300µs each way, held for 50µs.
+        let sample = Sample::new(10_000, 11_300, 11_350, 10_650).expect("timestamps in range");
+        assert_eq!(sample, Sample { offset_us: 1000, delay_us: 600, outbound_us: 1300, inbound_us: -700 });
+        assert_eq!(sample.asymmetry_us(), 2000);
+    }
+
+    #[test]
+    fn behind_peer() {
+        // The peer's clock is 5000µs behind; 100µs out and 300µs back.
+        let sample = Sample::new(10_000, 5_100, 5_100, 10_400).expect("timestamps in range");
+        assert_eq!(sample.offset_us, -5100);
+        assert_eq!(sample.delay_us, 400);
+        assert_eq!(sample.asymmetry_us(), -10_200);
+    }
+
+    #[test]
+    fn asymmetric_paths() {
+        // The clocks agree; 400µs out and 100µs back, held for 100µs.
+        let sample = Sample::new(0, 400, 500, 600).expect("timestamps in range");
+        assert_eq!((sample.offset_us, sample.delay_us), (150, 500));
+        assert_eq!(sample.asymmetry_us(), 300);
+    }
+
+    #[test]
+    fn out_of_range_timestamps() {
+        assert_eq!(Sample::new(-1, i64::MAX, 0, 0), None);
+        assert_eq!(Sample::new(0, 0, i64::MIN, 1), None);
+        assert_eq!(Sample::new(0, i64::MAX, 1, 0), None);
+        assert_eq!(Sample::new(0, i64::MAX / 2 + 1, 0, i64::MAX / 2 + 1), None);
+    }
+}
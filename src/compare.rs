@@ -0,0 +1,135 @@
+//! Measurement of the handshake time and RTT to a peer over one transport and
+//! security combination, for the `compare` subcommand.
+
+use libp2p::identity::Keypair;
+use libp2p::swarm::SwarmEvent;
+use libp2p::{identify, Multiaddr};
+use libp2p_ping_tut::{BehaviourEvent, NodeConfig, PingNode, PingStats, SecurityChoice, TransportChoice};
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// How long connections get to close before the next combination is tried.
+const CLOSE_GRACE: Duration = Duration::from_secs(1);
+
+/// A transport, with the security handshake on top for those that need one.
+#[derive(Debug, Clone, Copy)]
+pub struct Combination {
+    pub transport: TransportChoice,
+    /// `None` for QUIC, which brings its own TLS, and WebTransport, which
+    /// always uses Noise.
+    pub security: Option<SecurityChoice>,
+}
+
+/// Every combination compared, in this order.
+pub const COMBINATIONS: [Combination; 6] = [
+    Combination { transport: TransportChoice::Tcp, security: Some(SecurityChoice::Tls) },
+    Combination { transport: TransportChoice::Tcp, security: Some(SecurityChoice::Noise) },
+    Combination { transport: TransportChoice::Quic, security: None },
+    Combination { transport: TransportChoice::Ws, security: Some(SecurityChoice::Tls) },
+    Combination { transport: TransportChoice::Ws, security: Some(SecurityChoice::Noise) },
+    Combination { transport: TransportChoice::WebTransport, security: None },
+];
+
+impl fmt::Display for Combination {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.security {
+            Some(security) => write!(f, "{}+{security}", self.transport),
+            None => write!(f, "{}", self.transport),
+        }
+    }
+}
+
+/// What was measured over one combination.
+pub struct Measurement {
+    pub address: Multiaddr,
+    /// Time from dialing to the established connection.
+    pub handshake: Duration,
+    /// Pings after the first, which also pays for negotiating the protocol.
+    pub pings: PingStats,
+}
+
+/// Returns the first of `candidates` that `transport` dials, preferring those
+/// on the same host as `preferred`.
+pub fn pick_address(candidates: &[Multiaddr], transport: TransportChoice, preferred: &Multiaddr) -> Option<Multiaddr> {
+    let host = preferred.iter().next();
+    let mut matching = candidates
+        .iter()
+        .filter(|addr| TransportChoice::for_addr(addr) == Some(transport));
+    matching
+        .clone()
+        .find(|addr| addr.iter().next() == host)
+        .or_else(|| matching.next())
+        .cloned()
+}
+
+/// Connects to `address` with a node running only `combination`, and pings
+/// the peer until `count` pings after the first have completed.
+///
+/// The connection must be established within the ping timeout of `config`.
+/// The listen addresses the peer reports via identify are added to `learned`,
+/// so that the other transports can be tried even if only one address was
+/// given.
+pub async fn measure(
+    keypair: Keypair,
+    mut config: NodeConfig,
+    combination: Combination,
+    address: Multiaddr,
+    count: u64,
+    learned: &mut Vec<Multiaddr>,
+) -> Result<Measurement, String> {
+    config.transports = vec![combination.transport];
+    if let Some(security) = combination.security {
+        config.security = security;
+    }
+    let timeout = config.ping_timeout;
+    let mut node = PingNode::with_keypair(keypair, config).map_err(|e| e.to_string())?;
+
+    let started = Instant::now();
+    let dial = node.dial(address.clone()).map_err(|e| e.to_string())?;
+    let mut handshake = None;
+    let mut pings = PingStats::default();
+    let mut first_ping = true;
+
+    let connect_timeout = tokio::time::sleep(timeout);
+    tokio::pin!(connect_timeout);
+    let outcome = loop {
+        let event = tokio::select! {
+            event = node.next_event() => event,
+            _ = &mut connect_timeout, if handshake.is_none() => {
+                break Err(format!("not connected within {}", humantime::format_duration(timeout)));
+            }
+        };
+        match event {
+            SwarmEvent::ConnectionEstablished { connection_id, .. } if connection_id == dial => {
+                handshake = Some(started.elapsed());
+            }
+            SwarmEvent::OutgoingConnectionError { connection_id, error, .. } if connection_id == dial => {
+                break Err(error.to_string());
+            }
+            SwarmEvent::ConnectionClosed { num_established: 0, cause, .. } => {
+                break Err(cause.map_or_else(|| "connection closed".to_owned(), |e| e.to_string()));
+            }
+            SwarmEvent::Behaviour(BehaviourEvent::Identify(identify::Event::Received { info, .. })) => {
+                learned.extend(info.listen_addrs);
+            }
+            SwarmEvent::Behaviour(BehaviourEvent::Ping(_)) if first_ping => first_ping = false,
+            SwarmEvent::Behaviour(BehaviourEvent::Ping(event)) => {
+                match event.result {
+                    Ok(rtt) => pings.record_success(rtt),
+                    Err(_) => pings.record_failure(),
+                }
+                if pings.transmitted() >= count {
+                    break Ok(());
+                }
+            }
+            _ => {}
+        }
+    };
+    node.shutdown(CLOSE_GRACE).await;
+
+    outcome.map(|()| Measurement {
+        address,
+        handshake: handshake.expect("pings only arrive on established connections"),
+        pings,
+    })
+}
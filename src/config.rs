@@ -0,0 +1,929 @@
+//! TOML configuration file and its merge with the command line.
+//!
+//! A configuration file looks like this; every key is optional:
+//!
+//! ```toml
+//! listen = ["/ip4/0.0.0.0/tcp/4001", "/ip4/0.0.0.0/udp/4001/quic-v1"]
+//! peers = ["/ip4/192.0.2.1/tcp/4001"]
+//! peers-file = "peers.txt"
+//! interval = "5s"
+//! adaptive = true
+//! max-interval = "2m"
+//! timeout = "10s"
+//! dial-timeout = "10s"
+//! idle-timeout = "2m"
+//! keep-alive = false
+//! race-delay = "250ms"
+//! bind-address = ["192.0.2.10"]
+//! interface = "eth1"
+//! tcp-nodelay = true
+//! tcp-keepalive = "30s"
+//! ttl = 64
+//! dscp = "ef"
+//! port-reuse = true
+//! proxy = "socks5h://127.0.0.1:9050"
+//! max-concurrent-dials = 64
+//! identity = "node.key"
+//! transport = ["tcp", "quic"]
+//! security = "both"
+//! store = "results.db"
+//! address-book = "peers.db"
+//! summary-interval = "60s"
+//! webhook = "https://hooks.slack.com/services/..."
+//! report-file = "report.json"
+//! external-addrs = ["/ip4/203.0.113.7/tcp/4001"]
+//! peer-exchange = true
+//! exchange-depth = 2
+//! exchange-fan-out = 16
+//! bootstrap = ["/ip4/198.51.100.1/tcp/4001/p2p/12D3KooWLdnJ6Yu9Zt7UdZPQFgHRbnsJ7AxwRTqcG5E4VkhBDDai"]
+//! deny-peers = ["12D3KooWRBy97UB99e3J6hiPesre1MZeuNQvfan4gBziswrRJsNK"]
+//! via-relay = "/ip4/198.51.100.1/tcp/4001/p2p/12D3KooWLdnJ6Yu9Zt7UdZPQFgHRbnsJ7AxwRTqcG5E4VkhBDDai"
+//! schedule = "0 2 * * *"
+//! window = "1h"
+//!
+//! [relay]
+//! server = true
+//! max-circuits = 32
+//! max-circuit-duration = "1h"
+//!
+//! [autonat]
+//! server = true
+//! max-dial-backs = 60
+//! max-dial-backs-per-peer = 3
+//! period = "1m"
+//! allow-private = true
+//!
+//! [rendezvous]
+//! namespace = "myproject"
+//! point = "/ip4/198.51.100.1/tcp/4001/p2p/12D3KooWLdnJ6Yu9Zt7UdZPQFgHRbnsJ7AxwRTqcG5E4VkhBDDai"
+//!
+//! [labels]
+//! region = "eu-west"
+//! role = "edge"
+//!
+//! [limits]
+//! max-pending-incoming = 64
+//! max-incoming-connections = 256
+//! max-outgoing-connections = 256
+//! max-connections-per-peer = 2
+//! max-inbound-pings = 10
+//! ping-limit-action = "disconnect"
+//! ```
+//!
+//! `peers` can also be a table naming each peer, to show the names instead of
+//! PeerIds in the output, optionally with the `interval`, `timeout`, `count`,
+//! `size` and `max-rtt` it is pinged with instead of the global ones:
+//!
+//! ```toml
+//! [peers]
+//! berlin-edge-1 = "/ip4/192.0.2.1/tcp/4001"
+//! lan-nas = { address = "/ip4/192.168.1.10/tcp/4001", interval = "1s", max-rtt = "5ms" }
+//! new-york = { address = "/dns4/ny.example.com/tcp/4001", interval = "1m", timeout = "5s", count = 10, size = 1024 }
+//! ```
+//!
+//! Pings are sent as often as the shortest interval of a peer, and time out
+//! after the longest timeout, as of the start; peers added on a reload can't
+//! go beyond them. A peer's `timeout` can't be shorter than the global one,
+//! which the pings of the other peers wait for.
+
+use libp2p::multiaddr::Protocol;
+use libp2p::{Multiaddr, PeerId};
+use libp2p_ping_tut::labels::{self, Labels};
+use libp2p_ping_tut::ping_limit::{PingLimit, PingLimitAction};
+use libp2p_ping_tut::{
+    echo, keyfile, AdaptiveInterval, AutonatLimits, ConnectionLimits, LocalBinding, NodeConfig,
+    PeerExchange, RelayLimits, Rendezvous, SecurityChoice, Socks5Proxy, TcpOptions, TransportChoice,
+    WsTls,
+};
+use reqwest::Url;
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer};
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::cli::{self, Cli, Command, NamedPeer};
+use crate::output::Format;
+use crate::schedule::{Schedule, Windows};
+use crate::targets::{Overrides, RetryPolicy, Thresholds};
+
+/// Default delay cap between re-dials of a lost peer.
+const DEFAULT_BACKOFF_MAX: Duration = Duration::from_secs(60);
+
+/// File name of the control socket in the default directory.
+const CONTROL_SOCKET_NAME: &str = "libp2p-ping-tut.sock";
+
+/// Default longest interval between pings with `--adaptive`.
+const DEFAULT_MAX_INTERVAL: Duration = Duration::from_secs(300);
+
+/// Default time between latency reports published to the mesh.
+const DEFAULT_MESH_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Default time between pings while benchmarking or comparing transports,
+/// frequent enough to see the RTT under load and to finish quickly.
+const DEFAULT_BENCH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Default length of a measurement window of `--schedule`.
+const DEFAULT_WINDOW: Duration = Duration::from_secs(60);
+
+/// The contents of a configuration file.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct FileConfig {
+    pub listen: Vec<Multiaddr>,
+    #[serde(deserialize_with = "peers")]
+    pub peers: Vec<NamedPeer>,
+    pub peers_file: Option<PathBuf>,
+    #[serde(deserialize_with = "duration")]
+    pub interval: Option<Duration>,
+    pub adaptive: bool,
+    #[serde(deserialize_with = "duration")]
+    pub max_interval: Option<Duration>,
+    #[serde(deserialize_with = "duration")]
+    pub timeout: Option<Duration>,
+    #[serde(deserialize_with = "duration")]
+    pub idle_timeout: Option<Duration>,
+    pub keep_alive: bool,
+    #[serde(deserialize_with = "duration")]
+    pub dial_timeout: Option<Duration>,
+    #[serde(deserialize_with = "delay")]
+    pub race_delay: Option<Duration>,
+    pub max_concurrent_dials: Option<usize>,
+    pub bind_address: Vec<IpAddr>,
+    pub interface: Option<String>,
+    pub tcp_nodelay: Option<bool>,
+    #[serde(deserialize_with = "duration")]
+    pub tcp_keepalive: Option<Duration>,
+    pub ttl: Option<u32>,
+    #[serde(deserialize_with = "dscp")]
+    pub dscp: Option<u8>,
+    pub port_reuse: bool,
+    #[serde(deserialize_with = "proxy")]
+    pub proxy: Option<Socks5Proxy>,
+    pub identity: Option<PathBuf>,
+    pub transport: Vec<TransportChoice>,
+    pub security: Option<SecurityChoice>,
+    pub psk: Option<PathBuf>,
+    pub wss_cert: Option<PathBuf>,
+    pub wss_key: Option<PathBuf>,
+    pub mdns: bool,
+    pub upnp: bool,
+    pub bench_server: bool,
+    pub metrics: Option<SocketAddr>,
+    pub api: Option<SocketAddr>,
+    pub grpc: Option<SocketAddr>,
+    pub store: Option<PathBuf>,
+    pub address_book: Option<PathBuf>,
+    pub daemon: bool,
+    pub control_socket: Option<PathBuf>,
+    pub external_addrs: Vec<Multiaddr>,
+    pub kademlia: bool,
+    pub bootstrap: Vec<Multiaddr>,
+    pub mesh: bool,
+    pub peer_exchange: bool,
+    pub exchange_depth: Option<u32>,
+    pub exchange_fan_out: Option<usize>,
+    pub allow_peers: Vec<PeerId>,
+    pub deny_peers: Vec<PeerId>,
+    #[serde(deserialize_with = "duration")]
+    pub mesh_interval: Option<Duration>,
+    #[serde(deserialize_with = "duration")]
+    pub summary_interval: Option<Duration>,
+    pub webhook: Option<String>,
+    pub report_file: Option<PathBuf>,
+    pub max_retries: Option<u32>,
+    pub max_failures: Option<u32>,
+    pub evict: bool,
+    #[serde(deserialize_with = "duration")]
+    pub backoff_max: Option<Duration>,
+    pub fail_under: Option<f64>,
+    #[serde(deserialize_with = "duration")]
+    pub max_rtt: Option<Duration>,
+    pub size: Option<usize>,
+    pub clock: bool,
+    pub warmup: Option<u64>,
+    pub schedule: Option<String>,
+    #[serde(deserialize_with = "duration")]
+    pub window: Option<Duration>,
+    pub via_relay: Option<Multiaddr>,
+    pub relay: RelayFileConfig,
+    pub autonat: AutonatFileConfig,
+    pub rendezvous: RendezvousFileConfig,
+    pub limits: LimitsFileConfig,
+    pub labels: Labels,
+}
+
+/// The `[relay]` table of a configuration file.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct RelayFileConfig {
+    pub server: bool,
+    pub max_reservations: Option<usize>,
+    pub max_circuits: Option<usize>,
+    #[serde(deserialize_with = "duration")]
+    pub max_circuit_duration: Option<Duration>,
+    pub max_circuit_bytes: Option<u64>,
+}
+
+/// The `[autonat]` table of a configuration file.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct AutonatFileConfig {
+    pub server: bool,
+    pub max_dial_backs: Option<usize>,
+    pub max_dial_backs_per_peer: Option<usize>,
+    #[serde(deserialize_with = "duration")]
+    pub period: Option<Duration>,
+    pub allow_private: bool,
+}
+
+/// The `[rendezvous]` table of a configuration file.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct RendezvousFileConfig {
+    pub namespace: Option<String>,
+    pub point: Option<Multiaddr>,
+    pub server: bool,
+}
+
+/// The `[limits]` table of a configuration file.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct LimitsFileConfig {
+    pub max_pending_incoming: Option<u32>,
+    pub max_incoming_connections: Option<u32>,
+    pub max_outgoing_connections: Option<u32>,
+    pub max_connections_per_peer: Option<u32>,
+    pub max_inbound_pings: Option<u32>,
+    pub ping_limit_action: Option<PingLimitAction>,
+}
+
+impl FileConfig {
+    /// Reads and parses the configuration file at `path`.
+    ///
+    /// Relative file paths are taken relative to the file's directory.
+    pub fn read(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {e}", path.display()))?;
+        let mut config: Self = toml::from_str(&text).map_err(|e| format!("{}: {e}", path.display()))?;
+        if let Some(dir) = path.parent() {
+            let files = [
+                &mut config.identity,
+                &mut config.peers_file,
+                &mut config.psk,
+                &mut config.store,
+                &mut config.address_book,
+                &mut config.control_socket,
+                &mut config.report_file,
+                &mut config.wss_cert,
+                &mut config.wss_key,
+            ];
+            for file in files.into_iter().flatten() {
+                *file = dir.join(&*file);
+            }
+        }
+        Ok(config)
+    }
+}
+
+/// Deserializes a human-readable duration such as `"5s"`.
+fn duration<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Duration>, D::Error> {
+    let s = String::deserialize(deserializer)?;
+    cli::parse_duration(&s).map(Some).map_err(D::Error::custom)
+}
+
+/// Deserializes a human-readable duration that may be zero, such as `"0"`.
+fn delay<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Duration>, D::Error> {
+    let s = String::deserialize(deserializer)?;
+    cli::parse_delay(&s).map(Some).map_err(D::Error::custom)
+}
+
+/// Deserializes a proxy URL, see [`Socks5Proxy`].
+fn proxy<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Socks5Proxy>, D::Error> {
+    String::deserialize(deserializer)?.parse().map(Some).map_err(D::Error::custom)
+}
+
+/// Deserializes a DSCP given either as a number or by name.
+fn dscp<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<u8>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Dscp {
+        Number(u64),
+        Name(String),
+    }
+    let s = match Dscp::deserialize(deserializer)? {
+        Dscp::Number(n) => n.to_string(),
+        Dscp::Name(name) => name,
+    };
+    cli::parse_dscp(&s).map(Some).map_err(D::Error::custom)
+}
+
+/// Deserializes `peers` given either as a list of addresses or as a table of
+/// names and addresses, or of names and tables of an address and overrides.
+fn peers<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<NamedPeer>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Peers {
+        List(Vec<Multiaddr>),
+        Named(BTreeMap<String, Peer>),
+    }
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Peer {
+        Address(Multiaddr),
+        Table(PeerTable),
+    }
+    #[derive(Deserialize)]
+    #[serde(deny_unknown_fields, rename_all = "kebab-case")]
+    struct PeerTable {
+        address: Multiaddr,
+        #[serde(default, deserialize_with = "duration")]
+        interval: Option<Duration>,
+        #[serde(default, deserialize_with = "duration")]
+        timeout: Option<Duration>,
+        #[serde(default)]
+        count: Option<u64>,
+        #[serde(default)]
+        size: Option<usize>,
+        #[serde(default, deserialize_with = "duration")]
+        max_rtt: Option<Duration>,
+    }
+    let peers = match Peers::deserialize(deserializer)? {
+        Peers::List(addrs) => return Ok(addrs.into_iter().map(NamedPeer::from).collect()),
+        Peers::Named(peers) => peers,
+    };
+    let mut named = Vec::new();
+    for (name, peer) in peers {
+        let (addr, overrides) = match peer {
+            Peer::Address(addr) => (addr, Overrides::default()),
+            Peer::Table(table) => {
+                let overrides = Overrides {
+                    interval: table.interval,
+                    timeout: table.timeout,
+                    count: table.count,
+                    size: table.size,
+                    max_rtt: table.max_rtt,
+                };
+                if overrides.count == Some(0) {
+                    return Err(D::Error::custom(format!("peer {name}: `count` must be at least 1")));
+                }
+                if overrides.size.is_some_and(|size| size == 0 || size > echo::MAX_SIZE) {
+                    let message = format!("peer {name}: `size` must be between 1 and {} bytes", echo::MAX_SIZE);
+                    return Err(D::Error::custom(message));
+                }
+                (table.address, overrides)
+            }
+        };
+        named.push(NamedPeer { name: Some(name), addr, overrides });
+    }
+    Ok(named)
+}
+
+/// Everything a run needs, with command-line options taking precedence over
+/// the configuration file and the file over built-in defaults.
+#[derive(Debug)]
+pub struct Settings {
+    pub node: NodeConfig,
+    pub listen: Vec<Multiaddr>,
+    pub no_ipv4: bool,
+    pub no_ipv6: bool,
+    pub identity: Option<PathBuf>,
+    pub metrics: Option<SocketAddr>,
+    pub api: Option<SocketAddr>,
+    #[cfg_attr(not(feature = "grpc"), allow(dead_code))]
+    pub grpc: Option<SocketAddr>,
+    pub store: Option<PathBuf>,
+    pub address_book: Option<PathBuf>,
+    pub external_addrs: Vec<Multiaddr>,
+    pub mesh_interval: Duration,
+    pub tui: bool,
+    pub graph: bool,
+    pub quiet: bool,
+    pub summary_interval: Option<Duration>,
+    pub webhook: Option<Url>,
+    pub daemon: bool,
+    pub control_socket: PathBuf,
+    pub output: Format,
+    pub out_file: Option<PathBuf>,
+    pub report_file: Option<PathBuf>,
+    /// Configuration file the settings were read from, if any.
+    pub config_file: Option<PathBuf>,
+    /// Peers to dial and ping; empty for `listen`.
+    pub peers: Vec<NamedPeer>,
+    /// File of more peers to dial and ping, watched for changes.
+    pub peers_file: Option<PathBuf>,
+    /// Configuration file whose `peers` are re-read on SIGHUP, if those are
+    /// the peers pinged.
+    pub config_peers: Option<PathBuf>,
+    /// Peers to look up in the DHT and ping; empty for `listen`.
+    pub peer_ids: Vec<PeerId>,
+    pub count: Option<u64>,
+    /// The ping timeout of peers without one of their own; that of `node` is
+    /// the longest of all.
+    pub timeout: Duration,
+    /// Pings of each peer whose RTTs are left out of the statistics at first.
+    pub warmup: u64,
+    /// When to ping; always if `None`.
+    pub windows: Option<Windows>,
+    /// Relay to also ping the peers through, comparing both paths.
+    pub via_relay: Option<Multiaddr>,
+    pub deadline: Option<Duration>,
+    pub policy: RetryPolicy,
+    pub thresholds: Thresholds,
+}
+
+impl Settings {
+    /// Merges `cli` with the configuration file it names, if any.
+    pub fn resolve(cli: &Cli) -> Result<Self, Box<dyn Error>> {
+        let file = match &cli.config {
+            Some(path) => FileConfig::read(path)?,
+            None => FileConfig::default(),
+        };
+        let defaults = NodeConfig::default();
+
+        let transports = first_non_empty(&cli.transport, file.transport).unwrap_or(defaults.transports.clone());
+        let metrics = cli.metrics.or(file.metrics);
+        let grpc = cli.grpc.or(file.grpc);
+        if grpc.is_some() && !cfg!(feature = "grpc") {
+            return Err("`grpc` needs a build with the `grpc` feature".into());
+        }
+        let relay_server = (cli.relay_server || file.relay.server).then(|| {
+            let limits = RelayLimits::default();
+            RelayLimits {
+                max_reservations: cli.relay_max_reservations.or(file.relay.max_reservations).unwrap_or(limits.max_reservations),
+                max_circuits: cli.relay_max_circuits.or(file.relay.max_circuits).unwrap_or(limits.max_circuits),
+                max_circuit_duration: cli
+                    .relay_max_circuit_duration
+                    .or(file.relay.max_circuit_duration)
+                    .unwrap_or(limits.max_circuit_duration),
+                max_circuit_bytes: cli.relay_max_circuit_bytes.or(file.relay.max_circuit_bytes).unwrap_or(limits.max_circuit_bytes),
+                ..limits
+            }
+        });
+        let autonat_server = (cli.autonat_server || file.autonat.server).then(|| {
+            let limits = AutonatLimits::default();
+            AutonatLimits {
+                max_dial_backs: cli.autonat_max_dial_backs.or(file.autonat.max_dial_backs).unwrap_or(limits.max_dial_backs),
+                max_dial_backs_per_peer: cli
+                    .autonat_max_dial_backs_per_peer
+                    .or(file.autonat.max_dial_backs_per_peer)
+                    .unwrap_or(limits.max_dial_backs_per_peer),
+                period: cli.autonat_period.or(file.autonat.period).unwrap_or(limits.period),
+            }
+        });
+        let bootstrap = first_non_empty(&cli.bootstrap, file.bootstrap).unwrap_or_default();
+        let rendezvous = match (cli.rendezvous.clone().or(file.rendezvous.namespace), cli.rendezvous_point.clone().or(file.rendezvous.point)) {
+            (Some(namespace), Some(point)) => Some(Rendezvous { point, namespace }),
+            (None, None) => None,
+            (Some(_), None) => return Err("a rendezvous namespace needs a rendezvous point to meet at".into()),
+            (None, Some(_)) => return Err("a rendezvous point needs a namespace to meet under".into()),
+        };
+        let ws_tls = match (cli.wss_cert.as_ref().or(file.wss_cert.as_ref()), cli.wss_key.as_ref().or(file.wss_key.as_ref())) {
+            (Some(cert), Some(key)) => Some(WsTls::from_pem_files(cert, key)?),
+            (None, None) => None,
+            _ => return Err("`wss-cert` and `wss-key` must be given together".into()),
+        };
+        let psk = cli
+            .psk
+            .as_ref()
+            .or(file.psk.as_ref())
+            .map(|path| keyfile::read_psk(path))
+            .transpose()?;
+        let mut local_binding = match cli.interface.as_ref().or(file.interface.as_ref()) {
+            Some(name) => LocalBinding::interface(name)?,
+            None => LocalBinding::default(),
+        };
+        if let Some(addresses) = first_non_empty(&cli.bind_address, file.bind_address) {
+            if addresses.iter().any(IpAddr::is_unspecified) {
+                return Err("`bind-address` must be a specific IP, not 0.0.0.0 or ::".into());
+            }
+            if addresses.iter().filter(|ip| ip.is_ipv4()).count() > 1 || addresses.iter().filter(|ip| ip.is_ipv6()).count() > 1 {
+                return Err("`bind-address` takes at most one IPv4 and one IPv6 address".into());
+            }
+            local_binding.addresses = addresses;
+        }
+        let default_interval = match cli.command {
+            Command::Bench { .. } | Command::Compare { .. } | Command::Sweep { .. } => DEFAULT_BENCH_INTERVAL,
+            _ => defaults.ping_interval,
+        };
+        let mut node = NodeConfig {
+            ping_interval: cli.interval.or(file.interval).unwrap_or(default_interval),
+            ping_timeout: cli.timeout.or(file.timeout).unwrap_or(defaults.ping_timeout),
+            idle_timeout: cli.idle_timeout.or(file.idle_timeout).unwrap_or(defaults.idle_timeout),
+            keep_alive: cli.keep_alive || file.keep_alive,
+            dial_timeout: cli.dial_timeout.or(file.dial_timeout).unwrap_or(defaults.dial_timeout),
+            race_delay: cli.race_delay.or(file.race_delay).unwrap_or(defaults.race_delay),
+            max_concurrent_dials: cli.max_concurrent_dials.map(|n| n as usize).or(file.max_concurrent_dials),
+            transports,
+            security: cli.security.or(file.security).unwrap_or(defaults.security),
+            psk,
+            ws_tls,
+            local_binding,
+            proxy: cli.proxy.clone().or(file.proxy),
+            tcp_options: TcpOptions {
+                nodelay: cli.tcp_nodelay.or(file.tcp_nodelay).unwrap_or(defaults.tcp_options.nodelay),
+                keepalive: cli.tcp_keepalive.or(file.tcp_keepalive),
+                ttl: cli.ttl.or(file.ttl),
+                dscp: cli.dscp.or(file.dscp),
+                port_reuse: cli.port_reuse || file.port_reuse,
+            },
+            mdns: cli.mdns || file.mdns,
+            upnp: cli.upnp || file.upnp,
+            bench_server: cli.bench_server || file.bench_server,
+            metrics: metrics.is_some(),
+            relay_server,
+            autonat_server,
+            autonat_allow_private: cli.autonat_allow_private || file.autonat.allow_private,
+            kademlia: cli.kademlia || file.kademlia || !bootstrap.is_empty(),
+            bootstrap,
+            mesh: cli.mesh || file.mesh,
+            peer_exchange: (cli.peer_exchange || file.peer_exchange).then(|| {
+                let defaults = PeerExchange::default();
+                PeerExchange {
+                    depth: cli.exchange_depth.or(file.exchange_depth).unwrap_or(defaults.depth),
+                    fan_out: cli.exchange_fan_out.map(|n| n as usize).or(file.exchange_fan_out).unwrap_or(defaults.fan_out),
+                }
+            }),
+            rendezvous,
+            rendezvous_server: cli.rendezvous_server || file.rendezvous.server,
+            allow_peers: first_non_empty(&cli.allow_peers, file.allow_peers).unwrap_or_default(),
+            deny_peers: first_non_empty(&cli.deny_peers, file.deny_peers).unwrap_or_default(),
+            connection_limits: ConnectionLimits {
+                max_pending_incoming: cli.max_pending_incoming.or(file.limits.max_pending_incoming),
+                max_established_incoming: cli.max_incoming_connections.or(file.limits.max_incoming_connections),
+                max_established_outgoing: cli.max_outgoing_connections.or(file.limits.max_outgoing_connections),
+                max_established_per_peer: cli.max_connections_per_peer.or(file.limits.max_connections_per_peer),
+            },
+            inbound_ping_limit: cli.max_inbound_pings.or(file.limits.max_inbound_pings).map(|rate| PingLimit {
+                rate,
+                action: cli.ping_limit_action.or(file.limits.ping_limit_action).unwrap_or_default(),
+            }),
+            labels: file.labels.into_iter().chain(cli.labels.iter().cloned()).collect(),
+            ..defaults
+        };
+        if node.peer_exchange.is_some_and(|exchange| exchange.depth == 0 || exchange.fan_out == 0) {
+            return Err("`exchange-depth` and `exchange-fan-out` must be at least 1".into());
+        }
+        if cli.adaptive || file.adaptive {
+            let max = cli.max_interval.or(file.max_interval).unwrap_or(DEFAULT_MAX_INTERVAL);
+            if max < node.ping_interval {
+                return Err("`max-interval` must not be shorter than `interval`".into());
+            }
+            node.adaptive_interval = Some(AdaptiveInterval { min: node.ping_interval, max });
+        }
+
+        for (key, value) in &node.labels {
+            labels::check(key, value)?;
+        }
+        if node.inbound_ping_limit.is_some_and(|limit| limit.rate == 0) {
+            return Err("`max-inbound-pings` must be at least 1".into());
+        }
+        if node.tcp_options.ttl.is_some_and(|ttl| !(1..=255).contains(&ttl)) {
+            return Err("`ttl` must be from 1 to 255".into());
+        }
+        if node.max_concurrent_dials == Some(0) {
+            return Err("`max-concurrent-dials` must be at least 1".into());
+        }
+        if node.keep_alive && cli.idle_timeout.or(file.idle_timeout).is_some() {
+            return Err("`keep-alive` keeps idle connections open, it can't be combined with `idle-timeout`".into());
+        }
+        let peers_file = match &cli.command {
+            Command::Ping { peers_file, .. } => peers_file.clone().or(file.peers_file),
+            _ => None,
+        };
+        let warmup = match &cli.command {
+            Command::Ping { warmup, .. } => warmup.or(file.warmup).unwrap_or(0),
+            _ => file.warmup.unwrap_or(0),
+        };
+        let (schedule, window) = match &cli.command {
+            Command::Ping { schedule, window, .. } => (schedule.clone(), window.or(file.window)),
+            _ => (None, file.window),
+        };
+        let schedule = match (schedule, file.schedule) {
+            (Some(schedule), _) => Some(schedule),
+            (None, Some(schedule)) => Some(schedule.parse::<Schedule>()?),
+            (None, None) => None,
+        };
+        if window.is_some() && schedule.is_none() {
+            return Err("`window` needs a `schedule`".into());
+        }
+        let windows = schedule.map(|schedule| Windows::new(schedule, window.unwrap_or(DEFAULT_WINDOW)));
+        let via_relay = match &cli.command {
+            Command::Ping { via_relay, .. } => via_relay.clone().or(file.via_relay),
+            Command::Doctor { via_relay, .. } => via_relay.clone(),
+            _ => None,
+        };
+        if let Some(relay) = &via_relay {
+            if !matches!(relay.iter().last(), Some(Protocol::P2p(_))) || relay.iter().any(|p| p == Protocol::P2pCircuit) {
+                return Err(format!("relay address {relay} must end with /p2p/<relay id> and not be relayed itself").into());
+            }
+        }
+        let address_book = cli.address_book.clone().or(file.address_book);
+        let mut config_peers = None;
+        let timeout = node.ping_timeout;
+        let (peers, peer_ids, count, deadline, policy, thresholds) = match &cli.command {
+            Command::Ping {
+                addrs,
+                peers,
+                peer_ids,
+                expect_peer,
+                count,
+                deadline,
+                fail_under,
+                max_rtt,
+                size,
+                clock,
+                max_retries,
+                backoff_max,
+                max_failures,
+                evict,
+                ..
+            } => {
+                let mut remotes: Vec<NamedPeer> =
+                    addrs.iter().cloned().map(NamedPeer::from).chain(peers.iter().cloned()).collect();
+                if remotes.is_empty() && peer_ids.is_empty() {
+                    remotes = file.peers;
+                    // Unless changed with `--expect-peer`, which reloads would
+                    // lose.
+                    config_peers = cli.config.clone().filter(|_| expect_peer.is_none());
+                }
+                if remotes.is_empty() && peer_ids.is_empty() && peers_file.is_none() && address_book.is_none() {
+                    return Err("no peers to ping; pass their addresses or set `peers` in the configuration file".into());
+                }
+                if let Some(expected) = expect_peer {
+                    let [remote] = remotes.as_mut_slice() else {
+                        return Err("--expect-peer needs exactly one peer address".into());
+                    };
+                    match remote.addr.iter().last() {
+                        Some(Protocol::P2p(peer_id)) if peer_id != *expected => {
+                            return Err(format!("{} is the address of {peer_id}, not of {expected}", remote.addr).into());
+                        }
+                        Some(Protocol::P2p(_)) => {}
+                        _ => remote.addr.push(Protocol::P2p(*expected)),
+                    }
+                }
+                if !peer_ids.is_empty() && node.bootstrap.is_empty() {
+                    return Err("--peer-id needs at least one --bootstrap node to look peers up".into());
+                }
+                let known_ids = remotes
+                    .iter()
+                    .filter_map(|peer| match peer.addr.iter().last() {
+                        Some(Protocol::P2p(peer_id)) => Some(peer_id),
+                        _ => None,
+                    })
+                    .chain(peer_ids.iter().copied());
+                for peer_id in known_ids {
+                    let allowed = node.allow_peers.is_empty() || node.allow_peers.contains(&peer_id);
+                    if !allowed || node.deny_peers.contains(&peer_id) {
+                        return Err(format!("cannot ping {peer_id}: the allow or deny list refuses it").into());
+                    }
+                }
+                let policy = RetryPolicy {
+                    max_retries: max_retries.or(file.max_retries),
+                    backoff_max: backoff_max.or(file.backoff_max).unwrap_or(DEFAULT_BACKOFF_MAX),
+                    max_failures: max_failures.or(file.max_failures),
+                    evict: *evict || file.evict,
+                };
+                if policy.max_failures == Some(0) {
+                    return Err("`max-failures` must be at least 1".into());
+                }
+                if policy.evict && policy.max_failures.is_none() {
+                    return Err("`evict` needs `max-failures`".into());
+                }
+                let fail_under = fail_under.or(file.fail_under);
+                if fail_under.is_some_and(|percent| !(0.0..=100.0).contains(&percent)) {
+                    return Err("`fail-under` must be between 0 and 100".into());
+                }
+                let thresholds = Thresholds { fail_under, max_rtt: max_rtt.or(file.max_rtt) };
+                node.echo_size = size.map(|size| size as usize).or(file.size);
+                if node.echo_size.is_some_and(|size| size == 0 || size > echo::MAX_SIZE) {
+                    return Err(format!("`size` must be between 1 and {} bytes", echo::MAX_SIZE).into());
+                }
+                node.clock_probe = *clock || file.clock;
+                check_peer_timeouts(&remotes, timeout)?;
+                let overrides: Vec<Overrides> = remotes.iter().map(|peer| peer.overrides).collect();
+                node.min_peer_interval = overrides.iter().filter_map(|o| o.interval).min();
+                if let Some(longest) = overrides.iter().filter_map(|o| o.timeout).max() {
+                    node.ping_timeout = node.ping_timeout.max(longest);
+                }
+                (remotes, peer_ids.clone(), *count, *deadline, policy, thresholds)
+            }
+            _ => (
+                Vec::new(),
+                Vec::new(),
+                None,
+                None,
+                // Peers added to a daemon through its control socket.
+                RetryPolicy {
+                    max_retries: file.max_retries,
+                    backoff_max: file.backoff_max.unwrap_or(DEFAULT_BACKOFF_MAX),
+                    max_failures: None,
+                    evict: false,
+                },
+                Thresholds::default(),
+            ),
+        };
+
+        Ok(Self {
+            node,
+            listen: first_non_empty(&cli.listen, file.listen).unwrap_or_default(),
+            no_ipv4: cli.no_ipv4,
+            no_ipv6: cli.no_ipv6,
+            identity: cli.identity.clone().or(file.identity),
+            metrics,
+            api: cli.api.or(file.api),
+            grpc,
+            store: cli.store.clone().or(file.store),
+            address_book,
+            external_addrs: first_non_empty(&cli.external_addrs, file.external_addrs).unwrap_or_default(),
+            mesh_interval: cli.mesh_interval.or(file.mesh_interval).unwrap_or(DEFAULT_MESH_INTERVAL),
+            tui: cli.tui || cli.graph,
+            graph: cli.graph,
+            quiet: cli.quiet,
+            summary_interval: cli.summary_interval.or(file.summary_interval),
+            webhook: cli
+                .webhook
+                .clone()
+                .or(file.webhook)
+                .map(|url| Url::parse(&url).map_err(|e| format!("webhook {url}: {e}")))
+                .transpose()?,
+            daemon: cli.daemon || file.daemon,
+            control_socket: cli.control_socket.clone().or(file.control_socket).unwrap_or_else(default_control_socket),
+            output: cli.output,
+            out_file: cli.out_file.clone(),
+            report_file: cli.report_file.clone().or(file.report_file),
+            config_file: cli.config.clone(),
+            peers,
+            peers_file,
+            config_peers,
+            peer_ids,
+            count,
+            timeout,
+            warmup,
+            windows,
+            via_relay,
+            deadline,
+            policy,
+            thresholds,
+        })
+    }
+}
+
+/// Returns where the control socket is put unless configured: in the user's
+/// runtime directory if there is one.
+fn default_control_socket() -> PathBuf {
+    std::env::var_os("XDG_RUNTIME_DIR")
+        .map_or_else(std::env::temp_dir, PathBuf::from)
+        .join(CONTROL_SOCKET_NAME)
+}
+
+/// Fails if a peer of `peers` has a timeout shorter than the global `timeout`:
+/// pings wait for the longest of all, so a shorter one would only turn late
+/// answers into timeouts after the fact.
+pub fn check_peer_timeouts(peers: &[NamedPeer], timeout: Duration) -> Result<(), String> {
+    match peers.iter().find(|peer| peer.overrides.timeout.is_some_and(|own| own < timeout)) {
+        Some(peer) => Err(format!(
+            "peer {peer}: `timeout` can't be shorter than the global timeout of {}",
+            humantime::format_duration(timeout)
+        )),
+        None => Ok(()),
+    }
+}
+
+/// Returns the command-line list if given, else the file's if that is non-empty.
+fn first_non_empty<T: Clone>(cli: &[T], file: Vec<T>) -> Option<Vec<T>> {
+    if !cli.is_empty() {
+        Some(cli.to_vec())
+    } else if !file.is_empty() {
+        Some(file)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn peers(toml: &str) -> Result<Vec<NamedPeer>, String> {
+        toml::from_str::<FileConfig>(toml).map(|config| config.peers).map_err(|e| e.to_string())
+    }
+
+    fn addr(s: &str) -> Multiaddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn relative_paths_are_taken_from_the_file_directory() {
+        let dir = std::env::temp_dir().join(format!("libp2p-ping-tut-config-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("ping.toml");
+        let text = r#"
+            identity = "node.key"
+            store = "/var/lib/ping/results.db"
+            address-book = "peers.db"
+            report-file = "report.json"
+        "#;
+        std::fs::write(&path, text).unwrap();
+        let config = FileConfig::read(&path);
+        std::fs::remove_dir_all(&dir).unwrap();
+        let config = config.unwrap();
+        assert_eq!(config.identity, Some(dir.join("node.key")));
+        assert_eq!(config.store, Some(PathBuf::from("/var/lib/ping/results.db")));
+        assert_eq!(config.address_book, Some(dir.join("peers.db")));
+        assert_eq!(config.report_file, Some(dir.join("report.json")));
+    }
+
+    #[test]
+    fn peers_as_a_list() {
+        let peers = peers(r#"peers = ["/ip4/192.0.2.1/tcp/4001", "/ip4/192.0.2.2/udp/4001/quic-v1"]"#).unwrap();
+        assert_eq!(peers.len(), 2);
+        assert_eq!((peers[0].name.as_deref(), &peers[0].addr), (None, &addr("/ip4/192.0.2.1/tcp/4001")));
+        assert_eq!(peers[1].addr, addr("/ip4/192.0.2.2/udp/4001/quic-v1"));
+        assert!(peers.iter().all(|peer| peer.overrides == Overrides::default()));
+    }
+
+    #[test]
+    fn peers_as_a_table_of_names() {
+        let peers = peers(
+            r#"
+            [peers]
+            frankfurt = "/ip4/192.0.2.2/tcp/4001"
+            berlin = "/ip4/192.0.2.1/tcp/4001"
+            "#,
+        )
+        .unwrap();
+        // In the order of their names.
+        let named: Vec<_> = peers.iter().map(|peer| (peer.name.as_deref().unwrap(), peer.addr.to_string())).collect();
+        assert_eq!(
+            named,
+            [("berlin", "/ip4/192.0.2.1/tcp/4001".to_owned()), ("frankfurt", "/ip4/192.0.2.2/tcp/4001".to_owned())]
+        );
+    }
+
+    #[test]
+    fn peers_with_overrides() {
+        let peers = peers(
+            r#"
+            [peers]
+            berlin = "/ip4/192.0.2.1/tcp/4001"
+            sydney = { address = "/ip4/192.0.2.3/tcp/4001", interval = "1m", timeout = "2s", count = 5, size = 1024, max-rtt = "300ms" }
+            tokyo = { address = "/ip4/192.0.2.4/tcp/4001", count = 1 }
+            "#,
+        )
+        .unwrap();
+        assert_eq!(peers[0].overrides, Overrides::default());
+        assert_eq!(peers[1].name.as_deref(), Some("sydney"));
+        assert_eq!(peers[1].addr, addr("/ip4/192.0.2.3/tcp/4001"));
+        assert_eq!(
+            peers[1].overrides,
+            Overrides {
+                interval: Some(Duration::from_secs(60)),
+                timeout: Some(Duration::from_secs(2)),
+                count: Some(5),
+                size: Some(1024),
+                max_rtt: Some(Duration::from_millis(300)),
+            }
+        );
+        assert_eq!(peers[2].overrides, Overrides { count: Some(1), ..Overrides::default() });
+    }
+
+    #[test]
+    fn invalid_overrides() {
+        for (table, error) in [
+            (r#"{ address = "/ip4/192.0.2.1/tcp/4001", count = 0 }"#, "peer berlin: `count` must be at least 1"),
+            (r#"{ address = "/ip4/192.0.2.1/tcp/4001", size = 0 }"#, "peer berlin: `size` must be between 1 and 65536 bytes"),
+            (r#"{ address = "/ip4/192.0.2.1/tcp/4001", size = 65537 }"#, "peer berlin: `size` must be between 1"),
+        ] {
+            let result = peers(&format!("[peers]\nberlin = {table}"));
+            assert!(result.as_ref().is_err_and(|e| e.contains(error)), "{table}: {result:?}");
+        }
+        // Typos, a zero interval and missing or invalid addresses match no
+        // form of peer at all.
+        for table in [
+            r#"{ address = "/ip4/192.0.2.1/tcp/4001", intervall = "1s" }"#,
+            r#"{ address = "/ip4/192.0.2.1/tcp/4001", interval = "0s" }"#,
+            r#"{ interval = "1s" }"#,
+            r#"{ address = "192.0.2.1:4001" }"#,
+            r#""192.0.2.1:4001""#,
+        ] {
+            assert!(peers(&format!("[peers]\nberlin = {table}")).is_err(), "{table}");
+        }
+    }
+
+    #[test]
+    fn peer_timeouts_below_the_global_one_are_refused() {
+        let peers = peers(
+            r#"
+            [peers]
+            berlin = { address = "/ip4/192.0.2.1/tcp/4001", timeout = "20s" }
+            sydney = { address = "/ip4/192.0.2.3/tcp/4001", timeout = "2s" }
+            "#,
+        )
+        .unwrap();
+        assert_eq!(check_peer_timeouts(&peers, Duration::from_secs(2)), Ok(()));
+        let error = check_peer_timeouts(&peers, Duration::from_secs(10)).unwrap_err();
+        assert!(error.starts_with("peer sydney=/ip4/192.0.2.3/tcp/4001: `timeout` can't be shorter"), "{error}");
+    }
+}
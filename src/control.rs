@@ -0,0 +1,158 @@
+//! Unix-socket control interface of a `--daemon` node, and the client used by
+//! the `ctl` subcommand.
+//!
+//! Clients send one JSON request per line, e.g. `{"command":"stats"}` or
+//! `{"command":"pause","peer":"berlin-edge-1"}`, and get one JSON response
+//! line back for each.
+
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::io;
+use std::path::{Path, PathBuf};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{UnixListener, UnixStream};
+use tokio::sync::{mpsc, oneshot};
+
+/// A command sent to the daemon.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "command", rename_all = "kebab-case")]
+pub enum Request {
+    /// Start pinging a peer given as `[NAME=]MULTIADDR`.
+    AddPeer { peer: String },
+    /// Stop pinging the target with this name, address or PeerId.
+    RemovePeer { peer: String },
+    /// Suspend pinging the target with this name, address or PeerId, or every
+    /// peer without one, keeping the connections open.
+    Pause {
+        #[serde(default)]
+        peer: Option<String>,
+    },
+    /// Resume pinging what `pause` suspended.
+    Resume {
+        #[serde(default)]
+        peer: Option<String>,
+    },
+    /// Report the statistics of every target.
+    Stats,
+    /// Stop the daemon.
+    Shutdown,
+}
+
+/// The daemon's answer to a [`Request`].
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum Response {
+    Ok,
+    Stats { targets: Vec<TargetStats> },
+    /// No target has the name, address or PeerId the request gave.
+    NotFound { message: String },
+    Error { message: String },
+}
+
+/// Ping statistics of one target, as reported by [`Request::Stats`].
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TargetStats {
+    pub target: String,
+    pub address: String,
+    pub peer_id: Option<String>,
+    pub connected: bool,
+    /// Whether the target is paused, alone or with every peer.
+    #[serde(default)]
+    pub paused: bool,
+    /// `up`, `degraded` or `down`; `None` before the first ping result.
+    #[serde(default)]
+    pub health: Option<String>,
+    pub transmitted: u64,
+    pub received: u64,
+    pub loss_percent: f64,
+    pub avg_us: Option<u64>,
+}
+
+/// A request together with the channel to answer it on.
+pub type Command = (Request, oneshot::Sender<Response>);
+
+/// The listening control socket; the socket file is removed on drop.
+pub struct ControlSocket {
+    path: PathBuf,
+}
+
+impl ControlSocket {
+    /// Listens at `path`, replacing a socket left behind by a daemon that is
+    /// no longer running, and forwards the requests of clients to `commands`.
+    pub async fn bind(path: &Path, commands: mpsc::Sender<Command>) -> Result<Self, Box<dyn Error>> {
+        if path.exists() {
+            if UnixStream::connect(path).await.is_ok() {
+                return Err(format!("{}: another daemon is already listening", path.display()).into());
+            }
+            std::fs::remove_file(path).map_err(|e| format!("{}: {e}", path.display()))?;
+        }
+        let listener = UnixListener::bind(path).map_err(|e| format!("{}: {e}", path.display()))?;
+        tokio::spawn(accept(listener, commands));
+        Ok(Self { path: path.to_owned() })
+    }
+
+    /// Returns where the socket listens.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for ControlSocket {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// Accepts clients, serving each in its own task.
+async fn accept(listener: UnixListener, commands: mpsc::Sender<Command>) {
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => {
+                tokio::spawn(serve(stream, commands.clone()));
+            }
+            Err(e) => tracing::warn!("control socket accept failed: {e}"),
+        }
+    }
+}
+
+/// Forwards the requests of one client to the node and writes back its answers.
+async fn serve(stream: UnixStream, commands: mpsc::Sender<Command>) -> io::Result<()> {
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+    while let Some(line) = lines.next_line().await? {
+        let response = match serde_json::from_str(&line) {
+            Ok(request) => forward(&commands, request).await,
+            Err(e) => Response::Error { message: format!("invalid request: {e}") },
+        };
+        let mut line = serde_json::to_string(&response).expect("responses serialize to JSON");
+        line.push('\n');
+        writer.write_all(line.as_bytes()).await?;
+    }
+    Ok(())
+}
+
+/// Hands `request` to the node and waits for its answer.
+pub async fn forward(commands: &mpsc::Sender<Command>, request: Request) -> Response {
+    let (reply, answer) = oneshot::channel();
+    if commands.send((request, reply)).await.is_err() {
+        return Response::Error { message: "the node is shutting down".into() };
+    }
+    answer.await.unwrap_or(Response::Error { message: "the node is shutting down".into() })
+}
+
+/// Sends `request` to the daemon listening at `path` and waits for its answer.
+pub async fn send(path: &Path, request: &Request) -> Result<Response, Box<dyn Error>> {
+    let stream = UnixStream::connect(path)
+        .await
+        .map_err(|e| format!("{}: {e}; is a --daemon running?", path.display()))?;
+    let (reader, mut writer) = stream.into_split();
+    let mut line = serde_json::to_string(request)?;
+    line.push('\n');
+    writer.write_all(line.as_bytes()).await?;
+    let answer = BufReader::new(reader)
+        .lines()
+        .next_line()
+        .await?
+        .ok_or("the daemon closed the connection without answering")?;
+    Ok(serde_json::from_str(&answer)?)
+}
@@ -0,0 +1,70 @@
+//! Limit on the number of outgoing dials in flight at once, see
+//! [`NodeConfig::max_concurrent_dials`](crate::NodeConfig::max_concurrent_dials).
+
+use libp2p::swarm::dial_opts::DialOpts;
+use libp2p::swarm::{ConnectionId, SwarmEvent};
+use libp2p::{Multiaddr, PeerId};
+use std::collections::{HashSet, VecDeque};
+
+use crate::BehaviourEvent;
+
+/// A dial waiting for a free slot, with the peer and address to trace it with.
+pub(crate) struct QueuedDial {
+    pub(crate) opts: DialOpts,
+    pub(crate) peer_id: Option<PeerId>,
+    pub(crate) addr: Option<Multiaddr>,
+}
+
+/// Dials in flight, and those waiting until fewer are, in the order they were
+/// requested.
+pub(crate) struct DialQueue {
+    /// `None` doesn't limit the dials.
+    limit: Option<usize>,
+    in_flight: HashSet<ConnectionId>,
+    queued: VecDeque<QueuedDial>,
+}
+
+impl DialQueue {
+    pub(crate) fn new(limit: Option<usize>) -> Self {
+        Self { limit, in_flight: HashSet::new(), queued: VecDeque::new() }
+    }
+
+    fn has_room(&self) -> bool {
+        self.limit.is_none_or(|limit| self.in_flight.len() < limit)
+    }
+
+    /// Returns `dial` back if it may start right away, after earlier dials
+    /// that are still waiting; otherwise queues it.
+    pub(crate) fn admit(&mut self, dial: QueuedDial) -> Option<QueuedDial> {
+        if self.queued.is_empty() && self.has_room() {
+            return Some(dial);
+        }
+        self.queued.push_back(dial);
+        None
+    }
+
+    /// Notes that a dial was started.
+    pub(crate) fn started(&mut self, connection_id: ConnectionId) {
+        self.in_flight.insert(connection_id);
+    }
+
+    /// Frees the slot of a dial once it succeeded or failed.
+    pub(crate) fn observe(&mut self, event: &SwarmEvent<BehaviourEvent>) {
+        match event {
+            SwarmEvent::ConnectionEstablished { connection_id, .. }
+            | SwarmEvent::OutgoingConnectionError { connection_id, .. } => {
+                self.in_flight.remove(connection_id);
+            }
+            _ => {}
+        }
+    }
+
+    /// Returns the next queued dial, if a slot is free.
+    pub(crate) fn next(&mut self) -> Option<QueuedDial> {
+        if self.has_room() {
+            self.queued.pop_front()
+        } else {
+            None
+        }
+    }
+}
@@ -0,0 +1,447 @@
+//! Connectivity diagnosis of a peer, for the `doctor` subcommand: every step
+//! from resolving its address to hole punching, as a checklist.
+//!
+//! The direct path is checked first. With a relay, a second node with the
+//! same identity then reaches the peer through it, so that the peer tries to
+//! hole punch a connection it doesn't have yet.
+
+use libp2p::autonat::{self, OutboundProbeError, OutboundProbeEvent, ResponseError};
+use libp2p::identity::Keypair;
+use libp2p::multiaddr::Protocol;
+use libp2p::swarm::{ConnectionId, DialError, SwarmEvent};
+use libp2p::{dcutr, identify, ping, relay, Multiaddr, PeerId};
+use libp2p_ping_tut::{BehaviourEvent, ConnectionTiming, DialErrorKind, NodeConfig, PingNode};
+use std::error::Error;
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// How long connections get to close before the relayed path is checked.
+const CLOSE_GRACE: Duration = Duration::from_secs(1);
+
+/// The outcome of a check.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    Pass,
+    Fail,
+    /// Not applicable, or impossible after an earlier failure.
+    Skip,
+}
+
+/// A step of the diagnosis.
+#[derive(Debug, Clone)]
+pub struct Check {
+    /// `dns`, `dial`, `security`, `identify`, `ping`, `autonat`, `relay` or
+    /// `hole-punch`.
+    pub name: &'static str,
+    pub status: Status,
+    /// How long the step took, if it ran.
+    pub time: Option<Duration>,
+    /// What was found, or why the step failed or was skipped.
+    pub detail: String,
+}
+
+impl Check {
+    fn pass(name: &'static str, time: Option<Duration>, detail: impl Into<String>) -> Self {
+        Self { name, status: Status::Pass, time, detail: detail.into() }
+    }
+
+    fn fail(name: &'static str, time: Option<Duration>, detail: impl Into<String>) -> Self {
+        Self { name, status: Status::Fail, time, detail: detail.into() }
+    }
+
+    fn skip(name: &'static str, detail: impl Into<String>) -> Self {
+        Self { name, status: Status::Skip, time: None, detail: detail.into() }
+    }
+}
+
+/// Diagnoses the connectivity to the peer at `addr` with nodes of `keypair`
+/// and `config` listening on `listen`, and through `relay` if given.
+///
+/// A relayed `addr` is checked through its relay only, which `relay` then
+/// mustn't name as well.
+pub async fn diagnose(
+    keypair: Keypair,
+    config: NodeConfig,
+    listen: &[Multiaddr],
+    addr: &Multiaddr,
+    relay: Option<Multiaddr>,
+) -> Result<Vec<Check>, Box<dyn Error>> {
+    let (direct, relay) = match split_relayed(addr) {
+        Some(_) if relay.is_some() => return Err("give either a relayed address or --via-relay, not both".into()),
+        Some((relay, peer_id)) => (None, Some((relay, Some(peer_id)))),
+        None => (Some(addr), relay.map(|relay| (relay, peer_id(addr)))),
+    };
+
+    let mut checks = vec![resolve(addr).await];
+    let mut target = None;
+    match direct {
+        Some(addr) => {
+            let mut node = start(keypair.clone(), config.clone(), listen)?;
+            let (direct_checks, peer_id) = check_direct(&mut node, addr, &config).await;
+            node.shutdown(CLOSE_GRACE).await;
+            checks.extend(direct_checks);
+            target = peer_id;
+        }
+        None => {
+            for name in ["dial", "security", "identify", "ping", "autonat"] {
+                checks.push(Check::skip(name, "the address is relayed, so only the relayed path is checked"));
+            }
+        }
+    }
+
+    match relay {
+        Some((relay, peer_id)) => match peer_id.or(target) {
+            Some(peer_id) => {
+                let mut node = start(keypair, config.clone(), listen)?;
+                checks.extend(check_relayed(&mut node, &relay, peer_id, &config).await);
+                node.shutdown(CLOSE_GRACE).await;
+            }
+            None => {
+                let reason = "the PeerId of the peer is unknown; end its address with /p2p/<peer id>";
+                checks.push(Check::skip("relay", reason));
+                checks.push(Check::skip("hole-punch", reason));
+            }
+        },
+        None => {
+            checks.push(Check::skip("relay", "no relay given with --via-relay"));
+            checks.push(Check::skip("hole-punch", "needs a relayed connection; give a relay with --via-relay"));
+        }
+    }
+    Ok(checks)
+}
+
+/// Creates a node listening on `listen`, which may fail as long as one address
+/// works, for the peer to dial back and hole punch.
+fn start(keypair: Keypair, config: NodeConfig, listen: &[Multiaddr]) -> Result<PingNode, Box<dyn Error>> {
+    let mut node = PingNode::with_keypair(keypair, config)?;
+    let listening = listen.iter().filter(|addr| node.listen((*addr).clone()).is_ok()).count();
+    if listening == 0 {
+        return Err("could not listen on any address".into());
+    }
+    Ok(node)
+}
+
+/// Resolves the DNS name `addr` starts with, if any, as dialing would.
+async fn resolve(addr: &Multiaddr) -> Check {
+    let (name, family) = match addr.iter().next() {
+        Some(Protocol::Dns(name)) => (name, None),
+        Some(Protocol::Dns4(name)) => (name, Some(true)),
+        Some(Protocol::Dns6(name)) => (name, Some(false)),
+        Some(Protocol::Dnsaddr(_)) => return Check::skip("dns", "/dnsaddr names are resolved to peer addresses when dialing"),
+        _ => return Check::skip("dns", "the address has no DNS name"),
+    };
+    let started = Instant::now();
+    let result = tokio::net::lookup_host((name.as_ref(), 0)).await;
+    let time = Some(started.elapsed());
+    match result {
+        Ok(resolved) => {
+            let ips: Vec<IpAddr> = resolved
+                .map(|addr: SocketAddr| addr.ip())
+                .filter(|ip| family.is_none_or(|ipv4| ip.is_ipv4() == ipv4))
+                .collect();
+            if ips.is_empty() {
+                return Check::fail("dns", time, format!("{name} has no address of the requested family"));
+            }
+            let ips: Vec<String> = ips.iter().map(ToString::to_string).collect();
+            Check::pass("dns", time, format!("{name} resolves to {}", ips.join(", ")))
+        }
+        Err(e) => Check::fail("dns", time, format!("{name}: {e}")),
+    }
+}
+
+/// Dials `addr` directly and checks the handshakes, identify, a ping
+/// and whether the peer can dial us back, returning those checks and the
+/// PeerId of the peer, if it was reached.
+async fn check_direct(node: &mut PingNode, addr: &Multiaddr, config: &NodeConfig) -> (Vec<Check>, Option<PeerId>) {
+    let started = Instant::now();
+    let dial = match node.dial(addr.clone()) {
+        Ok(dial) => dial,
+        Err(e) => {
+            let mut checks = vec![Check::fail("dial", None, e.to_string())];
+            for name in ["security", "identify", "ping", "autonat"] {
+                checks.push(Check::skip(name, "not connected"));
+            }
+            return (checks, None);
+        }
+    };
+    // The first probe of AutoNAT waits this long after the node started.
+    let autonat_deadline = started + autonat::Config::default().boot_delay + config.ping_timeout;
+
+    let mut peer = None;
+    let mut established: Option<Instant> = None;
+    let (mut connected, mut identified, mut pinged, mut reachable) = (None, None, None, None);
+    let mut probe_started = None;
+    loop {
+        if identified.is_some() && pinged.is_some() && reachable.is_some() {
+            break;
+        }
+        let deadline = match established {
+            None => started + config.dial_timeout,
+            Some(at) if reachable.is_some() => at + config.ping_timeout,
+            Some(at) => (at + config.ping_timeout).max(autonat_deadline),
+        };
+        let Ok(event) = tokio::time::timeout_at(deadline, node.next_event()).await else {
+            break;
+        };
+        match event {
+            SwarmEvent::ConnectionEstablished { peer_id, connection_id, .. } if connection_id == dial => {
+                peer = Some(peer_id);
+                established = Some(Instant::now());
+            }
+            SwarmEvent::OutgoingConnectionError { connection_id, error, .. } if connection_id == dial => {
+                let time = Some(started.elapsed());
+                connected = Some(match &error {
+                    DialError::WrongPeerId { obtained, .. } => (
+                        Check::pass("dial", None, "connection opened"),
+                        Check::fail("security", time, format!("the peer authenticated as {obtained}, not the PeerId of the address")),
+                    ),
+                    error if failed_in_handshake(error) => (
+                        Check::pass("dial", None, "connection opened"),
+                        Check::fail("security", time, format!("{error}; does the peer offer the same --security?")),
+                    ),
+                    error => (Check::fail("dial", time, error.to_string()), Check::skip("security", "not connected")),
+                });
+                break;
+            }
+            SwarmEvent::ConnectionClosed { connection_id, cause, .. } if connection_id == dial => {
+                let cause = cause.map_or_else(|| "the connection closed".to_owned(), |e| format!("the connection closed: {e}"));
+                identified.get_or_insert_with(|| Check::fail("identify", None, cause.clone()));
+                pinged.get_or_insert_with(|| Check::fail("ping", None, cause));
+                break;
+            }
+            SwarmEvent::Behaviour(BehaviourEvent::Identify(identify::Event::Received { peer_id, info })) if Some(peer_id) == peer => {
+                let time = established.map(|at| at.elapsed());
+                if !info.protocols.contains(&autonat::DEFAULT_PROTOCOL_NAME) {
+                    reachable = Some(Check::skip("autonat", "the peer doesn't speak AutoNAT"));
+                }
+                let detail = format!("{}, {} protocols", info.agent_version, info.protocols.len());
+                identified = Some(Check::pass("identify", time, detail));
+            }
+            SwarmEvent::Behaviour(BehaviourEvent::Identify(identify::Event::Error { peer_id, error })) if Some(peer_id) == peer => {
+                identified = Some(Check::fail("identify", established.map(|at| at.elapsed()), error.to_string()));
+            }
+            SwarmEvent::Behaviour(BehaviourEvent::Ping(ping::Event { connection, result, .. }))
+                if connection == dial && pinged.is_none() =>
+            {
+                pinged = Some(match result {
+                    Ok(rtt) => Check::pass("ping", Some(rtt), format!("answered over {addr}")),
+                    Err(e) => Check::fail("ping", None, e.to_string()),
+                });
+                connected = Some(timed_handshake(node.take_connection_timing(dial), established.map(|at| at - started)));
+            }
+            SwarmEvent::Behaviour(BehaviourEvent::Autonat(autonat::Event::OutboundProbe(event))) if reachable.is_none() => {
+                match event {
+                    OutboundProbeEvent::Request { .. } => probe_started = Some(Instant::now()),
+                    OutboundProbeEvent::Response { peer: server, address, .. } => {
+                        let time = probe_started.map(|at| at.elapsed());
+                        reachable = Some(Check::pass("autonat", time, format!("{server} dialed us back at {address}")));
+                    }
+                    OutboundProbeEvent::Error { peer: server, error, .. } => {
+                        reachable = Some(probe_failed(server, &error, probe_started.map(|at| at.elapsed())));
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let (dialed, secured) = connected.unwrap_or_else(|| match established {
+        // Established, but the first ping didn't arrive in time.
+        Some(at) => timed_handshake(None, Some(at - started)),
+        None => {
+            let waited = humantime::format_duration(config.dial_timeout);
+            (Check::fail("dial", None, format!("not connected within {waited}")), Check::skip("security", "not connected"))
+        }
+    });
+    let reached = dialed.status == Status::Pass && secured.status == Status::Pass;
+    let missing = |name, waited: Duration| match reached {
+        true => Check::fail(name, None, format!("no answer within {}", humantime::format_duration(waited))),
+        false => Check::skip(name, "not connected"),
+    };
+    let checks = vec![
+        dialed,
+        secured,
+        identified.unwrap_or_else(|| missing("identify", config.ping_timeout)),
+        pinged.unwrap_or_else(|| missing("ping", config.ping_timeout)),
+        reachable.unwrap_or_else(|| match reached {
+            true => Check::fail("autonat", None, "no AutoNAT probe finished in time"),
+            false => Check::skip("autonat", "not connected"),
+        }),
+    ];
+    (checks, peer.filter(|_| reached))
+}
+
+/// The dial and security checks of an established connection, split by
+/// `timing` if known, or else all in `established`.
+fn timed_handshake(timing: Option<ConnectionTiming>, established: Option<Duration>) -> (Check, Check) {
+    match timing {
+        Some(timing) => match (timing.security, timing.muxer) {
+            (Some(security), muxer) => (
+                Check::pass("dial", Some(timing.connect), "connection opened"),
+                Check::pass("security", Some(security + muxer.unwrap_or_default()), "authenticated, streams multiplexed"),
+            ),
+            (None, _) => (
+                Check::pass("dial", Some(timing.connect), "QUIC handshake done"),
+                Check::pass("security", None, "part of the QUIC handshake"),
+            ),
+        },
+        None => (
+            Check::pass("dial", established, "connected, including the handshakes"),
+            Check::pass("security", None, "authenticated, streams multiplexed"),
+        ),
+    }
+}
+
+/// Whether a dial failed after the connection opened, in the security or
+/// multiplexer handshake.
+pub fn failed_in_handshake(error: &DialError) -> bool {
+    DialErrorKind::of(error) == DialErrorKind::NegotiationFailed
+}
+
+/// The AutoNAT check for a probe of `server` that failed with `error`.
+fn probe_failed(server: Option<PeerId>, error: &OutboundProbeError, time: Option<Duration>) -> Check {
+    let server = server.map_or_else(|| "the peer".to_owned(), |server| server.to_string());
+    match error {
+        OutboundProbeError::Response(ResponseError::DialError) => Check::fail(
+            "autonat",
+            time,
+            format!("{server} couldn't dial us back; inbound connections need port forwarding or a relay"),
+        ),
+        OutboundProbeError::Response(ResponseError::DialRefused) => {
+            Check::skip("autonat", format!("{server} refused to dial us back; is it running with --autonat-server?"))
+        }
+        OutboundProbeError::Response(error) => Check::fail("autonat", time, format!("{server} answered {error:?}")),
+        OutboundProbeError::OutboundRequest(failure) => Check::fail("autonat", time, failure.to_string()),
+        OutboundProbeError::NoServer => Check::skip(
+            "autonat",
+            "the peer can't be asked; one at a private address needs --autonat-allow-private on both sides",
+        ),
+        OutboundProbeError::NoAddresses => Check::skip("autonat", "we have no address to be dialed back at"),
+    }
+}
+
+/// Reaches `peer_id` through `relay` and waits for the peer to hole punch a
+/// direct connection.
+async fn check_relayed(node: &mut PingNode, relay: &Multiaddr, peer_id: PeerId, config: &NodeConfig) -> Vec<Check> {
+    let started = Instant::now();
+    let relay_dial = match node.dial(relay.clone()) {
+        Ok(dial) => dial,
+        Err(e) => return vec![Check::fail("relay", None, e.to_string()), Check::skip("hole-punch", "no relayed connection")],
+    };
+    let relay_peer = match wait_for_relay(node, relay_dial, config.dial_timeout + config.ping_timeout).await {
+        Ok(relay_peer) => relay_peer,
+        Err(e) => {
+            let check = Check::fail("relay", Some(started.elapsed()), format!("relay {relay}: {e}"));
+            return vec![check, Check::skip("hole-punch", "no relayed connection")];
+        }
+    };
+
+    let circuit = relay.clone().with(Protocol::P2pCircuit).with(Protocol::P2p(peer_id));
+    let circuit_started = Instant::now();
+    let circuit_dial = match node.dial(circuit) {
+        Ok(dial) => dial,
+        Err(e) => return vec![Check::fail("relay", None, e.to_string()), Check::skip("hole-punch", "no relayed connection")],
+    };
+    let deadline = circuit_started + config.dial_timeout;
+    let relayed = loop {
+        let Ok(event) = tokio::time::timeout_at(deadline, node.next_event()).await else {
+            let waited = humantime::format_duration(config.dial_timeout);
+            let check = Check::fail("relay", None, format!("relay {relay_peer} reached, but no circuit within {waited}"));
+            return vec![check, Check::skip("hole-punch", "no relayed connection")];
+        };
+        match event {
+            SwarmEvent::ConnectionEstablished { connection_id, .. } if connection_id == circuit_dial => {
+                let time = circuit_started.elapsed();
+                break Check::pass("relay", Some(time), format!("circuit to {peer_id} through {relay_peer}"));
+            }
+            SwarmEvent::OutgoingConnectionError { connection_id, error, .. } if connection_id == circuit_dial => {
+                let detail = format!("relay {relay_peer} reached, but the circuit failed: {error}; is the peer reserved there?");
+                let check = Check::fail("relay", Some(circuit_started.elapsed()), detail);
+                return vec![check, Check::skip("hole-punch", "no relayed connection")];
+            }
+            SwarmEvent::Behaviour(BehaviourEvent::Dcutr(event)) if event.remote_peer_id == peer_id => {
+                // Raced ahead of the event establishing the circuit.
+                let check = Check::pass("relay", Some(circuit_started.elapsed()), format!("circuit to {peer_id} through {relay_peer}"));
+                return vec![check, hole_punched(&event, circuit_started.elapsed())];
+            }
+            _ => {}
+        }
+    };
+    let circuit_established = Instant::now();
+
+    let punch_deadline = circuit_established + config.dial_timeout;
+    let punch = loop {
+        let Ok(event) = tokio::time::timeout_at(punch_deadline, node.next_event()).await else {
+            let waited = humantime::format_duration(config.dial_timeout);
+            break Check::fail("hole-punch", None, format!("the peer didn't attempt a hole punch within {waited}"));
+        };
+        if let SwarmEvent::Behaviour(BehaviourEvent::Dcutr(event)) = event {
+            if event.remote_peer_id == peer_id {
+                break hole_punched(&event, circuit_established.elapsed());
+            }
+        }
+    };
+    vec![relayed, punch]
+}
+
+/// Waits for the connection `dial` to a relay and its identify info, returning
+/// its PeerId if it is a relay.
+async fn wait_for_relay(node: &mut PingNode, dial: ConnectionId, wait: Duration) -> Result<PeerId, String> {
+    let deadline = Instant::now() + wait;
+    let mut peer = None;
+    loop {
+        let Ok(event) = tokio::time::timeout_at(deadline, node.next_event()).await else {
+            return Err(match peer {
+                Some(_) => "no identify info in time".to_owned(),
+                None => format!("not connected within {}", humantime::format_duration(wait)),
+            });
+        };
+        match event {
+            SwarmEvent::ConnectionEstablished { peer_id, connection_id, .. } if connection_id == dial => peer = Some(peer_id),
+            SwarmEvent::OutgoingConnectionError { connection_id, error, .. } if connection_id == dial => {
+                return Err(error.to_string());
+            }
+            SwarmEvent::Behaviour(BehaviourEvent::Identify(identify::Event::Received { peer_id, info })) if Some(peer_id) == peer => {
+                return match info.protocols.contains(&relay::HOP_PROTOCOL_NAME) {
+                    true => Ok(peer_id),
+                    false => Err(format!("not a relay; it doesn't speak {}", relay::HOP_PROTOCOL_NAME)),
+                };
+            }
+            _ => {}
+        }
+    }
+}
+
+/// The hole punch check for the DCUtR `event`, after `time`.
+fn hole_punched(event: &dcutr::Event, time: Duration) -> Check {
+    match &event.result {
+        Ok(_) => Check::pass("hole-punch", Some(time), "direct connection established"),
+        Err(e) => Check::fail("hole-punch", Some(time), e.to_string()),
+    }
+}
+
+/// Splits `addr` into its relay and the PeerId reached through it, if it is a
+/// relayed address.
+fn split_relayed(addr: &Multiaddr) -> Option<(Multiaddr, PeerId)> {
+    let mut relay = Multiaddr::empty();
+    let mut protocols = addr.iter();
+    for protocol in protocols.by_ref() {
+        if protocol == Protocol::P2pCircuit {
+            return match protocols.next() {
+                Some(Protocol::P2p(peer_id)) => Some((relay, peer_id)),
+                _ => None,
+            };
+        }
+        relay.push(protocol);
+    }
+    None
+}
+
+/// The PeerId `addr` ends with, if any.
+fn peer_id(addr: &Multiaddr) -> Option<PeerId> {
+    match addr.iter().last() {
+        Some(Protocol::P2p(peer_id)) => Some(peer_id),
+        _ => None,
+    }
+}
@@ -0,0 +1,276 @@
+//! Echo protocol measuring round-trip times with payloads of a chosen size.
+//!
+//! Ping always sends 32 bytes, which fit in any packet. Echoing larger
+//! payloads, e.g. 1400 bytes, shows the RTT of realistic traffic and exposes
+//! paths that drop packets close to the MTU. Every echo is sent on a new
+//! stream, so its RTT includes negotiating the protocol on it.
+
+use async_trait::async_trait;
+use futures::prelude::*;
+use libp2p::request_response::{self, OutboundFailure, OutboundRequestId, ProtocolSupport};
+use libp2p::swarm::FromSwarm;
+use libp2p::{PeerId, StreamProtocol};
+use rand::RngCore;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::io;
+use std::task::Context;
+use std::time::{Duration, Instant};
+
+use crate::forward::request_response_behaviour;
+use crate::ping_limit::Buckets;
+
+/// Protocol name of the echo protocol.
+pub const PROTOCOL_NAME: StreamProtocol = StreamProtocol::new("/libp2p-ping-tut/echo/1.0.0");
+
+/// Largest payload echoed back; larger requests are refused.
+pub const MAX_SIZE: usize = 64 * 1024;
+
+/// The result of an echo request to a peer.
+#[derive(Debug)]
+pub struct Event {
+    pub peer: PeerId,
+    /// Payload size in bytes.
+    pub size: usize,
+    pub result: Result<Duration, Failure>,
+}
+
+/// Why an echo request failed.
+#[derive(Debug)]
+pub enum Failure {
+    /// The peer didn't answer within the timeout.
+    Timeout,
+    /// The peer doesn't support the echo protocol; it is not sent further
+    /// requests.
+    Unsupported,
+    /// The peer answered with a different payload.
+    Mismatch,
+    /// The request failed for another reason, e.g. the connection closed.
+    Other(OutboundFailure),
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Failure::Timeout => write!(f, "echo timeout"),
+            Failure::Unsupported => write!(f, "echo protocol not supported"),
+            Failure::Mismatch => write!(f, "echoed payload differs from the one sent"),
+            Failure::Other(e) => write!(f, "echo failed: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for Failure {}
+
+/// Answers echo requests from every peer and, if a payload size is set for all
+/// or for the peer, sends one to each connected peer every interval.
+pub struct Behaviour {
+    inner: request_response::Behaviour<Codec>,
+    /// Payload sent to every peer; `None` only answers requests.
+    payload: Option<Vec<u8>>,
+    /// Payloads of the peers with a size of their own.
+    peer_payloads: HashMap<PeerId, Vec<u8>>,
+    interval: tokio::time::Interval,
+    /// Connected peers that haven't refused the protocol.
+    peers: HashSet<PeerId>,
+    /// When each pending request was sent.
+    sent: HashMap<OutboundRequestId, Instant>,
+    /// Whether sending is paused; requests are answered either way.
+    paused: bool,
+    /// Peers for which sending is paused.
+    paused_peers: HashSet<PeerId>,
+    /// Buckets of the inbound ping limit, if any; requests over it are refused.
+    limit: Option<Buckets>,
+}
+
+impl Behaviour {
+    /// Sends `size` random bytes every `interval` if `size` is set, and waits
+    /// up to `timeout` for each echo.
+    pub fn new(size: Option<usize>, interval: Duration, timeout: Duration) -> Self {
+        let config = request_response::Config::default().with_request_timeout(timeout);
+        let payload = size.map(random_payload);
+        Self {
+            inner: request_response::Behaviour::with_codec(Codec, [(PROTOCOL_NAME, ProtocolSupport::Full)], config),
+            payload,
+            peer_payloads: HashMap::new(),
+            interval: tokio::time::interval(interval),
+            peers: HashSet::new(),
+            sent: HashMap::new(),
+            paused: false,
+            paused_peers: HashSet::new(),
+            limit: None,
+        }
+    }
+
+    /// Answers the requests of each peer only while it is within the inbound
+    /// ping limit of `buckets`.
+    pub(crate) fn set_limit(&mut self, buckets: Option<Buckets>) {
+        self.limit = buckets;
+    }
+
+    /// Stops sending echo requests, or starts again.
+    pub fn set_paused(&mut self, paused: bool) {
+        self.paused = paused;
+    }
+
+    /// Stops sending echo requests to `peer`, or starts again.
+    pub fn set_peer_paused(&mut self, peer: PeerId, paused: bool) {
+        if paused {
+            self.paused_peers.insert(peer);
+        } else {
+            self.paused_peers.remove(&peer);
+        }
+    }
+
+    /// Sends payloads of `size` random bytes to `peer` instead of the payload
+    /// of every peer, or that again with `None`.
+    pub fn set_peer_size(&mut self, peer: PeerId, size: Option<usize>) {
+        match size {
+            Some(size) => {
+                self.peer_payloads.insert(peer, random_payload(size));
+            }
+            None => {
+                self.peer_payloads.remove(&peer);
+            }
+        }
+    }
+
+    /// The payload sent to `peer`, if any.
+    fn payload_for(&self, peer: &PeerId) -> Option<&Vec<u8>> {
+        self.peer_payloads.get(peer).or(self.payload.as_ref())
+    }
+
+    fn send(&mut self, peer: PeerId) {
+        if let Some(payload) = self.payload_for(&peer).filter(|_| !self.paused && !self.paused_peers.contains(&peer)) {
+            let request_id = self.inner.send_request(&peer, payload.clone());
+            self.sent.insert(request_id, Instant::now());
+        }
+    }
+
+    /// Answers requests and turns responses into events.
+    fn on_inner_event(&mut self, event: request_response::Event<Vec<u8>, Vec<u8>>) -> Option<Event> {
+        let size = |this: &Self, peer| this.payload_for(&peer).map_or(0, Vec::len);
+        match event {
+            request_response::Event::Message { peer, message } => match message {
+                request_response::Message::Request { request, channel, .. } => {
+                    if self.limit.as_ref().is_some_and(|limit| !limit.try_take(peer)) {
+                        // Dropping the channel refuses the request.
+                        return None;
+                    }
+                    // Fails only if the connection closed in the meantime.
+                    let _ = self.inner.send_response(channel, request);
+                    None
+                }
+                request_response::Message::Response { request_id, response } => {
+                    let sent = self.sent.remove(&request_id)?;
+                    let result = if Some(&response) == self.payload_for(&peer) {
+                        Ok(sent.elapsed())
+                    } else {
+                        Err(Failure::Mismatch)
+                    };
+                    Some(Event { peer, size: size(self, peer), result })
+                }
+            },
+            request_response::Event::OutboundFailure { peer, request_id, error } => {
+                self.sent.remove(&request_id)?;
+                let failure = match error {
+                    OutboundFailure::Timeout => Failure::Timeout,
+                    OutboundFailure::UnsupportedProtocols => {
+                        self.peers.remove(&peer);
+                        Failure::Unsupported
+                    }
+                    error => Failure::Other(error),
+                };
+                Some(Event { peer, size: size(self, peer), result: Err(failure) })
+            }
+            _ => None,
+        }
+    }
+
+    /// Tracks the peers to send requests to, echoing new ones right away rather
+    /// than at the next interval.
+    fn track_peers(&mut self, event: FromSwarm) {
+        match event {
+            FromSwarm::ConnectionEstablished(established) if established.other_established == 0 => {
+                self.peers.insert(established.peer_id);
+                self.send(established.peer_id);
+            }
+            FromSwarm::ConnectionClosed(closed) if closed.remaining_established == 0 => {
+                self.peers.remove(&closed.peer_id);
+            }
+            _ => {}
+        }
+    }
+
+    /// Sends a request to every peer each interval.
+    fn send_due(&mut self, cx: &mut Context<'_>) {
+        let sending = self.payload.is_some() || !self.peer_payloads.is_empty();
+        if sending && self.interval.poll_tick(cx).is_ready() {
+            let peers: Vec<PeerId> = self.peers.iter().copied().collect();
+            for peer in peers {
+                self.send(peer);
+            }
+        }
+    }
+}
+
+request_response_behaviour!(Behaviour, Codec, Event, on_swarm_event: track_peers, poll: send_due);
+
+fn random_payload(size: usize) -> Vec<u8> {
+    let mut payload = vec![0; size];
+    rand::thread_rng().fill_bytes(&mut payload);
+    payload
+}
+
+/// Sends payloads as they are, closing the stream to delimit them.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Codec;
+
+#[async_trait]
+impl request_response::Codec for Codec {
+    type Protocol = StreamProtocol;
+    type Request = Vec<u8>;
+    type Response = Vec<u8>;
+
+    async fn read_request<T>(&mut self, _: &StreamProtocol, io: &mut T) -> io::Result<Vec<u8>>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        read_payload(io).await
+    }
+
+    async fn read_response<T>(&mut self, _: &StreamProtocol, io: &mut T) -> io::Result<Vec<u8>>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        read_payload(io).await
+    }
+
+    async fn write_request<T>(&mut self, _: &StreamProtocol, io: &mut T, payload: Vec<u8>) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        write_payload(io, &payload).await
+    }
+
+    async fn write_response<T>(&mut self, _: &StreamProtocol, io: &mut T, payload: Vec<u8>) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        write_payload(io, &payload).await
+    }
+}
+
+async fn read_payload<T: AsyncRead + Unpin + Send>(io: &mut T) -> io::Result<Vec<u8>> {
+    let mut payload = Vec::new();
+    io.take(MAX_SIZE as u64 + 1).read_to_end(&mut payload).await?;
+    if payload.len() > MAX_SIZE {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "echo payload too large"));
+    }
+    Ok(payload)
+}
+
+async fn write_payload<T: AsyncWrite + Unpin + Send>(io: &mut T, payload: &[u8]) -> io::Result<()> {
+    io.write_all(payload).await?;
+    io.close().await
+}
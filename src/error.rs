@@ -0,0 +1,400 @@
+//! The error type of the library's fallible APIs, and the classification of
+//! failed dials.
+
+use either::Either;
+use libp2p::core::transport::timeout::TransportTimeoutError;
+use libp2p::core::upgrade::NegotiationError;
+use libp2p::gossipsub::PublishError;
+use libp2p::swarm::DialError;
+use libp2p::{dns, noise, quic, tls, Multiaddr, TransportError};
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+use wtransport::error::{ConnectingError, ConnectionError};
+use wtransport::tls::rustls;
+
+/// Why a [`PingNode`](crate::PingNode) couldn't be built, or a call on it or
+/// on the [`keyfile`](crate::keyfile) and [`testing`](crate::testing) helpers
+/// failed.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum PingError {
+    /// An address that can't be used as given, e.g. a bootstrap address that
+    /// doesn't end with the PeerId of the node.
+    InvalidMultiaddr { addr: String, reason: String },
+    /// None of the enabled transports dials or listens on the address.
+    TransportNotSupported(Multiaddr),
+    /// Listening on the address failed, e.g. because it is in use.
+    ListenFailure { addr: Multiaddr, cause: io::Error },
+    /// A dial couldn't be started.
+    DialFailure { cause: DialError },
+    /// What was waited for didn't happen in time.
+    Timeout,
+    /// A ping failed.
+    PingFailure(String),
+    /// Reading or writing a keypair, private network key or certificate file
+    /// failed, or its contents are invalid.
+    IdentityLoad { path: PathBuf, cause: Box<dyn Error + Send + Sync> },
+    /// The call needs a feature the [`NodeConfig`](crate::NodeConfig)
+    /// doesn't enable, e.g. Kademlia.
+    NotEnabled(&'static str),
+    /// The [`NodeConfig`](crate::NodeConfig) is inconsistent or out of range.
+    InvalidConfig(String),
+    /// Setting up the transports or protocols failed.
+    Build(Box<dyn Error + Send + Sync>),
+    /// Publishing RTTs to the latency mesh failed.
+    Publish(PublishError),
+}
+
+impl PingError {
+    /// The error of reading or writing the file at `path`.
+    pub(crate) fn identity(path: impl Into<PathBuf>, cause: impl Into<Box<dyn Error + Send + Sync>>) -> Self {
+        PingError::IdentityLoad { path: path.into(), cause: cause.into() }
+    }
+
+    /// The error of a dial that couldn't be started, telling addresses no
+    /// transport supports apart.
+    pub(crate) fn dial(cause: DialError) -> Self {
+        match &cause {
+            DialError::Transport(errors)
+                if !errors.is_empty()
+                    && errors.iter().all(|(_, error)| matches!(error, TransportError::MultiaddrNotSupported(_))) =>
+            {
+                PingError::TransportNotSupported(errors[0].0.clone())
+            }
+            _ => PingError::DialFailure { cause },
+        }
+    }
+
+    /// What kind of dial failure this is, if it is one.
+    pub fn dial_error_kind(&self) -> Option<DialErrorKind> {
+        match self {
+            PingError::DialFailure { cause } => Some(DialErrorKind::of(cause)),
+            PingError::TransportNotSupported(_) => Some(DialErrorKind::Unsupported),
+            _ => None,
+        }
+    }
+
+    /// The error of listening on `addr`.
+    pub(crate) fn listen(addr: Multiaddr, error: TransportError<io::Error>) -> Self {
+        match error {
+            TransportError::MultiaddrNotSupported(addr) => PingError::TransportNotSupported(addr),
+            TransportError::Other(cause) => PingError::ListenFailure { addr, cause },
+        }
+    }
+}
+
+impl fmt::Display for PingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PingError::InvalidMultiaddr { addr, reason } => write!(f, "invalid address {addr}: {reason}"),
+            PingError::TransportNotSupported(addr) => write!(f, "no enabled transport supports {addr}"),
+            PingError::ListenFailure { addr, cause } => write!(f, "cannot listen on {addr}: {cause}"),
+            PingError::DialFailure { cause } => write!(f, "cannot dial: {cause}"),
+            PingError::Timeout => f.write_str("timed out"),
+            PingError::PingFailure(reason) => write!(f, "ping failed: {reason}"),
+            PingError::IdentityLoad { path, cause } => write!(f, "{}: {cause}", path.display()),
+            PingError::NotEnabled(feature) => write!(f, "{feature} is not enabled"),
+            PingError::InvalidConfig(reason) => f.write_str(reason),
+            PingError::Build(cause) => write!(f, "cannot set up the node: {cause}"),
+            PingError::Publish(cause) => write!(f, "cannot publish to the latency mesh: {cause}"),
+        }
+    }
+}
+
+impl Error for PingError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            PingError::ListenFailure { cause, .. } => Some(cause),
+            PingError::DialFailure { cause } => Some(cause),
+            PingError::IdentityLoad { cause, .. } | PingError::Build(cause) => Some(cause.as_ref()),
+            PingError::Publish(cause) => Some(cause),
+            _ => None,
+        }
+    }
+}
+
+/// Why an outgoing connection failed, telling a host that is down from one
+/// that speaks other protocols.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum DialErrorKind {
+    /// The host answered, but nothing listens on the port.
+    Refused,
+    /// There is no route to the host or its network.
+    Unreachable,
+    /// The host didn't answer within the dial timeout.
+    Timeout,
+    /// The host authenticated as another peer than the address ends with.
+    WrongPeer,
+    /// The connection opened, but the security or multiplexer handshake
+    /// failed, e.g. because the peer offers other protocols.
+    NegotiationFailed,
+    /// None of the enabled transports supports the address.
+    Unsupported,
+    /// Refused locally, by a connection limit or the allow or deny list.
+    Denied,
+    /// Anything else, e.g. no address to dial.
+    Other,
+}
+
+impl DialErrorKind {
+    /// Classifies a failed dial. Of the addresses tried, the one that got the
+    /// furthest counts, so that a handshake failing on one isn't hidden by
+    /// another address being unreachable.
+    pub fn of(error: &DialError) -> Self {
+        match error {
+            DialError::WrongPeerId { .. } => DialErrorKind::WrongPeer,
+            DialError::Denied { .. } => DialErrorKind::Denied,
+            DialError::Transport(errors) => errors
+                .iter()
+                .map(|(_, error)| match error {
+                    TransportError::MultiaddrNotSupported(_) => DialErrorKind::Unsupported,
+                    TransportError::Other(error) => Self::of_io(error),
+                })
+                .max_by_key(|kind| kind.progress())
+                .unwrap_or(DialErrorKind::Other),
+            _ => DialErrorKind::Other,
+        }
+    }
+
+    /// Classifies the error of dialing one address by the first error with a
+    /// telling type or I/O error kind in its chain, or else by the messages of
+    /// the chain.
+    fn of_io(error: &io::Error) -> Self {
+        let mut message = String::new();
+        let mut next: Option<&(dyn Error + 'static)> = Some(error);
+        while let Some(error) = next {
+            if let Some(kind) = Self::of_cause(error) {
+                return kind;
+            }
+            message.push_str(&error.to_string());
+            next = Self::cause_of(error);
+        }
+        // A few transports hide their errors from the chain, e.g. behind
+        // wrappers with the source of the error they wrap.
+        if message.contains("Handshake failed") || message.contains("negotiation failed") {
+            DialErrorKind::NegotiationFailed
+        } else if message.contains("Connection refused") {
+            DialErrorKind::Refused
+        } else if message.contains("timed out") {
+            DialErrorKind::Timeout
+        } else if message.contains("No route to host") || message.contains("unreachable") {
+            DialErrorKind::Unreachable
+        } else {
+            DialErrorKind::Other
+        }
+    }
+
+    /// The kind of dial failure one error in the chain tells of, if any.
+    fn of_cause(error: &(dyn Error + 'static)) -> Option<Self> {
+        if let Some(error) = error.downcast_ref::<io::Error>() {
+            return match error.kind() {
+                io::ErrorKind::ConnectionRefused => Some(DialErrorKind::Refused),
+                io::ErrorKind::TimedOut => Some(DialErrorKind::Timeout),
+                io::ErrorKind::HostUnreachable | io::ErrorKind::NetworkUnreachable | io::ErrorKind::NetworkDown => {
+                    Some(DialErrorKind::Unreachable)
+                }
+                _ => None,
+            };
+        }
+        // Dials time out after the dial timeout, and after the connection
+        // timeout of the swarm builder if that is shorter.
+        let timeout = matches!(error.downcast_ref(), Some(TransportTimeoutError::<io::Error>::Timeout))
+            || matches!(error.downcast_ref(), Some(TransportTimeoutError::<Either<io::Error, io::Error>>::Timeout));
+        if timeout {
+            return Some(DialErrorKind::Timeout);
+        }
+        if let Some(dns::Error::MultiaddrNotSupported(_)) = error.downcast_ref::<dns::Error<io::Error>>() {
+            return Some(DialErrorKind::Unsupported);
+        }
+        if let Some(error) = error.downcast_ref::<quic::Error>() {
+            return match error {
+                quic::Error::HandshakeTimedOut => Some(DialErrorKind::Timeout),
+                // The remote answered, but closed the connection or broke the
+                // protocol during the handshake.
+                quic::Error::Connection(_) => Some(DialErrorKind::NegotiationFailed),
+                _ => None,
+            };
+        }
+        if let Some(error) = error.downcast_ref::<ConnectingError>() {
+            return match error {
+                ConnectingError::ConnectionError(ConnectionError::TimedOut) => Some(DialErrorKind::Timeout),
+                ConnectingError::ConnectionError(_) | ConnectingError::SessionRejected => {
+                    Some(DialErrorKind::NegotiationFailed)
+                }
+                _ => None,
+            };
+        }
+        // The remote answered, but the security or multiplexer handshake failed,
+        // or a WebTransport dial didn't trust its certificate.
+        let handshake = error.is::<NegotiationError>()
+            || error.is::<rustls::Error>()
+            || error.is::<noise::Error>()
+            || error.is::<tls::UpgradeError>()
+            || error.is::<Either<tls::UpgradeError, noise::Error>>();
+        handshake.then_some(DialErrorKind::NegotiationFailed)
+    }
+
+    /// The error `error` wraps. I/O errors, which boxed transports wrap errors
+    /// into, the `Either`s of transports combined by the swarm builder and the
+    /// transparent errors of QUIC have the source of the error they wrap rather
+    /// than the error itself.
+    fn cause_of<'a>(error: &'a (dyn Error + 'static)) -> Option<&'a (dyn Error + 'static)> {
+        if let Some(error) = error.downcast_ref::<io::Error>() {
+            return error.get_ref().map(|inner| inner as &(dyn Error + 'static));
+        }
+        if let Some(either) = error.downcast_ref::<Either<io::Error, io::Error>>() {
+            return Some(either.as_ref().into_inner());
+        }
+        if let Some(quic::Error::Io(error)) = error.downcast_ref::<quic::Error>() {
+            return Some(error);
+        }
+        error.source()
+    }
+
+    /// How far a dial failing this way got, for picking among addresses.
+    fn progress(self) -> u8 {
+        match self {
+            DialErrorKind::WrongPeer | DialErrorKind::NegotiationFailed => 5,
+            DialErrorKind::Refused => 4,
+            DialErrorKind::Timeout => 3,
+            DialErrorKind::Unreachable => 2,
+            DialErrorKind::Denied | DialErrorKind::Other => 1,
+            DialErrorKind::Unsupported => 0,
+        }
+    }
+
+    /// The name of the kind, as shown in the output and the metrics.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            DialErrorKind::Refused => "refused",
+            DialErrorKind::Unreachable => "unreachable",
+            DialErrorKind::Timeout => "timeout",
+            DialErrorKind::WrongPeer => "wrong_peer",
+            DialErrorKind::NegotiationFailed => "negotiation_failed",
+            DialErrorKind::Unsupported => "unsupported",
+            DialErrorKind::Denied => "denied",
+            DialErrorKind::Other => "other",
+        }
+    }
+}
+
+impl fmt::Display for DialErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libp2p::core::ConnectedPoint;
+    use libp2p::core::Endpoint;
+    use libp2p::swarm::ConnectionDenied;
+    use libp2p::PeerId;
+
+    fn addr() -> Multiaddr {
+        "/ip4/192.0.2.1/tcp/4001".parse().expect("valid address")
+    }
+
+    /// The failure of a dial to `addr()` with `error`, wrapped like the boxed
+    /// transports wrap it.
+    fn failed(error: impl Error + Send + Sync + 'static) -> DialError {
+        DialError::Transport(vec![(addr(), TransportError::Other(io::Error::other(error)))])
+    }
+
+    /// The failure of a dial to one address per kind of I/O error.
+    fn failed_all(kinds: &[io::ErrorKind]) -> DialError {
+        DialError::Transport(
+            kinds.iter().map(|kind| (addr(), TransportError::Other(io::Error::from(*kind)))).collect(),
+        )
+    }
+
+    #[test]
+    fn io_error_kinds() {
+        let cases = [
+            (io::ErrorKind::ConnectionRefused, DialErrorKind::Refused),
+            (io::ErrorKind::TimedOut, DialErrorKind::Timeout),
+            (io::ErrorKind::HostUnreachable, DialErrorKind::Unreachable),
+            (io::ErrorKind::NetworkUnreachable, DialErrorKind::Unreachable),
+            (io::ErrorKind::NetworkDown, DialErrorKind::Unreachable),
+            (io::ErrorKind::PermissionDenied, DialErrorKind::Other),
+        ];
+        for (kind, expected) in cases {
+            assert_eq!(DialErrorKind::of(&failed(io::Error::from(kind))), expected, "{kind:?}");
+        }
+    }
+
+    #[test]
+    fn transport_errors() {
+        let refused = Either::<io::Error, io::Error>::Right(io::Error::from(io::ErrorKind::ConnectionRefused));
+        assert_eq!(DialErrorKind::of(&failed(refused)), DialErrorKind::Refused);
+        let unreachable = quic::Error::Io(io::Error::from(io::ErrorKind::NetworkUnreachable));
+        assert_eq!(DialErrorKind::of(&failed(unreachable)), DialErrorKind::Unreachable);
+        let timeout = TransportTimeoutError::<io::Error>::Timeout;
+        assert_eq!(DialErrorKind::of(&failed(timeout)), DialErrorKind::Timeout);
+        let timeout = TransportTimeoutError::<Either<io::Error, io::Error>>::Timeout;
+        assert_eq!(DialErrorKind::of(&failed(timeout)), DialErrorKind::Timeout);
+        let resolved = dns::Error::<io::Error>::MultiaddrNotSupported(addr());
+        assert_eq!(DialErrorKind::of(&failed(resolved)), DialErrorKind::Unsupported);
+    }
+
+    #[test]
+    fn handshake_failures() {
+        let errors: [Box<dyn Error + Send + Sync>; 5] = [
+            Box::new(NegotiationError::Failed),
+            Box::new(noise::Error::BadSignature),
+            Box::new(Either::<tls::UpgradeError, noise::Error>::Right(noise::Error::AuthenticationFailed)),
+            Box::new(ConnectingError::SessionRejected),
+            Box::new(rustls::Error::InvalidCertificate(rustls::CertificateError::UnknownIssuer)),
+        ];
+        for error in errors {
+            let message = error.to_string();
+            assert_eq!(DialErrorKind::of(&failed(io::Error::other(error))), DialErrorKind::NegotiationFailed, "{message}");
+        }
+    }
+
+    #[test]
+    fn handshake_timeouts() {
+        assert_eq!(DialErrorKind::of(&failed(quic::Error::HandshakeTimedOut)), DialErrorKind::Timeout);
+        let timed_out = ConnectingError::ConnectionError(ConnectionError::TimedOut);
+        assert_eq!(DialErrorKind::of(&failed(timed_out)), DialErrorKind::Timeout);
+    }
+
+    #[test]
+    fn hidden_errors_by_their_message() {
+        let refused = io::Error::other("dial failed: Connection refused (os error 111)");
+        assert_eq!(DialErrorKind::of(&failed(refused)), DialErrorKind::Refused);
+        assert_eq!(DialErrorKind::of(&failed(io::Error::other("no such thing"))), DialErrorKind::Other);
+    }
+
+    #[test]
+    fn dial_errors() {
+        let unsupported = DialError::Transport(vec![(addr(), TransportError::MultiaddrNotSupported(addr()))]);
+        assert_eq!(DialErrorKind::of(&unsupported), DialErrorKind::Unsupported);
+        let endpoint = ConnectedPoint::Dialer { address: addr(), role_override: Endpoint::Dialer };
+        let wrong_peer = DialError::WrongPeerId { obtained: PeerId::random(), endpoint };
+        assert_eq!(DialErrorKind::of(&wrong_peer), DialErrorKind::WrongPeer);
+        let denied = DialError::Denied { cause: ConnectionDenied::new("over the limit") };
+        assert_eq!(DialErrorKind::of(&denied), DialErrorKind::Denied);
+        assert_eq!(DialErrorKind::of(&DialError::NoAddresses), DialErrorKind::Other);
+        assert_eq!(DialErrorKind::of(&DialError::Transport(Vec::new())), DialErrorKind::Other);
+    }
+
+    #[test]
+    fn furthest_address_counts() {
+        use io::ErrorKind::{ConnectionRefused, HostUnreachable, PermissionDenied, TimedOut};
+        assert_eq!(DialErrorKind::of(&failed_all(&[HostUnreachable, ConnectionRefused, TimedOut])), DialErrorKind::Refused);
+        assert_eq!(DialErrorKind::of(&failed_all(&[HostUnreachable, TimedOut])), DialErrorKind::Timeout);
+        assert_eq!(DialErrorKind::of(&failed_all(&[PermissionDenied, HostUnreachable])), DialErrorKind::Unreachable);
+
+        let mut errors = vec![(addr(), TransportError::MultiaddrNotSupported(addr()))];
+        errors.push((addr(), TransportError::Other(io::Error::from(PermissionDenied))));
+        assert_eq!(DialErrorKind::of(&DialError::Transport(errors)), DialErrorKind::Other);
+
+        let DialError::Transport(mut errors) = failed_all(&[ConnectionRefused]) else { unreachable!() };
+        errors.push((addr(), TransportError::Other(io::Error::other(NegotiationError::Failed))));
+        assert_eq!(DialErrorKind::of(&DialError::Transport(errors)), DialErrorKind::NegotiationFailed);
+    }
+}
@@ -0,0 +1,114 @@
+//! Simplified events for embedders that don't need the full [`SwarmEvent`].
+
+use libp2p::swarm::{ConnectionId, SwarmEvent};
+use libp2p::{mdns, ping, rendezvous, Multiaddr, PeerId};
+use serde::{Serialize, Serializer};
+use std::fmt::Display;
+use std::time::Duration;
+
+use crate::BehaviourEvent;
+
+/// What happened to a [`PingNode`](crate::PingNode), as reported by
+/// [`PingNode::events`](crate::PingNode::events).
+///
+/// Serializes to JSON objects tagged with the `event` name, e.g.
+/// `{"event":"ping_success","peer_id":"12D3…","connection_id":"1","rtt_us":2041}`.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum PingEvent {
+    /// The node is listening on a new address.
+    ListenAddr { address: Multiaddr },
+    /// A peer has been discovered on the local network via mDNS, or at the
+    /// rendezvous point.
+    PeerDiscovered { peer_id: PeerId, address: Multiaddr },
+    /// A connection to a peer has been established.
+    Connected {
+        peer_id: PeerId,
+        #[serde(serialize_with = "display")]
+        connection_id: ConnectionId,
+        address: Multiaddr,
+        relayed: bool,
+    },
+    /// A peer answered a ping.
+    PingSuccess {
+        peer_id: PeerId,
+        #[serde(serialize_with = "display")]
+        connection_id: ConnectionId,
+        #[serde(rename = "rtt_us", serialize_with = "micros")]
+        rtt: Duration,
+    },
+    /// A peer didn't answer a ping within the timeout.
+    PingTimeout {
+        peer_id: PeerId,
+        #[serde(serialize_with = "display")]
+        connection_id: ConnectionId,
+    },
+    /// A ping failed for another reason, e.g. the peer doesn't support the protocol.
+    PingFailed {
+        peer_id: PeerId,
+        #[serde(serialize_with = "display")]
+        connection_id: ConnectionId,
+        error: String,
+    },
+    /// A connection to a peer has been closed, optionally because of an error.
+    Disconnected {
+        peer_id: PeerId,
+        #[serde(serialize_with = "display")]
+        connection_id: ConnectionId,
+        cause: Option<String>,
+    },
+}
+
+impl PingEvent {
+    /// Translates a swarm event into the ping events it stands for, if any.
+    ///
+    /// Useful next to [`PingNode::next_event`](crate::PingNode::next_event),
+    /// which hands out the swarm events themselves.
+    pub fn from_swarm(event: &SwarmEvent<BehaviourEvent>) -> Vec<Self> {
+        match event {
+            SwarmEvent::NewListenAddr { address, .. } => vec![Self::ListenAddr { address: address.clone() }],
+            SwarmEvent::Behaviour(BehaviourEvent::Mdns(mdns::Event::Discovered(discovered))) => discovered
+                .iter()
+                .map(|(peer_id, address)| Self::PeerDiscovered { peer_id: *peer_id, address: address.clone() })
+                .collect(),
+            SwarmEvent::Behaviour(BehaviourEvent::RendezvousClient(rendezvous::client::Event::Discovered {
+                registrations,
+                ..
+            })) => registrations
+                .iter()
+                .flat_map(|registration| {
+                    let peer_id = registration.record.peer_id();
+                    registration.record.addresses().iter().map(move |address| Self::PeerDiscovered { peer_id, address: address.clone() })
+                })
+                .collect(),
+            SwarmEvent::ConnectionEstablished { peer_id, connection_id, endpoint, .. } => vec![Self::Connected {
+                peer_id: *peer_id,
+                connection_id: *connection_id,
+                address: endpoint.get_remote_address().clone(),
+                relayed: endpoint.is_relayed(),
+            }],
+            SwarmEvent::Behaviour(BehaviourEvent::Ping(ping::Event { peer, connection, result })) => {
+                let (peer_id, connection_id) = (*peer, *connection);
+                vec![match result {
+                    Ok(rtt) => Self::PingSuccess { peer_id, connection_id, rtt: *rtt },
+                    Err(ping::Failure::Timeout) => Self::PingTimeout { peer_id, connection_id },
+                    Err(error) => Self::PingFailed { peer_id, connection_id, error: error.to_string() },
+                }]
+            }
+            SwarmEvent::ConnectionClosed { peer_id, connection_id, cause, .. } => vec![Self::Disconnected {
+                peer_id: *peer_id,
+                connection_id: *connection_id,
+                cause: cause.as_ref().map(ToString::to_string),
+            }],
+            _ => Vec::new(),
+        }
+    }
+}
+
+fn display<S: Serializer>(value: &impl Display, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(value)
+}
+
+fn micros<S: Serializer>(rtt: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_u64(rtt.as_micros() as u64)
+}
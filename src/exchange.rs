@@ -0,0 +1,241 @@
+//! Peer exchange protocol, through which a node asks a connected peer for the
+//! peers it is connected to, to reach the nodes beyond it as well.
+//!
+//! Every node answers with the listen addresses its peers reported via
+//! identify, so a single node to start from is enough to find the rest of a
+//! network. Only nodes with [`PeerExchange`] enabled ask, and dial the peers
+//! they learn of.
+
+use async_trait::async_trait;
+use futures::prelude::*;
+use libp2p::request_response::{self, OutboundFailure, ProtocolSupport};
+use libp2p::swarm::{FromSwarm, SwarmEvent};
+use libp2p::{Multiaddr, PeerId, StreamProtocol, Swarm};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io;
+
+use crate::forward::request_response_behaviour;
+use crate::{Behaviour as NodeBehaviour, BehaviourEvent};
+
+/// Protocol name of the peer exchange protocol.
+pub const PROTOCOL_NAME: StreamProtocol = StreamProtocol::new("/libp2p-ping-tut/peer-exchange/1.0.0");
+
+/// Most peers shared in one answer.
+pub const MAX_PEERS: usize = 64;
+
+/// Most addresses shared for each peer.
+const MAX_ADDRESSES: usize = 8;
+
+/// Largest answer read; longer ones are refused.
+const MAX_ANSWER_SIZE: u64 = 64 * 1024;
+
+/// How far and wide a node spreads out from its peers through peer exchange.
+#[derive(Debug, Clone, Copy)]
+pub struct PeerExchange {
+    /// How many hops beyond the peers connected to directly are dialed; 1 only
+    /// dials the peers of those.
+    pub depth: u32,
+    /// Most peers dialed from the answer of each peer.
+    pub fan_out: usize,
+}
+
+impl Default for PeerExchange {
+    fn default() -> Self {
+        Self { depth: 1, fan_out: 16 }
+    }
+}
+
+/// A peer known to the peer that was asked, as shared on the wire.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SharedPeer {
+    pub peer_id: PeerId,
+    pub addresses: Vec<Multiaddr>,
+}
+
+/// The answer of a peer asked with [`Behaviour::ask`].
+#[derive(Debug)]
+pub struct Event {
+    pub peer: PeerId,
+    pub result: Result<Vec<SharedPeer>, OutboundFailure>,
+}
+
+/// Answers the requests of every peer, and asks those passed to [`Self::ask`].
+pub struct Behaviour {
+    inner: request_response::Behaviour<Codec>,
+    /// Listen addresses of the connected peers, as reported via identify.
+    known: HashMap<PeerId, Vec<Multiaddr>>,
+}
+
+impl Default for Behaviour {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Behaviour {
+    pub fn new() -> Self {
+        Self {
+            inner: request_response::Behaviour::with_codec(
+                Codec,
+                [(PROTOCOL_NAME, ProtocolSupport::Full)],
+                request_response::Config::default(),
+            ),
+            known: HashMap::new(),
+        }
+    }
+
+    /// Asks a connected peer for its peers; the answer is reported in an
+    /// [`Event`].
+    pub fn ask(&mut self, peer: PeerId) {
+        self.inner.send_request(&peer, Request);
+    }
+
+    /// Records the listen addresses a connected peer reported, to share them
+    /// with others.
+    pub fn set_addresses(&mut self, peer: PeerId, addresses: &[Multiaddr]) {
+        self.known.insert(peer, addresses.iter().take(MAX_ADDRESSES).cloned().collect());
+    }
+
+    /// The peers shared with `enquirer`: all that reported their addresses,
+    /// except the enquirer itself.
+    fn shared_with(&self, enquirer: &PeerId) -> Vec<SharedPeer> {
+        self.known
+            .iter()
+            .filter(|(peer_id, addresses)| *peer_id != enquirer && !addresses.is_empty())
+            .take(MAX_PEERS)
+            .map(|(peer_id, addresses)| SharedPeer { peer_id: *peer_id, addresses: addresses.clone() })
+            .collect()
+    }
+
+    /// Answers requests and turns answers into events.
+    fn on_inner_event(&mut self, event: request_response::Event<Request, Vec<SharedPeer>>) -> Option<Event> {
+        match event {
+            request_response::Event::Message { peer, message } => match message {
+                request_response::Message::Request { channel, .. } => {
+                    // Fails only if the connection closed in the meantime.
+                    let _ = self.inner.send_response(channel, self.shared_with(&peer));
+                    None
+                }
+                request_response::Message::Response { response, .. } => Some(Event { peer, result: Ok(response) }),
+            },
+            request_response::Event::OutboundFailure { peer, error, .. } => Some(Event { peer, result: Err(error) }),
+            _ => None,
+        }
+    }
+    /// Stops sharing the addresses of a peer once its last connection closes.
+    fn forget_peers(&mut self, event: FromSwarm) {
+        if let FromSwarm::ConnectionClosed(closed) = event {
+            if closed.remaining_established == 0 {
+                self.known.remove(&closed.peer_id);
+            }
+        }
+    }
+}
+
+request_response_behaviour!(Behaviour, Codec, Event, on_swarm_event: forget_peers);
+
+/// A request for the peers of the remote, which carries nothing.
+#[derive(Debug, Clone, Copy)]
+pub struct Request;
+
+/// Reads and writes requests, which are empty, and answers, which are JSON
+/// lists of [`SharedPeer`]s.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Codec;
+
+#[async_trait]
+impl request_response::Codec for Codec {
+    type Protocol = StreamProtocol;
+    type Request = Request;
+    type Response = Vec<SharedPeer>;
+
+    async fn read_request<T>(&mut self, _: &StreamProtocol, _: &mut T) -> io::Result<Request>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        Ok(Request)
+    }
+
+    async fn read_response<T>(&mut self, _: &StreamProtocol, io: &mut T) -> io::Result<Vec<SharedPeer>>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        let mut answer = Vec::new();
+        io.take(MAX_ANSWER_SIZE + 1).read_to_end(&mut answer).await?;
+        if answer.len() as u64 > MAX_ANSWER_SIZE {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "peer exchange answer too large"));
+        }
+        let mut peers: Vec<SharedPeer> = serde_json::from_slice(&answer)?;
+        peers.truncate(MAX_PEERS);
+        Ok(peers)
+    }
+
+    async fn write_request<T>(&mut self, _: &StreamProtocol, io: &mut T, _: Request) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        io.close().await
+    }
+
+    async fn write_response<T>(&mut self, _: &StreamProtocol, io: &mut T, peers: Vec<SharedPeer>) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        io.write_all(&serde_json::to_vec(&peers)?).await?;
+        io.close().await
+    }
+}
+
+/// Asks peers for their peers and picks those to dial, up to the configured
+/// depth and fan-out.
+pub(crate) struct Spread {
+    config: PeerExchange,
+    /// Hops from the peers connected to directly, of each peer learned of
+    /// through peer exchange; the others are those peers themselves.
+    hops: HashMap<PeerId, u32>,
+}
+
+impl Spread {
+    pub(crate) fn new(config: PeerExchange) -> Self {
+        Self { config, hops: HashMap::new() }
+    }
+
+    /// Asks newly connected peers short of the depth for their peers, and
+    /// returns the addresses of those to dial from their answers.
+    pub(crate) fn observe(&mut self, swarm: &mut Swarm<NodeBehaviour>, event: &SwarmEvent<BehaviourEvent>) -> Vec<(PeerId, Multiaddr)> {
+        match event {
+            SwarmEvent::ConnectionEstablished { peer_id, num_established, .. }
+                if num_established.get() == 1 && self.hops(peer_id) < self.config.depth =>
+            {
+                swarm.behaviour_mut().exchange.ask(*peer_id);
+            }
+            SwarmEvent::Behaviour(BehaviourEvent::Exchange(Event { peer, result: Ok(peers) })) => {
+                let hops = self.hops(peer) + 1;
+                let local_peer_id = *swarm.local_peer_id();
+                let new: Vec<&SharedPeer> = peers
+                    .iter()
+                    .filter(|shared| {
+                        shared.peer_id != local_peer_id
+                            && !self.hops.contains_key(&shared.peer_id)
+                            && !swarm.is_connected(&shared.peer_id)
+                    })
+                    .take(self.config.fan_out)
+                    .collect();
+                for shared in &new {
+                    self.hops.insert(shared.peer_id, hops);
+                }
+                return new
+                    .into_iter()
+                    .flat_map(|shared| shared.addresses.iter().map(|addr| (shared.peer_id, addr.clone())))
+                    .collect();
+            }
+            _ => {}
+        }
+        Vec::new()
+    }
+
+    fn hops(&self, peer_id: &PeerId) -> u32 {
+        self.hops.get(peer_id).copied().unwrap_or(0)
+    }
+}
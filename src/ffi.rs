@@ -0,0 +1,232 @@
+//! C bindings of the [`PingNode`], built with the `ffi` feature and declared
+//! in `include/ping_node.h`.
+//!
+//! Each node runs its connections on a Tokio runtime of its own, but the node
+//! itself, which handles and reports what they do, only runs while
+//! [`ping_node_poll_event`] is being called, so embedders call it in a loop,
+//! e.g. on a thread of its own. A node must not be used from two threads at
+//! once.
+
+use libp2p::Multiaddr;
+use std::collections::VecDeque;
+use std::ffi::{c_char, c_int, CStr, CString};
+use std::time::Duration;
+use tokio::runtime::Runtime;
+
+use crate::{PingEvent, PingNode};
+
+/// How long [`ping_node_free`] waits for connections to close.
+const SHUTDOWN_GRACE: Duration = Duration::from_secs(1);
+
+/// A node as handed out to C, `ping_node_t` in the header.
+pub struct PingNodeHandle {
+    // Dropped before the runtime its connections run on.
+    node: PingNode,
+    runtime: Runtime,
+    /// Events translated from one swarm event but not yet polled.
+    queued: VecDeque<PingEvent>,
+    /// Message for `ping_node_last_error`.
+    error: CString,
+}
+
+impl PingNodeHandle {
+    fn fail(&mut self, error: impl ToString) -> c_int {
+        self.error = CString::new(error.to_string().replace('\0', " ")).expect("NUL bytes are replaced");
+        -1
+    }
+}
+
+/// What a [`PingNodeEvent`] reports, `ping_node_event_kind` in the header.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PingNodeEventKind {
+    ListenAddr = 1,
+    PeerDiscovered = 2,
+    Connected = 3,
+    PingSuccess = 4,
+    PingTimeout = 5,
+    PingFailed = 6,
+    Disconnected = 7,
+}
+
+/// Longest PeerId, address and error of a [`PingNodeEvent`]; longer ones are
+/// cut off.
+const PEER_ID_LEN: usize = 64;
+const ADDRESS_LEN: usize = 256;
+const ERROR_LEN: usize = 256;
+
+/// An event of a node, `ping_node_event` in the header; see [`PingEvent`] for
+/// which fields each kind sets. Unset strings are empty.
+#[repr(C)]
+pub struct PingNodeEvent {
+    pub kind: PingNodeEventKind,
+    pub rtt_us: u64,
+    pub relayed: bool,
+    pub peer_id: [c_char; PEER_ID_LEN],
+    pub address: [c_char; ADDRESS_LEN],
+    pub error: [c_char; ERROR_LEN],
+}
+
+impl PingNodeEvent {
+    fn new(event: &PingEvent) -> Self {
+        let mut this = Self {
+            kind: PingNodeEventKind::ListenAddr,
+            rtt_us: 0,
+            relayed: false,
+            peer_id: [0; PEER_ID_LEN],
+            address: [0; ADDRESS_LEN],
+            error: [0; ERROR_LEN],
+        };
+        match event {
+            PingEvent::ListenAddr { address } => {
+                copy(&mut this.address, &address.to_string());
+            }
+            PingEvent::PeerDiscovered { peer_id, address } => {
+                this.kind = PingNodeEventKind::PeerDiscovered;
+                copy(&mut this.peer_id, &peer_id.to_string());
+                copy(&mut this.address, &address.to_string());
+            }
+            PingEvent::Connected { peer_id, address, relayed, .. } => {
+                this.kind = PingNodeEventKind::Connected;
+                this.relayed = *relayed;
+                copy(&mut this.peer_id, &peer_id.to_string());
+                copy(&mut this.address, &address.to_string());
+            }
+            PingEvent::PingSuccess { peer_id, rtt, .. } => {
+                this.kind = PingNodeEventKind::PingSuccess;
+                this.rtt_us = rtt.as_micros() as u64;
+                copy(&mut this.peer_id, &peer_id.to_string());
+            }
+            PingEvent::PingTimeout { peer_id, .. } => {
+                this.kind = PingNodeEventKind::PingTimeout;
+                copy(&mut this.peer_id, &peer_id.to_string());
+            }
+            PingEvent::PingFailed { peer_id, error, .. } => {
+                this.kind = PingNodeEventKind::PingFailed;
+                copy(&mut this.peer_id, &peer_id.to_string());
+                copy(&mut this.error, error);
+            }
+            PingEvent::Disconnected { peer_id, cause, .. } => {
+                this.kind = PingNodeEventKind::Disconnected;
+                copy(&mut this.peer_id, &peer_id.to_string());
+                copy(&mut this.error, cause.as_deref().unwrap_or_default());
+            }
+        }
+        this
+    }
+}
+
+/// Copies `s` into `dest` as a NUL-terminated string, cut off at a character
+/// boundary if it doesn't fit.
+fn copy(dest: &mut [c_char], s: &str) {
+    let mut len = s.len().min(dest.len() - 1);
+    while !s.is_char_boundary(len) {
+        len -= 1;
+    }
+    for (dest, byte) in dest.iter_mut().zip(&s.as_bytes()[..len]) {
+        *dest = *byte as c_char;
+    }
+    dest[len] = 0;
+}
+
+/// Creates a node with a random identity and the default settings, or returns
+/// NULL if that fails.
+#[no_mangle]
+pub extern "C" fn ping_node_new() -> *mut PingNodeHandle {
+    let Ok(runtime) = tokio::runtime::Builder::new_multi_thread().worker_threads(1).enable_all().build() else {
+        return std::ptr::null_mut();
+    };
+    let node = {
+        let _runtime = runtime.enter();
+        PingNode::new()
+    };
+    match node {
+        Ok(node) => Box::into_raw(Box::new(PingNodeHandle {
+            node,
+            runtime,
+            queued: VecDeque::new(),
+            error: CString::default(),
+        })),
+        Err(_) => std::ptr::null_mut(),
+    }
+}
+
+/// Dials the peer at `multiaddr` and pings it once connected. Returns 0, or
+/// -1 if the address is invalid or can't be dialed.
+///
+/// # Safety
+///
+/// `node` must come from [`ping_node_new`] and `multiaddr` be a
+/// NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn ping_node_dial(node: *mut PingNodeHandle, multiaddr: *const c_char) -> c_int {
+    let node = &mut *node;
+    let addr: Multiaddr = match CStr::from_ptr(multiaddr).to_str().map(str::parse) {
+        Ok(Ok(addr)) => addr,
+        Ok(Err(e)) => return node.fail(e),
+        Err(e) => return node.fail(e),
+    };
+    let _runtime = node.runtime.enter();
+    match node.node.dial(addr) {
+        Ok(_) => 0,
+        Err(e) => node.fail(e),
+    }
+}
+
+/// Waits up to `timeout_ms` for the next event and writes it to `event`.
+/// Returns 1 if it did, or 0 if the time ran out.
+///
+/// # Safety
+///
+/// `node` must come from [`ping_node_new`] and `event` point to writable
+/// memory for a [`PingNodeEvent`].
+#[no_mangle]
+pub unsafe extern "C" fn ping_node_poll_event(node: *mut PingNodeHandle, event: *mut PingNodeEvent, timeout_ms: u32) -> c_int {
+    let node = &mut *node;
+    if node.queued.is_empty() {
+        let timeout = Duration::from_millis(timeout_ms.into());
+        let PingNodeHandle { runtime, node: ping_node, queued, .. } = node;
+        runtime.block_on(async {
+            let _ = tokio::time::timeout(timeout, async {
+                while queued.is_empty() {
+                    queued.extend(PingEvent::from_swarm(&ping_node.next_event().await));
+                }
+            })
+            .await;
+        });
+    }
+    match node.queued.pop_front() {
+        Some(next) => {
+            event.write(PingNodeEvent::new(&next));
+            1
+        }
+        None => 0,
+    }
+}
+
+/// Returns the error of the last call on `node` that returned -1. The string
+/// stays valid until the next call on `node`.
+///
+/// # Safety
+///
+/// `node` must come from [`ping_node_new`].
+#[no_mangle]
+pub unsafe extern "C" fn ping_node_last_error(node: *const PingNodeHandle) -> *const c_char {
+    (*node).error.as_ptr()
+}
+
+/// Closes the connections of `node`, waiting up to a second for them, and
+/// frees it. NULL is ignored.
+///
+/// # Safety
+///
+/// `node` must come from [`ping_node_new`] and not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn ping_node_free(node: *mut PingNodeHandle) {
+    if node.is_null() {
+        return;
+    }
+    let mut node = Box::from_raw(node);
+    let PingNodeHandle { runtime, node: ping_node, .. } = &mut *node;
+    runtime.block_on(ping_node.shutdown(SHUTDOWN_GRACE));
+}
@@ -0,0 +1,104 @@
+//! Forwarding of the [`NetworkBehaviour`](libp2p::swarm::NetworkBehaviour) of
+//! the protocols built on [`request_response`](libp2p::request_response) to the
+//! request-response behaviour they wrap.
+
+/// Implements [`NetworkBehaviour`](libp2p::swarm::NetworkBehaviour) for
+/// `$behaviour`, forwarding everything to the `inner`
+/// [`request_response::Behaviour`](libp2p::request_response::Behaviour) of
+/// `$codec`. Its events go through the `on_inner_event` method of
+/// `$behaviour`, which answers requests and returns the `$event`s to report.
+///
+/// The methods given as `on_swarm_event` and `poll` run after those of
+/// `inner` and before it is polled, respectively, e.g. to track the connected
+/// peers and send them requests.
+macro_rules! request_response_behaviour {
+    ($behaviour:ty, $codec:ty, $event:ty $(, on_swarm_event: $on_swarm_event:ident)? $(, poll: $poll:ident)? $(,)?) => {
+        // In a block of its own for the imports.
+        const _: () = {
+            use libp2p::core::Endpoint;
+            use libp2p::swarm::{
+                ConnectionDenied, ConnectionId, FromSwarm, NetworkBehaviour, THandler, THandlerInEvent,
+                THandlerOutEvent, ToSwarm,
+            };
+            use libp2p::{request_response, Multiaddr, PeerId};
+            use std::task::{Context, Poll};
+
+            impl NetworkBehaviour for $behaviour {
+                type ConnectionHandler = THandler<request_response::Behaviour<$codec>>;
+                type ToSwarm = $event;
+
+                fn handle_pending_inbound_connection(
+                    &mut self,
+                    connection_id: ConnectionId,
+                    local_addr: &Multiaddr,
+                    remote_addr: &Multiaddr,
+                ) -> Result<(), ConnectionDenied> {
+                    self.inner.handle_pending_inbound_connection(connection_id, local_addr, remote_addr)
+                }
+
+                fn handle_established_inbound_connection(
+                    &mut self,
+                    connection_id: ConnectionId,
+                    peer: PeerId,
+                    local_addr: &Multiaddr,
+                    remote_addr: &Multiaddr,
+                ) -> Result<THandler<Self>, ConnectionDenied> {
+                    self.inner.handle_established_inbound_connection(connection_id, peer, local_addr, remote_addr)
+                }
+
+                fn handle_pending_outbound_connection(
+                    &mut self,
+                    connection_id: ConnectionId,
+                    maybe_peer: Option<PeerId>,
+                    addresses: &[Multiaddr],
+                    effective_role: Endpoint,
+                ) -> Result<Vec<Multiaddr>, ConnectionDenied> {
+                    self.inner.handle_pending_outbound_connection(connection_id, maybe_peer, addresses, effective_role)
+                }
+
+                fn handle_established_outbound_connection(
+                    &mut self,
+                    connection_id: ConnectionId,
+                    peer: PeerId,
+                    addr: &Multiaddr,
+                    role_override: Endpoint,
+                ) -> Result<THandler<Self>, ConnectionDenied> {
+                    self.inner
+                        .handle_established_outbound_connection(connection_id, peer, addr, role_override)
+                }
+
+                fn on_swarm_event(&mut self, event: FromSwarm) {
+                    self.inner.on_swarm_event(event);
+                    $(self.$on_swarm_event(event);)?
+                }
+
+                fn on_connection_handler_event(
+                    &mut self,
+                    peer: PeerId,
+                    connection_id: ConnectionId,
+                    event: THandlerOutEvent<Self>,
+                ) {
+                    self.inner.on_connection_handler_event(peer, connection_id, event);
+                }
+
+                fn poll(&mut self, cx: &mut Context<'_>) -> Poll<ToSwarm<Self::ToSwarm, THandlerInEvent<Self>>> {
+                    $(self.$poll(cx);)?
+                    // The inner behaviour doesn't register for wake-ups, so drain it fully.
+                    while let Poll::Ready(event) = self.inner.poll(cx) {
+                        match event {
+                            ToSwarm::GenerateEvent(event) => {
+                                if let Some(event) = self.on_inner_event(event) {
+                                    return Poll::Ready(ToSwarm::GenerateEvent(event));
+                                }
+                            }
+                            event => return Poll::Ready(event.map_out(|_| unreachable!("events are handled above"))),
+                        }
+                    }
+                    Poll::Pending
+                }
+            }
+        };
+    };
+}
+
+pub(crate) use request_response_behaviour;
@@ -0,0 +1,154 @@
+//! gRPC control API of a node (`--grpc`), built with the `grpc` feature from
+//! `proto/ping.proto`, for orchestration systems that already manage their
+//! fleets over gRPC. It offers what the REST API does, minus the dashboard.
+
+use futures::stream::{self, BoxStream};
+use libp2p_ping_tut::PingEvent;
+use std::error::Error;
+use std::time::SystemTime;
+use tokio::net::TcpListener;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::{broadcast, mpsc};
+use tonic::transport::server::TcpIncoming;
+use tonic::Status;
+
+use crate::control::{self, Command, Request};
+
+mod proto {
+    tonic::include_proto!("libp2p_ping_tut.v1");
+}
+
+use proto::ping_control_server::{PingControl, PingControlServer};
+use proto::{ping_result, PingResult};
+
+/// What the service's handlers share.
+struct Service {
+    commands: mpsc::Sender<Command>,
+    events: broadcast::Sender<PingEvent>,
+}
+
+/// Serves the `PingControl` service on `listener` until the process exits,
+/// handing the requests to the node through `commands` and streaming ping
+/// results from its `events`.
+pub async fn serve(
+    listener: TcpListener,
+    commands: mpsc::Sender<Command>,
+    events: broadcast::Sender<PingEvent>,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let incoming = TcpIncoming::from_listener(listener, true, None)?;
+    tonic::transport::Server::builder()
+        .add_service(PingControlServer::new(Service { commands, events }))
+        .serve_with_incoming(incoming)
+        .await?;
+    Ok(())
+}
+
+#[tonic::async_trait]
+impl PingControl for Service {
+    type StreamPingResultsStream = BoxStream<'static, Result<PingResult, Status>>;
+
+    async fn add_peer(
+        &self,
+        request: tonic::Request<proto::AddPeerRequest>,
+    ) -> Result<tonic::Response<proto::AddPeerResponse>, Status> {
+        let peer = request.into_inner().peer;
+        match control::forward(&self.commands, Request::AddPeer { peer }).await {
+            control::Response::Ok => Ok(tonic::Response::new(proto::AddPeerResponse {})),
+            control::Response::Error { message } => Err(Status::invalid_argument(message)),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    async fn remove_peer(
+        &self,
+        request: tonic::Request<proto::RemovePeerRequest>,
+    ) -> Result<tonic::Response<proto::RemovePeerResponse>, Status> {
+        let peer = request.into_inner().peer;
+        match control::forward(&self.commands, Request::RemovePeer { peer }).await {
+            control::Response::Ok => Ok(tonic::Response::new(proto::RemovePeerResponse {})),
+            control::Response::NotFound { message } => Err(Status::not_found(message)),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    async fn stream_ping_results(
+        &self,
+        _: tonic::Request<proto::StreamPingResultsRequest>,
+    ) -> Result<tonic::Response<Self::StreamPingResultsStream>, Status> {
+        let results = stream::unfold(self.events.subscribe(), |mut events| async move {
+            loop {
+                match events.recv().await {
+                    Ok(event) => {
+                        if let Some(result) = ping_result(&event) {
+                            return Some((Ok(result), events));
+                        }
+                    }
+                    // Clients too slow to keep up miss results rather than holding up the node.
+                    Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => return None,
+                }
+            }
+        });
+        Ok(tonic::Response::new(Box::pin(results)))
+    }
+
+    async fn get_stats(
+        &self,
+        _: tonic::Request<proto::GetStatsRequest>,
+    ) -> Result<tonic::Response<proto::GetStatsResponse>, Status> {
+        match control::forward(&self.commands, Request::Stats).await {
+            control::Response::Stats { targets } => Ok(tonic::Response::new(proto::GetStatsResponse {
+                targets: targets.into_iter().map(target_stats).collect(),
+            })),
+            response => Err(unexpected(response)),
+        }
+    }
+}
+
+/// Returns the result of a ping that `event` reports, if it does.
+fn ping_result(event: &PingEvent) -> Option<PingResult> {
+    let (peer_id, connection_id, result) = match event {
+        PingEvent::PingSuccess { peer_id, connection_id, rtt } => {
+            (peer_id, connection_id, ping_result::Result::RttUs(rtt.as_micros() as u64))
+        }
+        PingEvent::PingTimeout { peer_id, connection_id } => {
+            (peer_id, connection_id, ping_result::Result::Timeout(ping_result::Timeout {}))
+        }
+        PingEvent::PingFailed { peer_id, connection_id, error } => {
+            (peer_id, connection_id, ping_result::Result::Error(error.clone()))
+        }
+        _ => return None,
+    };
+    let unix_time_ms = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |since| since.as_millis() as u64);
+    Some(PingResult {
+        peer_id: peer_id.to_string(),
+        connection_id: connection_id.to_string(),
+        result: Some(result),
+        unix_time_ms,
+    })
+}
+
+fn target_stats(stats: control::TargetStats) -> proto::TargetStats {
+    proto::TargetStats {
+        target: stats.target,
+        address: stats.address,
+        peer_id: stats.peer_id.unwrap_or_default(),
+        connected: stats.connected,
+        health: stats.health.unwrap_or_default(),
+        transmitted: stats.transmitted,
+        received: stats.received,
+        loss_percent: stats.loss_percent,
+        avg_us: stats.avg_us,
+    }
+}
+
+/// Answers a response that doesn't fit the request, e.g. an error because the
+/// node is shutting down.
+fn unexpected(response: control::Response) -> Status {
+    match response {
+        control::Response::Error { message } => Status::unavailable(message),
+        response => Status::internal(format!("unexpected answer {response:?}")),
+    }
+}
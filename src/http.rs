@@ -0,0 +1,202 @@
+//! Embedded HTTP servers exposing the node's Prometheus metrics and a REST API
+//! to manage its peers.
+
+use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
+use axum::extract::{Path, State};
+use axum::http::{header, StatusCode};
+use axum::response::{Html, IntoResponse, Response};
+use axum::routing::{delete, get, post};
+use axum::{Json, Router};
+use libp2p::metrics::Registry;
+use libp2p_ping_tut::PingEvent;
+use prometheus_client::encoding::text::encode;
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::sync::Arc;
+use std::time::SystemTime;
+use tokio::net::TcpListener;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::{broadcast, mpsc};
+
+use crate::control::{self, Command, Request};
+
+/// Content type of the OpenMetrics text exposition format.
+const OPENMETRICS_CONTENT_TYPE: &str = "application/openmetrics-text; version=1.0.0; charset=utf-8";
+
+/// Serves `GET /metrics` on `listener` until the process exits.
+pub async fn serve(listener: TcpListener, registry: Arc<Registry>) -> io::Result<()> {
+    let app = Router::new().route("/metrics", get(metrics)).with_state(registry);
+    axum::serve(listener, app).await
+}
+
+/// Encodes the current value of every registered metric.
+async fn metrics(State(registry): State<Arc<Registry>>) -> Response {
+    let mut body = String::new();
+    match encode(&mut body, &registry) {
+        Ok(()) => ([(header::CONTENT_TYPE, OPENMETRICS_CONTENT_TYPE)], body).into_response(),
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+}
+
+/// What the API handlers share.
+#[derive(Clone)]
+struct Api {
+    commands: mpsc::Sender<Command>,
+    events: broadcast::Sender<PingEvent>,
+}
+
+/// A message of the `/events` WebSocket.
+#[derive(Serialize)]
+struct EventMessage<'a> {
+    timestamp: String,
+    #[serde(flatten)]
+    event: &'a PingEvent,
+}
+
+/// The web dashboard, fed by `/peers` and `/events`.
+const DASHBOARD: &str = include_str!("dashboard.html");
+
+/// Body of `POST /peers`.
+#[derive(Deserialize)]
+struct NewPeer {
+    /// The peer to ping as `[NAME=]MULTIADDR`.
+    peer: String,
+}
+
+/// Body of error responses.
+#[derive(Serialize)]
+struct ApiError {
+    error: String,
+}
+
+/// Serves the REST API on `listener` until the process exits, handing the
+/// requests to the node through `commands`:
+///
+/// - `GET /peers` lists every target with its ping statistics.
+/// - `GET /peers/{id}/stats` returns those of the target with this name,
+///   URL-encoded address or PeerId.
+/// - `POST /peers` with `{"peer": "[NAME=]MULTIADDR"}` dials a new target.
+/// - `DELETE /peers/{id}` stops pinging a target and disconnects from it.
+/// - `POST /peers/{id}/pause` and `POST /peers/{id}/resume` suspend and
+///   resume pinging a target, keeping its connections and statistics;
+///   `POST /pause` and `POST /resume` do so for every peer.
+/// - `GET /events` upgrades to a WebSocket streaming the node's `events` as
+///   JSON [`PingEvent`]s, one per text message.
+/// - `GET /` is a web dashboard of the peers with live RTT charts.
+pub async fn serve_api(
+    listener: TcpListener,
+    commands: mpsc::Sender<Command>,
+    events: broadcast::Sender<PingEvent>,
+) -> io::Result<()> {
+    let app = Router::new()
+        .route("/peers", get(list_peers).post(add_peer))
+        .route("/peers/:id", delete(remove_peer))
+        .route("/peers/:id/stats", get(peer_stats))
+        .route("/peers/:id/pause", post(pause_peer))
+        .route("/peers/:id/resume", post(resume_peer))
+        .route("/pause", post(pause_all))
+        .route("/resume", post(resume_all))
+        .route("/events", get(subscribe))
+        .route("/", get(|| async { Html(DASHBOARD) }))
+        .with_state(Api { commands, events });
+    axum::serve(listener, app).await
+}
+
+async fn list_peers(State(api): State<Api>) -> Response {
+    match control::forward(&api.commands, Request::Stats).await {
+        control::Response::Stats { targets } => Json(targets).into_response(),
+        response => unexpected(response),
+    }
+}
+
+async fn peer_stats(State(api): State<Api>, Path(id): Path<String>) -> Response {
+    match control::forward(&api.commands, Request::Stats).await {
+        control::Response::Stats { targets } => {
+            let mut targets = targets.into_iter();
+            match targets.find(|t| t.target == id || t.address == id || t.peer_id.as_deref() == Some(&id)) {
+                Some(stats) => Json(stats).into_response(),
+                None => error(StatusCode::NOT_FOUND, format!("not pinging {id}")),
+            }
+        }
+        response => unexpected(response),
+    }
+}
+
+async fn add_peer(State(api): State<Api>, Json(new): Json<NewPeer>) -> Response {
+    match control::forward(&api.commands, Request::AddPeer { peer: new.peer }).await {
+        control::Response::Ok => StatusCode::CREATED.into_response(),
+        control::Response::Error { message } => error(StatusCode::BAD_REQUEST, message),
+        response => unexpected(response),
+    }
+}
+
+async fn remove_peer(State(api): State<Api>, Path(id): Path<String>) -> Response {
+    match control::forward(&api.commands, Request::RemovePeer { peer: id }).await {
+        control::Response::Ok => StatusCode::NO_CONTENT.into_response(),
+        control::Response::NotFound { message } => error(StatusCode::NOT_FOUND, message),
+        response => unexpected(response),
+    }
+}
+
+async fn pause_peer(State(api): State<Api>, Path(id): Path<String>) -> Response {
+    paused(control::forward(&api.commands, Request::Pause { peer: Some(id) }).await)
+}
+
+async fn resume_peer(State(api): State<Api>, Path(id): Path<String>) -> Response {
+    paused(control::forward(&api.commands, Request::Resume { peer: Some(id) }).await)
+}
+
+async fn pause_all(State(api): State<Api>) -> Response {
+    paused(control::forward(&api.commands, Request::Pause { peer: None }).await)
+}
+
+async fn resume_all(State(api): State<Api>) -> Response {
+    paused(control::forward(&api.commands, Request::Resume { peer: None }).await)
+}
+
+/// Answers a pause or resume request.
+fn paused(response: control::Response) -> Response {
+    match response {
+        control::Response::Ok => StatusCode::NO_CONTENT.into_response(),
+        control::Response::NotFound { message } => error(StatusCode::NOT_FOUND, message),
+        response => unexpected(response),
+    }
+}
+
+async fn subscribe(State(api): State<Api>, upgrade: WebSocketUpgrade) -> Response {
+    let events = api.events.subscribe();
+    upgrade.on_upgrade(|socket| stream_events(socket, events))
+}
+
+/// Sends each event to the client until it goes away.
+async fn stream_events(mut socket: WebSocket, mut events: broadcast::Receiver<PingEvent>) {
+    loop {
+        let event = match events.recv().await {
+            Ok(event) => event,
+            // Clients too slow to keep up miss events rather than holding up the node.
+            Err(RecvError::Lagged(_)) => continue,
+            Err(RecvError::Closed) => break,
+        };
+        let message = EventMessage {
+            timestamp: humantime::format_rfc3339_micros(SystemTime::now()).to_string(),
+            event: &event,
+        };
+        let text = serde_json::to_string(&message).expect("events serialize to JSON");
+        if socket.send(Message::Text(text)).await.is_err() {
+            break;
+        }
+    }
+}
+
+fn error(status: StatusCode, message: String) -> Response {
+    (status, Json(ApiError { error: message })).into_response()
+}
+
+/// Answers a response that doesn't fit the request, e.g. an error because the
+/// node is shutting down.
+fn unexpected(response: control::Response) -> Response {
+    match response {
+        control::Response::Error { message } => error(StatusCode::SERVICE_UNAVAILABLE, message),
+        response => error(StatusCode::INTERNAL_SERVER_ERROR, format!("unexpected answer {response:?}")),
+    }
+}
@@ -0,0 +1,60 @@
+//! Loading and storing node identities and private network keys on disk.
+//!
+//! Keypairs are stored in libp2p's protobuf encoding so the files stay
+//! compatible with other libp2p tooling.
+
+use libp2p::identity::Keypair;
+use libp2p::pnet::PreSharedKey;
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+
+use crate::PingError;
+
+/// Reads a keypair previously written with [`write`].
+pub fn read(path: &Path) -> Result<Keypair, PingError> {
+    let bytes = fs::read(path).map_err(|e| PingError::identity(path, e))?;
+    Keypair::from_protobuf_encoding(&bytes).map_err(|e| PingError::identity(path, e))
+}
+
+/// Writes `keypair` to `path`, refusing to overwrite an existing file.
+///
+/// On Unix the file is created readable by its owner only.
+pub fn write(path: &Path, keypair: &Keypair) -> Result<(), PingError> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+
+    let encoded = keypair.to_protobuf_encoding().map_err(|e| PingError::identity(path, e))?;
+    let mut file = options.open(path).map_err(|e| PingError::identity(path, e))?;
+    file.write_all(&encoded).map_err(|e| PingError::identity(path, e))
+}
+
+/// Reads a private network key in the `swarm.key` format shared with
+/// go-libp2p and IPFS:
+///
+/// ```text
+/// /key/swarm/psk/1.0.0/
+/// /base16/
+/// <64 hex digits>
+/// ```
+pub fn read_psk(path: &Path) -> Result<PreSharedKey, PingError> {
+    let text = fs::read_to_string(path).map_err(|e| PingError::identity(path, e))?;
+    text.parse().map_err(|e| PingError::identity(path, e))
+}
+
+/// Loads the keypair at `path`, generating and storing a new Ed25519 keypair
+/// if the file does not exist yet.
+pub fn load_or_generate(path: &Path) -> Result<Keypair, PingError> {
+    if path.exists() {
+        return read(path);
+    }
+
+    let keypair = Keypair::generate_ed25519();
+    write(path, &keypair)?;
+    Ok(keypair)
+}
@@ -0,0 +1,55 @@
+//! Key/value labels describing a node, such as its region or role.
+//!
+//! Labels are carried in the identify agent version after the name and
+//! version of the crate, e.g. `libp2p-ping-tut/0.1.0 region=eu-west role=edge`,
+//! so that peers learn them without another protocol.
+
+use std::collections::BTreeMap;
+
+/// Labels by key, in the order they are displayed.
+pub type Labels = BTreeMap<String, String>;
+
+/// Checks that a label can be carried in the agent version: neither the key
+/// nor the value may be empty or contain whitespace, and the key may not
+/// contain `=`.
+pub fn check(key: &str, value: &str) -> Result<(), String> {
+    if key.is_empty() || value.is_empty() {
+        return Err("label keys and values must not be empty".into());
+    }
+    if key.contains('=') {
+        return Err(format!("label key `{key}` must not contain `=`"));
+    }
+    if key.contains(char::is_whitespace) || value.contains(char::is_whitespace) {
+        return Err(format!("label `{key}={value}` must not contain whitespace"));
+    }
+    Ok(())
+}
+
+/// Returns `agent_version` followed by `labels`.
+pub(crate) fn agent_version(agent_version: &str, labels: &Labels) -> String {
+    labels
+        .iter()
+        .fold(agent_version.to_owned(), |agent_version, (key, value)| format!("{agent_version} {key}={value}"))
+}
+
+/// Returns the labels carried in the agent version of a peer; peers running
+/// other software usually have none.
+pub fn parse(agent_version: &str) -> Labels {
+    agent_version
+        .split_whitespace()
+        .skip(1)
+        .filter_map(|word| word.split_once('='))
+        .filter(|(key, value)| check(key, value).is_ok())
+        .map(|(key, value)| (key.to_owned(), value.to_owned()))
+        .collect()
+}
+
+/// Formats `labels` to be shown after a peer, e.g. ` [region=eu-west role=edge]`,
+/// or nothing if there are none.
+pub fn suffix(labels: &Labels) -> String {
+    if labels.is_empty() {
+        return String::new();
+    }
+    let labels: Vec<String> = labels.iter().map(|(key, value)| format!("{key}={value}")).collect();
+    format!(" [{}]", labels.join(" "))
+}
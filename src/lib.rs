@@ -0,0 +1,115 @@
+//! # Reusable Libp2p Ping Node
+//!
+//! This crate wraps the swarm setup of the ping example into a small library so
+//! other applications can embed a ping node without copying the builder chain.
+//! The node communicates over TCP or WebSocket (with TLS and/or Noise encryption
+//! and Yamux stream multiplexing) and/or QUIC, and runs the ping protocol to check
+//! connectivity with peers. Peers behind NAT can be reached through circuit relays by dialing
+//! `/p2p-circuit` addresses, after which the node tries to upgrade to a direct
+//! connection by hole punching (DCUtR). A publicly reachable node can act as
+//! such a relay itself. Connections beyond the configured
+//! [`ConnectionLimits`] are denied. AutoNAT probes tell whether the node itself is
+//! reachable from the outside, optionally after mapping the listening ports on
+//! the local router via UPnP, and the optional Kademlia DHT finds peers known
+//! only by their [`PeerId`](libp2p::PeerId). Nodes meeting at a [`Rendezvous`]
+//! point find each other by namespace, and any node can serve as such a point.
+//! Through [`PeerExchange`] a node also dials the peers of its peers.
+//! Nodes joining the latency mesh share their RTTs over gossipsub to build a [`LatencyMatrix`] of the whole network. The bytes
+//! exchanged with each peer are counted as its [`Traffic`].
+//!
+//! Most settings are fields of [`NodeConfig`]; [`PingNode::builder`] also
+//! accepts a custom identity and ping protocol configuration. The [`testing`]
+//! module connects nodes within one process over the memory transport.
+//! Fallible calls return a [`PingError`] to match on.
+//!
+//! With the `ffi` feature the `ffi` module exposes the node to C, as declared
+//! in `include/ping_node.h`, and the `python` feature builds the `p2p_ping`
+//! Python module. Built for `wasm32-unknown-unknown`, the crate instead exports
+//! `pingPeer` to JavaScript, which pings a peer from a browser over WebSocket
+//! or WebTransport and reports its RTTs with the same [`PingStats`].
+//!
+//! ## Example
+//! ```no_run
+//! use libp2p_ping_tut::PingNode;
+//!
+//! # async fn run() -> Result<(), Box<dyn std::error::Error>> {
+//! let mut node = PingNode::new()?;
+//! node.listen("/ip4/0.0.0.0/tcp/0".parse()?)?;
+//! loop {
+//!     println!("{:?}", node.next_event().await);
+//! }
+//! # }
+//! ```
+
+/// Declares items only built for native targets; the browser has neither the
+/// transports nor the runtime of the [`PingNode`].
+macro_rules! native {
+    ($($item:item)*) => {
+        $(
+            #[cfg(not(target_arch = "wasm32"))]
+            $item
+        )*
+    };
+}
+
+native! {
+    mod adaptive;
+    mod backoff;
+    mod bandwidth;
+    mod bind;
+    pub mod bench;
+    mod behaviour;
+    mod builder;
+    pub mod clock;
+    mod dials;
+    mod error;
+    pub mod echo;
+    mod events;
+    pub mod exchange;
+    mod forward;
+    #[cfg(feature = "ffi")]
+    pub mod ffi;
+    pub mod keyfile;
+    pub mod labels;
+    mod mesh;
+    mod metrics;
+    mod node;
+    pub mod ping_limit;
+    #[cfg(feature = "python")]
+    mod python;
+    mod race;
+    mod rendezvous;
+    mod security;
+    mod socks;
+    mod spans;
+    pub mod testing;
+    mod timing;
+    mod transport;
+    mod webtransport;
+
+    pub use adaptive::AdaptiveInterval;
+    pub use backoff::Backoff;
+    pub use bandwidth::Traffic;
+    pub use bind::{LocalBinding, TcpOptions};
+    pub use behaviour::{Behaviour, BehaviourEvent};
+    pub use builder::PingNodeBuilder;
+    pub use error::{DialErrorKind, PingError};
+    pub use events::PingEvent;
+    pub use exchange::PeerExchange;
+    pub use mesh::LatencyMatrix;
+    pub use node::{AutonatLimits, ConnectionLimits, NodeConfig, PingNode, RelayLimits};
+    pub use race::Race;
+    pub use rendezvous::Rendezvous;
+    pub use security::SecurityChoice;
+    pub use socks::Socks5Proxy;
+    pub use timing::ConnectionTiming;
+    pub use transport::{TransportChoice, WsTls};
+}
+
+mod stats;
+#[cfg(target_arch = "wasm32")]
+mod wasm;
+
+pub use stats::PingStats;
+#[cfg(target_arch = "wasm32")]
+pub use wasm::ping_peer;
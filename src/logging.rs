@@ -0,0 +1,177 @@
+//! Log files written next to the console logs (`--log-file`), rotated by size
+//! or time so that long-running nodes don't fill their disks.
+//!
+//! Rotated files are numbered like logrotate does: `node.log` is renamed to
+//! `node.log.1`, `node.log.1` to `node.log.2` and so on, and the oldest one
+//! beyond `--log-keep` is deleted.
+
+use std::ffi::OsString;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::SystemTime;
+
+/// How log lines are formatted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum LogFormat {
+    /// Human-readable lines.
+    Text,
+    /// One JSON object per line, with the fields of each event, such as
+    /// `event` and `rtt_us`, next to its message and those of its spans, such
+    /// as `peer_id`, in `span` and `spans`.
+    Json,
+}
+
+/// When a log file is rotated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rotation {
+    /// Before a line would make the file larger than this many bytes.
+    Size(u64),
+    /// On the first line of each hour, in UTC.
+    Hourly,
+    /// On the first line of each day, in UTC.
+    Daily,
+    /// Never; the file grows without bounds.
+    Never,
+}
+
+impl Rotation {
+    /// Returns the number of the hour or day `time` falls in, which changes
+    /// when the file is due.
+    fn period(self, time: SystemTime) -> u64 {
+        let secs = time.duration_since(SystemTime::UNIX_EPOCH).map_or(0, |since| since.as_secs());
+        match self {
+            Rotation::Hourly => secs / 3600,
+            Rotation::Daily => secs / 86_400,
+            Rotation::Size(_) | Rotation::Never => 0,
+        }
+    }
+}
+
+impl FromStr for Rotation {
+    type Err = String;
+
+    /// Parses `hourly`, `daily`, `never` or a size such as `100MB`, with
+    /// decimal (`KB`, `MB`, `GB`) or binary (`KiB`, `MiB`, `GiB`) units.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "hourly" => return Ok(Rotation::Hourly),
+            "daily" => return Ok(Rotation::Daily),
+            "never" => return Ok(Rotation::Never),
+            _ => {}
+        }
+        let digits = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+        let (number, unit) = s.split_at(digits);
+        let number: u64 = number
+            .parse()
+            .map_err(|_| format!("`{s}` is neither `hourly`, `daily`, `never` nor a size such as `100MB`"))?;
+        let unit = match unit.trim() {
+            "" | "B" => 1,
+            "KB" => 1000,
+            "MB" => 1000 * 1000,
+            "GB" => 1000 * 1000 * 1000,
+            "KiB" => 1024,
+            "MiB" => 1024 * 1024,
+            "GiB" => 1024 * 1024 * 1024,
+            unit => return Err(format!("unknown unit `{unit}`; use B, KB, MB, GB, KiB, MiB or GiB")),
+        };
+        match number.checked_mul(unit) {
+            Some(0) => Err("size must be greater than zero".into()),
+            Some(bytes) => Ok(Rotation::Size(bytes)),
+            None => Err(format!("`{s}` is too large")),
+        }
+    }
+}
+
+/// A log file that rotates itself as lines are written to it.
+///
+/// Wrapped in a [`Mutex`](std::sync::Mutex), it is a writer for
+/// `tracing_subscriber::fmt`, which writes every line at once.
+pub struct LogFile {
+    path: PathBuf,
+    rotation: Rotation,
+    /// How many rotated files to keep.
+    keep: usize,
+    file: File,
+    /// Size of the file so far.
+    size: u64,
+    /// Period of [`Rotation::period`] the file was last written in.
+    period: u64,
+}
+
+impl LogFile {
+    /// Opens the file at `path` to append to it, creating it if needed.
+    ///
+    /// An existing file counts as written when it was last modified, so a
+    /// daily file left from yesterday is rotated on the first line.
+    pub fn open(path: &Path, rotation: Rotation, keep: usize) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let metadata = file.metadata()?;
+        Ok(Self {
+            path: path.to_owned(),
+            rotation,
+            keep,
+            file,
+            size: metadata.len(),
+            period: rotation.period(metadata.modified()?),
+        })
+    }
+
+    /// Returns whether the file has to be rotated before `len` more bytes are
+    /// written to it.
+    fn due(&self, len: usize) -> bool {
+        match self.rotation {
+            Rotation::Size(max) => self.size > 0 && self.size + len as u64 > max,
+            Rotation::Hourly | Rotation::Daily => self.rotation.period(SystemTime::now()) != self.period,
+            Rotation::Never => false,
+        }
+    }
+
+    /// Shifts the rotated files by one, drops the oldest one and starts a new,
+    /// empty file.
+    fn rotate(&mut self) -> io::Result<()> {
+        if self.keep == 0 {
+            fs::remove_file(&self.path).or_else(ignore_missing)?;
+        } else {
+            fs::remove_file(self.rotated(self.keep)).or_else(ignore_missing)?;
+            for number in (1..self.keep).rev() {
+                fs::rename(self.rotated(number), self.rotated(number + 1)).or_else(ignore_missing)?;
+            }
+            fs::rename(&self.path, self.rotated(1)).or_else(ignore_missing)?;
+        }
+        self.file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+        self.size = 0;
+        Ok(())
+    }
+
+    /// Returns the path of the `number`th most recent rotated file.
+    fn rotated(&self, number: usize) -> PathBuf {
+        let mut path = OsString::from(&self.path);
+        path.push(format!(".{number}"));
+        path.into()
+    }
+}
+
+impl Write for LogFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.due(buf.len()) {
+            self.rotate()?;
+        }
+        self.period = self.rotation.period(SystemTime::now());
+        let written = self.file.write(buf)?;
+        self.size += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+fn ignore_missing(e: io::Error) -> io::Result<()> {
+    match e.kind() {
+        io::ErrorKind::NotFound => Ok(()),
+        _ => Err(e),
+    }
+}
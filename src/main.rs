@@ -1,70 +1,1556 @@
 //! # Simple Libp2p Application with Ping Protocol
 //!
-//! This example demonstrates how to set up a basic libp2p node using the `tokio`
-//! asynchronous runtime. It configures the node to communicate over TCP with TLS
-//! encryption and Yamux stream multiplexing. Additionally, it incorporates the
-//! ping protocol to allow nodes to check connectivity with each other.
+//! This example demonstrates how to run a basic libp2p node using the `tokio`
+//! asynchronous runtime. The swarm setup lives in the library's [`PingNode`],
+//! which communicates over TCP with TLS encryption and Yamux stream
+//! multiplexing and runs the ping protocol to check connectivity with peers.
 //!
 //! ## Features Demonstrated
-//! - Initializing a libp2p swarm with a new identity.
-//! - Configuring transports (TCP) and stream multiplexers (Yamux).
-//! - Adding behavior to the swarm (ping protocol).
-//! - Listening on a random port and dialing peers.
+//! - Initializing a libp2p swarm with a new or saved identity.
+//! - Configuring transports (TCP, QUIC, WebSocket or WebTransport), security
+//!   protocols and stream multiplexers (Yamux).
+//! - Adding behavior to the swarm: ping, identify, mDNS, the Kademlia DHT,
+//!   circuit relays with hole punching, AutoNAT, UPnP, rendezvous and
+//!   gossipsub.
+//! - Listening on random ports, dialing peers by address, DNS name or PeerId,
+//!   and re-dialing them when connections drop.
+//! - Measuring RTTs, throughput, clock offsets and handshake times, and
+//!   checking them against thresholds.
+//! - Reporting the results as text, JSON or CSV, in a terminal dashboard,
+//!   over Prometheus, OpenTelemetry and webhooks, and storing them in SQLite.
+//! - Managing the peers of a long-running node over a Unix socket, a REST API
+//!   or gRPC.
 //! - Handling swarm events asynchronously.
 //!
 //! ## Usage
-//! Run this application and optionally pass a multi-address of a peer to dial as
-//! a command-line argument. The application will start listening on a random port
-//! and print out the address it's listening on. If a peer address is provided,
-//! it will attempt to establish a connection to that peer and send pings.
+//! Run `listen` to start a node that answers pings, or `ping <peer_multiaddr>`
+//! to also dial peers and send them pings. Both print the addresses the node
+//! is listening on. The other subcommands measure or diagnose a peer
+//! (`bench`, `compare`, `doctor`, `probe`, `sweep`), check peers for health
+//! checks (`healthcheck`), summarize stored results (`report`), simulate a
+//! network path (`simulate`), generate identities (`keygen`) and manage a
+//! `--daemon` (`ctl`); `--help` lists them all with their options. Options
+//! can also be read from a TOML file given with `--config`; command-line
+//! options take precedence. The program runs on Unix only, as it handles
+//! SIGHUP and SIGUSR1 and serves `ctl` over a Unix socket.
 //!
+//! ```text
+//! libp2p-ping-tut listen
+//! libp2p-ping-tut ping [peer_multiaddr]...
+//! libp2p-ping-tut bench [--duration 10s] <peer_multiaddr>
+//! libp2p-ping-tut ctl add-peer [name=]<peer_multiaddr>
 //! ```
 //!
 //  Replace `[peer_multiaddr]` with the actual multi-address of the peer you wish
 //  to connect to, e.g., `/ip4/127.0.0.1/tcp/12345/p2p/Qm...`.
 //!
+//! ## Exit codes
+//! 0 on success and 1 if peers didn't answer or violated the thresholds; 2
+//! for invalid arguments. Errors that stop a run exit with a code of their
+//! own: 3 for an unusable address, 4 for one no enabled transport supports, 5
+//! for a keypair, key or certificate file that can't be loaded, 6 if
+//! listening fails, 7 if a dial can't be started and 8 on a timeout. Other
+//! errors exit with 1.
+//!
+
+mod address_book;
+mod cli;
+mod compare;
+mod config;
+mod control;
+mod doctor;
+#[cfg(feature = "grpc")]
+mod grpc;
+mod http;
+mod logging;
+mod otlp;
+mod output;
+mod peers_file;
+mod probe;
+mod report_file;
+mod schedule;
+mod simulate;
+mod store;
+mod sweep;
+mod systemd;
+mod targets;
+mod tui;
+mod webhook;
 
-use futures::prelude::*;
-use libp2p::swarm::SwarmEvent;
-use libp2p::{ping, Multiaddr};
+use clap::Parser;
+use futures::future::{self, BoxFuture};
+use futures::stream::{FuturesUnordered, StreamExt};
+use futures::FutureExt;
+use libp2p::multiaddr::Protocol;
+use libp2p::request_response::OutboundFailure;
+use libp2p::swarm::{DialError, ListenError, SwarmEvent};
+use libp2p::{autonat, dcutr, identify, identity, kad, mdns, ping, relay, rendezvous, Multiaddr, PeerId};
+use libp2p_ping_tut::labels::{self, Labels};
+use libp2p_ping_tut::testing::Impairment;
+use libp2p_ping_tut::{bench, keyfile, BehaviourEvent, DialErrorKind, PingError, PingEvent, PingNode, PingStats, Traffic};
+use std::collections::HashMap;
 use std::error::Error;
-use std::time::Duration;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+use tokio::signal::unix::{signal, Signal, SignalKind};
+use tokio::sync::{broadcast, mpsc};
+use tracing::Level;
+use tracing_subscriber::filter;
+use tracing_subscriber::prelude::*;
 use tracing_subscriber::EnvFilter;
 
-/// Main entry point of the application.
+use crate::address_book::AddressBook;
+use crate::cli::{Cli, Command, CtlCommand, NamedPeer};
+use crate::config::{check_peer_timeouts, FileConfig, Settings};
+use crate::control::{ControlSocket, Request, Response, TargetStats};
+use crate::logging::{LogFile, LogFormat};
+use crate::otlp::Otlp;
+use crate::output::Output;
+use crate::peers_file::{Change, PeersFile};
+use crate::report_file::RunReport;
+use crate::schedule::{State, Windows};
+use crate::store::Store;
+use crate::systemd::Systemd;
+use crate::targets::{Connection, Retry, Targets};
+use crate::tui::Dashboard;
+use crate::webhook::Webhook;
+
+/// How many events WebSocket clients may fall behind before missing some.
+const EVENT_BUFFER: usize = 256;
+
+/// How often the `--peers-file` is checked for changes.
+const PEERS_FILE_POLL: Duration = Duration::from_secs(2);
+
+/// How long connections get to close cleanly on shutdown.
+const SHUTDOWN_GRACE: Duration = Duration::from_secs(2);
+
+/// Main entry point of the application; prints the error that stopped it, if
+/// any, with the exit code of its kind.
 #[tokio::main]
-async fn main() -> Result<(), Box<dyn Error>> {
-    // Initialize logging with environment filter for log level control.
-    tracing_subscriber::fmt().with_env_filter(EnvFilter::from_default_env()).init();
-
-    // Create a new libp2p SwarmBuilder instance with a randomly generated identity.
-    let mut swarm = libp2p::SwarmBuilder::with_new_identity()
-        .with_tokio() // Specify tokio as the executor for async operations.
-        .with_tcp(
-            libp2p::tcp::Config::default(), // Default TCP configuration.
-            libp2p::tls::Config::new, // Enable TLS for secure communication.
-            libp2p::yamux::Config::default, // Use Yamux for stream multiplexing.
-        )?
-        .with_behaviour(|_| ping::Behaviour::default())? // Add ping behaviour to the swarm.
-        .with_swarm_config(|cfg| cfg.with_idle_connection_timeout(Duration::from_secs(30))) // Set idle connection timeout.
-        .build(); // Finalize building the swarm.
-
-    // Start listening on all interfaces at a random OS-assigned port.
-    swarm.listen_on("/ip4/0.0.0.0/tcp/0".parse()?)?;
-
-    // Attempt to dial a peer if a multi-address is provided as a command-line argument.
-    if let Some(addr) = std::env::args().nth(1) {
-        let remote: Multiaddr = addr.parse()?; // Parse the multi-address.
-        swarm.dial(remote)?; // Dial the peer.
-        println!("Dialed {addr}");
-    }
-
-    // Event loop to handle incoming swarm events.
+async fn main() -> ExitCode {
+    match start(Cli::parse()).await {
+        Ok(code) => code,
+        Err(e) => {
+            eprintln!("Error: {e}");
+            let error = e.downcast_ref::<PingError>();
+            if let Some(hint) = error.and_then(hint) {
+                eprintln!("{hint}");
+            }
+            ExitCode::from(error.map_or(1, exit_code))
+        }
+    }
+}
+
+/// The exit code of a run stopped by `error`.
+fn exit_code(error: &PingError) -> u8 {
+    match error {
+        PingError::InvalidMultiaddr { .. } => 3,
+        PingError::TransportNotSupported(_) => 4,
+        PingError::IdentityLoad { .. } => 5,
+        PingError::ListenFailure { .. } => 6,
+        PingError::DialFailure { .. } => 7,
+        PingError::Timeout => 8,
+        _ => 1,
+    }
+}
+
+/// What to do about `error`, if there is anything obvious.
+fn hint(error: &PingError) -> Option<&'static str> {
+    match error {
+        PingError::TransportNotSupported(_) => Some("Enable the transport of the address with --transport, e.g. --transport tcp,quic."),
+        PingError::ListenFailure { .. } => Some("Is another node listening there? Pick another port, or 0 for a random one."),
+        PingError::NotEnabled("Kademlia") => Some("Enable the DHT with --kademlia."),
+        _ => None,
+    }
+}
+
+/// Runs the subcommand of `cli`.
+async fn start(cli: Cli) -> Result<ExitCode, Box<dyn Error>> {
+    // Initialize logging with environment filter for log level control. The
+    // spans of this crate are exported at debug level whatever the filter says,
+    // and the log file has a filter of its own.
+    let otlp = cli.otlp.as_deref().map(Otlp::new).transpose()?;
+    let exported = filter::Targets::new().with_target("libp2p_ping_tut", Level::DEBUG);
+    let console = EnvFilter::from_default_env();
+    let (mut text_console, mut json_console) = (None, None);
+    match cli.log_format {
+        LogFormat::Text => text_console = Some(tracing_subscriber::fmt::layer().with_filter(console)),
+        LogFormat::Json => json_console = Some(tracing_subscriber::fmt::layer().json().flatten_event(true).with_filter(console)),
+    }
+    let (mut text_file, mut json_file) = (None, None);
+    if let Some(path) = &cli.log_file {
+        let file = LogFile::open(path, cli.log_rotate, cli.log_keep).map_err(|e| format!("{}: {e}", path.display()))?;
+        let filter = EnvFilter::try_new(&cli.log_file_level).map_err(|e| format!("--log-file-level: {e}"))?;
+        let writer = Mutex::new(file);
+        match cli.log_file_format {
+            LogFormat::Text => {
+                text_file = Some(tracing_subscriber::fmt::layer().with_ansi(false).with_writer(writer).with_filter(filter));
+            }
+            LogFormat::Json => {
+                json_file =
+                    Some(tracing_subscriber::fmt::layer().json().flatten_event(true).with_writer(writer).with_filter(filter));
+            }
+        }
+    }
+    tracing_subscriber::registry()
+        .with(text_console)
+        .with(json_console)
+        .with(text_file)
+        .with(json_file)
+        .with(otlp.as_ref().map(|otlp| otlp.layer().with_filter(exported)))
+        .init();
+
+    let result = match &cli.command {
+        Command::Ping { oneshot: true, .. } => oneshot(Settings::resolve(&cli)?).await,
+        Command::Ping { .. } | Command::Listen => run(Settings::resolve(&cli)?, otlp.as_ref()).await,
+        Command::Compare { addrs, count } => compare(Settings::resolve(&cli)?, addrs, *count).await,
+        Command::Bench { addr, duration } => bench(Settings::resolve(&cli)?, addr, *duration).await,
+        Command::Doctor { addr, .. } => doctor(Settings::resolve(&cli)?, addr).await,
+        Command::Probe { addr } => probe(Settings::resolve(&cli)?, addr).await,
+        Command::Sweep { key, count } => sweep(Settings::resolve(&cli)?, *key, *count).await,
+        Command::Simulate { peers, count, latency, jitter, loss, seed } => {
+            let impairment = Impairment { latency: *latency, jitter: jitter.unwrap_or_default(), loss: loss / 100.0, seed: *seed };
+            simulate(&cli, Settings::resolve(&cli)?, impairment, *peers as usize, *count).await
+        }
+        Command::Healthcheck { addrs, local, within } => Ok(healthcheck(&cli, addrs, *local, *within).await),
+        Command::Report { from, to, sla } => report(Settings::resolve(&cli)?, *from, *to, *sla),
+        Command::Ctl { command } => ctl(Settings::resolve(&cli)?, command).await,
+        Command::Keygen { out, seed } => {
+            let keypair = match seed {
+                Some(seed) => identity::Keypair::ed25519_from_bytes(*seed)?,
+                None => identity::Keypair::generate_ed25519(),
+            };
+            if let Some(path) = out {
+                keyfile::write(path, &keypair)?;
+            }
+            println!("{}", PeerId::from(keypair.public()));
+            Ok(ExitCode::SUCCESS)
+        }
+    };
+
+    // Flushing blocks on the exporters' tasks, so keep it off the runtime.
+    if let Some(otlp) = otlp {
+        tokio::task::spawn_blocking(move || otlp.shutdown()).await?;
+    }
+    result
+}
+
+/// Runs a ping node, dialing every peer in `settings`.
+///
+/// Lost peers are re-dialed according to the retry policy. Returns once every
+/// dialed peer has answered `count` pings or been given up on, once the
+/// deadline has passed, or on SIGINT/SIGTERM. A `--daemon` only returns on the
+/// latter two or when told to through its control socket. Connections are then
+/// closed, the ping statistics of each dialed peer are printed, and the exit
+/// code is a failure if any of them never answered or violates the thresholds.
+///
+/// Peers can be added, removed, paused and resumed while running through the
+/// control socket and the HTTP API, and by changing the peers file or, on
+/// SIGHUP, the `peers` of the configuration file. SIGUSR1 prints the
+/// statistics so far.
+async fn run(settings: Settings, otlp: Option<&Otlp>) -> Result<ExitCode, Box<dyn Error>> {
+    let keypair = load_keypair(&settings)?;
+    let default_listen = default_listen_addrs(&settings);
+    let echo_size = settings.node.echo_size;
+    let autonat_server = settings.node.autonat_server.is_some();
+    let store = settings.store.as_deref().map(Store::open).transpose()?;
+    let address_book = settings.address_book.as_deref().map(AddressBook::open).transpose()?;
+    let webhook = settings.webhook.clone().map(Webhook::new);
+    let mut report = settings.report_file.clone().map(|path| RunReport::new(path, &settings));
+    let mut peers_file = settings.peers_file.as_deref().map(PeersFile::open).transpose()?;
+    // The dashboard owns the terminal, so event lines are dropped while it
+    // runs, unless they go to a file.
+    let output = match (&settings.out_file, settings.tui) {
+        (None, true) => Output::with_writer(settings.output, Box::new(io::sink())),
+        _ => open_output(&settings)?,
+    };
+    let mut node = PingNode::with_keypair(keypair, settings.node)?;
+    for addr in &settings.external_addrs {
+        node.add_external_address(addr.clone());
+    }
+    let mut dashboard = settings.tui.then(|| Dashboard::new(node.local_peer_id(), settings.graph));
+    output.started(&node.local_peer_id());
+
+    // Serve metrics in the background; bind first so a taken port fails fast.
+    if let (Some(addr), Some(registry)) = (settings.metrics, node.metrics_registry()) {
+        let listener = tokio::net::TcpListener::bind(addr).await?;
+        output.serving_metrics(&listener.local_addr()?);
+        tokio::spawn(http::serve(listener, registry));
+    }
+
+    // Start listening on the requested addresses, or else on the default ones,
+    // which may fail, e.g. on hosts without IPv6, as long as at least one of
+    // them works.
+    if settings.listen.is_empty() {
+        let mut listening = false;
+        for addr in default_listen {
+            match node.listen(addr.clone()) {
+                Ok(_) => listening = true,
+                Err(e) => output.listen_failed(&addr, &e),
+            }
+        }
+        if !listening {
+            return Err("could not listen on any default address".into());
+        }
+    } else {
+        for addr in &settings.listen {
+            node.listen(addr.clone())?;
+        }
+    }
+
+    // Dial every peer given on the command line, in the configuration file or
+    // in the peers file.
+    let count = settings.count;
+    let mut targets = Targets::new(settings.policy, settings.warmup, settings.via_relay);
+    // The `peers` of the configuration file as last read, to compare with
+    // those after a SIGHUP.
+    let mut config_peers: Option<(PathBuf, Vec<NamedPeer>)> =
+        settings.config_peers.map(|path| (path, settings.peers.clone()));
+    let file_peers = peers_file.iter().flat_map(|file| file.peers().iter().cloned());
+    for peer in settings.peers.into_iter().chain(file_peers.collect::<Vec<_>>()) {
+        if targets.find(&peer.addr.to_string()).is_some() {
+            continue;
+        }
+        let connection_id = node.dial(peer.addr.clone())?;
+        output.dialing(&peer.addr);
+        let index = targets.add(peer.addr, peer.name, connection_id);
+        targets.set_overrides(index, peer.overrides);
+        dial_detour(index, &mut node, &mut targets, &output);
+    }
+    // Resume the peers of earlier runs that weren't given again.
+    for entry in address_book.as_ref().map(AddressBook::entries).transpose()?.unwrap_or_default() {
+        if targets.find(&entry.address.to_string()).or_else(|| targets.find(&entry.peer_id.to_string())).is_some() {
+            continue;
+        }
+        // A peer may have gone for good, or be denied by now.
+        let connection_id = match node.dial(entry.address.clone()) {
+            Ok(connection_id) => connection_id,
+            Err(e) => {
+                output.dial_failed(&entry.address, e.dial_error_kind(), &e);
+                continue;
+            }
+        };
+        output.resuming(&entry);
+        let index = targets.add(entry.address, entry.name, connection_id);
+        dial_detour(index, &mut node, &mut targets, &output);
+    }
+    // Look up the peers given by id only; the lookup connects to them if found.
+    for peer_id in settings.peer_ids {
+        let index = targets.add_lookup(peer_id);
+        targets.lookup_started(index, node.find_peer(peer_id)?);
+        output.looking_up(&peer_id);
+    }
+
+    // Commands from the control socket and the HTTP API; `sender` stays alive
+    // so that the loop keeps waiting for them when neither is enabled.
+    let (sender, mut commands) = mpsc::channel(16);
+    let control = if settings.daemon {
+        Some(ControlSocket::bind(&settings.control_socket, sender.clone()).await?)
+    } else {
+        None
+    };
+    if let Some(control) = &control {
+        output.control_socket(control.path());
+    }
+    // Events for the WebSocket clients of the HTTP API and the gRPC streams.
+    let (events, _) = broadcast::channel(EVENT_BUFFER);
+    if let Some(addr) = settings.api {
+        let listener = tokio::net::TcpListener::bind(addr).await?;
+        output.serving_api(&listener.local_addr()?);
+        tokio::spawn(http::serve_api(listener, sender.clone(), events.clone()));
+    }
+    #[cfg(feature = "grpc")]
+    if let Some(addr) = settings.grpc {
+        let listener = tokio::net::TcpListener::bind(addr).await?;
+        output.serving_grpc(&listener.local_addr()?);
+        tokio::spawn(grpc::serve(listener, sender.clone(), events.clone()));
+    }
+
+    // Pending re-dials, each resolving to the index of its target.
+    let mut redials: FuturesUnordered<BoxFuture<'static, usize>> = FuturesUnordered::new();
+
+    // Latency reports for the mesh; the first tick completes immediately and
+    // is skipped as there is nothing to report yet.
+    let mut mesh_reports = tokio::time::interval(settings.mesh_interval);
+    mesh_reports.tick().await;
+    let mut peers_file_polls = peers_file.as_ref().map(|_| tokio::time::interval(PEERS_FILE_POLL));
+    let mut summaries = settings.summary_interval.map(tokio::time::interval);
+    let mut reported_total = Traffic::default();
+    if let Some(summaries) = &mut summaries {
+        summaries.tick().await;
+    }
+
+    // Readiness, reloads on SIGHUP and the watchdog when run by systemd.
+    let systemd = Systemd::from_env();
+    let mut notified_ready = false;
+    let mut watchdog = systemd.as_ref().and_then(Systemd::watchdog_interval).map(tokio::time::interval);
+    let mut hangups = signal(SignalKind::hangup()).ok();
+    let mut dumps = signal(SignalKind::user_defined1()).ok();
+
+    let shutdown = shutdown_signal();
+    tokio::pin!(shutdown);
+    let deadline = tokio::time::sleep(settings.deadline.unwrap_or_default());
+    tokio::pin!(deadline);
+
+    // Measurement windows of the schedule, when the open one opened, and when
+    // the next one opens or the open one closes.
+    let mut window_opened = None;
+    let mut window_change = settings.windows.as_ref().and_then(|windows| {
+        update_window(windows, &mut window_opened, &mut node, &mut targets, &output)
+    });
+    let window_sleep = tokio::time::sleep_until(instant_at(window_change.unwrap_or_else(SystemTime::now)));
+    tokio::pin!(window_sleep);
+
+    // Event loop to handle incoming swarm events until done or interrupted.
     loop {
-        match swarm.select_next_some().await {
-            SwarmEvent::NewListenAddr { address, .. } => println!("Listening on {:?}", address), // Log new listen addresses.
-            SwarmEvent::Behaviour(event) => println!("{:?}", event), // Log behaviour-specific events (e.g., ping responses).
+        let event = tokio::select! {
+            event = node.next_event() => event,
+            Some(index) = redials.next() => {
+                if targets.get(index).is_removed() {
+                    continue;
+                }
+                let started = match targets.get(index).lookup_peer_id() {
+                    Some(peer_id) => node.find_peer(peer_id).map(|query_id| targets.lookup_started(index, query_id)),
+                    None => node.dial(targets.get(index).addr.clone()).map(|connection_id| targets.redialed(index, connection_id)),
+                };
+                // Dials refused right away, e.g. by a limit, are retried like failed ones.
+                if let Err(e) = started {
+                    output.dial_failed(&targets.get(index).addr, e.dial_error_kind(), &e);
+                    let retry = targets.redial_failed(index, e.dial_error_kind().unwrap_or(DialErrorKind::Other));
+                    schedule_retry(retry, &targets, &output, &mut redials);
+                }
+                continue;
+            }
+            () = &mut window_sleep, if window_change.is_some() => {
+                let windows = settings.windows.as_ref().expect("windows only change with a schedule");
+                window_change = update_window(windows, &mut window_opened, &mut node, &mut targets, &output);
+                if let Some(at) = window_change {
+                    window_sleep.as_mut().reset(instant_at(at));
+                }
+                continue;
+            }
+            _ = mesh_reports.tick(), if node.latency_matrix().is_some() => {
+                if let Err(e) = node.publish_latencies() {
+                    output.publish_failed(&e);
+                }
+                if let Some(matrix) = node.latency_matrix().filter(|m| !m.is_empty()) {
+                    output.latency_matrix(matrix);
+                }
+                continue;
+            }
+            () = tick(&mut summaries) => {
+                let interval = settings.summary_interval.expect("summaries are only scheduled with an interval");
+                for target in targets.iter_mut() {
+                    let traffic = target.peer_id.map_or_else(Traffic::default, |peer_id| node.traffic(&peer_id));
+                    let recent = traffic - target.reported_traffic;
+                    output.interval_summary(target.label(), &target.labels, interval, &target.recent, &recent);
+                    target.recent = PingStats::default();
+                    target.reported_traffic = traffic;
+                }
+                let total = node.total_traffic();
+                output.total_traffic(Some(interval), &(total - reported_total));
+                reported_total = total;
+                continue;
+            }
+            () = tick(&mut peers_file_polls) => {
+                let file = peers_file.as_mut().expect("the peers file is only polled if given");
+                reload_peers_file(file, &mut node, &mut targets, &output);
+                continue;
+            }
+            () = received(&mut hangups) => {
+                if let Some(systemd) = &systemd {
+                    systemd.reloading();
+                }
+                if let Some(file) = &mut peers_file {
+                    reload_peers_file(file, &mut node, &mut targets, &output);
+                }
+                if let Some((path, peers)) = &mut config_peers {
+                    reload_config_peers(path, peers, settings.timeout, &mut node, &mut targets, &output);
+                }
+                if let Some(systemd) = &systemd {
+                    systemd.ready(&status(&targets));
+                }
+                continue;
+            }
+            () = received(&mut dumps) => {
+                print_statistics(&targets, &node, &output, echo_size);
+                output.total_traffic(None, &node.total_traffic());
+                continue;
+            }
+            () = tick(&mut watchdog) => {
+                if let Some(systemd) = &systemd {
+                    systemd.pet_watchdog();
+                }
+                continue;
+            }
+            Some((request, reply)) = commands.recv() => {
+                let shutdown = matches!(request, Request::Shutdown);
+                let _ = reply.send(handle_command(request, &mut node, &mut targets, &output));
+                // Pinging stays paused outside of the measurement windows.
+                let in_window = settings.windows.is_none() || window_opened.is_some();
+                node.set_paused(targets.all_paused || !in_window);
+                if shutdown {
+                    break;
+                }
+                continue;
+            }
+            quit = update_dashboard(&mut dashboard) => {
+                if quit? {
+                    break;
+                }
+                continue;
+            }
+            _ = &mut shutdown => break,
+            _ = &mut deadline, if settings.deadline.is_some() => break,
+        };
+        let event = late_as_timeout(event, &targets, settings.timeout);
+        if let Some(dashboard) = &mut dashboard {
+            dashboard.observe(&event);
+        }
+        if events.receiver_count() > 0 {
+            for ping_event in PingEvent::from_swarm(&event) {
+                let _ = events.send(ping_event);
+            }
+        }
+
+        // Set when a target lost its connection or failed to connect.
+        let mut retry = None;
+
+        match event {
+            SwarmEvent::NewListenAddr { address, .. } => {
+                output.listening(&address);
+                if let Some(systemd) = systemd.as_ref().filter(|_| !notified_ready) {
+                    systemd.ready(&status(&targets));
+                    notified_ready = true;
+                }
+            }
+            SwarmEvent::ExternalAddrConfirmed { address } => output.external_address(&address),
+            SwarmEvent::ConnectionEstablished { peer_id, connection_id, endpoint, .. } => {
+                targets.connection_established(connection_id, peer_id, &endpoint);
+                if targets.get_by_connection(connection_id).is_some_and(|target| target.paused) {
+                    node.set_peer_paused(peer_id, true);
+                }
+                if let Some(overrides) = targets.get_by_connection(connection_id).map(|target| target.overrides) {
+                    if overrides.interval.is_some() {
+                        node.set_peer_interval(peer_id, overrides.interval);
+                    }
+                    if overrides.size.is_some() {
+                        node.set_peer_echo_size(peer_id, overrides.size);
+                    }
+                }
+                if let Some(name) = targets.name(&peer_id) {
+                    output.name_peer(peer_id, name);
+                    if let Some(dashboard) = &mut dashboard {
+                        dashboard.name_peer(peer_id, name);
+                    }
+                }
+                output.connected(&peer_id, endpoint.get_remote_address());
+                if let Some(book) = address_book.as_ref().filter(|_| endpoint.is_dialer()) {
+                    // Targets are kept as given, other peers at the address they answered on.
+                    let address = match targets.get_by_connection(connection_id) {
+                        Some(target) if target.detour_of.is_some() => None,
+                        Some(target) => Some(target.addr.clone()),
+                        None => Some(address_book::with_peer_id(endpoint.get_remote_address(), peer_id)),
+                    };
+                    if let Err(e) = address.map_or(Ok(()), |address| book.dialed(&peer_id, &address, targets.name(&peer_id))) {
+                        output.write_failed(book.path(), &e);
+                    }
+                }
+                if let Some(race) = node.take_race(connection_id) {
+                    output.race_won(&peer_id, &race);
+                }
+            }
+            SwarmEvent::ConnectionClosed { peer_id, connection_id, cause, .. } => {
+                retry = targets.connection_closed(connection_id);
+                match &cause {
+                    Some(cause) => record_unreachable(store.as_ref(), &targets, &retry, cause, &output),
+                    None => record_unreachable(store.as_ref(), &targets, &retry, &"connection closed", &output),
+                }
+                output.disconnected(&peer_id, cause.as_ref().map(|e| e as &dyn std::fmt::Display));
+            }
+            SwarmEvent::IncomingConnectionError { send_back_addr, error: ListenError::Denied { cause }, .. } => {
+                output.denied(&send_back_addr, &cause);
+            }
+            SwarmEvent::OutgoingConnectionError { connection_id, peer_id, error: DialError::Denied { cause } } => {
+                retry = targets.dial_denied(connection_id);
+                record_unreachable(store.as_ref(), &targets, &retry, &cause, &output);
+                match peer_id {
+                    Some(peer_id) => output.denied(&peer_id, &cause),
+                    None => output.denied(&"unknown peer", &cause),
+                }
+            }
+            SwarmEvent::OutgoingConnectionError { connection_id, error: DialError::WrongPeerId { obtained, .. }, .. } => {
+                retry = targets.dial_failed(connection_id, DialErrorKind::WrongPeer);
+                record_unreachable(store.as_ref(), &targets, &retry, &format!("wrong peer {obtained}"), &output);
+                if let Some(Retry::After { index, .. } | Retry::GiveUp { index }) = &retry {
+                    output.wrong_peer(&targets.get(*index).addr, &obtained);
+                }
+            }
+            SwarmEvent::OutgoingConnectionError { connection_id, error, .. } => {
+                let kind = DialErrorKind::of(&error);
+                retry = targets.dial_failed(connection_id, kind);
+                record_unreachable(store.as_ref(), &targets, &retry, &error, &output);
+                if let Some(Retry::After { index, .. } | Retry::GiveUp { index }) = &retry {
+                    output.dial_failed(&targets.get(*index).addr, Some(kind), &error);
+                }
+            }
+            SwarmEvent::Behaviour(BehaviourEvent::Mdns(mdns::Event::Discovered(discovered))) => {
+                for (peer_id, address) in &discovered {
+                    output.discovered(peer_id, address);
+                }
+            }
+            SwarmEvent::Behaviour(BehaviourEvent::RelayClient(relay::client::Event::ReservationReqAccepted {
+                relay_peer_id,
+                renewal,
+                ..
+            })) => output.reserved(&relay_peer_id, renewal),
+            // Peers not speaking the protocol have nothing to share.
+            SwarmEvent::Behaviour(BehaviourEvent::Exchange(event))
+                if !matches!(event.result, Err(OutboundFailure::UnsupportedProtocols)) =>
+            {
+                output.peers_exchanged(&event.peer, &event.result);
+            }
+            SwarmEvent::Behaviour(BehaviourEvent::RendezvousClient(event)) => match event {
+                rendezvous::client::Event::Registered { rendezvous_node, ttl, namespace } => {
+                    output.rendezvous_registered(&rendezvous_node, &namespace, ttl);
+                }
+                rendezvous::client::Event::RegisterFailed { rendezvous_node, namespace, error } => {
+                    output.rendezvous_failed(&rendezvous_node, "register", Some(&namespace), error);
+                }
+                rendezvous::client::Event::Discovered { registrations, .. } => {
+                    for registration in registrations.iter().filter(|registration| registration.record.peer_id() != node.local_peer_id()) {
+                        for address in registration.record.addresses() {
+                            output.discovered(&registration.record.peer_id(), address);
+                        }
+                    }
+                }
+                rendezvous::client::Event::DiscoverFailed { rendezvous_node, namespace, error } => {
+                    output.rendezvous_failed(&rendezvous_node, "discover", namespace.as_ref(), error);
+                }
+                rendezvous::client::Event::Expired { .. } => {}
+            },
+            SwarmEvent::Behaviour(BehaviourEvent::RendezvousServer(rendezvous::server::Event::PeerRegistered {
+                peer,
+                registration,
+            })) => output.rendezvous_peer_registered(&peer, &registration.namespace),
+            SwarmEvent::Behaviour(BehaviourEvent::RelayServer(event)) => match event {
+                relay::Event::ReservationReqAccepted { src_peer_id, renewed: false } => output.relay_reservation(&src_peer_id),
+                relay::Event::CircuitReqAccepted { src_peer_id, dst_peer_id } => output.relay_circuit(&src_peer_id, &dst_peer_id),
+                relay::Event::ReservationReqDenied { src_peer_id } => output.relay_denied(&src_peer_id, None),
+                relay::Event::CircuitReqDenied { src_peer_id, dst_peer_id } => output.relay_denied(&src_peer_id, Some(&dst_peer_id)),
+                _ => {}
+            },
+            SwarmEvent::Behaviour(BehaviourEvent::Identify(identify::Event::Received { peer_id, info })) => {
+                output.identified(&peer_id, &info);
+                if let Some(report) = &mut report {
+                    report.identified(peer_id, &info.agent_version);
+                }
+                let peer_labels = labels::parse(&info.agent_version);
+                if let Some(target) = targets.get_mut(&peer_id) {
+                    target.labels = peer_labels.clone();
+                }
+                output.label_peer(peer_id, peer_labels);
+            }
+            SwarmEvent::Behaviour(BehaviourEvent::Autonat(autonat::Event::StatusChanged { new, .. })) => {
+                output.reachability(&new);
+            }
+            // Without serving, every request is refused, which isn't worth reporting.
+            SwarmEvent::Behaviour(BehaviourEvent::Autonat(autonat::Event::InboundProbe(event))) if autonat_server => match event {
+                autonat::InboundProbeEvent::Response { peer, address, .. } => output.dial_back(&peer, Ok(&address)),
+                autonat::InboundProbeEvent::Error { peer, error, .. } => output.dial_back(&peer, Err(&error)),
+                autonat::InboundProbeEvent::Request { .. } => {}
+            },
+            SwarmEvent::Behaviour(BehaviourEvent::Upnp(event)) => output.port_mapping(&event),
+            SwarmEvent::Behaviour(BehaviourEvent::Kademlia(kad::Event::OutboundQueryProgressed { id, step, .. })) if step.last => {
+                // Dial the peer if the lookup found it but didn't leave a connection.
+                if let Some(index) = targets.lookup_finished(id) {
+                    let target = targets.get(index);
+                    let peer_id = target.lookup_peer_id().expect("lookups are only started for peer ids");
+                    if !node.is_connected(&peer_id) {
+                        match node.dial_peer(peer_id) {
+                            Ok(connection_id) => targets.redialed(index, connection_id),
+                            Err(e) => {
+                                output.dial_failed(&target.addr, e.dial_error_kind(), &e);
+                                retry = Some(targets.lookup_failed(index));
+                            }
+                        }
+                    }
+                }
+            }
+            SwarmEvent::Behaviour(BehaviourEvent::Dcutr(dcutr::Event { remote_peer_id, result })) => {
+                output.hole_punch(&remote_peer_id, &result);
+            }
+            SwarmEvent::Behaviour(BehaviourEvent::Echo(event)) => {
+                if !settings.quiet {
+                    output.echo(&event.peer, event.size, &event.result);
+                }
+                if let Some(target) = targets.get_mut(&event.peer) {
+                    target.record_echo(&event.result);
+                }
+            }
+            SwarmEvent::Behaviour(BehaviourEvent::Clock(event)) => {
+                if !settings.quiet {
+                    output.clock(&event.peer, &event.result);
+                }
+                if let (Some(target), Ok(sample)) = (targets.get_mut(&event.peer), event.result) {
+                    target.record_clock(sample);
+                }
+            }
+            SwarmEvent::Behaviour(BehaviourEvent::Ping(event)) => {
+                let connection = targets.connection(event.connection).cloned();
+                let relayed = connection.as_ref().is_some_and(|connection| connection.relayed);
+                let (mut transition, mut breach, mut warmup) = (None, None, false);
+                let jitter = connection.as_ref().and_then(|connection| {
+                    let target = targets.get_by_connection(connection.id)?;
+                    warmup = target.warming_up();
+                    transition = target.record(connection, &event.result).map(|t| (target.label(), t));
+                    breach = target.check(&settings.thresholds).map(|reasons| (target.label(), reasons));
+                    target.stats.jitter()
+                });
+                // Peers done with their count stay connected, but unpinged.
+                if targets.peer_done(&event.peer, count) && !node.is_peer_paused(&event.peer) {
+                    node.set_peer_paused(event.peer, true);
+                }
+                if !settings.quiet {
+                    // Name the connection when the peer has several, to tell the paths apart.
+                    let parallel = targets.connections_to(&event.peer) > 1;
+                    output.ping(&event.peer, connection.as_ref(), parallel, warmup, &event.result, jitter);
+                    if let Some((relayed, direct)) = targets.detour(event.connection).filter(|_| event.result.is_ok()) {
+                        output.relay_detour(direct.label(), &relayed.stats, &direct.stats);
+                    }
+                }
+                if let Some((label, transition)) = transition {
+                    output.health_changed(&label, Some(&event.peer), &transition);
+                    if let Some(report) = &mut report {
+                        report.health_changed(label.clone(), Some(&event.peer), &transition);
+                    }
+                    if let Some(webhook) = &webhook {
+                        webhook.health_changed(label, Some(&event.peer), &transition);
+                    }
+                }
+                if let (Some(webhook), Some((label, reasons))) = (&webhook, breach) {
+                    webhook.threshold_breached(label, Some(&event.peer), reasons);
+                }
+                if let Some(index) = targets.dead(event.connection) {
+                    let (target, policy) = (targets.get(index), settings.policy);
+                    let failures = policy.max_failures.expect("targets are only dead with a limit");
+                    output.peer_dead(target.label(), &event.peer, failures, policy.evict);
+                    // Without `--evict`, the connection is only renewed by re-dialing.
+                    if policy.evict {
+                        node.evict(event.peer);
+                    } else {
+                        node.disconnect(event.peer);
+                    }
+                }
+                if let Some(timing) = node.take_connection_timing(event.connection) {
+                    output.connection_timing(&event.peer, &timing);
+                }
+                if let Some(otlp) = otlp {
+                    otlp.record(&event.peer, relayed, &event.result);
+                }
+                if let Some(store) = &store {
+                    if let Err(e) = store.record(&event.peer, relayed, &event.result) {
+                        output.write_failed(store.path(), &e);
+                    }
+                }
+                if let (Some(book), Ok(rtt)) = (&address_book, &event.result) {
+                    if let Err(e) = book.answered(&event.peer, *rtt) {
+                        output.write_failed(book.path(), &e);
+                    }
+                }
+            }
             _ => {} // Ignore other events.
         }
+
+        if let Some(Retry::After { index, .. } | Retry::GiveUp { index }) = &retry {
+            if let Some(transition) = targets.lost(*index) {
+                let target = targets.get(*index);
+                output.health_changed(target.label(), target.peer_id.as_ref(), &transition);
+                if let Some(report) = &mut report {
+                    report.health_changed(target.label(), target.peer_id.as_ref(), &transition);
+                }
+                if let Some(webhook) = &webhook {
+                    webhook.health_changed(target.label(), target.peer_id.as_ref(), &transition);
+                }
+            }
+        }
+        if let Some(retry) = retry {
+            schedule_retry(retry, &targets, &output, &mut redials);
+        }
+        if !settings.daemon && targets.all_done(count) {
+            break;
+        }
+    }
+    drop(control);
+    if let Some(systemd) = &systemd {
+        systemd.stopping();
+    }
+
+    let nat_status = node.nat_status();
+    let matrix = node.latency_matrix().cloned();
+    node.shutdown(SHUTDOWN_GRACE).await;
+    if let Some(webhook) = webhook {
+        webhook.flush().await;
+    }
+
+    // Restore the terminal and print the final report there.
+    let output = match dashboard.take() {
+        Some(_) if settings.out_file.is_none() => Output::new(settings.output),
+        _ => output,
+    };
+
+    let mut failed = !targets.all_answered();
+    print_statistics(&targets, &node, &output, echo_size);
+    for target in targets.iter() {
+        for reason in target.thresholds(&settings.thresholds).violations(&target.stats) {
+            output.threshold_failed(target.label(), &reason);
+            failed = true;
+        }
+    }
+    output.total_traffic(None, &node.total_traffic());
+    if let Some(matrix) = matrix.filter(|m| !m.is_empty()) {
+        output.latency_matrix(&matrix);
+    }
+    output.reachability(&nat_status);
+    if let Some(report) = &report {
+        report.write(&node, &targets, &settings.thresholds, &nat_status, !failed)?;
+    }
+    Ok(if failed { ExitCode::FAILURE } else { ExitCode::SUCCESS })
+}
+
+/// Returns the persistent identity if one was requested, otherwise a random one.
+fn load_keypair(settings: &Settings) -> Result<identity::Keypair, PingError> {
+    match &settings.identity {
+        Some(path) => keyfile::load_or_generate(path),
+        None => Ok(identity::Keypair::generate_ed25519()),
+    }
+}
+
+/// Returns the output to `--out-file` if given, otherwise to stdout.
+fn open_output(settings: &Settings) -> Result<Output, String> {
+    match &settings.out_file {
+        Some(path) => Output::to_file(settings.output, path).map_err(|e| format!("{}: {e}", path.display())),
+        None => Ok(Output::new(settings.output)),
+    }
+}
+
+/// Returns the addresses to listen on without `listen` addresses: a random
+/// port of every enabled transport and IP family, at the bound addresses if
+/// any.
+fn default_listen_addrs(settings: &Settings) -> Vec<Multiaddr> {
+    let bound = &settings.node.local_binding.addresses;
+    let mut ips: Vec<IpAddr> = Vec::new();
+    if !settings.no_ipv4 {
+        ips.push(bound.iter().copied().find(IpAddr::is_ipv4).unwrap_or(Ipv4Addr::UNSPECIFIED.into()));
+    }
+    if !settings.no_ipv6 {
+        ips.push(bound.iter().copied().find(IpAddr::is_ipv6).unwrap_or(Ipv6Addr::UNSPECIFIED.into()));
+    }
+    // Listening on all addresses of a family without a bound one would take
+    // inbound connections from every uplink.
+    if !bound.is_empty() {
+        ips.retain(|ip| !ip.is_unspecified());
+    }
+    let transports = &settings.node.transports;
+    transports.iter().flat_map(|transport| ips.iter().map(|ip| transport.listen_addr(*ip))).collect()
+}
+
+/// Dials every peer in `settings` and waits for one answered ping from each,
+/// printing its RTT, for at most the deadline or else the dial timeout plus
+/// the ping timeout.
+///
+/// The exit code is a failure unless every peer answered in time.
+async fn oneshot(settings: Settings) -> Result<ExitCode, Box<dyn Error>> {
+    let keypair = load_keypair(&settings)?;
+    let wait = settings.deadline.unwrap_or(settings.node.dial_timeout + settings.node.ping_timeout);
+    let output = open_output(&settings)?;
+    let mut node = PingNode::with_keypair(keypair, settings.node)?;
+    let answered = ping_once(&mut node, settings.peers, wait, &output, false).await?;
+    node.shutdown(SHUTDOWN_GRACE).await;
+    Ok(if answered { ExitCode::SUCCESS } else { ExitCode::FAILURE })
+}
+
+/// Checks that every peer in `addrs` answers a ping within `within`, and with
+/// `local` also the node configured in the `--config` file.
+///
+/// Only failures are printed, to stderr. The exit code is 0 if all peers
+/// answered, 1 if any didn't, and 2 if the settings are invalid.
+async fn healthcheck(cli: &Cli, addrs: &[Multiaddr], local: bool, within: Duration) -> ExitCode {
+    const UNHEALTHY: u8 = 1;
+    const INVALID: u8 = 2;
+
+    let settings = match Settings::resolve(cli).and_then(|settings| {
+        let mut peers: Vec<NamedPeer> = addrs.iter().cloned().map(NamedPeer::from).collect();
+        if local {
+            peers.extend(local_addrs(&settings)?.into_iter().map(NamedPeer::from));
+        }
+        if peers.is_empty() {
+            return Err("no peers to check; pass their addresses or --self".into());
+        }
+        Ok(Settings { peers, ..settings })
+    }) {
+        Ok(settings) => settings,
+        Err(e) => {
+            eprintln!("{e}");
+            return ExitCode::from(INVALID);
+        }
+    };
+    // A node of its own, as the node checked with `--self` couldn't dial itself.
+    let mut node = match PingNode::with_config(settings.node) {
+        Ok(node) => node,
+        Err(e) => {
+            eprintln!("{e}");
+            return ExitCode::from(INVALID);
+        }
+    };
+    let output = Output::with_writer(settings.output, Box::new(io::stderr()));
+    let answered = ping_once(&mut node, settings.peers, within, &output, true).await;
+    node.shutdown(SHUTDOWN_GRACE).await;
+    match answered {
+        Ok(true) => ExitCode::SUCCESS,
+        Ok(false) => ExitCode::from(UNHEALTHY),
+        Err(e) => {
+            eprintln!("{e}");
+            ExitCode::from(INVALID)
+        }
+    }
+}
+
+/// Returns the addresses the node configured in `settings` can be reached at
+/// on this host, ending with its PeerId if it has a persistent identity.
+fn local_addrs(settings: &Settings) -> Result<Vec<Multiaddr>, Box<dyn Error>> {
+    if settings.listen.is_empty() {
+        return Err("--self needs `listen` addresses with fixed ports in the configuration file".into());
+    }
+    let peer_id = match &settings.identity {
+        Some(path) => Some(keyfile::read(path)?.public().to_peer_id()),
+        None => None,
+    };
+    settings
+        .listen
+        .iter()
+        .map(|addr| {
+            let mut local = Multiaddr::empty();
+            for protocol in addr.iter() {
+                local.push(match protocol {
+                    Protocol::Ip4(ip) if ip.is_unspecified() => Protocol::Ip4(Ipv4Addr::LOCALHOST),
+                    Protocol::Ip6(ip) if ip.is_unspecified() => Protocol::Ip6(Ipv6Addr::LOCALHOST),
+                    Protocol::Tcp(0) | Protocol::Udp(0) => {
+                        return Err(format!("--self can't check {addr}, which has a random port").into());
+                    }
+                    protocol => protocol,
+                });
+            }
+            Ok(match peer_id {
+                Some(peer_id) => local.with(Protocol::P2p(peer_id)),
+                None => local,
+            })
+        })
+        .collect()
+}
+
+/// Dials each of `peers` and waits for one answered ping from each, printing
+/// its RTT unless `quiet`, for at most `wait`; returns whether all of them
+/// answered.
+async fn ping_once(
+    node: &mut PingNode,
+    peers: Vec<NamedPeer>,
+    wait: Duration,
+    output: &Output,
+    quiet: bool,
+) -> Result<bool, Box<dyn Error>> {
+    // Peers still to answer, by the connection dialed to them.
+    let mut pending = HashMap::new();
+    for peer in peers {
+        pending.insert(node.dial(peer.addr.clone())?, peer);
+    }
+    let mut failed = false;
+
+    let deadline = tokio::time::sleep(wait);
+    tokio::pin!(deadline);
+    while !pending.is_empty() {
+        let event = tokio::select! {
+            event = node.next_event() => event,
+            _ = &mut deadline => break,
+        };
+        match event {
+            SwarmEvent::ConnectionEstablished { peer_id, connection_id, .. } => {
+                if let Some(name) = pending.get(&connection_id).and_then(|peer| peer.name.as_deref()) {
+                    output.name_peer(peer_id, name);
+                }
+            }
+            SwarmEvent::OutgoingConnectionError { connection_id, error, .. } => {
+                if let Some(peer) = pending.remove(&connection_id) {
+                    output.dial_failed(&peer.addr, Some(DialErrorKind::of(&error)), &error);
+                    failed = true;
+                }
+            }
+            SwarmEvent::Behaviour(BehaviourEvent::Ping(ping::Event { peer, connection, result: result @ Ok(_) })) => {
+                if let Some(dialed) = pending.remove(&connection).filter(|_| !quiet) {
+                    let connection = Connection { id: connection, local: None, remote: dialed.addr, relayed: false };
+                    output.ping(&peer, Some(&connection), false, false, &result, None);
+                }
+            }
+            _ => {}
+        }
+    }
+    for peer in pending.values() {
+        output.no_answer(&peer.addr, wait);
+        failed = true;
+    }
+    Ok(!failed)
+}
+
+/// Measures the upload and then the download throughput to the peer at `addr`
+/// for `duration` each, pinging it meanwhile, and prints the results.
+///
+/// The exit code is a failure if the peer can't be reached or either transfer
+/// fails.
+async fn bench(settings: Settings, addr: &Multiaddr, duration: Duration) -> Result<ExitCode, Box<dyn Error>> {
+    if duration > bench::MAX_DURATION {
+        return Err(format!("--duration must be at most {}", humantime::format_duration(bench::MAX_DURATION)).into());
+    }
+    let keypair = load_keypair(&settings)?;
+    let output = open_output(&settings)?;
+    let mut node = PingNode::with_keypair(keypair, settings.node)?;
+    output.started(&node.local_peer_id());
+
+    let dial = node.dial(addr.clone())?;
+    output.dialing(addr);
+    // Pings answered while the transfers load the connection.
+    let mut pings = PingStats::default();
+    let mut failed = true;
+
+    let shutdown = shutdown_signal();
+    tokio::pin!(shutdown);
+    loop {
+        let event = tokio::select! {
+            event = node.next_event() => event,
+            _ = &mut shutdown => break,
+        };
+        match event {
+            SwarmEvent::ConnectionEstablished { peer_id, connection_id, endpoint, .. } if connection_id == dial => {
+                output.connected(&peer_id, endpoint.get_remote_address());
+                node.bench(peer_id, bench::Direction::Upload, duration);
+            }
+            SwarmEvent::OutgoingConnectionError { connection_id, error, .. } if connection_id == dial => {
+                output.dial_failed(addr, Some(DialErrorKind::of(&error)), &error);
+                break;
+            }
+            SwarmEvent::ConnectionClosed { peer_id, cause, num_established: 0, .. } => {
+                output.disconnected(&peer_id, cause.as_ref().map(|e| e as &dyn std::fmt::Display));
+                break;
+            }
+            SwarmEvent::Behaviour(BehaviourEvent::Ping(event)) => {
+                match event.result {
+                    Ok(rtt) => pings.record_success(rtt),
+                    Err(_) => pings.record_failure(),
+                }
+                let connection = Connection { id: event.connection, local: None, remote: addr.clone(), relayed: false };
+                output.ping(&event.peer, Some(&connection), false, false, &event.result, pings.jitter());
+            }
+            SwarmEvent::Behaviour(BehaviourEvent::Bench(event)) => {
+                output.bench(&event.peer, event.direction, &event.result);
+                match (event.direction, event.result) {
+                    (bench::Direction::Upload, Ok(_)) => node.bench(event.peer, bench::Direction::Download, duration),
+                    (bench::Direction::Download, Ok(_)) => {
+                        failed = false;
+                        break;
+                    }
+                    (_, Err(_)) => break,
+                }
+            }
+            _ => {}
+        }
+    }
+    node.shutdown(SHUTDOWN_GRACE).await;
+
+    if pings.transmitted() > 0 {
+        output.summary(addr, &Labels::new(), &pings, &node.total_traffic());
+    }
+    Ok(if failed { ExitCode::FAILURE } else { ExitCode::SUCCESS })
+}
+
+/// Connects to the peer at `addrs` over each transport and security combination
+/// in turn, pinging it `count` times over each, and prints a comparison table.
+///
+/// The exit code is a failure if no combination worked.
+async fn compare(settings: Settings, addrs: &[Multiaddr], count: u64) -> Result<ExitCode, Box<dyn Error>> {
+    let keypair = load_keypair(&settings)?;
+    let output = open_output(&settings)?;
+    output.started(&keypair.public().to_peer_id());
+
+    let mut candidates = addrs.to_vec();
+    let mut rows = Vec::new();
+    for combination in compare::COMBINATIONS {
+        let Some(address) = compare::pick_address(&candidates, combination.transport, &addrs[0]) else {
+            rows.push((combination, None));
+            continue;
+        };
+        output.dialing(&address);
+        let mut learned = Vec::new();
+        let result = compare::measure(keypair.clone(), settings.node.clone(), combination, address, count, &mut learned).await;
+        for addr in learned {
+            if !candidates.contains(&addr) {
+                candidates.push(addr);
+            }
+        }
+        rows.push((combination, Some(result)));
+    }
+
+    output.comparison(&rows);
+    let worked = rows.iter().any(|(_, result)| matches!(result, Some(Ok(_))));
+    Ok(if worked { ExitCode::SUCCESS } else { ExitCode::FAILURE })
+}
+
+/// Diagnoses the connectivity to the peer at `addr`, and through the
+/// `--via-relay` if given, printing a checklist of the steps.
+///
+/// The exit code is a failure if any step failed.
+async fn doctor(settings: Settings, addr: &Multiaddr) -> Result<ExitCode, Box<dyn Error>> {
+    let keypair = load_keypair(&settings)?;
+    let output = open_output(&settings)?;
+    output.started(&keypair.public().to_peer_id());
+
+    let listen = match settings.listen.is_empty() {
+        true => default_listen_addrs(&settings),
+        false => settings.listen.clone(),
+    };
+    output.dialing(addr);
+    let checks = doctor::diagnose(keypair, settings.node, &listen, addr, settings.via_relay).await?;
+    output.checklist(&checks);
+    let failed = checks.iter().any(|check| check.status == doctor::Status::Fail);
+    Ok(if failed { ExitCode::FAILURE } else { ExitCode::SUCCESS })
+}
+
+/// Probes the protocol compatibility of the peer at `addr`; the exit code is
+/// a failure if any incompatibility was found.
+async fn probe(settings: Settings, addr: &Multiaddr) -> Result<ExitCode, Box<dyn Error>> {
+    let keypair = load_keypair(&settings)?;
+    let output = open_output(&settings)?;
+    output.started(&keypair.public().to_peer_id());
+    output.dialing(addr);
+    let findings = probe::probe(keypair, settings.node, addr).await?;
+    output.probe_findings(&findings);
+    Ok(if findings.problems.is_empty() { ExitCode::SUCCESS } else { ExitCode::FAILURE })
+}
+
+/// Looks up the peers closest to `key`, or to our own PeerId, in the DHT,
+/// pings each of them `count` times and prints them ranked by RTT.
+///
+/// Each peer has the dial timeout plus `count` ping intervals and a ping
+/// timeout to answer. The exit code is a failure unless any peer answered.
+async fn sweep(settings: Settings, key: Option<PeerId>, count: u64) -> Result<ExitCode, Box<dyn Error>> {
+    if settings.node.bootstrap.is_empty() {
+        return Err("`sweep` needs at least one --bootstrap node to join the DHT".into());
+    }
+    let keypair = load_keypair(&settings)?;
+    let pings = settings.node.ping_interval.saturating_mul(u32::try_from(count).unwrap_or(u32::MAX));
+    let wait = settings.node.dial_timeout.saturating_add(pings).saturating_add(settings.node.ping_timeout);
+    let output = open_output(&settings)?;
+    let mut node = PingNode::with_keypair(keypair, settings.node)?;
+    output.started(&node.local_peer_id());
+
+    let key = key.unwrap_or(node.local_peer_id());
+    output.looking_up(&key);
+    let neighbors = sweep::run(&mut node, key, count, wait).await?;
+    node.shutdown(SHUTDOWN_GRACE).await;
+    output.sweep(&key, &neighbors);
+    let answered = neighbors.iter().any(|neighbor| neighbor.stats.received() > 0);
+    Ok(if answered { ExitCode::SUCCESS } else { ExitCode::FAILURE })
+}
+
+/// Pings `peers` virtual peers answering under `impairment` `count` times each,
+/// and prints their statistics next to what they simulate.
+///
+/// Pings time out after the longest simulated delay plus a second unless
+/// `--timeout` is given. The exit code is a failure if any statistic is off.
+async fn simulate(
+    cli: &Cli,
+    mut settings: Settings,
+    impairment: Impairment,
+    peers: usize,
+    count: u64,
+) -> Result<ExitCode, Box<dyn Error>> {
+    settings.node.ping_interval = cli.interval.unwrap_or(simulate::INTERVAL);
+    settings.node.ping_timeout = cli
+        .timeout
+        .unwrap_or(impairment.latency + impairment.jitter + Duration::from_secs(1));
+    let output = open_output(&settings)?;
+    let outcomes = simulate::run(settings.node, impairment, peers, count).await?;
+    output.simulation(&outcomes);
+    let passed = outcomes.iter().flat_map(|outcome| &outcome.checks).all(simulate::Check::passed);
+    Ok(if passed { ExitCode::SUCCESS } else { ExitCode::FAILURE })
+}
+
+/// Prints the report of every peer with results in the store between `from`
+/// and `to`.
+fn report(settings: Settings, from: Option<SystemTime>, to: Option<SystemTime>, sla: bool) -> Result<ExitCode, Box<dyn Error>> {
+    let path = settings.store.as_deref().ok_or("`report` needs the --store to read")?;
+    let store = Store::open_existing(path)?;
+    let output = open_output(&settings)?;
+    if sla {
+        let slas = store.sla(from, to)?;
+        if slas.is_empty() {
+            return Err("no results stored in the selected time window".into());
+        }
+        for sla in &slas {
+            output.sla(sla);
+        }
+        return Ok(ExitCode::SUCCESS);
+    }
+    let reports = store.report(from, to)?;
+    if reports.is_empty() {
+        return Err("no results stored in the selected time window".into());
+    }
+    for report in &reports {
+        output.report(report);
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Sends a command to the daemon and prints its answer.
+async fn ctl(settings: Settings, command: &CtlCommand) -> Result<ExitCode, Box<dyn Error>> {
+    let request = match command {
+        CtlCommand::AddPeer { peer } => Request::AddPeer { peer: peer.to_string() },
+        CtlCommand::RemovePeer { peer } => Request::RemovePeer { peer: peer.clone() },
+        CtlCommand::Pause { peer } => Request::Pause { peer: peer.clone() },
+        CtlCommand::Resume { peer } => Request::Resume { peer: peer.clone() },
+        CtlCommand::Stats => Request::Stats,
+        CtlCommand::Shutdown => Request::Shutdown,
+    };
+    match control::send(&settings.control_socket, &request).await? {
+        Response::Ok => {}
+        Response::Stats { targets } => {
+            let output = Output::new(settings.output);
+            for stats in targets {
+                output.target_stats(stats);
+            }
+        }
+        Response::NotFound { message } | Response::Error { message } => return Err(message.into()),
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Carries out a command received through the control socket or the HTTP API.
+///
+/// Pausing and resuming every peer only sets [`Targets::all_paused`], for the
+/// caller to apply with the measurement windows.
+fn handle_command(request: Request, node: &mut PingNode, targets: &mut Targets, output: &Output) -> Response {
+    let error = |message: String| Response::Error { message };
+    match request {
+        Request::AddPeer { peer } => {
+            let peer: NamedPeer = match peer.parse() {
+                Ok(peer) => peer,
+                Err(e) => return error(e),
+            };
+            if targets.find(&peer.addr.to_string()).is_some() {
+                return error(format!("already pinging {}", peer.addr));
+            }
+            match node.dial(peer.addr.clone()) {
+                Ok(connection_id) => {
+                    output.dialing(&peer.addr);
+                    let index = targets.add(peer.addr, peer.name, connection_id);
+                    dial_detour(index, node, targets, output);
+                    Response::Ok
+                }
+                Err(e) => error(format!("cannot dial {}: {e}", peer.addr)),
+            }
+        }
+        Request::RemovePeer { peer } => match targets.find(&peer) {
+            Some(index) => {
+                if let Some(peer_id) = targets.remove(index) {
+                    node.disconnect(peer_id);
+                }
+                Response::Ok
+            }
+            None => Response::NotFound { message: format!("not pinging {peer}") },
+        },
+        Request::Pause { peer: None } => {
+            targets.all_paused = true;
+            output.paused(None);
+            Response::Ok
+        }
+        // Resuming all peers also resumes those paused one by one.
+        Request::Resume { peer: None } => {
+            targets.all_paused = false;
+            for index in 0..targets.iter().count() {
+                if let Some(peer_id) = targets.set_paused(index, false) {
+                    node.set_peer_paused(peer_id, false);
+                }
+            }
+            output.resumed(None);
+            Response::Ok
+        }
+        Request::Pause { peer: Some(ref peer) } | Request::Resume { peer: Some(ref peer) } => {
+            let pause = matches!(request, Request::Pause { .. });
+            match targets.find(peer) {
+                Some(index) => {
+                    // Peers not connected yet are paused once they are.
+                    if let Some(peer_id) = targets.set_paused(index, pause) {
+                        node.set_peer_paused(peer_id, pause);
+                    }
+                    let label = Some(targets.get(index).label());
+                    if pause {
+                        output.paused(label);
+                    } else {
+                        output.resumed(label);
+                    }
+                    Response::Ok
+                }
+                None => Response::NotFound { message: format!("not pinging {peer}") },
+            }
+        }
+        Request::Stats => Response::Stats {
+            targets: targets
+                .iter()
+                .map(|target| TargetStats {
+                    target: target.label(),
+                    address: target.addr.to_string(),
+                    peer_id: target.peer_id.map(|peer_id| peer_id.to_string()),
+                    connected: target.peer_id.is_some_and(|peer_id| node.is_connected(&peer_id)),
+                    paused: target.paused || targets.all_paused,
+                    health: target.health().map(|health| health.to_string()),
+                    transmitted: target.stats.transmitted(),
+                    received: target.stats.received(),
+                    loss_percent: target.stats.loss_percent(),
+                    avg_us: target.stats.avg().map(|rtt| rtt.as_micros() as u64),
+                })
+                .collect(),
+        },
+        Request::Shutdown => Response::Ok,
+    }
+}
+
+/// Drives the dashboard, if any; resolves to `true` once the user quits it.
+async fn update_dashboard(dashboard: &mut Option<Dashboard>) -> io::Result<bool> {
+    match dashboard {
+        Some(dashboard) => dashboard.update().await,
+        None => future::pending().await,
+    }
+}
+
+/// Resolves on the next tick of `interval`, or never without one.
+async fn tick(interval: &mut Option<tokio::time::Interval>) {
+    match interval {
+        Some(interval) => {
+            interval.tick().await;
+        }
+        None => future::pending().await,
+    }
+}
+
+/// Prints the statistics of every target so far.
+fn print_statistics(targets: &Targets, node: &PingNode, output: &Output, echo_size: Option<usize>) {
+    for target in targets.iter() {
+        let traffic = target.peer_id.map_or_else(Traffic::default, |peer_id| node.traffic(&peer_id));
+        output.summary(target.label(), &target.labels, &target.stats, &traffic);
+        // The relayed path of a target pinged both ways is compared with the direct one instead.
+        if target.relayed.transmitted() > 0 && target.detour_of.is_none() {
+            output.path_summary(target.label(), &target.relayed, &target.direct);
+        }
+        if target.connections.len() > 1 {
+            for (connection, stats) in &target.connections {
+                output.connection_summary(target.label(), connection, stats);
+            }
+        }
+        if let Some(direct) = target.detour_of.map(|index| targets.get(index)) {
+            output.relay_detour(direct.label(), &target.stats, &direct.stats);
+        }
+        if let Some(size) = target.overrides.size.or(echo_size).filter(|_| target.echo.transmitted() > 0) {
+            output.echo_summary(target.label(), size, &target.echo);
+        }
+        if let Some(best) = &target.clock {
+            output.clock_summary(target.label(), target.clock_samples, best);
+        }
+        if !target.dial_failures.is_empty() {
+            output.dial_failures(target.label(), &target.dial_failures);
+        }
+    }
+}
+
+/// Turns answers later than the timeout of their target or, for other peers,
+/// than `timeout` into timeouts; the node waits for the longest of all.
+fn late_as_timeout(event: SwarmEvent<BehaviourEvent>, targets: &Targets, timeout: Duration) -> SwarmEvent<BehaviourEvent> {
+    match event {
+        SwarmEvent::Behaviour(BehaviourEvent::Ping(ping::Event { peer, connection, result: Ok(rtt) }))
+            if rtt > targets.timeout_of(connection).unwrap_or(timeout) =>
+        {
+            let result = Err(ping::Failure::Timeout);
+            SwarmEvent::Behaviour(BehaviourEvent::Ping(ping::Event { peer, connection, result }))
+        }
+        event => event,
+    }
+}
+
+/// Pings while a window of `windows` is open, unless all targets are paused,
+/// and pauses otherwise, reporting the results of a window once it closes.
+/// Returns when to check again: when the open window closes or the next one
+/// opens, if any does.
+fn update_window(
+    windows: &Windows,
+    opened: &mut Option<SystemTime>,
+    node: &mut PingNode,
+    targets: &mut Targets,
+    output: &Output,
+) -> Option<SystemTime> {
+    let now = SystemTime::now();
+    match windows.state(now) {
+        State::Open(until) => {
+            if opened.is_none() {
+                *opened = Some(now);
+                for target in targets.iter_mut() {
+                    target.window = PingStats::default();
+                }
+                node.set_paused(targets.all_paused);
+                output.window_opened(until);
+            }
+            Some(until)
+        }
+        State::Closed(next) => {
+            if let Some(opened) = opened.take() {
+                for target in targets.iter() {
+                    output.window_summary(target.label(), &target.labels, opened, now, &target.window);
+                }
+            }
+            if !node.is_paused() {
+                node.set_paused(true);
+                output.window_closed(next);
+            }
+            next
+        }
+    }
+}
+
+/// Returns the instant of the wall-clock `time`.
+fn instant_at(time: SystemTime) -> tokio::time::Instant {
+    tokio::time::Instant::now() + time.duration_since(SystemTime::now()).unwrap_or_default()
+}
+
+/// Also dials the target at `index` through the `--via-relay`, if it is to
+/// be pinged both ways.
+fn dial_detour(index: usize, node: &mut PingNode, targets: &mut Targets, output: &Output) {
+    let Some(addr) = targets.detour_addr(index) else {
+        return;
+    };
+    match node.dial(addr.clone()) {
+        Ok(connection_id) => {
+            output.dialing(&addr);
+            targets.add_detour(index, addr, connection_id);
+        }
+        Err(e) => output.dial_failed(&addr, e.dial_error_kind(), &e),
+    }
+}
+
+/// Schedules re-dialing the target `retry` is for, or reports giving up on it.
+fn schedule_retry(retry: Retry, targets: &Targets, output: &Output, redials: &mut FuturesUnordered<BoxFuture<'static, usize>>) {
+    match retry {
+        Retry::After { index, delay, attempt } => {
+            output.redialing(&targets.get(index).addr, attempt, delay);
+            redials.push(tokio::time::sleep(delay).map(move |_| index).boxed());
+        }
+        Retry::GiveUp { index } => output.gave_up(&targets.get(index).addr),
+    }
+}
+
+/// Stores that the target `retry` is for went unreachable because of `error`,
+/// if there is a store and the target's PeerId is known; a failed write is
+/// reported, and the run goes on.
+fn record_unreachable(store: Option<&Store>, targets: &Targets, retry: &Option<Retry>, error: &dyn std::fmt::Display, output: &Output) {
+    let (Some(store), Some(Retry::After { index, .. } | Retry::GiveUp { index })) = (store, retry) else {
+        return;
+    };
+    if let Some(peer_id) = targets.get(*index).peer_id {
+        if let Err(e) = store.record_unreachable(&peer_id, &error.to_string()) {
+            output.write_failed(store.path(), &e);
+        }
+    }
+}
+
+/// Applies the changes of the peers file, if any, dialing the peers added to
+/// it and dropping those removed.
+fn reload_peers_file(file: &mut PeersFile, node: &mut PingNode, targets: &mut Targets, output: &Output) {
+    match file.reload() {
+        Ok(Some(change)) => apply_change(file.path(), change, node, targets, output),
+        Ok(None) => {}
+        Err(e) => output.peers_file_failed(file.path(), &e),
+    }
+}
+
+/// Re-reads the `peers` of the configuration file at `path`, last read as
+/// `peers`, and applies their changes unless one has a `timeout` shorter than
+/// the global `timeout`; the rest of the file only takes effect on a restart.
+fn reload_config_peers(
+    path: &Path,
+    peers: &mut Vec<NamedPeer>,
+    timeout: Duration,
+    node: &mut PingNode,
+    targets: &mut Targets,
+    output: &Output,
+) {
+    let config = match FileConfig::read(path) {
+        Ok(config) => config,
+        Err(e) => return output.config_reload_failed(&e.to_string()),
+    };
+    if let Err(e) = check_peer_timeouts(&config.peers, timeout) {
+        return output.config_reload_failed(&e);
+    }
+    let change = Change::between(peers, &config.peers);
+    *peers = config.peers;
+    if !change.added.is_empty() || !change.removed.is_empty() {
+        apply_change(path, change, node, targets, output);
+    }
+}
+
+/// Dials the peers added to the file at `path` and drops those removed.
+fn apply_change(path: &Path, change: Change, node: &mut PingNode, targets: &mut Targets, output: &Output) {
+    output.peers_file_changed(path, &change.added, &change.removed);
+    for peer in &change.removed {
+        let request = Request::RemovePeer { peer: peer.addr.to_string() };
+        if let Response::NotFound { message } | Response::Error { message } = handle_command(request, node, targets, output) {
+            output.peers_file_failed(path, &message);
+        }
+    }
+    for peer in &change.added {
+        match handle_command(Request::AddPeer { peer: peer.to_string() }, node, targets, output) {
+            Response::Error { message } => output.peers_file_failed(path, &message),
+            _ => {
+                if let Some(index) = targets.find(&peer.addr.to_string()) {
+                    targets.set_overrides(index, peer.overrides);
+                }
+            }
+        }
+    }
+}
+
+/// A one-line status for systemd, such as `pinging 3 peers`.
+fn status(targets: &Targets) -> String {
+    match targets.iter().count() {
+        0 => "answering pings".to_owned(),
+        1 => "pinging 1 peer".to_owned(),
+        peers => format!("pinging {peers} peers"),
+    }
+}
+
+/// Resolves on the next delivery of `signal`, or never if it can't be caught.
+async fn received(signal: &mut Option<Signal>) {
+    match signal {
+        Some(signal) => {
+            signal.recv().await;
+        }
+        None => future::pending().await,
+    }
+}
+
+/// Resolves on Ctrl-C or SIGTERM.
+async fn shutdown_signal() {
+    let mut sigterm = signal(SignalKind::terminate()).ok();
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => {}
+        () = received(&mut sigterm) => {}
     }
 }
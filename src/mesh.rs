@@ -0,0 +1,93 @@
+//! Latency matrix shared between nodes over gossipsub.
+
+use libp2p::gossipsub::IdentTopic;
+use libp2p::PeerId;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::time::Duration;
+
+/// Topic the latency reports are published on.
+pub(crate) const MESH_TOPIC: &str = "/libp2p-ping-tut/latency/1";
+
+/// Returns the gossipsub topic of the latency mesh.
+pub(crate) fn topic() -> IdentTopic {
+    IdentTopic::new(MESH_TOPIC)
+}
+
+/// The RTTs one node measured to its peers, as published on the wire.
+#[derive(Serialize, Deserialize)]
+struct LatencyReport {
+    /// Latest RTT in microseconds, keyed by the base58 peer id.
+    rtts_us: BTreeMap<String, u64>,
+}
+
+/// The latest RTT measured from each node of the mesh to each of its peers.
+#[derive(Debug, Clone, Default)]
+pub struct LatencyMatrix {
+    rows: BTreeMap<PeerId, BTreeMap<PeerId, Duration>>,
+}
+
+impl LatencyMatrix {
+    /// Records an RTT measured by `from` to `to`.
+    pub fn record(&mut self, from: PeerId, to: PeerId, rtt: Duration) {
+        self.rows.entry(from).or_default().insert(to, rtt);
+    }
+
+    /// Drops the RTT from `from` to `to`, e.g. after they disconnected.
+    pub fn forget(&mut self, from: &PeerId, to: &PeerId) {
+        if let Some(row) = self.rows.get_mut(from) {
+            row.remove(to);
+        }
+    }
+
+    /// Returns the latest RTT measured by `from` to `to`, if any.
+    pub fn get(&self, from: &PeerId, to: &PeerId) -> Option<Duration> {
+        self.rows.get(from)?.get(to).copied()
+    }
+
+    /// Returns every node appearing in the matrix, as measuring or measured
+    /// peer, in a stable order.
+    pub fn peers(&self) -> Vec<PeerId> {
+        let mut peers: Vec<PeerId> = self
+            .rows
+            .iter()
+            .flat_map(|(from, row)| std::iter::once(from).chain(row.keys()))
+            .copied()
+            .collect();
+        peers.sort();
+        peers.dedup();
+        peers
+    }
+
+    /// Returns `true` if no RTT has been recorded yet.
+    pub fn is_empty(&self) -> bool {
+        self.rows.values().all(BTreeMap::is_empty)
+    }
+
+    /// Encodes the row of `from` as a report to publish.
+    pub(crate) fn encode_row(&self, from: &PeerId) -> Vec<u8> {
+        let report = LatencyReport {
+            rtts_us: self
+                .rows
+                .get(from)
+                .into_iter()
+                .flatten()
+                .map(|(to, rtt)| (to.to_base58(), rtt.as_micros() as u64))
+                .collect(),
+        };
+        serde_json::to_vec(&report).expect("reports serialize to JSON")
+    }
+
+    /// Replaces the row of `from` with a report it published.
+    pub(crate) fn decode_row(&mut self, from: PeerId, data: &[u8]) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let report: LatencyReport = serde_json::from_slice(data)?;
+        let row = report
+            .rtts_us
+            .into_iter()
+            .map(|(to, us)| Ok((to.parse()?, Duration::from_micros(us))))
+            .collect::<Result<_, Box<dyn Error + Send + Sync>>>()?;
+        self.rows.insert(from, row);
+        Ok(())
+    }
+}
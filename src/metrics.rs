@@ -0,0 +1,178 @@
+//! Prometheus metrics recorded from swarm and protocol events.
+
+use libp2p::autonat::{self, InboundProbeError, InboundProbeEvent, ResponseError};
+use libp2p::metrics::{Metrics, Recorder, Registry};
+use libp2p::swarm::SwarmEvent;
+use libp2p::{ping, PeerId};
+use prometheus_client::encoding::EncodeLabelSet;
+use prometheus_client::metrics::counter::Counter;
+use prometheus_client::metrics::family::Family;
+use prometheus_client::metrics::gauge::Gauge;
+use std::collections::HashMap;
+use std::sync::atomic::AtomicU64;
+use std::sync::Arc;
+
+use crate::bandwidth::Bandwidth;
+use crate::ping_limit::PingCounters;
+use crate::{BehaviourEvent, DialErrorKind, PingStats};
+
+/// Percentiles exported for the RTTs of each peer, with their `quantile` labels.
+const QUANTILES: [(f64, &str); 4] = [(50.0, "0.5"), (90.0, "0.9"), (99.0, "0.99"), (99.9, "0.999")];
+
+/// Labels of the RTT percentile gauges.
+#[derive(Debug, Clone, Hash, PartialEq, Eq, EncodeLabelSet)]
+struct QuantileLabels {
+    peer_id: String,
+    quantile: String,
+}
+
+/// Labels of the AutoNAT dial-back counters.
+#[derive(Debug, Clone, Hash, PartialEq, Eq, EncodeLabelSet)]
+struct DialBackLabels {
+    outcome: &'static str,
+}
+
+/// Labels of the failed outgoing connection counters.
+#[derive(Debug, Clone, Hash, PartialEq, Eq, EncodeLabelSet)]
+struct DialFailureLabels {
+    kind: &'static str,
+}
+
+/// libp2p metrics together with the registry they are exported from.
+pub(crate) struct NodeMetrics {
+    metrics: Metrics,
+    registry: Arc<Registry>,
+    /// RTT percentiles of each peer, from the histograms in `rtts`.
+    rtt_quantiles: Family<QuantileLabels, Gauge<f64, AtomicU64>>,
+    rtts: HashMap<PeerId, PingStats>,
+    /// Peers disconnected for failing too many pings in a row.
+    evictions: Counter,
+    /// AutoNAT dial-back requests of other peers, by outcome.
+    dial_backs: Family<DialBackLabels, Counter>,
+    /// Failed outgoing connections, by kind.
+    dial_failures: Family<DialFailureLabels, Counter>,
+}
+
+impl NodeMetrics {
+    /// Creates the metrics, exporting the counts of inbound pings from `pings`
+    /// and the bytes of each peer from `bandwidth`.
+    pub(crate) fn new(pings: &PingCounters, bandwidth: &Bandwidth) -> Self {
+        let mut registry = Registry::default();
+        let metrics = Metrics::new(&mut registry);
+        let rtt_quantiles = Family::default();
+        let evictions = Counter::default();
+        let dial_backs = Family::default();
+        let dial_failures = Family::default();
+        let own = registry.sub_registry_with_prefix("libp2p_ping_tut");
+        own.register(
+            "rtt_seconds",
+            "Percentiles of the round-trip times of all pings answered by each peer",
+            rtt_quantiles.clone(),
+        );
+        own.register("inbound_pings", "Pings of other peers answered", pings.answered.clone());
+        own.register(
+            "inbound_pings_throttled",
+            "Pings of other peers answered late for going over the inbound ping limit",
+            pings.throttled.clone(),
+        );
+        own.register(
+            "inbound_ping_disconnects",
+            "Peers disconnected for going over the inbound ping limit",
+            pings.disconnects.clone(),
+        );
+        own.register(
+            "evicted_peers",
+            "Peers disconnected after failing too many pings in a row",
+            evictions.clone(),
+        );
+        own.register(
+            "autonat_dial_backs",
+            "AutoNAT dial-back requests of other peers, by outcome: dialed_back, unreachable, refused (over a limit or not serving) or error",
+            dial_backs.clone(),
+        );
+        own.register(
+            "dial_failures",
+            "Failed outgoing connections, by kind: refused, unreachable, timeout, wrong_peer, negotiation_failed, unsupported, denied or other",
+            dial_failures.clone(),
+        );
+        own.register(
+            "sent_bytes",
+            "Bytes sent over the streams of all connections with each peer; their sum is the total",
+            bandwidth.sent.clone(),
+        );
+        own.register(
+            "received_bytes",
+            "Bytes received over the streams of all connections with each peer; their sum is the total",
+            bandwidth.received.clone(),
+        );
+        Self {
+            metrics,
+            registry: Arc::new(registry),
+            rtt_quantiles,
+            rtts: HashMap::new(),
+            evictions,
+            dial_backs,
+            dial_failures,
+        }
+    }
+
+    /// Counts a peer disconnected with [`PingNode::evict`](crate::PingNode::evict).
+    pub(crate) fn record_eviction(&self) {
+        self.evictions.inc();
+    }
+
+    /// The registry to encode when serving `/metrics`.
+    pub(crate) fn registry(&self) -> Arc<Registry> {
+        self.registry.clone()
+    }
+
+    /// Updates the metrics for a swarm event and any protocol event it carries.
+    pub(crate) fn record(&mut self, event: &SwarmEvent<BehaviourEvent>) {
+        self.metrics.record(event);
+        match event {
+            SwarmEvent::Behaviour(BehaviourEvent::Ping(event)) => {
+                self.metrics.record(event);
+                self.record_rtt(event);
+            }
+            SwarmEvent::Behaviour(BehaviourEvent::Identify(event)) => self.metrics.record(event),
+            SwarmEvent::Behaviour(BehaviourEvent::RelayServer(event)) => self.metrics.record(event),
+            SwarmEvent::Behaviour(BehaviourEvent::Dcutr(event)) => self.metrics.record(event),
+            SwarmEvent::Behaviour(BehaviourEvent::Kademlia(event)) => self.metrics.record(event),
+            SwarmEvent::Behaviour(BehaviourEvent::Gossipsub(event)) => self.metrics.record(event),
+            SwarmEvent::Behaviour(BehaviourEvent::Autonat(autonat::Event::InboundProbe(event))) => self.record_dial_back(event),
+            SwarmEvent::OutgoingConnectionError { error, .. } => {
+                let kind = DialErrorKind::of(error).as_str();
+                self.dial_failures.get_or_create(&DialFailureLabels { kind }).inc();
+            }
+            _ => {}
+        }
+    }
+
+    /// Counts the outcome of a dial-back request, once it has one.
+    fn record_dial_back(&self, event: &InboundProbeEvent) {
+        let outcome = match event {
+            InboundProbeEvent::Request { .. } => return,
+            InboundProbeEvent::Response { .. } => "dialed_back",
+            InboundProbeEvent::Error { error: InboundProbeError::Response(ResponseError::DialError), .. } => "unreachable",
+            InboundProbeEvent::Error { error: InboundProbeError::Response(ResponseError::DialRefused), .. } => "refused",
+            InboundProbeEvent::Error { .. } => "error",
+        };
+        self.dial_backs.get_or_create(&DialBackLabels { outcome }).inc();
+    }
+
+    /// Adds a successful ping to its peer's histogram and updates the exported
+    /// percentiles.
+    fn record_rtt(&mut self, event: &ping::Event) {
+        let Ok(rtt) = event.result else {
+            return;
+        };
+        let stats = self.rtts.entry(event.peer).or_default();
+        stats.record_success(rtt);
+        for (percent, quantile) in QUANTILES {
+            if let Some(value) = stats.percentile(percent) {
+                let labels = QuantileLabels { peer_id: event.peer.to_string(), quantile: quantile.to_owned() };
+                self.rtt_quantiles.get_or_create(&labels).set(value.as_secs_f64());
+            }
+        }
+    }
+}
@@ -0,0 +1,756 @@
+//! The ping node and its settings.
+
+use futures::prelude::*;
+use libp2p::core::transport::ListenerId;
+use libp2p::gossipsub::{self, PublishError};
+use libp2p::identity::Keypair;
+use libp2p::metrics::Registry;
+use libp2p::pnet::PreSharedKey;
+use libp2p::swarm::dial_opts::{DialOpts, PeerCondition};
+use libp2p::swarm::{ConnectionId, DialError, SwarmEvent};
+use libp2p::{autonat, connection_limits, identify, kad, mdns, ping, relay, Multiaddr, PeerId, Swarm};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::bandwidth::{Bandwidth, Traffic};
+use crate::behaviour::{Behaviour, BehaviourEvent};
+use crate::dials::{DialQueue, QueuedDial};
+use crate::exchange::{PeerExchange, Spread};
+use crate::mesh::{self, LatencyMatrix};
+use crate::metrics::NodeMetrics;
+use crate::race::{Race, RaceResults, Races};
+use crate::rendezvous::{Meeting, Rendezvous};
+use crate::spans::ConnectionSpans;
+use crate::timing::{ConnectionTiming, ConnectionTimings, DialTimer};
+use crate::{
+    bench, labels, ping_limit, testing, AdaptiveInterval, LocalBinding, PingError, PingEvent, PingNodeBuilder,
+    SecurityChoice, Socks5Proxy, TcpOptions, TransportChoice, WsTls,
+};
+
+/// Settings used when building a [`PingNode`].
+#[derive(Debug, Clone)]
+pub struct NodeConfig {
+    /// Interval between outbound pings on each connection.
+    pub ping_interval: Duration,
+    /// Lengthen the interval between the pings of stable peers up to the
+    /// maximum, and shorten it down to the minimum, which replaces
+    /// [`Self::ping_interval`], when their RTTs vary more or pings fail.
+    pub adaptive_interval: Option<AdaptiveInterval>,
+    /// The shortest interval [`PingNode::set_peer_interval`] will give a peer;
+    /// if below the ping interval, pings are sent this often and held back
+    /// for the other peers. Longer intervals of peers need none.
+    pub min_peer_interval: Option<Duration>,
+    /// Time to wait for a ping response before it counts as a failure.
+    pub ping_timeout: Duration,
+    /// How long a connection without active streams is kept open. The streams
+    /// of outbound pings don't count, so connections pinged less often than
+    /// this close in between unless [`Self::keep_alive`] is set.
+    pub idle_timeout: Duration,
+    /// Keep every connection open however long it is idle.
+    pub keep_alive: bool,
+    /// How long a dial, including the security handshake and multiplexer
+    /// negotiation, may take before it fails.
+    pub dial_timeout: Duration,
+    /// Head start of each address of a peer over the next one when dialing
+    /// several at once, e.g. those a DNS name resolves to; the first to
+    /// connect wins. Zero dials them all at once.
+    pub race_delay: Duration,
+    /// Dials started by the node beyond this many at once wait for earlier
+    /// ones to finish; `None` starts every dial right away.
+    pub max_concurrent_dials: Option<usize>,
+    /// Also measure round-trip times by echoing payloads of this many bytes,
+    /// at most [`echo::MAX_SIZE`](crate::echo::MAX_SIZE), on every connection at the ping interval.
+    pub echo_size: Option<usize>,
+    /// Also estimate the clock offset and one-way delays to every peer by
+    /// exchanging timestamps at the ping interval.
+    pub clock_probe: bool,
+    /// Take part in the throughput benchmarks other peers start; without,
+    /// their requests are refused.
+    pub bench_server: bool,
+    /// Transports to enable; dialing picks whichever matches the address.
+    pub transports: Vec<TransportChoice>,
+    /// Security handshake(s) offered on TCP and WebSocket connections.
+    pub security: SecurityChoice,
+    /// Pre-shared key of a private network; only nodes holding the same key
+    /// can connect. Not supported with QUIC.
+    pub psk: Option<PreSharedKey>,
+    /// Certificate for listening on `/wss` addresses with the WebSocket transport.
+    pub ws_tls: Option<WsTls>,
+    /// Local addresses and interface that TCP and WebSocket dials go out from.
+    pub local_binding: LocalBinding,
+    /// Socket options of TCP and WebSocket connections.
+    pub tcp_options: TcpOptions,
+    /// SOCKS5 proxy that TCP and WebSocket dials are tunneled through. Not
+    /// supported with QUIC.
+    pub proxy: Option<Socks5Proxy>,
+    /// Discover peers on the local network via mDNS and dial them automatically.
+    pub mdns: bool,
+    /// Map the listening ports on the router via UPnP, reporting the mapped
+    /// addresses as external addresses.
+    pub upnp: bool,
+    /// Record Prometheus metrics, available via [`PingNode::metrics_registry`].
+    pub metrics: bool,
+    /// Relay circuits between other peers, within the given limits.
+    pub relay_server: Option<RelayLimits>,
+    /// Answer the AutoNAT dial-back requests of other peers, within the given
+    /// limits, telling them whether they are reachable; without, requests are
+    /// refused. Peers ask the ones they are connected to, so a few public
+    /// nodes serving this can check a whole fleet.
+    pub autonat_server: Option<AutonatLimits>,
+    /// Also ask, and as a server dial back, peers seen at private addresses,
+    /// for a fleet on a private network.
+    pub autonat_allow_private: bool,
+    /// Join the Kademlia DHT to look up peers by [`PeerId`].
+    ///
+    /// Nodes only answer DHT queries, and can only be found by others, once
+    /// they have a confirmed external address.
+    pub kademlia: bool,
+    /// DHT nodes to join through; each address must end with `/p2p/<peer id>`.
+    /// Requires `kademlia`.
+    pub bootstrap: Vec<Multiaddr>,
+    /// Join the latency mesh, exchanging measured RTTs with other members via
+    /// gossipsub; see [`PingNode::publish_latencies`].
+    pub mesh: bool,
+    /// Ask peers for the peers they are connected to and dial those too, up to
+    /// the given depth and fan-out.
+    pub peer_exchange: Option<PeerExchange>,
+    /// Register at a rendezvous point under a namespace and dial the other
+    /// peers registered under it, as they show up.
+    ///
+    /// Registering takes an external address, so nodes without one only
+    /// discover the others.
+    pub rendezvous: Option<Rendezvous>,
+    /// Act as a rendezvous point for other nodes.
+    pub rendezvous_server: bool,
+    /// If non-empty, only these peers may connect, in either direction.
+    pub allow_peers: Vec<PeerId>,
+    /// Peers that may never connect, in either direction.
+    pub deny_peers: Vec<PeerId>,
+    /// Connections beyond these limits are denied.
+    pub connection_limits: ConnectionLimits,
+    /// Limit on the pings answered for each peer; `None` answers all.
+    pub inbound_ping_limit: Option<ping_limit::PingLimit>,
+    /// Labels advertised to peers in the identify agent version, each passing
+    /// [`labels::check`].
+    pub labels: labels::Labels,
+    /// Answer inbound pings late or not at all, to simulate a bad network path
+    /// in tests.
+    pub impairment: Option<testing::Impairment>,
+}
+
+impl Default for NodeConfig {
+    fn default() -> Self {
+        Self {
+            ping_interval: Duration::from_secs(15),
+            adaptive_interval: None,
+            min_peer_interval: None,
+            ping_timeout: Duration::from_secs(20),
+            idle_timeout: Duration::from_secs(30),
+            keep_alive: false,
+            dial_timeout: Duration::from_secs(30),
+            race_delay: Duration::from_millis(250),
+            max_concurrent_dials: None,
+            echo_size: None,
+            clock_probe: false,
+            bench_server: false,
+            transports: vec![TransportChoice::Tcp],
+            security: SecurityChoice::Tls,
+            psk: None,
+            ws_tls: None,
+            local_binding: LocalBinding::default(),
+            tcp_options: TcpOptions::default(),
+            proxy: None,
+            mdns: false,
+            upnp: false,
+            metrics: false,
+            relay_server: None,
+            autonat_server: None,
+            autonat_allow_private: false,
+            kademlia: false,
+            bootstrap: Vec::new(),
+            mesh: false,
+            peer_exchange: None,
+            rendezvous: None,
+            rendezvous_server: false,
+            allow_peers: Vec::new(),
+            deny_peers: Vec::new(),
+            connection_limits: ConnectionLimits::default(),
+            inbound_ping_limit: None,
+            labels: labels::Labels::new(),
+            impairment: None,
+        }
+    }
+}
+
+/// Limits on the number of connections, protecting a public node from peers
+/// opening more than it can handle; `None` doesn't limit.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ConnectionLimits {
+    /// Maximum number of incoming connections still doing their handshakes.
+    pub max_pending_incoming: Option<u32>,
+    /// Maximum number of established incoming connections.
+    pub max_established_incoming: Option<u32>,
+    /// Maximum number of established outgoing connections.
+    pub max_established_outgoing: Option<u32>,
+    /// Maximum number of established connections with a single peer.
+    pub max_established_per_peer: Option<u32>,
+}
+
+impl From<&ConnectionLimits> for connection_limits::ConnectionLimits {
+    fn from(limits: &ConnectionLimits) -> Self {
+        connection_limits::ConnectionLimits::default()
+            .with_max_pending_incoming(limits.max_pending_incoming)
+            .with_max_established_incoming(limits.max_established_incoming)
+            .with_max_established_outgoing(limits.max_established_outgoing)
+            .with_max_established_per_peer(limits.max_established_per_peer)
+    }
+}
+
+/// Resource limits of a node acting as a circuit relay.
+///
+/// The defaults match those of libp2p, which keep circuits short; raise
+/// `max_circuit_duration` to ping through the relay for longer.
+#[derive(Debug, Clone)]
+pub struct RelayLimits {
+    /// Maximum number of peers holding a reservation at the same time.
+    pub max_reservations: usize,
+    /// Maximum number of reservations a single peer may hold.
+    pub max_reservations_per_peer: usize,
+    /// How long a reservation lasts before it has to be renewed.
+    pub reservation_duration: Duration,
+    /// Maximum number of circuits relayed at the same time.
+    pub max_circuits: usize,
+    /// Maximum number of circuits a single peer may have relayed.
+    pub max_circuits_per_peer: usize,
+    /// Circuits are closed after this long.
+    pub max_circuit_duration: Duration,
+    /// Circuits are closed after relaying this many bytes in each direction.
+    pub max_circuit_bytes: u64,
+}
+
+impl Default for RelayLimits {
+    fn default() -> Self {
+        let config = relay::Config::default();
+        Self {
+            max_reservations: config.max_reservations,
+            max_reservations_per_peer: config.max_reservations_per_peer,
+            reservation_duration: config.reservation_duration,
+            max_circuits: config.max_circuits,
+            max_circuits_per_peer: config.max_circuits_per_peer,
+            max_circuit_duration: config.max_circuit_duration,
+            max_circuit_bytes: config.max_circuit_bytes,
+        }
+    }
+}
+
+impl From<&RelayLimits> for relay::Config {
+    fn from(limits: &RelayLimits) -> Self {
+        relay::Config {
+            max_reservations: limits.max_reservations,
+            max_reservations_per_peer: limits.max_reservations_per_peer,
+            reservation_duration: limits.reservation_duration,
+            max_circuits: limits.max_circuits,
+            max_circuits_per_peer: limits.max_circuits_per_peer,
+            max_circuit_duration: limits.max_circuit_duration,
+            max_circuit_bytes: limits.max_circuit_bytes,
+            ..relay::Config::default()
+        }
+    }
+}
+
+/// Rate limits of a node acting as an AutoNAT server, which dials peers back
+/// at the addresses they think they are reachable at.
+///
+/// The defaults match those of libp2p.
+#[derive(Debug, Clone)]
+pub struct AutonatLimits {
+    /// Maximum number of dial-backs within `period`.
+    pub max_dial_backs: usize,
+    /// Maximum number of dial-backs for a single peer within `period`.
+    pub max_dial_backs_per_peer: usize,
+    pub period: Duration,
+}
+
+impl Default for AutonatLimits {
+    fn default() -> Self {
+        let config = autonat::Config::default();
+        Self {
+            max_dial_backs: config.throttle_clients_global_max,
+            max_dial_backs_per_peer: config.throttle_clients_peer_max,
+            period: config.throttle_clients_period,
+        }
+    }
+}
+
+/// The AutoNAT configuration of `config`, serving dial-backs within the limits
+/// of its server, or refusing them all without.
+pub(crate) fn autonat_config(config: &NodeConfig) -> autonat::Config {
+    let defaults = autonat::Config { only_global_ips: !config.autonat_allow_private, ..autonat::Config::default() };
+    match &config.autonat_server {
+        Some(limits) => autonat::Config {
+            throttle_clients_global_max: limits.max_dial_backs,
+            throttle_clients_peer_max: limits.max_dial_backs_per_peer,
+            throttle_clients_period: limits.period,
+            ..defaults
+        },
+        None => autonat::Config { throttle_clients_global_max: 0, ..defaults },
+    }
+}
+
+/// A libp2p node running the ping protocol, plus any optional protocols enabled
+/// in its [`NodeConfig`].
+pub struct PingNode {
+    swarm: Swarm<Behaviour>,
+    metrics: Option<NodeMetrics>,
+    /// Bytes sent and received over the connections.
+    bandwidth: Bandwidth,
+    /// Whether listen addresses are advertised as external addresses.
+    advertise_listen_addrs: bool,
+    /// RTTs of the latency mesh, if joined.
+    mesh: Option<LatencyMatrix>,
+    /// Tracing spans of the open connections.
+    spans: ConnectionSpans,
+    /// Setup phases of outgoing connections.
+    timings: ConnectionTimings,
+    /// Winners of the races between the addresses of outgoing connections.
+    races: RaceResults,
+    /// Registration and discovery at the rendezvous point, if any.
+    meeting: Option<Meeting>,
+    /// Peers of peers asked for and dialed, if peer exchange is enabled.
+    spread: Option<Spread>,
+    /// Dials in flight and those waiting for a free slot.
+    dials: DialQueue,
+    /// Failures of queued dials that couldn't even be started, reported as
+    /// events.
+    failed_dials: VecDeque<SwarmEvent<BehaviourEvent>>,
+    /// Peers evicted whose connections haven't all closed yet, so that they
+    /// are only counted once.
+    evicting: HashSet<PeerId>,
+}
+
+impl PingNode {
+    /// Creates a new node with a randomly generated identity and default settings.
+    pub fn new() -> Result<Self, PingError> {
+        Self::with_config(NodeConfig::default())
+    }
+
+    /// Creates a new node with a randomly generated identity and the given settings.
+    pub fn with_config(config: NodeConfig) -> Result<Self, PingError> {
+        Self::with_keypair(Keypair::generate_ed25519(), config)
+    }
+
+    /// Creates a new node using an existing identity, e.g. one loaded with
+    /// [`keyfile::load_or_generate`](crate::keyfile::load_or_generate).
+    pub fn with_keypair(keypair: Keypair, config: NodeConfig) -> Result<Self, PingError> {
+        Self::builder().with_identity(keypair).with_config(config).build()
+    }
+
+    /// Returns a builder to customize more of the node than a [`NodeConfig`]
+    /// allows, such as the ping protocol.
+    pub fn builder() -> PingNodeBuilder {
+        PingNodeBuilder::new()
+    }
+
+    /// Wraps a swarm built by [`PingNodeBuilder`] according to `config`, whose
+    /// transports note the phases of their dials in `timer`, race addresses
+    /// in `races` and count their bytes in `bandwidth`.
+    pub(crate) fn from_swarm(
+        swarm: Swarm<Behaviour>,
+        config: &NodeConfig,
+        timer: DialTimer,
+        races: Races,
+        meeting: Option<Meeting>,
+        bandwidth: Bandwidth,
+    ) -> Self {
+        Self {
+            metrics: config.metrics.then(|| NodeMetrics::new(swarm.behaviour().ping.counters(), &bandwidth)),
+            bandwidth,
+            swarm,
+            advertise_listen_addrs: config.relay_server.is_some(),
+            mesh: config.mesh.then(LatencyMatrix::default),
+            spans: ConnectionSpans::default(),
+            timings: ConnectionTimings::new(timer),
+            races: RaceResults::new(races),
+            meeting,
+            spread: config.peer_exchange.map(Spread::new),
+            dials: DialQueue::new(config.max_concurrent_dials),
+            failed_dials: VecDeque::new(),
+            evicting: HashSet::new(),
+        }
+    }
+
+    /// Returns the [`PeerId`] derived from this node's identity.
+    pub fn local_peer_id(&self) -> PeerId {
+        *self.swarm.local_peer_id()
+    }
+
+    /// Returns the registry holding the node's metrics, if metrics are enabled.
+    ///
+    /// Encode it with `prometheus_client::encoding::text::encode` to serve it.
+    pub fn metrics_registry(&self) -> Option<Arc<Registry>> {
+        self.metrics.as_ref().map(NodeMetrics::registry)
+    }
+
+    /// Returns the bytes sent to and received from `peer_id` over the streams
+    /// of all connections with it so far, without the overhead of the
+    /// security protocol and the multiplexer.
+    pub fn traffic(&self, peer_id: &PeerId) -> Traffic {
+        self.bandwidth.peer(peer_id)
+    }
+
+    /// Returns the bytes sent and received over all connections so far, like
+    /// [`Self::traffic`].
+    pub fn total_traffic(&self) -> Traffic {
+        self.bandwidth.total()
+    }
+
+    /// Returns whether AutoNAT found this node to be publicly reachable, and at
+    /// which address.
+    pub fn nat_status(&self) -> autonat::NatStatus {
+        self.swarm.behaviour().autonat.nat_status()
+    }
+
+    /// Returns the latest RTTs between all members of the latency mesh, if
+    /// this node joined it.
+    pub fn latency_matrix(&self) -> Option<&LatencyMatrix> {
+        self.mesh.as_ref()
+    }
+
+    /// Publishes the RTTs this node measured to the rest of the latency mesh.
+    ///
+    /// Does nothing while no other member is connected.
+    pub fn publish_latencies(&mut self) -> Result<(), PingError> {
+        let matrix = self.mesh.as_ref().ok_or(PingError::NotEnabled("the latency mesh"))?;
+        let report = matrix.encode_row(self.swarm.local_peer_id());
+        let gossipsub = self.swarm.behaviour_mut().gossipsub.as_mut().ok_or(PingError::NotEnabled("the latency mesh"))?;
+        match gossipsub.publish(mesh::topic(), report) {
+            Ok(_) | Err(PublishError::InsufficientPeers) => Ok(()),
+            Err(e) => Err(PingError::Publish(e)),
+        }
+    }
+
+    /// Starts listening on the given multi-address.
+    ///
+    /// Listening on a relayed address such as
+    /// `/ip4/198.51.100.1/tcp/4001/p2p/<relay>/p2p-circuit` makes a reservation
+    /// with that relay, so that peers can reach this node through it.
+    pub fn listen(&mut self, addr: Multiaddr) -> Result<ListenerId, PingError> {
+        self.swarm.listen_on(addr.clone()).map_err(|e| PingError::listen(addr, e))
+    }
+
+    /// Announces an address other peers can reach this node at, e.g. a public
+    /// address forwarded to it by a NAT.
+    ///
+    /// Relay servers need at least one: it is handed out to the peers making a
+    /// reservation. Without any, a relay server advertises its listen addresses.
+    pub fn add_external_address(&mut self, addr: Multiaddr) {
+        self.advertise_listen_addrs = false;
+        self.swarm.add_external_address(addr);
+    }
+
+    /// Dials the peer at the given multi-address.
+    ///
+    /// The returned [`ConnectionId`] identifies the resulting connection in
+    /// later events.
+    pub fn dial(&mut self, addr: Multiaddr) -> Result<ConnectionId, PingError> {
+        let dial = QueuedDial { opts: DialOpts::from(addr.clone()), peer_id: None, addr: Some(addr) };
+        self.start_dial(dial).map_err(PingError::dial)
+    }
+
+    /// Starts looking up the addresses of `peer_id` in the DHT.
+    ///
+    /// The lookup connects to the peer if it is found; the returned query
+    /// finishes with a [`kad::Event::OutboundQueryProgressed`] event.
+    pub fn find_peer(&mut self, peer_id: PeerId) -> Result<kad::QueryId, PingError> {
+        let kademlia = self.swarm.behaviour_mut().kademlia.as_mut().ok_or(PingError::NotEnabled("Kademlia"))?;
+        Ok(kademlia.get_closest_peers(peer_id))
+    }
+
+    /// Dials `peer_id` at the addresses known for it, e.g. from the DHT or
+    /// identify, unless already connected or dialing.
+    pub fn dial_peer(&mut self, peer_id: PeerId) -> Result<ConnectionId, PingError> {
+        let opts = DialOpts::peer_id(peer_id)
+            .condition(PeerCondition::DisconnectedAndNotDialing)
+            .build();
+        self.start_dial(QueuedDial { opts, peer_id: Some(peer_id), addr: None }).map_err(PingError::dial)
+    }
+
+    /// Starts `dial`, or queues it if [`NodeConfig::max_concurrent_dials`] are
+    /// already in flight; the failures of queued dials are reported as
+    /// [`SwarmEvent::OutgoingConnectionError`]s.
+    fn start_dial(&mut self, dial: QueuedDial) -> Result<ConnectionId, DialError> {
+        let connection_id = dial.opts.connection_id();
+        if let Some(dial) = self.dials.admit(dial) {
+            let (result, candidates) = self.races.stagger(&mut self.swarm, dial.opts);
+            result?;
+            self.races.dialing(connection_id, candidates);
+            self.dials.started(connection_id);
+            self.spans.dialing(connection_id, dial.peer_id, dial.addr.as_ref());
+        }
+        Ok(connection_id)
+    }
+
+    /// Starts the queued dials there is room for now.
+    fn start_queued_dials(&mut self) {
+        while let Some(dial) = self.dials.next() {
+            let connection_id = dial.opts.connection_id();
+            let (result, candidates) = self.races.stagger(&mut self.swarm, dial.opts);
+            match result {
+                Ok(()) => {
+                    self.races.dialing(connection_id, candidates);
+                    self.dials.started(connection_id);
+                    self.spans.dialing(connection_id, dial.peer_id, dial.addr.as_ref());
+                }
+                Err(error) => self.failed_dials.push_back(SwarmEvent::OutgoingConnectionError {
+                    connection_id,
+                    peer_id: dial.peer_id,
+                    error,
+                }),
+            }
+        }
+    }
+
+    /// Starts transferring data to or from the connected `peer_id` for
+    /// `duration`; the throughput is reported in a [`BehaviourEvent::Bench`]
+    /// event.
+    pub fn bench(&mut self, peer_id: PeerId, direction: bench::Direction, duration: Duration) {
+        self.swarm.behaviour_mut().bench.start(peer_id, direction, duration);
+    }
+
+    /// Stops sending pings, echo and clock requests to every peer, or starts
+    /// again; the requests of peers are answered either way.
+    pub fn set_paused(&mut self, paused: bool) {
+        let behaviour = self.swarm.behaviour_mut();
+        behaviour.ping.set_paused(paused);
+        behaviour.echo.set_paused(paused);
+        behaviour.clock.set_paused(paused);
+    }
+
+    /// Returns `true` while sending is paused with [`Self::set_paused`].
+    pub fn is_paused(&self) -> bool {
+        self.swarm.behaviour().ping.is_paused()
+    }
+
+    /// Stops sending pings, echo and clock requests to `peer_id`, also once
+    /// reconnected, or starts again unless paused for all peers.
+    pub fn set_peer_paused(&mut self, peer_id: PeerId, paused: bool) {
+        let behaviour = self.swarm.behaviour_mut();
+        behaviour.ping.set_peer_paused(peer_id, paused);
+        behaviour.echo.set_peer_paused(peer_id, paused);
+        behaviour.clock.set_peer_paused(peer_id, paused);
+    }
+
+    /// Pings `peer_id` every `interval`, also once reconnected, instead of at
+    /// the ping interval, or again at that with `None`. Intervals below the
+    /// [`NodeConfig::min_peer_interval`] are lengthened to it.
+    pub fn set_peer_interval(&mut self, peer_id: PeerId, interval: Option<Duration>) {
+        self.swarm.behaviour_mut().ping.set_peer_interval(peer_id, interval);
+    }
+
+    /// Echoes payloads of `size` bytes to `peer_id`, also once reconnected,
+    /// instead of the configured echo size, or again that with `None`.
+    pub fn set_peer_echo_size(&mut self, peer_id: PeerId, size: Option<usize>) {
+        self.swarm.behaviour_mut().echo.set_peer_size(peer_id, size);
+    }
+
+    /// Returns `true` while sending to `peer_id` is paused with
+    /// [`Self::set_peer_paused`].
+    pub fn is_peer_paused(&self, peer_id: &PeerId) -> bool {
+        self.swarm.behaviour().ping.is_peer_paused(peer_id)
+    }
+
+    /// Closes all connections to `peer_id` for failing too many pings in a
+    /// row, counting it in the metrics unless it is still being evicted;
+    /// returns `false` if there were none.
+    pub fn evict(&mut self, peer_id: PeerId) -> bool {
+        let evicted = self.disconnect(peer_id);
+        if evicted && self.evicting.insert(peer_id) {
+            if let Some(metrics) = &self.metrics {
+                metrics.record_eviction();
+            }
+        }
+        evicted
+    }
+
+    /// Closes all connections to `peer_id`; returns `false` if there were none.
+    pub fn disconnect(&mut self, peer_id: PeerId) -> bool {
+        self.swarm.disconnect_peer_id(peer_id).is_ok()
+    }
+
+    /// Returns `true` if there is at least one connection to `peer_id`.
+    pub fn is_connected(&self, peer_id: &PeerId) -> bool {
+        self.swarm.is_connected(peer_id)
+    }
+
+    /// Returns how long each phase of setting up the outgoing connection
+    /// `connection_id` took, once its first ping has been answered.
+    ///
+    /// Each timing is handed out once; ask on the first successful
+    /// [`ping::Event`] of a connection.
+    pub fn take_connection_timing(&mut self, connection_id: ConnectionId) -> Option<ConnectionTiming> {
+        self.timings.take(connection_id)
+    }
+
+    /// Returns which address won when the outgoing connection `connection_id`
+    /// was dialed at several at once, e.g. those its DNS name resolved to or
+    /// those known for its peer, and how many there were.
+    ///
+    /// Each result is handed out once; ask on the
+    /// [`SwarmEvent::ConnectionEstablished`] of the connection.
+    pub fn take_race(&mut self, connection_id: ConnectionId) -> Option<Race> {
+        self.races.take(connection_id)
+    }
+
+    /// Waits for the next event produced by the swarm.
+    ///
+    /// Peers discovered via mDNS are dialed, and the listen addresses reported by
+    /// identified peers are remembered for later dials and DHT lookups, before
+    /// the event is returned. Relay servers without explicit external addresses
+    /// also advertise each new listen address, and mesh members update their
+    /// [`LatencyMatrix`]. Connections and pings are traced in `debug` spans.
+    pub async fn next_event(&mut self) -> SwarmEvent<BehaviourEvent> {
+        let event = match self.failed_dials.pop_front() {
+            Some(event) => event,
+            None => loop {
+                tokio::select! {
+                    event = self.swarm.select_next_some() => break event,
+                    () = tick(&mut self.meeting) => {
+                        if let Some(meeting) = &mut self.meeting {
+                            meeting.refresh(&mut self.swarm);
+                        }
+                    }
+                }
+            },
+        };
+        self.dials.observe(&event);
+        self.start_queued_dials();
+        if let Some(metrics) = &mut self.metrics {
+            metrics.record(&event);
+        }
+        self.spans.observe(&event);
+        self.timings.observe(&event);
+        self.races.observe(&event);
+        self.update_mesh(&event);
+        if let Some(meeting) = &mut self.meeting {
+            let discovered = meeting.observe(&mut self.swarm, &event);
+            self.dial_discovered(&discovered);
+        }
+        if let Some(spread) = &mut self.spread {
+            let learned = spread.observe(&mut self.swarm, &event);
+            self.dial_discovered(&learned);
+        }
+        match &event {
+            SwarmEvent::ConnectionClosed { peer_id, num_established: 0, .. } => {
+                self.evicting.remove(peer_id);
+            }
+            SwarmEvent::NewListenAddr { address, .. } if self.advertise_listen_addrs => {
+                self.swarm.add_external_address(address.clone());
+            }
+            SwarmEvent::Behaviour(BehaviourEvent::Mdns(mdns::Event::Discovered(discovered))) => {
+                self.dial_discovered(discovered);
+            }
+            SwarmEvent::Behaviour(BehaviourEvent::Identify(identify::Event::Received { peer_id, info })) => {
+                let dht_server = info.protocols.contains(&kad::PROTOCOL_NAME);
+                self.swarm.behaviour_mut().exchange.set_addresses(*peer_id, &info.listen_addrs);
+                for addr in &info.listen_addrs {
+                    self.swarm.add_peer_address(*peer_id, addr.clone());
+                    if let Some(kademlia) = self.swarm.behaviour_mut().kademlia.as_mut().filter(|_| dht_server) {
+                        kademlia.add_address(peer_id, addr.clone());
+                    }
+                }
+            }
+            _ => {}
+        }
+        event
+    }
+
+    /// Returns the events of the node as [`PingEvent`]s, handling everything
+    /// else like [`Self::next_event`] does.
+    ///
+    /// ```no_run
+    /// use futures::StreamExt;
+    /// use libp2p_ping_tut::{PingEvent, PingNode};
+    ///
+    /// # async fn run() -> Result<(), Box<dyn std::error::Error>> {
+    /// let mut node = PingNode::new()?;
+    /// node.dial("/ip4/127.0.0.1/tcp/4001".parse()?)?;
+    /// let mut events = std::pin::pin!(node.events());
+    /// while let Some(event) = events.next().await {
+    ///     if let PingEvent::PingSuccess { peer_id, rtt, .. } = event {
+    ///         println!("{peer_id}: {rtt:?}");
+    ///     }
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn events(&mut self) -> impl Stream<Item = PingEvent> + '_ {
+        stream::unfold(self, |node| async {
+            let events = PingEvent::from_swarm(&node.next_event().await);
+            Some((stream::iter(events), node))
+        })
+        .flatten()
+    }
+
+    /// Closes all connections politely and waits up to `grace` for them to shut
+    /// down before returning.
+    pub async fn shutdown(&mut self, grace: Duration) {
+        let peers: Vec<PeerId> = self.swarm.connected_peers().copied().collect();
+        for peer_id in peers {
+            let _ = self.swarm.disconnect_peer_id(peer_id);
+        }
+
+        let drain = async {
+            while self.swarm.network_info().num_peers() > 0 {
+                self.swarm.select_next_some().await;
+            }
+        };
+        let _ = tokio::time::timeout(grace, drain).await;
+    }
+
+    /// Keeps the latency matrix up to date with our own pings and the reports
+    /// of other mesh members; malformed reports are ignored.
+    fn update_mesh(&mut self, event: &SwarmEvent<BehaviourEvent>) {
+        let local_peer_id = *self.swarm.local_peer_id();
+        let Some(matrix) = &mut self.mesh else {
+            return;
+        };
+        match event {
+            SwarmEvent::Behaviour(BehaviourEvent::Ping(ping::Event { peer, result: Ok(rtt), .. })) => {
+                matrix.record(local_peer_id, *peer, *rtt);
+            }
+            SwarmEvent::ConnectionClosed { peer_id, num_established: 0, .. } => matrix.forget(&local_peer_id, peer_id),
+            SwarmEvent::Behaviour(BehaviourEvent::Gossipsub(gossipsub::Event::Message { message, .. })) => {
+                if let Some(source) = message.source.filter(|_| message.topic == mesh::topic().hash()) {
+                    let _ = matrix.decode_row(source, &message.data);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Dials each newly discovered peer once, on all of its addresses.
+    fn dial_discovered(&mut self, discovered: &[(PeerId, Multiaddr)]) {
+        let mut addresses: HashMap<PeerId, Vec<Multiaddr>> = HashMap::new();
+        for (peer_id, addr) in discovered {
+            addresses.entry(*peer_id).or_default().push(addr.clone());
+        }
+
+        for (peer_id, addrs) in addresses {
+            let opts = DialOpts::peer_id(peer_id)
+                .addresses(addrs)
+                .condition(PeerCondition::DisconnectedAndNotDialing)
+                .build();
+            // Dialing fails for peers we are already connected to, which is fine.
+            let _ = self.start_dial(QueuedDial { opts, peer_id: Some(peer_id), addr: None });
+        }
+    }
+}
+
+/// Waits for the next refresh of the rendezvous `meeting`, forever without one.
+async fn tick(meeting: &mut Option<Meeting>) {
+    match meeting {
+        Some(meeting) => meeting.tick().await,
+        None => future::pending().await,
+    }
+}
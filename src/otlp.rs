@@ -0,0 +1,86 @@
+//! Export of RTTs and connection spans over OpenTelemetry (`--otlp`).
+
+use libp2p::{ping, PeerId};
+use opentelemetry::metrics::{Counter, Histogram, MeterProvider as _};
+use opentelemetry::trace::TracerProvider as _;
+use opentelemetry::KeyValue;
+use opentelemetry_otlp::{MetricExporter, SpanExporter, WithExportConfig};
+use opentelemetry_sdk::metrics::{PeriodicReader, SdkMeterProvider};
+use opentelemetry_sdk::trace::TracerProvider;
+use opentelemetry_sdk::{runtime, Resource};
+use std::error::Error;
+use std::time::Duration;
+use tracing::Subscriber;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::Layer;
+
+/// Name the exported telemetry is attributed to.
+const SERVICE_NAME: &str = env!("CARGO_PKG_NAME");
+
+/// OTLP exporters for the spans of the node and its ping results.
+pub struct Otlp {
+    tracer_provider: TracerProvider,
+    meter_provider: SdkMeterProvider,
+    rtt: Histogram<f64>,
+    failures: Counter<u64>,
+}
+
+impl Otlp {
+    /// Sets up exporting to the OTLP/gRPC collector at `endpoint`, e.g.
+    /// `http://localhost:4317`. Must be called within the Tokio runtime.
+    pub fn new(endpoint: &str) -> Result<Self, Box<dyn Error>> {
+        let resource = Resource::new([KeyValue::new("service.name", SERVICE_NAME)]);
+
+        let spans = SpanExporter::builder().with_tonic().with_endpoint(endpoint).build()?;
+        let tracer_provider = TracerProvider::builder()
+            .with_batch_exporter(spans, runtime::Tokio)
+            .with_resource(resource.clone())
+            .build();
+
+        let metrics = MetricExporter::builder().with_tonic().with_endpoint(endpoint).build()?;
+        let meter_provider = SdkMeterProvider::builder()
+            .with_reader(PeriodicReader::builder(metrics, runtime::Tokio).build())
+            .with_resource(resource)
+            .build();
+        let meter = meter_provider.meter(SERVICE_NAME);
+        let rtt = meter
+            .f64_histogram("ping.rtt")
+            .with_unit("ms")
+            .with_description("Round-trip times of answered pings")
+            .build();
+        let failures = meter
+            .u64_counter("ping.failures")
+            .with_description("Pings that failed or timed out")
+            .build();
+
+        Ok(Self { tracer_provider, meter_provider, rtt, failures })
+    }
+
+    /// Returns a layer exporting tracing spans, to add to the subscriber.
+    pub fn layer<S>(&self) -> impl Layer<S>
+    where
+        S: Subscriber + for<'span> LookupSpan<'span>,
+    {
+        tracing_opentelemetry::layer().with_tracer(self.tracer_provider.tracer(SERVICE_NAME))
+    }
+
+    /// Records the result of a ping to `peer` over a relayed or direct connection.
+    pub fn record(&self, peer: &PeerId, relayed: bool, result: &Result<Duration, ping::Failure>) {
+        let attributes = [KeyValue::new("peer_id", peer.to_string()), KeyValue::new("relayed", relayed)];
+        match result {
+            Ok(rtt) => self.rtt.record(rtt.as_secs_f64() * 1000.0, &attributes),
+            Err(_) => self.failures.add(1, &attributes),
+        }
+    }
+
+    /// Flushes everything not exported yet; blocks until the collector
+    /// answered or gave up.
+    pub fn shutdown(self) {
+        if let Err(e) = self.tracer_provider.shutdown() {
+            eprintln!("Failed to export spans: {e}");
+        }
+        if let Err(e) = self.meter_provider.shutdown() {
+            eprintln!("Failed to export metrics: {e}");
+        }
+    }
+}
@@ -0,0 +1,2023 @@
+//! Rendering of node events for the terminal or for machine consumption.
+
+use libp2p::autonat::{InboundProbeError, NatStatus, ResponseError};
+use libp2p::rendezvous::{ErrorCode, Namespace};
+use libp2p::request_response::OutboundFailure;
+use libp2p::swarm::{ConnectionDenied, ConnectionId};
+use libp2p::{dcutr, identify, ping, upnp, Multiaddr, PeerId};
+use libp2p_ping_tut::exchange::SharedPeer;
+use libp2p_ping_tut::labels::{self, Labels};
+use libp2p_ping_tut::{bench, clock, echo, ConnectionTiming, DialErrorKind, LatencyMatrix, PingStats, Race, Traffic};
+use serde::Serialize;
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::fmt::{self, Display};
+use std::fs::File;
+use std::io::{self, Write};
+use std::net::SocketAddr;
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+
+use crate::address_book::Entry;
+use crate::cli::NamedPeer;
+use crate::compare::{Combination, Measurement};
+use crate::control::TargetStats;
+use crate::doctor::{self, Status};
+use crate::probe::Findings;
+use crate::simulate::{Check, Outcome};
+use crate::store::{PeerReport, PeerSla};
+use crate::sweep::Neighbor;
+use crate::targets::{Connection, Transition};
+
+/// How events are written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Format {
+    /// Human-readable lines.
+    Text,
+    /// One JSON object per line.
+    Json,
+    /// One comma-separated row per ping, after a header row; other events are
+    /// left out.
+    Csv,
+}
+
+/// Columns of the CSV output.
+const CSV_HEADER: &str = "timestamp,peer_id,address,rtt_us,result";
+
+/// A single JSON-lines record.
+#[derive(Serialize)]
+struct Line {
+    timestamp: String,
+    #[serde(flatten)]
+    record: Record,
+}
+
+/// The event-specific part of a JSON-lines record.
+#[derive(Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+enum Record {
+    Started {
+        peer_id: String,
+    },
+    Listening {
+        address: String,
+    },
+    ListenFailed {
+        address: String,
+        error: String,
+    },
+    ServingMetrics {
+        url: String,
+    },
+    ServingApi {
+        url: String,
+    },
+    ServingGrpc {
+        address: String,
+    },
+    ControlSocket {
+        path: String,
+    },
+    Dialing {
+        address: String,
+    },
+    Resuming {
+        peer_id: String,
+        name: Option<String>,
+        address: String,
+        last_seen: Option<String>,
+        avg_rtt_us: Option<u64>,
+    },
+    LookingUp {
+        peer_id: String,
+    },
+    DialFailed {
+        address: String,
+        kind: Option<&'static str>,
+        error: String,
+    },
+    WrongPeer {
+        address: String,
+        obtained: String,
+    },
+    Redialing {
+        address: String,
+        attempt: u32,
+        delay_ms: u64,
+    },
+    NoAnswer {
+        address: String,
+        wait_ms: u64,
+    },
+    GaveUp {
+        address: String,
+    },
+    PeerDead {
+        target: String,
+        peer_id: String,
+        failures: u32,
+        evicted: bool,
+    },
+    Discovered {
+        peer_id: String,
+        address: String,
+    },
+    Denied {
+        remote: String,
+        reason: String,
+    },
+    Connected {
+        peer_id: String,
+        name: Option<String>,
+        address: String,
+    },
+    RaceWon {
+        peer_id: String,
+        name: Option<String>,
+        winner: String,
+        candidates: usize,
+    },
+    ConnectionTiming {
+        peer_id: String,
+        name: Option<String>,
+        connect_us: u64,
+        security_us: Option<u64>,
+        muxer_us: Option<u64>,
+        first_ping_us: u64,
+    },
+    Reserved {
+        relay_peer_id: String,
+        renewal: bool,
+    },
+    RelayReservation {
+        peer_id: String,
+    },
+    RelayCircuit {
+        src_peer_id: String,
+        dst_peer_id: String,
+    },
+    RelayDenied {
+        src_peer_id: String,
+        dst_peer_id: Option<String>,
+    },
+    PeersExchanged {
+        peer_id: String,
+        name: Option<String>,
+        peers: usize,
+        error: Option<String>,
+    },
+    RendezvousRegistered {
+        point_peer_id: String,
+        namespace: String,
+        ttl_secs: u64,
+    },
+    RendezvousFailed {
+        point_peer_id: String,
+        namespace: Option<String>,
+        operation: &'static str,
+        error: String,
+    },
+    RendezvousPeerRegistered {
+        peer_id: String,
+        namespace: String,
+    },
+    HolePunch {
+        peer_id: String,
+        error: Option<String>,
+    },
+    Reachability {
+        status: &'static str,
+        address: Option<String>,
+    },
+    DialBack {
+        peer_id: String,
+        name: Option<String>,
+        address: Option<String>,
+        error: Option<&'static str>,
+    },
+    ExternalAddress {
+        address: String,
+    },
+    PortMapping {
+        status: &'static str,
+        address: Option<String>,
+    },
+    Identified {
+        peer_id: String,
+        agent_version: String,
+        protocol_version: String,
+        protocols: Vec<String>,
+        listen_addrs: Vec<String>,
+        observed_addr: String,
+    },
+    Disconnected {
+        peer_id: String,
+        cause: Option<String>,
+    },
+    HealthChanged {
+        target: String,
+        peer_id: Option<String>,
+        from: Option<String>,
+        to: String,
+    },
+    Ping {
+        peer_id: String,
+        name: Option<String>,
+        labels: Labels,
+        connection_id: Option<String>,
+        local_address: Option<String>,
+        remote_address: Option<String>,
+        relayed: bool,
+        warmup: bool,
+        rtt_us: Option<u64>,
+        jitter_us: Option<u64>,
+        error: Option<String>,
+    },
+    Stats(TargetStats),
+    Echo {
+        peer_id: String,
+        name: Option<String>,
+        size: usize,
+        rtt_us: Option<u64>,
+        error: Option<String>,
+    },
+    Clock {
+        peer_id: String,
+        name: Option<String>,
+        offset_us: Option<i64>,
+        delay_us: Option<i64>,
+        outbound_us: Option<i64>,
+        inbound_us: Option<i64>,
+        error: Option<String>,
+    },
+    Bench {
+        peer_id: String,
+        name: Option<String>,
+        direction: String,
+        bytes: Option<u64>,
+        elapsed_us: Option<u64>,
+        bytes_per_second: Option<f64>,
+        error: Option<String>,
+    },
+    Comparison {
+        combination: String,
+        address: Option<String>,
+        handshake_us: Option<u64>,
+        #[serde(flatten)]
+        pings: Option<PathStats>,
+        error: Option<String>,
+    },
+    Diagnosis {
+        check: &'static str,
+        status: &'static str,
+        time_us: Option<u64>,
+        detail: String,
+    },
+    Probe {
+        peer_id: Option<String>,
+        security: Vec<SecurityRecord>,
+        muxer: Option<&'static str>,
+        agent_version: Option<String>,
+        protocol_version: Option<String>,
+        protocols: Vec<String>,
+        features: BTreeMap<&'static str, bool>,
+        problems: Vec<String>,
+    },
+    SweepResult {
+        key: String,
+        rank: usize,
+        peer_id: String,
+        address: Option<String>,
+        #[serde(flatten)]
+        pings: PathStats,
+        error: Option<String>,
+    },
+    Simulation {
+        peer_id: String,
+        #[serde(flatten)]
+        pings: PathStats,
+        checks: Vec<CheckRecord>,
+        passed: bool,
+    },
+    EchoSummary {
+        target: String,
+        size: usize,
+        #[serde(flatten)]
+        stats: PathStats,
+    },
+    DialFailures {
+        target: String,
+        failures: BTreeMap<&'static str, u64>,
+    },
+    ClockSummary {
+        target: String,
+        samples: u64,
+        offset_us: i64,
+        delay_us: i64,
+        outbound_us: i64,
+        inbound_us: i64,
+    },
+    Summary {
+        target: String,
+        labels: Labels,
+        transmitted: u64,
+        received: u64,
+        loss_percent: f64,
+        min_us: Option<u64>,
+        avg_us: Option<u64>,
+        max_us: Option<u64>,
+        mdev_us: Option<u64>,
+        jitter_us: Option<u64>,
+        p50_us: Option<u64>,
+        p90_us: Option<u64>,
+        p99_us: Option<u64>,
+        p999_us: Option<u64>,
+        histogram: Vec<HistogramBucket>,
+        sent_bytes: u64,
+        received_bytes: u64,
+    },
+    IntervalSummary {
+        target: String,
+        labels: Labels,
+        interval_ms: u64,
+        transmitted: u64,
+        received: u64,
+        loss_percent: f64,
+        min_us: Option<u64>,
+        avg_us: Option<u64>,
+        max_us: Option<u64>,
+        p99_us: Option<u64>,
+        sent_bytes: u64,
+        received_bytes: u64,
+    },
+    Paused {
+        target: Option<String>,
+    },
+    Resumed {
+        target: Option<String>,
+    },
+    WindowOpened {
+        until: String,
+    },
+    WindowClosed {
+        next: Option<String>,
+    },
+    WindowSummary {
+        target: String,
+        labels: Labels,
+        opened: String,
+        closed: String,
+        transmitted: u64,
+        received: u64,
+        loss_percent: f64,
+        min_us: Option<u64>,
+        avg_us: Option<u64>,
+        max_us: Option<u64>,
+        p99_us: Option<u64>,
+    },
+    TotalTraffic {
+        interval_ms: Option<u64>,
+        sent_bytes: u64,
+        received_bytes: u64,
+    },
+    ThresholdFailed {
+        target: String,
+        reason: String,
+    },
+    PeersFileChanged {
+        path: String,
+        added: Vec<String>,
+        removed: Vec<String>,
+    },
+    PeersFileFailed {
+        path: String,
+        error: String,
+    },
+    ConfigReloadFailed {
+        error: String,
+    },
+    WriteFailed {
+        path: String,
+        error: String,
+    },
+    Report {
+        peer_id: String,
+        pings: u64,
+        answered: u64,
+        availability_percent: f64,
+        p50_us: Option<u64>,
+        p90_us: Option<u64>,
+        p99_us: Option<u64>,
+        max_us: Option<u64>,
+    },
+    Sla {
+        peer_id: String,
+        observed_s: f64,
+        downtime_s: f64,
+        availability_percent: f64,
+        outages: usize,
+        mttr_s: Option<f64>,
+        longest_outage_s: Option<f64>,
+        ongoing: bool,
+    },
+    LatencyMatrix {
+        /// RTT in microseconds from each row peer to each column peer.
+        rtts_us: BTreeMap<String, BTreeMap<String, u64>>,
+    },
+    PublishFailed {
+        error: String,
+    },
+    PathSummary {
+        target: String,
+        relayed: PathStats,
+        direct: PathStats,
+    },
+    RelayDetour {
+        target: String,
+        relayed_avg_us: u64,
+        direct_avg_us: u64,
+        detour_us: i64,
+    },
+    ConnectionSummary {
+        target: String,
+        connection_id: String,
+        local_address: Option<String>,
+        remote_address: String,
+        relayed: bool,
+        #[serde(flatten)]
+        stats: PathStats,
+    },
+}
+
+/// Answered pings with an RTT up to `le_us` and above the previous bucket's, in
+/// a `summary` record.
+#[derive(Serialize)]
+struct HistogramBucket {
+    le_us: u64,
+    count: u64,
+}
+
+/// Whether a peer accepted a security protocol, in a `probe` record.
+#[derive(Serialize)]
+struct SecurityRecord {
+    security: String,
+    accepted: bool,
+    time_us: Option<u64>,
+    error: Option<String>,
+}
+
+/// A statistic of a virtual peer checked against its simulated value, in a
+/// `simulation` record.
+#[derive(Serialize)]
+struct CheckRecord {
+    metric: &'static str,
+    expected: f64,
+    measured: f64,
+    tolerance: f64,
+    passed: bool,
+}
+
+impl From<&Check> for CheckRecord {
+    fn from(check: &Check) -> Self {
+        Self {
+            metric: check.metric,
+            expected: check.expected,
+            measured: check.measured,
+            tolerance: check.tolerance,
+            passed: check.passed(),
+        }
+    }
+}
+
+/// Ping results over one kind of connection, in a `path_summary` record.
+#[derive(Serialize)]
+struct PathStats {
+    transmitted: u64,
+    received: u64,
+    avg_us: Option<u64>,
+    mdev_us: Option<u64>,
+}
+
+impl From<&PingStats> for PathStats {
+    fn from(stats: &PingStats) -> Self {
+        Self {
+            transmitted: stats.transmitted(),
+            received: stats.received(),
+            avg_us: stats.avg().as_ref().map(micros),
+            mdev_us: stats.mdev().as_ref().map(micros),
+        }
+    }
+}
+
+/// Writes a line to the output's writer, like `println!`.
+macro_rules! out {
+    ($output:expr, $($arg:tt)*) => {
+        $output.write_line(format_args!($($arg)*))
+    };
+}
+
+/// Writes node events to stdout, or another writer, in the selected [`Format`].
+pub struct Output {
+    format: Format,
+    writer: RefCell<Box<dyn Write>>,
+    /// Whether the CSV header has been written yet.
+    csv_header: Cell<bool>,
+    /// Names given to peers, shown instead of their PeerIds.
+    names: RefCell<HashMap<PeerId, String>>,
+    /// Labels advertised by peers, shown next to their pings.
+    labels: RefCell<HashMap<PeerId, Labels>>,
+}
+
+impl Output {
+    /// Writes to stdout.
+    pub fn new(format: Format) -> Self {
+        Self::with_writer(format, Box::new(io::stdout()))
+    }
+
+    /// Writes to the given writer, e.g. [`io::sink`] while a dashboard owns the terminal.
+    pub fn with_writer(format: Format, writer: Box<dyn Write>) -> Self {
+        Self {
+            format,
+            writer: RefCell::new(writer),
+            csv_header: Cell::new(false),
+            names: RefCell::new(HashMap::new()),
+            labels: RefCell::new(HashMap::new()),
+        }
+    }
+
+    /// Writes to the file at `path`, replacing it if it exists.
+    pub fn to_file(format: Format, path: &Path) -> io::Result<Self> {
+        Ok(Self::with_writer(format, Box::new(File::create(path)?)))
+    }
+
+    /// Shows `name` instead of the PeerId of `peer_id` from now on.
+    pub fn name_peer(&self, peer_id: PeerId, name: &str) {
+        self.names.borrow_mut().insert(peer_id, name.to_owned());
+    }
+
+    /// Shows `labels` next to the pings of `peer_id` from now on.
+    pub fn label_peer(&self, peer_id: PeerId, labels: Labels) {
+        self.labels.borrow_mut().insert(peer_id, labels);
+    }
+
+    /// The node has started with the given identity.
+    pub fn started(&self, peer_id: &PeerId) {
+        match self.format {
+            Format::Text => out!(self, "Local peer id: {peer_id}"),
+            Format::Json => self.emit(Record::Started { peer_id: peer_id.to_string() }),
+            Format::Csv => {}
+        }
+    }
+
+    /// The node is listening on a new address.
+    pub fn listening(&self, address: &Multiaddr) {
+        match self.format {
+            Format::Text => out!(self, "Listening on {address}"),
+            Format::Json => self.emit(Record::Listening { address: address.to_string() }),
+            Format::Csv => {}
+        }
+    }
+
+    /// Listening on a default address failed and it was skipped.
+    pub fn listen_failed(&self, address: &Multiaddr, error: &dyn Display) {
+        match self.format {
+            Format::Text => out!(self, "Not listening on {address}: {error}"),
+            Format::Json => self.emit(Record::ListenFailed {
+                address: address.to_string(),
+                error: error.to_string(),
+            }),
+            Format::Csv => {}
+        }
+    }
+
+    /// The metrics endpoint is being served on the given address.
+    pub fn serving_metrics(&self, addr: &SocketAddr) {
+        let url = format!("http://{addr}/metrics");
+        match self.format {
+            Format::Text => out!(self, "Serving metrics at {url}"),
+            Format::Json => self.emit(Record::ServingMetrics { url }),
+            Format::Csv => {}
+        }
+    }
+
+    /// The REST API is being served at the given address.
+    pub fn serving_api(&self, addr: &SocketAddr) {
+        let url = format!("http://{addr}/peers");
+        match self.format {
+            Format::Text => out!(self, "Serving the API at {url}"),
+            Format::Json => self.emit(Record::ServingApi { url }),
+            Format::Csv => {}
+        }
+    }
+
+    /// The gRPC control API is being served at the given address.
+    #[cfg_attr(not(feature = "grpc"), allow(dead_code))]
+    pub fn serving_grpc(&self, addr: &SocketAddr) {
+        match self.format {
+            Format::Text => out!(self, "Serving the gRPC API at {addr}"),
+            Format::Json => self.emit(Record::ServingGrpc { address: addr.to_string() }),
+            Format::Csv => {}
+        }
+    }
+
+    /// The `--daemon` control socket is listening at `path`.
+    pub fn control_socket(&self, path: &Path) {
+        match self.format {
+            Format::Text => out!(self, "Control socket at {}", path.display()),
+            Format::Json => self.emit(Record::ControlSocket { path: path.display().to_string() }),
+            Format::Csv => {}
+        }
+    }
+
+    /// A dial to the given address has been started.
+    pub fn dialing(&self, address: &Multiaddr) {
+        match self.format {
+            Format::Text => out!(self, "Dialed {address}"),
+            Format::Json => self.emit(Record::Dialing { address: address.to_string() }),
+            Format::Csv => {}
+        }
+    }
+
+    /// A peer from the address book is dialed again.
+    pub fn resuming(&self, entry: &Entry) {
+        match self.format {
+            Format::Text => {
+                let seen = match entry.last_seen.and_then(|seen| seen.elapsed().ok()) {
+                    Some(ago) => format!("last seen {} ago", humantime::format_duration(Duration::from_secs(ago.as_secs()))),
+                    None => "never answered".to_owned(),
+                };
+                match entry.avg_rtt {
+                    Some(avg) => out!(self, "Resuming {} from the address book ({seen}, avg {:.3} ms)", entry.address, millis(avg)),
+                    None => out!(self, "Resuming {} from the address book ({seen})", entry.address),
+                }
+            }
+            Format::Json => self.emit(Record::Resuming {
+                peer_id: entry.peer_id.to_string(),
+                name: entry.name.clone(),
+                address: entry.address.to_string(),
+                last_seen: entry.last_seen.map(|seen| humantime::format_rfc3339_micros(seen).to_string()),
+                avg_rtt_us: entry.avg_rtt.as_ref().map(micros),
+            }),
+            Format::Csv => {}
+        }
+    }
+
+    /// A DHT lookup for the given peer has been started.
+    pub fn looking_up(&self, peer_id: &PeerId) {
+        match self.format {
+            Format::Text => out!(self, "Looking up {peer_id} in the DHT"),
+            Format::Json => self.emit(Record::LookingUp { peer_id: peer_id.to_string() }),
+            Format::Csv => {}
+        }
+    }
+
+    /// Dialing the given address failed.
+    pub fn dial_failed(&self, address: &Multiaddr, kind: Option<DialErrorKind>, error: &dyn Display) {
+        match self.format {
+            Format::Text => match kind {
+                Some(kind) => out!(self, "Failed to dial {address} ({kind}): {error}"),
+                None => out!(self, "Failed to dial {address}: {error}"),
+            },
+            Format::Json => self.emit(Record::DialFailed {
+                address: address.to_string(),
+                kind: kind.map(DialErrorKind::as_str),
+                error: error.to_string(),
+            }),
+            Format::Csv => {}
+        }
+    }
+
+    /// The peer at `address` authenticated as `obtained` instead of the PeerId
+    /// at the end of the address; the connection was closed.
+    pub fn wrong_peer(&self, address: &Multiaddr, obtained: &PeerId) {
+        match self.format {
+            Format::Text => out!(self, "Failed to dial {address}: the peer there authenticated as {obtained}"),
+            Format::Json => self.emit(Record::WrongPeer { address: address.to_string(), obtained: obtained.to_string() }),
+            Format::Csv => {}
+        }
+    }
+
+    /// The given address will be dialed again after `delay`.
+    pub fn redialing(&self, address: &Multiaddr, attempt: u32, delay: Duration) {
+        match self.format {
+            Format::Text => out!(self, "Re-dialing {address} in {:.1}s (attempt {attempt})", delay.as_secs_f64()),
+            Format::Json => self.emit(Record::Redialing {
+                address: address.to_string(),
+                attempt,
+                delay_ms: delay.as_millis() as u64,
+            }),
+            Format::Csv => {}
+        }
+    }
+
+    /// The peer at `address` didn't answer a ping within `wait`.
+    pub fn no_answer(&self, address: &Multiaddr, wait: Duration) {
+        match self.format {
+            Format::Text => out!(self, "No answer from {address} within {}", humantime::format_duration(wait)),
+            Format::Json => self.emit(Record::NoAnswer { address: address.to_string(), wait_ms: wait.as_millis() as u64 }),
+            Format::Csv => {}
+        }
+    }
+
+    /// The retry budget for the given address is exhausted.
+    pub fn gave_up(&self, address: &Multiaddr) {
+        match self.format {
+            Format::Text => out!(self, "Giving up on {address}"),
+            Format::Json => self.emit(Record::GaveUp { address: address.to_string() }),
+            Format::Csv => {}
+        }
+    }
+
+    /// A target failed `failures` pings in a row and its connections are being
+    /// closed; it is re-dialed later unless `evicted`.
+    pub fn peer_dead(&self, target: impl Display, peer_id: &PeerId, failures: u32, evicted: bool) {
+        match self.format {
+            Format::Text => {
+                let next = if evicted { "evicting it" } else { "re-dialing it later" };
+                out!(self, "{target} is dead after {failures} failed pings in a row, closing its connections and {next}");
+            }
+            Format::Json => self.emit(Record::PeerDead {
+                target: target.to_string(),
+                peer_id: peer_id.to_string(),
+                failures,
+                evicted,
+            }),
+            Format::Csv => {}
+        }
+    }
+
+    /// A connection with `remote`, an address or peer id, was refused by the
+    /// allow or deny list.
+    pub fn denied(&self, remote: &dyn Display, denied: &ConnectionDenied) {
+        // The denial itself only says "connection denied"; its source says why.
+        let reason = denied.source().map_or_else(|| denied.to_string(), ToString::to_string);
+        match self.format {
+            Format::Text => out!(self, "Denied connection with {remote}: {reason}"),
+            Format::Json => self.emit(Record::Denied {
+                remote: remote.to_string(),
+                reason: reason.clone(),
+            }),
+            Format::Csv => {}
+        }
+    }
+
+    /// A peer has been discovered on the local network.
+    pub fn discovered(&self, peer_id: &PeerId, address: &Multiaddr) {
+        match self.format {
+            Format::Text => out!(self, "Discovered {peer_id} at {address}"),
+            Format::Json => self.emit(Record::Discovered {
+                peer_id: peer_id.to_string(),
+                address: address.to_string(),
+            }),
+            Format::Csv => {}
+        }
+    }
+
+    /// A connection to a peer has been established.
+    pub fn connected(&self, peer_id: &PeerId, address: &Multiaddr) {
+        match self.format {
+            Format::Text => out!(self, "Connected to {} at {address}", self.peer(peer_id)),
+            Format::Json => self.emit(Record::Connected {
+                peer_id: peer_id.to_string(),
+                name: self.name(peer_id),
+                address: address.to_string(),
+            }),
+            Format::Csv => {}
+        }
+    }
+
+    /// The connection to `peer_id` was established at `race.winner`, the
+    /// fastest of the addresses dialed at once.
+    pub fn race_won(&self, peer_id: &PeerId, race: &Race) {
+        match self.format {
+            Format::Text => out!(
+                self,
+                "Fastest of {} addresses of {}: {}",
+                race.candidates,
+                self.peer(peer_id),
+                race.winner
+            ),
+            Format::Json => self.emit(Record::RaceWon {
+                peer_id: peer_id.to_string(),
+                name: self.name(peer_id),
+                winner: race.winner.to_string(),
+                candidates: race.candidates,
+            }),
+            Format::Csv => {}
+        }
+    }
+
+    /// A relay has accepted or renewed our reservation, making us reachable
+    /// through it.
+    pub fn reserved(&self, relay_peer_id: &PeerId, renewal: bool) {
+        match self.format {
+            Format::Text if renewal => out!(self, "Renewed reservation with relay {}", self.peer(relay_peer_id)),
+            Format::Text => out!(self, "Reservation accepted by relay {}", self.peer(relay_peer_id)),
+            Format::Json => self.emit(Record::Reserved {
+                relay_peer_id: relay_peer_id.to_string(),
+                renewal,
+            }),
+            Format::Csv => {}
+        }
+    }
+
+    /// As a relay server, we accepted a reservation from a peer.
+    pub fn relay_reservation(&self, peer_id: &PeerId) {
+        match self.format {
+            Format::Text => out!(self, "Relaying for {peer_id}"),
+            Format::Json => self.emit(Record::RelayReservation { peer_id: peer_id.to_string() }),
+            Format::Csv => {}
+        }
+    }
+
+    /// As a relay server, we opened a circuit from `src` to `dst`.
+    pub fn relay_circuit(&self, src: &PeerId, dst: &PeerId) {
+        match self.format {
+            Format::Text => out!(self, "Relaying circuit {src} -> {dst}"),
+            Format::Json => self.emit(Record::RelayCircuit {
+                src_peer_id: src.to_string(),
+                dst_peer_id: dst.to_string(),
+            }),
+            Format::Csv => {}
+        }
+    }
+
+    /// As a relay server, we denied a reservation (no `dst`) or a circuit,
+    /// typically because a limit was reached.
+    pub fn relay_denied(&self, src: &PeerId, dst: Option<&PeerId>) {
+        match self.format {
+            Format::Text => match dst {
+                Some(dst) => out!(self, "Denied circuit {src} -> {dst}"),
+                None => out!(self, "Denied reservation for {src}"),
+            },
+            Format::Json => self.emit(Record::RelayDenied {
+                src_peer_id: src.to_string(),
+                dst_peer_id: dst.map(|dst| dst.to_string()),
+            }),
+            Format::Csv => {}
+        }
+    }
+
+    /// A peer answered how many peers it is connected to, or failed to.
+    pub fn peers_exchanged(&self, peer_id: &PeerId, result: &Result<Vec<SharedPeer>, OutboundFailure>) {
+        match self.format {
+            Format::Text => match result {
+                Ok(peers) => out!(self, "{} shared {} peers", self.peer(peer_id), peers.len()),
+                Err(e) => out!(self, "Peer exchange with {} failed: {e}", self.peer(peer_id)),
+            },
+            Format::Json => self.emit(Record::PeersExchanged {
+                peer_id: peer_id.to_string(),
+                name: self.name(peer_id),
+                peers: result.as_ref().map_or(0, Vec::len),
+                error: result.as_ref().err().map(ToString::to_string),
+            }),
+            Format::Csv => {}
+        }
+    }
+
+    /// The rendezvous point registered us under `namespace` for `ttl` seconds.
+    pub fn rendezvous_registered(&self, point: &PeerId, namespace: &Namespace, ttl: u64) {
+        match self.format {
+            Format::Text => out!(
+                self,
+                "Registered under {namespace} at rendezvous point {} for {}",
+                self.peer(point),
+                humantime::format_duration(Duration::from_secs(ttl))
+            ),
+            Format::Json => self.emit(Record::RendezvousRegistered {
+                point_peer_id: point.to_string(),
+                namespace: namespace.to_string(),
+                ttl_secs: ttl,
+            }),
+            Format::Csv => {}
+        }
+    }
+
+    /// The rendezvous point refused to `register` us or to `discover` peers
+    /// under `namespace`.
+    pub fn rendezvous_failed(&self, point: &PeerId, operation: &'static str, namespace: Option<&Namespace>, error: ErrorCode) {
+        match self.format {
+            Format::Text => out!(self, "Rendezvous point {} failed to {operation}: {error:?}", self.peer(point)),
+            Format::Json => self.emit(Record::RendezvousFailed {
+                point_peer_id: point.to_string(),
+                namespace: namespace.map(ToString::to_string),
+                operation,
+                error: format!("{error:?}"),
+            }),
+            Format::Csv => {}
+        }
+    }
+
+    /// As a rendezvous point, we registered a peer under `namespace`.
+    pub fn rendezvous_peer_registered(&self, peer_id: &PeerId, namespace: &Namespace) {
+        match self.format {
+            Format::Text => out!(self, "Registered {peer_id} under {namespace}"),
+            Format::Json => self.emit(Record::RendezvousPeerRegistered {
+                peer_id: peer_id.to_string(),
+                namespace: namespace.to_string(),
+            }),
+            Format::Csv => {}
+        }
+    }
+
+    /// A direct connection upgrade via hole punching succeeded or failed.
+    pub fn hole_punch(&self, peer_id: &PeerId, result: &Result<ConnectionId, dcutr::Error>) {
+        match self.format {
+            Format::Text => match result {
+                Ok(_) => out!(self, "Hole punch to {} succeeded, direct connection established", self.peer(peer_id)),
+                Err(e) => out!(self, "Hole punch to {} failed: {e}", self.peer(peer_id)),
+            },
+            Format::Json => self.emit(Record::HolePunch {
+                peer_id: peer_id.to_string(),
+                error: result.as_ref().err().map(|e| e.to_string()),
+            }),
+            Format::Csv => {}
+        }
+    }
+
+    /// AutoNAT determined whether this node is reachable from the outside.
+    pub fn reachability(&self, status: &NatStatus) {
+        match self.format {
+            Format::Text => match status {
+                NatStatus::Public(address) => out!(self, "Reachability: public at {address}"),
+                NatStatus::Private => out!(self, "Reachability: private, inbound connections can only arrive via a relay"),
+                NatStatus::Unknown => out!(self, "Reachability: unknown"),
+            },
+            Format::Json => {
+                let (status, address) = match status {
+                    NatStatus::Public(address) => ("public", Some(address.to_string())),
+                    NatStatus::Private => ("private", None),
+                    NatStatus::Unknown => ("unknown", None),
+                };
+                self.emit(Record::Reachability { status, address });
+            }
+            Format::Csv => {}
+        }
+    }
+
+    /// As an AutoNAT server, we dialed a peer back at `address`, or failed or
+    /// refused to.
+    pub fn dial_back(&self, peer_id: &PeerId, result: Result<&Multiaddr, &InboundProbeError>) {
+        let result = result.map_err(|error| match error {
+            InboundProbeError::Response(ResponseError::DialError) => "unreachable",
+            InboundProbeError::Response(ResponseError::DialRefused) => "refused",
+            _ => "failed",
+        });
+        match self.format {
+            Format::Text => match result {
+                Ok(address) => out!(self, "Dialed back {} at {address} for AutoNAT", self.peer(peer_id)),
+                Err(error) => out!(self, "AutoNAT dial-back of {}: {error}", self.peer(peer_id)),
+            },
+            Format::Json => self.emit(Record::DialBack {
+                peer_id: peer_id.to_string(),
+                name: self.name(peer_id),
+                address: result.ok().map(ToString::to_string),
+                error: result.err(),
+            }),
+            Format::Csv => {}
+        }
+    }
+
+    /// An address of this node has been confirmed reachable by other peers.
+    pub fn external_address(&self, address: &Multiaddr) {
+        match self.format {
+            Format::Text => out!(self, "Confirmed external address {address}"),
+            Format::Json => self.emit(Record::ExternalAddress { address: address.to_string() }),
+            Format::Csv => {}
+        }
+    }
+
+    /// A port was mapped on the router via UPnP, its mapping expired, or no
+    /// router to map ports on was found.
+    pub fn port_mapping(&self, event: &upnp::Event) {
+        match self.format {
+            Format::Text => match event {
+                upnp::Event::NewExternalAddr(address) => out!(self, "Mapped port on the router, reachable at {address}"),
+                upnp::Event::ExpiredExternalAddr(address) => out!(self, "Port mapping for {address} expired"),
+                upnp::Event::GatewayNotFound => out!(self, "No router supporting UPnP found"),
+                upnp::Event::NonRoutableGateway => {
+                    out!(self, "The UPnP router is behind another NAT, mapping ports on it doesn't make us reachable")
+                }
+            },
+            Format::Json => {
+                let (status, address) = match event {
+                    upnp::Event::NewExternalAddr(address) => ("mapped", Some(address.to_string())),
+                    upnp::Event::ExpiredExternalAddr(address) => ("expired", Some(address.to_string())),
+                    upnp::Event::GatewayNotFound => ("gateway_not_found", None),
+                    upnp::Event::NonRoutableGateway => ("non_routable_gateway", None),
+                };
+                self.emit(Record::PortMapping { status, address });
+            }
+            Format::Csv => {}
+        }
+    }
+
+    /// A peer has sent its identify information, including the address it
+    /// observed us at.
+    pub fn identified(&self, peer_id: &PeerId, info: &identify::Info) {
+        match self.format {
+            Format::Text => {
+                out!(self, "Identified {}: {} ({})", self.peer(peer_id), info.agent_version, info.protocol_version);
+                let protocols: Vec<String> = info.protocols.iter().map(|p| p.to_string()).collect();
+                out!(self, "  protocols: {}", protocols.join(", "));
+                out!(self, "  observed us at {}", info.observed_addr);
+            }
+            Format::Json => self.emit(Record::Identified {
+                peer_id: peer_id.to_string(),
+                agent_version: info.agent_version.clone(),
+                protocol_version: info.protocol_version.clone(),
+                protocols: info.protocols.iter().map(|p| p.to_string()).collect(),
+                listen_addrs: info.listen_addrs.iter().map(|a| a.to_string()).collect(),
+                observed_addr: info.observed_addr.to_string(),
+            }),
+            Format::Csv => {}
+        }
+    }
+
+    /// A connection to a peer has been closed, optionally because of an error.
+    pub fn disconnected(&self, peer_id: &PeerId, cause: Option<&dyn Display>) {
+        match self.format {
+            Format::Text => match cause {
+                Some(cause) => out!(self, "Disconnected from {}: {cause}", self.peer(peer_id)),
+                None => out!(self, "Disconnected from {}", self.peer(peer_id)),
+            },
+            Format::Json => self.emit(Record::Disconnected {
+                peer_id: peer_id.to_string(),
+                cause: cause.map(|cause| cause.to_string()),
+            }),
+            Format::Csv => {}
+        }
+    }
+
+    /// A target went up, degraded or down.
+    pub fn health_changed(&self, target: impl Display, peer_id: Option<&PeerId>, transition: &Transition) {
+        match self.format {
+            Format::Text => match transition.from {
+                Some(from) => out!(self, "{target} is {} (was {from})", transition.to),
+                None => out!(self, "{target} is {}", transition.to),
+            },
+            Format::Json => self.emit(Record::HealthChanged {
+                target: target.to_string(),
+                peer_id: peer_id.map(ToString::to_string),
+                from: transition.from.map(|from| from.to_string()),
+                to: transition.to.to_string(),
+            }),
+            Format::Csv => {}
+        }
+    }
+
+    /// A ping round-trip to a peer completed or failed, over a relayed or
+    /// direct connection, possibly as a warm-up ping left out of the
+    /// statistics.
+    ///
+    /// `connection` is the one the ping was sent on, if known; text output
+    /// names it if `parallel`, as one of several to the peer.
+    pub fn ping(
+        &self,
+        peer_id: &PeerId,
+        connection: Option<&Connection>,
+        parallel: bool,
+        warmup: bool,
+        result: &Result<Duration, ping::Failure>,
+        jitter: Option<Duration>,
+    ) {
+        let relayed = connection.is_some_and(|connection| connection.relayed);
+        let via = match (relayed, warmup) {
+            (false, false) => "",
+            (true, false) => " (relayed)",
+            (false, true) => " (warmup)",
+            (true, true) => " (relayed, warmup)",
+        };
+        let peer_labels = self.labels.borrow().get(peer_id).cloned().unwrap_or_default();
+        match self.format {
+            Format::Text => {
+                let mut peer = format!("{}{}", self.peer(peer_id), labels::suffix(&peer_labels));
+                if let Some(connection) = connection.filter(|_| parallel) {
+                    peer += &format!(" over {connection}");
+                }
+                match result {
+                    Ok(rtt) => {
+                        let jitter = jitter.map_or(String::new(), |jitter| format!(" jitter={:.3} ms", millis(jitter)));
+                        out!(self, "Pong from {peer}: time={:.3} ms{jitter}{via}", millis(*rtt));
+                    }
+                    Err(e) => out!(self, "Ping to {peer} failed{via}: {e}"),
+                }
+            }
+            Format::Json => self.emit(Record::Ping {
+                peer_id: peer_id.to_string(),
+                name: self.name(peer_id),
+                labels: peer_labels,
+                connection_id: connection.map(|connection| connection.id.to_string()),
+                local_address: connection.and_then(|connection| connection.local.as_ref().map(ToString::to_string)),
+                remote_address: connection.map(|connection| connection.remote.to_string()),
+                relayed,
+                warmup,
+                rtt_us: result.as_ref().ok().map(micros),
+                jitter_us: jitter.as_ref().map(micros),
+                error: result.as_ref().err().map(|e| e.to_string()),
+            }),
+            Format::Csv => {
+                if !self.csv_header.replace(true) {
+                    out!(self, "{CSV_HEADER}");
+                }
+                let timestamp = humantime::format_rfc3339_micros(SystemTime::now());
+                let address = connection.map(|connection| connection.remote.to_string()).unwrap_or_default();
+                let (rtt_us, outcome) = match result {
+                    Ok(rtt) => (micros(rtt).to_string(), "success".to_owned()),
+                    Err(e) => (String::new(), e.to_string()),
+                };
+                out!(self, "{timestamp},{peer_id},{address},{rtt_us},{}", csv_field(&outcome));
+            }
+        }
+    }
+
+    /// How long setting up an outgoing connection to a peer took, phase by phase.
+    pub fn connection_timing(&self, peer_id: &PeerId, timing: &ConnectionTiming) {
+        match self.format {
+            Format::Text => {
+                let handshake = match (timing.security, timing.muxer) {
+                    (Some(security), Some(muxer)) => {
+                        format!("security {:.3} ms, muxer {:.3} ms", millis(security), millis(muxer))
+                    }
+                    _ => "security and muxer included".to_owned(),
+                };
+                out!(
+                    self,
+                    "Connection setup to {}: connect {:.3} ms, {handshake}, first ping {:.3} ms",
+                    self.peer(peer_id),
+                    millis(timing.connect),
+                    millis(timing.first_ping)
+                );
+            }
+            Format::Json => self.emit(Record::ConnectionTiming {
+                peer_id: peer_id.to_string(),
+                name: self.name(peer_id),
+                connect_us: micros(&timing.connect),
+                security_us: timing.security.as_ref().map(micros),
+                muxer_us: timing.muxer.as_ref().map(micros),
+                first_ping_us: micros(&timing.first_ping),
+            }),
+            Format::Csv => {}
+        }
+    }
+
+    /// An echo request of `size` bytes to a peer completed or failed.
+    pub fn echo(&self, peer_id: &PeerId, size: usize, result: &Result<Duration, echo::Failure>) {
+        match self.format {
+            Format::Text => match result {
+                Ok(rtt) => out!(self, "Echo from {}: {size} bytes time={:.3} ms", self.peer(peer_id), millis(*rtt)),
+                Err(e) => out!(self, "Echo of {size} bytes to {} failed: {e}", self.peer(peer_id)),
+            },
+            Format::Json => self.emit(Record::Echo {
+                peer_id: peer_id.to_string(),
+                name: self.name(peer_id),
+                size,
+                rtt_us: result.as_ref().ok().map(micros),
+                error: result.as_ref().err().map(|e| e.to_string()),
+            }),
+            Format::Csv => {}
+        }
+    }
+
+    /// An exchange of timestamps with a peer completed or failed.
+    pub fn clock(&self, peer_id: &PeerId, result: &Result<clock::Sample, impl Display>) {
+        match self.format {
+            Format::Text => match result {
+                Ok(sample) => out!(
+                    self,
+                    "Clock of {}: offset={:+.3} ms delay={:.3} ms one-way={:.3}/{:.3} ms",
+                    self.peer(peer_id),
+                    signed_millis(sample.offset_us),
+                    signed_millis(sample.delay_us),
+                    signed_millis(sample.outbound_us),
+                    signed_millis(sample.inbound_us),
+                ),
+                Err(e) => out!(self, "Clock exchange with {} failed: {e}", self.peer(peer_id)),
+            },
+            Format::Json => {
+                let sample = result.as_ref().ok();
+                self.emit(Record::Clock {
+                    peer_id: peer_id.to_string(),
+                    name: self.name(peer_id),
+                    offset_us: sample.map(|sample| sample.offset_us),
+                    delay_us: sample.map(|sample| sample.delay_us),
+                    outbound_us: sample.map(|sample| sample.outbound_us),
+                    inbound_us: sample.map(|sample| sample.inbound_us),
+                    error: result.as_ref().err().map(|e| e.to_string()),
+                })
+            }
+            Format::Csv => {}
+        }
+    }
+
+    /// A throughput benchmark transfer to or from a peer completed or failed.
+    pub fn bench(&self, peer_id: &PeerId, direction: bench::Direction, result: &Result<bench::Throughput, impl Display>) {
+        match self.format {
+            Format::Text => {
+                let direction = match direction {
+                    bench::Direction::Upload => "Upload to",
+                    bench::Direction::Download => "Download from",
+                };
+                match result {
+                    Ok(throughput) => out!(
+                        self,
+                        "{direction} {}: {:.2} MB/s ({} bytes in {:.2} s)",
+                        self.peer(peer_id),
+                        throughput.bytes_per_second() / 1e6,
+                        throughput.bytes,
+                        throughput.elapsed.as_secs_f64()
+                    ),
+                    Err(e) => out!(self, "{direction} {} failed: {e}", self.peer(peer_id)),
+                }
+            }
+            Format::Json => self.emit(Record::Bench {
+                peer_id: peer_id.to_string(),
+                name: self.name(peer_id),
+                direction: direction.to_string(),
+                bytes: result.as_ref().ok().map(|throughput| throughput.bytes),
+                elapsed_us: result.as_ref().ok().map(|throughput| micros(&throughput.elapsed)),
+                bytes_per_second: result.as_ref().ok().map(bench::Throughput::bytes_per_second),
+                error: result.as_ref().err().map(|e| e.to_string()),
+            }),
+            Format::Csv => {}
+        }
+    }
+
+    /// The handshake times and RTTs measured over each transport and security
+    /// combination; `None` if the peer has no address for it.
+    pub fn comparison(&self, rows: &[(Combination, Option<Result<Measurement, String>>)]) {
+        match self.format {
+            Format::Text => {
+                out!(self, "{:<10} {:<40} {:>12} {:>12} {:>12}", "transport", "address", "handshake", "rtt avg", "rtt mdev");
+                for (combination, result) in rows {
+                    match result {
+                        Some(Ok(measurement)) => {
+                            let rtt = |rtt: Option<Duration>| rtt.map_or("-".to_owned(), |rtt| format!("{:.3} ms", millis(rtt)));
+                            out!(
+                                self,
+                                "{:<10} {:<40} {:>12} {:>12} {:>12}",
+                                combination.to_string(),
+                                measurement.address.to_string(),
+                                rtt(Some(measurement.handshake)),
+                                rtt(measurement.pings.avg()),
+                                rtt(measurement.pings.mdev())
+                            );
+                        }
+                        Some(Err(e)) => out!(self, "{:<10} failed: {e}", combination.to_string()),
+                        None => out!(self, "{:<10} no address for this transport", combination.to_string()),
+                    }
+                }
+            }
+            Format::Json => {
+                for (combination, result) in rows {
+                    let measurement = result.as_ref().and_then(|result| result.as_ref().ok());
+                    self.emit(Record::Comparison {
+                        combination: combination.to_string(),
+                        address: measurement.map(|measurement| measurement.address.to_string()),
+                        handshake_us: measurement.map(|measurement| micros(&measurement.handshake)),
+                        pings: measurement.map(|measurement| (&measurement.pings).into()),
+                        error: match result {
+                            Some(Ok(_)) => None,
+                            Some(Err(e)) => Some(e.clone()),
+                            None => Some("no address for this transport".to_owned()),
+                        },
+                    });
+                }
+            }
+            Format::Csv => {}
+        }
+    }
+
+    /// The checks of a diagnosis in the order they ran, and how many passed.
+    pub fn checklist(&self, checks: &[doctor::Check]) {
+        let status = |check: &doctor::Check| match check.status {
+            Status::Pass => "pass",
+            Status::Fail => "fail",
+            Status::Skip => "skip",
+        };
+        match self.format {
+            Format::Text => {
+                for check in checks {
+                    let time = check.time.map_or("-".to_owned(), |time| format!("{:.3} ms", millis(time)));
+                    let label = match check.status {
+                        Status::Pass => "ok",
+                        Status::Fail => "FAILED",
+                        Status::Skip => "skipped",
+                    };
+                    out!(self, "{:<10} {:<8} {:>12}  {}", check.name, label, time, check.detail);
+                }
+                let count = |status| checks.iter().filter(|check| check.status == status).count();
+                out!(self, "{} passed, {} failed, {} skipped", count(Status::Pass), count(Status::Fail), count(Status::Skip));
+            }
+            Format::Json => {
+                for check in checks {
+                    self.emit(Record::Diagnosis {
+                        check: check.name,
+                        status: status(check),
+                        time_us: check.time.as_ref().map(micros),
+                        detail: check.detail.clone(),
+                    });
+                }
+            }
+            Format::Csv => {}
+        }
+    }
+
+    /// What probing a peer found out about its protocols.
+    pub fn probe_findings(&self, findings: &Findings) {
+        match self.format {
+            Format::Text => {
+                if let Some(peer_id) = &findings.peer_id {
+                    let version = match (&findings.agent_version, &findings.protocol_version) {
+                        (Some(agent), Some(protocol)) => format!("{agent} ({protocol})"),
+                        _ => "unknown version".to_owned(),
+                    };
+                    out!(self, "Peer {}: {version}", self.peer(peer_id));
+                }
+                for (security, result) in &findings.security {
+                    match result {
+                        Ok(time) => out!(self, "{:<10} {:<8} {:>12}  accepted", "security", security, format!("{:.3} ms", millis(*time))),
+                        // The error is in the problem if the peer accepts neither.
+                        Err(_) => out!(self, "{:<10} {:<8} {:>12}  rejected", "security", security, "-"),
+                    }
+                }
+                if let Some(muxer) = findings.muxer {
+                    out!(self, "{:<10} {muxer}", "muxer");
+                }
+                if !findings.features.is_empty() {
+                    let features: Vec<String> = findings
+                        .features
+                        .iter()
+                        .map(|(name, supported)| format!("{name} {}", if *supported { "yes" } else { "no" }))
+                        .collect();
+                    out!(self, "{:<10} {}", "features", features.join(", "));
+                }
+                for protocol in &findings.protocols {
+                    out!(self, "{:<10} {protocol}", "protocol");
+                }
+                for problem in &findings.problems {
+                    out!(self, "PROBLEM: {problem}");
+                }
+                if findings.problems.is_empty() {
+                    out!(self, "No incompatibilities found");
+                }
+            }
+            Format::Json => self.emit(Record::Probe {
+                peer_id: findings.peer_id.map(|peer_id| peer_id.to_string()),
+                security: findings
+                    .security
+                    .iter()
+                    .map(|(security, result)| SecurityRecord {
+                        security: security.to_string(),
+                        accepted: result.is_ok(),
+                        time_us: result.as_ref().ok().map(micros),
+                        error: result.as_ref().err().cloned(),
+                    })
+                    .collect(),
+                muxer: findings.muxer,
+                agent_version: findings.agent_version.clone(),
+                protocol_version: findings.protocol_version.clone(),
+                protocols: findings.protocols.clone(),
+                features: findings.features.iter().copied().collect(),
+                problems: findings.problems.clone(),
+            }),
+            Format::Csv => {}
+        }
+    }
+
+    /// The peers closest to `key` in the DHT, fastest first.
+    pub fn sweep(&self, key: &PeerId, neighbors: &[Neighbor]) {
+        match self.format {
+            Format::Text => {
+                out!(self, "{} peers closest to {key}:", neighbors.len());
+                out!(self, "{:>4} {:<52} {:<40} {:>6} {:>12} {:>12}", "rank", "peer", "address", "loss", "rtt avg", "rtt mdev");
+                for (rank, neighbor) in neighbors.iter().enumerate() {
+                    let address = neighbor.address.as_ref().map_or("-".to_owned(), ToString::to_string);
+                    let stats = &neighbor.stats;
+                    if stats.received() == 0 {
+                        let reason = neighbor.error.as_deref().unwrap_or("no pings answered");
+                        out!(self, "{:>4} {:<52} {:<40} {reason}", rank + 1, neighbor.peer_id.to_string(), address);
+                        continue;
+                    }
+                    let rtt = |rtt: Option<Duration>| rtt.map_or("-".to_owned(), |rtt| format!("{:.3} ms", millis(rtt)));
+                    out!(
+                        self,
+                        "{:>4} {:<52} {:<40} {:>5.1}% {:>12} {:>12}",
+                        rank + 1,
+                        neighbor.peer_id.to_string(),
+                        address,
+                        stats.loss_percent(),
+                        rtt(stats.avg()),
+                        rtt(stats.mdev())
+                    );
+                }
+            }
+            Format::Json => {
+                for (rank, neighbor) in neighbors.iter().enumerate() {
+                    self.emit(Record::SweepResult {
+                        key: key.to_string(),
+                        rank: rank + 1,
+                        peer_id: neighbor.peer_id.to_string(),
+                        address: neighbor.address.as_ref().map(ToString::to_string),
+                        pings: (&neighbor.stats).into(),
+                        error: neighbor.error.clone(),
+                    });
+                }
+            }
+            Format::Csv => {}
+        }
+    }
+
+    /// Statistics of each virtual peer of a simulation, and how they compare
+    /// with what it simulates.
+    pub fn simulation(&self, outcomes: &[Outcome]) {
+        match self.format {
+            Format::Text => {
+                for outcome in outcomes {
+                    out!(self, "{}", outcome.stats.report(outcome.peer_id));
+                    for check in &outcome.checks {
+                        out!(
+                            self,
+                            "{:<12} expected {:>9.3}, measured {:>9.3}, tolerance {:>7.3}: {}",
+                            check.metric,
+                            check.expected,
+                            check.measured,
+                            check.tolerance,
+                            if check.passed() { "ok" } else { "FAILED" }
+                        );
+                    }
+                }
+            }
+            Format::Json => {
+                for outcome in outcomes {
+                    self.emit(Record::Simulation {
+                        peer_id: outcome.peer_id.to_string(),
+                        pings: (&outcome.stats).into(),
+                        checks: outcome.checks.iter().map(Into::into).collect(),
+                        passed: outcome.checks.iter().all(Check::passed),
+                    });
+                }
+            }
+            Format::Csv => {}
+        }
+    }
+
+    /// Final statistics of the echo requests of `size` bytes to a target.
+    pub fn echo_summary(&self, target: impl Display, size: usize, stats: &PingStats) {
+        match self.format {
+            Format::Text => match stats.rtt_summary() {
+                Some(rtt) => out!(self, "echo {size} bytes: {} received, rtt {rtt}", stats.received()),
+                None => out!(self, "echo {size} bytes: {} transmitted, none received", stats.transmitted()),
+            },
+            Format::Json => self.emit(Record::EchoSummary { target: target.to_string(), size, stats: stats.into() }),
+            Format::Csv => {}
+        }
+    }
+
+    /// The clock estimate of a target: its `best` of `samples` clock samples,
+    /// the one with the lowest delay.
+    pub fn clock_summary(&self, target: impl Display, samples: u64, best: &clock::Sample) {
+        match self.format {
+            Format::Text => out!(
+                self,
+                "clock: offset {:+.3} ms, one-way {:.3}/{:.3} ms (best of {samples}, delay {:.3} ms)",
+                signed_millis(best.offset_us),
+                signed_millis(best.outbound_us),
+                signed_millis(best.inbound_us),
+                signed_millis(best.delay_us),
+            ),
+            Format::Json => self.emit(Record::ClockSummary {
+                target: target.to_string(),
+                samples,
+                offset_us: best.offset_us,
+                delay_us: best.delay_us,
+                outbound_us: best.outbound_us,
+                inbound_us: best.inbound_us,
+            }),
+            Format::Csv => {}
+        }
+    }
+
+    /// The failed dials of a target, by why they failed.
+    pub fn dial_failures(&self, target: impl Display, failures: &BTreeMap<DialErrorKind, u64>) {
+        match self.format {
+            Format::Text => {
+                let counts: Vec<String> = failures.iter().map(|(kind, count)| format!("{count} {kind}")).collect();
+                out!(self, "dial failures: {}", counts.join(", "));
+            }
+            Format::Json => self.emit(Record::DialFailures {
+                target: target.to_string(),
+                failures: failures.iter().map(|(kind, count)| (kind.as_str(), *count)).collect(),
+            }),
+            Format::Csv => {}
+        }
+    }
+
+    /// Final statistics for a ping target, with the labels it advertised and
+    /// the bytes exchanged with it.
+    pub fn summary(&self, target: impl Display, labels: &Labels, stats: &PingStats, traffic: &Traffic) {
+        match self.format {
+            Format::Text => {
+                out!(self, "{}", stats.report(format_args!("{target}{}", labels::suffix(labels))));
+                out!(self, "{} bytes sent, {} bytes received", traffic.sent, traffic.received);
+            }
+            Format::Json => self.emit(Record::Summary {
+                target: target.to_string(),
+                labels: labels.clone(),
+                transmitted: stats.transmitted(),
+                received: stats.received(),
+                loss_percent: stats.loss_percent(),
+                min_us: stats.min().as_ref().map(micros),
+                avg_us: stats.avg().as_ref().map(micros),
+                max_us: stats.max().as_ref().map(micros),
+                mdev_us: stats.mdev().as_ref().map(micros),
+                jitter_us: stats.jitter().as_ref().map(micros),
+                p50_us: stats.percentile(50.0).as_ref().map(micros),
+                p90_us: stats.percentile(90.0).as_ref().map(micros),
+                p99_us: stats.percentile(99.0).as_ref().map(micros),
+                p999_us: stats.percentile(99.9).as_ref().map(micros),
+                histogram: stats
+                    .histogram()
+                    .into_iter()
+                    .map(|(le, count)| HistogramBucket { le_us: micros(&le), count })
+                    .collect(),
+                sent_bytes: traffic.sent,
+                received_bytes: traffic.received,
+            }),
+            Format::Csv => {}
+        }
+    }
+
+    /// Statistics of a target and the bytes exchanged with it over the last
+    /// `interval`, for `--summary-interval`.
+    pub fn interval_summary(
+        &self,
+        target: impl Display,
+        labels: &Labels,
+        interval: Duration,
+        stats: &PingStats,
+        traffic: &Traffic,
+    ) {
+        match self.format {
+            Format::Text => {
+                let rtt = stats.rtt_summary().map_or_else(String::new, |rtt| format!(", rtt {rtt}"));
+                out!(
+                    self,
+                    "{target}{}: {} transmitted, {} received, {:.1}% packet loss{rtt}, {} bytes sent, {} bytes received in the last {}",
+                    labels::suffix(labels),
+                    stats.transmitted(),
+                    stats.received(),
+                    stats.loss_percent(),
+                    traffic.sent,
+                    traffic.received,
+                    humantime::format_duration(interval),
+                );
+            }
+            Format::Json => self.emit(Record::IntervalSummary {
+                target: target.to_string(),
+                labels: labels.clone(),
+                interval_ms: interval.as_millis() as u64,
+                transmitted: stats.transmitted(),
+                received: stats.received(),
+                loss_percent: stats.loss_percent(),
+                min_us: stats.min().as_ref().map(micros),
+                avg_us: stats.avg().as_ref().map(micros),
+                max_us: stats.max().as_ref().map(micros),
+                p99_us: stats.percentile(99.0).as_ref().map(micros),
+                sent_bytes: traffic.sent,
+                received_bytes: traffic.received,
+            }),
+            Format::Csv => {}
+        }
+    }
+
+    /// Pinging `target`, or every peer without one, was paused through the
+    /// control socket.
+    pub fn paused(&self, target: Option<String>) {
+        match self.format {
+            Format::Text => match &target {
+                Some(target) => out!(self, "Paused pinging {target}"),
+                None => out!(self, "Paused pinging all peers"),
+            },
+            Format::Json => self.emit(Record::Paused { target }),
+            Format::Csv => {}
+        }
+    }
+
+    /// Pinging `target`, or every peer without one, was resumed through the
+    /// control socket.
+    pub fn resumed(&self, target: Option<String>) {
+        match self.format {
+            Format::Text => match &target {
+                Some(target) => out!(self, "Resumed pinging {target}"),
+                None => out!(self, "Resumed pinging all peers"),
+            },
+            Format::Json => self.emit(Record::Resumed { target }),
+            Format::Csv => {}
+        }
+    }
+
+    /// A measurement window of `--schedule` opened, and pinging started.
+    pub fn window_opened(&self, until: SystemTime) {
+        match self.format {
+            Format::Text => out!(self, "Measurement window open until {}", humantime::format_rfc3339_seconds(until)),
+            Format::Json => self.emit(Record::WindowOpened { until: humantime::format_rfc3339_seconds(until).to_string() }),
+            Format::Csv => {}
+        }
+    }
+
+    /// The measurement window closed, or the node started outside of one, and
+    /// pinging stopped until the `next` one, if any.
+    pub fn window_closed(&self, next: Option<SystemTime>) {
+        match self.format {
+            Format::Text => match next {
+                Some(next) => out!(self, "Measurement window closed, next at {}", humantime::format_rfc3339_seconds(next)),
+                None => out!(self, "Measurement window closed, no more in the schedule"),
+            },
+            Format::Json => self.emit(Record::WindowClosed {
+                next: next.map(|next| humantime::format_rfc3339_seconds(next).to_string()),
+            }),
+            Format::Csv => {}
+        }
+    }
+
+    /// The results of a target in the measurement window from `opened` to
+    /// `closed`.
+    pub fn window_summary(&self, target: impl Display, labels: &Labels, opened: SystemTime, closed: SystemTime, stats: &PingStats) {
+        match self.format {
+            Format::Text => {
+                let rtt = stats.rtt_summary().map_or_else(String::new, |rtt| format!(", rtt {rtt}"));
+                out!(
+                    self,
+                    "{target}{}: {} transmitted, {} received, {:.1}% packet loss{rtt} in the window from {} to {}",
+                    labels::suffix(labels),
+                    stats.transmitted(),
+                    stats.received(),
+                    stats.loss_percent(),
+                    humantime::format_rfc3339_seconds(opened),
+                    humantime::format_rfc3339_seconds(closed),
+                );
+            }
+            Format::Json => self.emit(Record::WindowSummary {
+                target: target.to_string(),
+                labels: labels.clone(),
+                opened: humantime::format_rfc3339_seconds(opened).to_string(),
+                closed: humantime::format_rfc3339_seconds(closed).to_string(),
+                transmitted: stats.transmitted(),
+                received: stats.received(),
+                loss_percent: stats.loss_percent(),
+                min_us: stats.min().as_ref().map(micros),
+                avg_us: stats.avg().as_ref().map(micros),
+                max_us: stats.max().as_ref().map(micros),
+                p99_us: stats.percentile(99.0).as_ref().map(micros),
+            }),
+            Format::Csv => {}
+        }
+    }
+
+    /// Bytes exchanged with all peers, over the last `interval` of
+    /// `--summary-interval` or, without one, the whole run.
+    pub fn total_traffic(&self, interval: Option<Duration>, traffic: &Traffic) {
+        match self.format {
+            Format::Text => {
+                let period = interval.map_or_else(String::new, |interval| {
+                    format!(" in the last {}", humantime::format_duration(interval))
+                });
+                out!(self, "total: {} bytes sent, {} bytes received{period}", traffic.sent, traffic.received);
+            }
+            Format::Json => self.emit(Record::TotalTraffic {
+                interval_ms: interval.map(|interval| interval.as_millis() as u64),
+                sent_bytes: traffic.sent,
+                received_bytes: traffic.received,
+            }),
+            Format::Csv => {}
+        }
+    }
+
+    /// Peers were added to or removed from the `--peers-file`, or from the
+    /// `peers` of the configuration file.
+    pub fn peers_file_changed(&self, path: &Path, added: &[NamedPeer], removed: &[NamedPeer]) {
+        match self.format {
+            Format::Text => out!(self, "Reloaded {}: {} added, {} removed", path.display(), added.len(), removed.len()),
+            Format::Json => self.emit(Record::PeersFileChanged {
+                path: path.display().to_string(),
+                added: added.iter().map(ToString::to_string).collect(),
+                removed: removed.iter().map(ToString::to_string).collect(),
+            }),
+            Format::Csv => {}
+        }
+    }
+
+    /// The `--peers-file` couldn't be read, or one of its peers added, or one
+    /// of the `peers` of the configuration file.
+    pub fn peers_file_failed(&self, path: &Path, error: &str) {
+        match self.format {
+            Format::Text => out!(self, "Error in {}: {error}", path.display()),
+            Format::Json => self.emit(Record::PeersFileFailed { path: path.display().to_string(), error: error.to_owned() }),
+            Format::Csv => {}
+        }
+    }
+
+    /// The configuration file couldn't be re-read on SIGHUP; the error names
+    /// the file.
+    pub fn config_reload_failed(&self, error: &str) {
+        match self.format {
+            Format::Text => out!(self, "Error reloading the configuration: {error}"),
+            Format::Json => self.emit(Record::ConfigReloadFailed { error: error.to_owned() }),
+            Format::Csv => {}
+        }
+    }
+
+    /// A result couldn't be written to the `--store` or `--address-book` at
+    /// `path`; it is lost, but the run goes on.
+    pub fn write_failed(&self, path: &Path, error: &dyn Display) {
+        match self.format {
+            Format::Text => out!(self, "Error writing to {}: {error}", path.display()),
+            Format::Json => self.emit(Record::WriteFailed { path: path.display().to_string(), error: error.to_string() }),
+            Format::Csv => {}
+        }
+    }
+
+    /// Statistics of a target of a daemon, as reported to `ctl stats`.
+    pub fn target_stats(&self, stats: TargetStats) {
+        match self.format {
+            Format::Text => {
+                let avg = stats.avg_us.map_or("-".to_owned(), |us| format!("{:.3} ms", us as f64 / 1000.0));
+                out!(
+                    self,
+                    "{}: {}{}, {} transmitted, {} received, {:.1}% packet loss, avg {avg}",
+                    stats.target,
+                    stats.health.as_deref().unwrap_or("unknown"),
+                    if stats.paused { " (paused)" } else { "" },
+                    stats.transmitted,
+                    stats.received,
+                    stats.loss_percent
+                );
+            }
+            Format::Json => self.emit(Record::Stats(stats)),
+            Format::Csv => {}
+        }
+    }
+
+    /// Availability and RTT percentiles of a peer from the stored history.
+    pub fn report(&self, report: &PeerReport) {
+        let [p50, p90, p99, max] = [50.0, 90.0, 99.0, 100.0].map(|percent| report.percentile(percent));
+        match self.format {
+            Format::Text => {
+                out!(self, "--- {} report ---", report.peer_id);
+                out!(
+                    self,
+                    "{} pings, {} answered, {:.2}% availability",
+                    report.pings,
+                    report.answered(),
+                    report.availability_percent()
+                );
+                if let (Some(p50), Some(p90), Some(p99), Some(max)) = (p50, p90, p99, max) {
+                    out!(
+                        self,
+                        "rtt p50/p90/p99/max = {:.3}/{:.3}/{:.3}/{:.3} ms",
+                        millis(p50),
+                        millis(p90),
+                        millis(p99),
+                        millis(max)
+                    );
+                }
+            }
+            Format::Json => self.emit(Record::Report {
+                peer_id: report.peer_id.clone(),
+                pings: report.pings,
+                answered: report.answered(),
+                availability_percent: report.availability_percent(),
+                p50_us: p50.as_ref().map(micros),
+                p90_us: p90.as_ref().map(micros),
+                p99_us: p99.as_ref().map(micros),
+                max_us: max.as_ref().map(micros),
+            }),
+            Format::Csv => {}
+        }
+    }
+
+    /// Prints the uptime of a peer over the stored history.
+    pub fn sla(&self, sla: &PeerSla) {
+        let format = |d: Duration| humantime::format_duration(Duration::from_secs(d.as_secs())).to_string();
+        match self.format {
+            Format::Text => {
+                out!(self, "--- {} SLA ---", sla.peer_id);
+                out!(
+                    self,
+                    "{:.3}% available over {}, down for {}",
+                    sla.availability_percent(),
+                    format(sla.observed),
+                    format(sla.downtime)
+                );
+                match sla.longest_outage() {
+                    Some(longest) => out!(
+                        self,
+                        "{} {}, MTTR {}, longest outage {}{}",
+                        sla.outages(),
+                        if sla.outages() == 1 { "outage" } else { "outages" },
+                        sla.mttr().map_or("n/a".to_owned(), format),
+                        format(longest),
+                        if sla.ongoing() { " (ongoing)" } else { "" }
+                    ),
+                    None => out!(self, "no outages"),
+                }
+            }
+            Format::Json => self.emit(Record::Sla {
+                peer_id: sla.peer_id.clone(),
+                observed_s: sla.observed.as_secs_f64(),
+                downtime_s: sla.downtime.as_secs_f64(),
+                availability_percent: sla.availability_percent(),
+                outages: sla.outages(),
+                mttr_s: sla.mttr().map(|mttr| mttr.as_secs_f64()),
+                longest_outage_s: sla.longest_outage().map(|longest| longest.as_secs_f64()),
+                ongoing: sla.ongoing(),
+            }),
+            Format::Csv => {}
+        }
+    }
+
+    /// A target's results violate a `--fail-under` or `--max-rtt` limit.
+    pub fn threshold_failed(&self, target: impl Display, reason: &str) {
+        match self.format {
+            Format::Text => out!(self, "FAIL {target}: {reason}"),
+            Format::Json => self.emit(Record::ThresholdFailed {
+                target: target.to_string(),
+                reason: reason.to_owned(),
+            }),
+            Format::Csv => {}
+        }
+    }
+
+    /// Ping statistics of a target split into relayed and direct connections,
+    /// e.g. before and after a hole punch.
+    pub fn path_summary(&self, target: impl Display, relayed: &PingStats, direct: &PingStats) {
+        match self.format {
+            Format::Text => {
+                for (path, stats) in [("relayed", relayed), ("direct", direct)] {
+                    match stats.rtt_summary() {
+                        Some(rtt) => out!(self, "{path}: {} received, rtt {rtt}", stats.received()),
+                        None => out!(self, "{path}: {} transmitted, none received", stats.transmitted()),
+                    }
+                }
+            }
+            Format::Json => self.emit(Record::PathSummary {
+                target: target.to_string(),
+                relayed: relayed.into(),
+                direct: direct.into(),
+            }),
+            Format::Csv => {}
+        }
+    }
+
+    /// How much longer the average RTT to a target is through the relay than
+    /// directly; nothing until both paths have answered.
+    pub fn relay_detour(&self, target: impl Display, relayed: &PingStats, direct: &PingStats) {
+        let (Some(relayed), Some(direct)) = (relayed.avg(), direct.avg()) else {
+            return;
+        };
+        match self.format {
+            Format::Text => out!(
+                self,
+                "Relay detour to {target}: {:+.3} ms (relayed avg {:.3} ms, direct avg {:.3} ms)",
+                millis(relayed) - millis(direct),
+                millis(relayed),
+                millis(direct)
+            ),
+            Format::Json => self.emit(Record::RelayDetour {
+                target: target.to_string(),
+                relayed_avg_us: micros(&relayed),
+                direct_avg_us: micros(&direct),
+                detour_us: micros(&relayed) as i64 - micros(&direct) as i64,
+            }),
+            Format::Csv => {}
+        }
+    }
+
+    /// Ping statistics of one of several connections to a target.
+    pub fn connection_summary(&self, target: impl Display, connection: &Connection, stats: &PingStats) {
+        match self.format {
+            Format::Text => match stats.rtt_summary() {
+                Some(rtt) => out!(self, "{connection}: {} received, rtt {rtt}", stats.received()),
+                None => out!(self, "{connection}: {} transmitted, none received", stats.transmitted()),
+            },
+            Format::Json => self.emit(Record::ConnectionSummary {
+                target: target.to_string(),
+                connection_id: connection.id.to_string(),
+                local_address: connection.local.as_ref().map(ToString::to_string),
+                remote_address: connection.remote.to_string(),
+                relayed: connection.relayed,
+                stats: stats.into(),
+            }),
+            Format::Csv => {}
+        }
+    }
+
+    /// The latest RTTs between all members of the latency mesh.
+    ///
+    /// Text output labels peers with the last characters of their id; rows are
+    /// the measuring peers, columns the measured ones.
+    pub fn latency_matrix(&self, matrix: &LatencyMatrix) {
+        let peers = matrix.peers();
+        match self.format {
+            Format::Text => {
+                out!(self, "Latency matrix (ms, row -> column):");
+                let mut header = format!("{:>8}", "");
+                for peer in &peers {
+                    header += &format!(" {:>8}", short_id(peer));
+                }
+                out!(self, "{header}");
+                for from in &peers {
+                    let mut line = format!("{:>8}", short_id(from));
+                    for to in &peers {
+                        match matrix.get(from, to) {
+                            Some(rtt) => line += &format!(" {:>8.1}", rtt.as_secs_f64() * 1000.0),
+                            None => line += &format!(" {:>8}", "-"),
+                        }
+                    }
+                    out!(self, "{line}");
+                }
+            }
+            Format::Json => {
+                let mut rtts_us: BTreeMap<String, BTreeMap<String, u64>> = BTreeMap::new();
+                for from in &peers {
+                    let row = rtts_us.entry(from.to_string()).or_default();
+                    for to in &peers {
+                        if let Some(rtt) = matrix.get(from, to) {
+                            row.insert(to.to_string(), micros(&rtt));
+                        }
+                    }
+                }
+                self.emit(Record::LatencyMatrix { rtts_us });
+            }
+            Format::Csv => {}
+        }
+    }
+
+    /// Our RTTs couldn't be published to the latency mesh; the next report
+    /// tries again.
+    pub fn publish_failed(&self, error: &dyn Display) {
+        match self.format {
+            Format::Text => out!(self, "Failed to publish RTTs to the latency mesh: {error}"),
+            Format::Json => self.emit(Record::PublishFailed { error: error.to_string() }),
+            Format::Csv => {}
+        }
+    }
+
+    /// Writes and flushes a single line. Unlike `println!`, write errors such
+    /// as a closed pipe are ignored instead of panicking.
+    fn write_line(&self, line: fmt::Arguments<'_>) {
+        let mut writer = self.writer.borrow_mut();
+        let _ = writeln!(writer, "{line}").and_then(|_| writer.flush());
+    }
+
+    /// Returns the name given to `peer_id`, if any.
+    fn name(&self, peer_id: &PeerId) -> Option<String> {
+        self.names.borrow().get(peer_id).cloned()
+    }
+
+    /// Returns the name given to `peer_id`, or the PeerId itself.
+    fn peer(&self, peer_id: &PeerId) -> String {
+        self.name(peer_id).unwrap_or_else(|| peer_id.to_string())
+    }
+
+    /// Prints a timestamped JSON record on its own line.
+    fn emit(&self, record: Record) {
+        let line = Line {
+            timestamp: humantime::format_rfc3339_micros(SystemTime::now()).to_string(),
+            record,
+        };
+        out!(self, "{}", serde_json::to_string(&line).expect("records serialize to JSON"));
+    }
+}
+
+/// Returns the last six characters of a peer id, enough to tell mesh members apart.
+fn short_id(peer_id: &PeerId) -> String {
+    let id = peer_id.to_base58();
+    id[id.len() - 6..].to_owned()
+}
+
+/// Quotes a CSV field if it contains a separator, quote or line break.
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_owned()
+    }
+}
+
+/// Converts a duration to fractional milliseconds.
+fn millis(d: Duration) -> f64 {
+    d.as_secs_f64() * 1000.0
+}
+
+/// Converts a duration to whole microseconds.
+fn micros(d: &Duration) -> u64 {
+    d.as_micros() as u64
+}
+
+/// Milliseconds of a possibly negative number of microseconds.
+fn signed_millis(us: i64) -> f64 {
+    us as f64 / 1000.0
+}
@@ -0,0 +1,113 @@
+//! Peer list files (`--peers-file`), re-read whenever they change.
+//!
+//! Each line holds a peer as `[NAME=]MULTIADDR`; blank lines and lines
+//! starting with `#` are ignored:
+//!
+//! ```text
+//! # edge nodes
+//! berlin-edge-1=/ip4/192.0.2.1/tcp/4001
+//! /ip4/192.0.2.2/udp/4001/quic-v1
+//! ```
+
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use crate::cli::NamedPeer;
+
+/// The peers of a file as last read.
+pub struct PeersFile {
+    path: PathBuf,
+    modified: SystemTime,
+    peers: Vec<NamedPeer>,
+    /// The error of the last reload, if it failed.
+    error: Option<String>,
+}
+
+/// How the peers of a file changed since it was last read.
+pub struct Change {
+    pub added: Vec<NamedPeer>,
+    pub removed: Vec<NamedPeer>,
+}
+
+impl Change {
+    /// Compares the peers of a file as last read, `old`, with those read now.
+    ///
+    /// Renamed peers, and those whose overrides changed, are both removed and
+    /// added.
+    pub fn between(old: &[NamedPeer], new: &[NamedPeer]) -> Self {
+        let contains = |peers: &[NamedPeer], peer: &NamedPeer| {
+            peers.iter().any(|p| p.name == peer.name && p.addr == peer.addr && p.overrides == peer.overrides)
+        };
+        Self {
+            added: new.iter().filter(|peer| !contains(old, peer)).cloned().collect(),
+            removed: old.iter().filter(|peer| !contains(new, peer)).cloned().collect(),
+        }
+    }
+}
+
+impl PeersFile {
+    /// Reads the peers of the file at `path`.
+    pub fn open(path: &Path) -> Result<Self, String> {
+        let (modified, peers) = read(path).map_err(|e| format!("{}: {e}", path.display()))?;
+        Ok(Self { path: path.to_owned(), modified, peers, error: None })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn peers(&self) -> &[NamedPeer] {
+        &self.peers
+    }
+
+    /// Re-reads the file if it was modified since it was last read; errors
+    /// leave out the path.
+    ///
+    /// If the file can't be read or parsed the previous peers are kept, and reading is tried again on the
+    /// next call; the same error is only returned once in a row.
+    pub fn reload(&mut self) -> Result<Option<Change>, String> {
+        match self.try_reload() {
+            Err(e) if self.error.as_ref() == Some(&e) => Ok(None),
+            Err(e) => {
+                self.error = Some(e.clone());
+                Err(e)
+            }
+            Ok(change) => {
+                self.error = None;
+                Ok(change)
+            }
+        }
+    }
+
+    fn try_reload(&mut self) -> Result<Option<Change>, String> {
+        let modified = modified(&self.path)?;
+        if modified == self.modified {
+            return Ok(None);
+        }
+        let (modified, peers) = read(&self.path)?;
+        let change = Change::between(&self.peers, &peers);
+        self.modified = modified;
+        self.peers = peers;
+        Ok(Some(change))
+    }
+}
+
+fn modified(path: &Path) -> Result<SystemTime, String> {
+    std::fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .map_err(|e| e.to_string())
+}
+
+/// Returns the modification time and the peers of the file at `path`.
+fn read(path: &Path) -> Result<(SystemTime, Vec<NamedPeer>), String> {
+    let modified = modified(path)?;
+    let text = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+    let peers = text
+        .lines()
+        .enumerate()
+        .map(|(number, line)| (number + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+        .map(|(number, line)| line.parse().map_err(|e| format!("line {number}: {e}")))
+        .collect::<Result<_, _>>()?;
+    Ok((modified, peers))
+}
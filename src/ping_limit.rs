@@ -0,0 +1,748 @@
+//! Rate limit on the inbound pings answered for each peer, so that a public
+//! node can't be used as a CPU and bandwidth sink.
+//!
+//! [`Behaviour`] pings every connected peer like [`ping::Behaviour`], and
+//! answers inbound pings taking a token per ping from a bucket that all
+//! connections of the peer share. Once the bucket is empty, the peer's pings
+//! are answered just late enough to hold it to the rate, or its connections
+//! are closed, depending on the [`PingLimitAction`]. Pings within the limit
+//! are then delayed or dropped according to the
+//! [`NodeConfig::impairment`](crate::NodeConfig::impairment), if any.
+//!
+//! With an [`AdaptiveInterval`], [`Handler`] waits the interval its [`Pacer`]
+//! picked between outbound pings, and likewise the interval of its peer set
+//! with [`Behaviour::set_peer_interval`], which takes precedence.
+//!
+//! While paused with [`Behaviour::set_paused`] or, for one peer,
+//! [`Behaviour::set_peer_paused`], the handlers finish the ping in flight and
+//! send no more until resumed, but keep answering.
+//!
+//! The echo and clock protocols answer requests taking a token from the same
+//! buckets, and refuse those over the limit whatever the action.
+//!
+//! Unlike the handler of [`ping::Behaviour`], [`Handler`] reports every failed
+//! ping, including the first one after an answered ping, and why it failed.
+
+use either::Either;
+use futures::future::BoxFuture;
+use futures::prelude::*;
+use libp2p::core::upgrade::ReadyUpgrade;
+use libp2p::core::Endpoint;
+use libp2p::swarm::handler::{
+    ConnectionEvent, DialUpgradeError, FullyNegotiatedInbound, FullyNegotiatedOutbound, StreamUpgradeError,
+};
+use libp2p::swarm::{
+    CloseConnection, ConnectionDenied, ConnectionHandler, ConnectionHandlerEvent, ConnectionId, FromSwarm,
+    NetworkBehaviour, NotifyHandler, SubstreamProtocol, THandler, THandlerInEvent, THandlerOutEvent, ToSwarm,
+};
+use libp2p::{ping, Multiaddr, PeerId, Stream, StreamProtocol};
+use prometheus_client::metrics::counter::Counter;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::io;
+use std::pin::Pin;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use tokio::time::Sleep;
+
+use crate::adaptive::{AdaptiveInterval, Pacer};
+use crate::testing::{Impairer, Impairment};
+
+/// Size of a ping, which is sent back as it is.
+const PING_SIZE: usize = 32;
+
+/// What happens to a peer pinging faster than the limit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PingLimitAction {
+    /// Answer its pings late enough to hold it to the rate, the default.
+    #[default]
+    Throttle,
+    /// Close all connections with the peer.
+    Disconnect,
+}
+
+impl fmt::Display for PingLimitAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PingLimitAction::Throttle => f.write_str("throttle"),
+            PingLimitAction::Disconnect => f.write_str("disconnect"),
+        }
+    }
+}
+
+impl FromStr for PingLimitAction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "throttle" => Ok(PingLimitAction::Throttle),
+            "disconnect" => Ok(PingLimitAction::Disconnect),
+            other => Err(format!("unknown ping limit action `{other}`, expected `throttle` or `disconnect`")),
+        }
+    }
+}
+
+/// Limit on the inbound pings answered for each peer.
+#[derive(Debug, Clone, Copy)]
+pub struct PingLimit {
+    /// Pings answered per second for each peer, across all its connections;
+    /// a peer that has been quiet may send up to this many at once.
+    pub rate: u32,
+    pub action: PingLimitAction,
+}
+
+/// Counts of the inbound pings, exported by the metrics.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct PingCounters {
+    /// Pings answered, throttled or not.
+    pub(crate) answered: Counter,
+    /// Pings answered late because the peer was over the limit.
+    pub(crate) throttled: Counter,
+    /// Peers disconnected for going over the limit.
+    pub(crate) disconnects: Counter,
+}
+
+/// Tokens left to a peer, refilled at the rate of the limit.
+#[derive(Debug)]
+struct Bucket {
+    /// Negative while throttled pings wait for tokens that haven't been
+    /// refilled yet.
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    /// A bucket as full as `limit` allows it to be.
+    fn full(limit: PingLimit, now: Instant) -> Self {
+        Self { tokens: f64::from(limit.rate), updated: now }
+    }
+
+    /// Refills the bucket up to `now` and takes a token for a ping, returning
+    /// how long to wait before answering it, or `None` if the peer is over the
+    /// limit and to be disconnected.
+    fn take(&mut self, limit: PingLimit, now: Instant) -> Option<Duration> {
+        self.refill(limit, now);
+        if self.tokens < 1.0 && limit.action == PingLimitAction::Disconnect {
+            return None;
+        }
+        self.tokens -= 1.0;
+        Some(Duration::from_secs_f64((-self.tokens).max(0.0) / f64::from(limit.rate)))
+    }
+
+    /// Refills the bucket up to `now` and takes a token for a request that is
+    /// answered right away or not at all, returning whether one was left.
+    fn try_take(&mut self, limit: PingLimit, now: Instant) -> bool {
+        self.refill(limit, now);
+        if self.tokens < 1.0 {
+            return false;
+        }
+        self.tokens -= 1.0;
+        true
+    }
+
+    fn refill(&mut self, limit: PingLimit, now: Instant) {
+        let rate = f64::from(limit.rate);
+        self.tokens = (self.tokens + now.saturating_duration_since(self.updated).as_secs_f64() * rate).min(rate);
+        self.updated = now;
+    }
+}
+
+/// The buckets of the connected peers, shared by their connections and by the
+/// [echo](crate::echo) and [clock](crate::clock) protocols.
+#[derive(Debug, Clone)]
+pub(crate) struct Buckets {
+    limit: PingLimit,
+    peers: Arc<Mutex<HashMap<PeerId, Bucket>>>,
+}
+
+impl Buckets {
+    fn new(limit: PingLimit) -> Self {
+        Self { limit, peers: Arc::default() }
+    }
+
+    /// Takes a token for a ping of `peer` like [`Bucket::take`].
+    fn take(&self, peer: PeerId) -> Option<Duration> {
+        let now = Instant::now();
+        let mut peers = self.peers.lock().expect("no panics while locked");
+        peers.entry(peer).or_insert_with(|| Bucket::full(self.limit, now)).take(self.limit, now)
+    }
+
+    /// Takes a token for a request of `peer` like [`Bucket::try_take`].
+    pub(crate) fn try_take(&self, peer: PeerId) -> bool {
+        let now = Instant::now();
+        let mut peers = self.peers.lock().expect("no panics while locked");
+        peers.entry(peer).or_insert_with(|| Bucket::full(self.limit, now)).try_take(self.limit, now)
+    }
+
+    fn remove(&self, peer: &PeerId) {
+        self.peers.lock().expect("no panics while locked").remove(peer);
+    }
+}
+
+/// Reported by a [`Handler`] whose peer went over the limit.
+#[derive(Debug)]
+pub struct Exceeded;
+
+/// Pings every connected peer like [`ping::Behaviour`], and answers their
+/// pings within the limit, if one is set.
+pub struct Behaviour {
+    /// How often each connection is pinged, unless a longer interval applies.
+    interval: Duration,
+    /// How long to wait for the answer to a ping.
+    timeout: Duration,
+    /// `None` answers every ping right away.
+    buckets: Option<Buckets>,
+    impairer: Option<Impairer>,
+    adaptive: Option<AdaptiveInterval>,
+    /// The interval of peers without one of their own, if `interval` is
+    /// shorter.
+    default_interval: Option<Duration>,
+    /// Intervals of peers set with [`Self::set_peer_interval`].
+    intervals: HashMap<PeerId, Duration>,
+    keep_alive: bool,
+    counters: PingCounters,
+    /// Peers that went over the limit, to be disconnected.
+    exceeded: VecDeque<PeerId>,
+    /// Peers that went over the limit until their last connection closed,
+    /// so that each is disconnected and counted once.
+    disconnecting: HashSet<PeerId>,
+    /// Whether outbound pings are paused.
+    paused: bool,
+    /// Peers whose outbound pings are paused, also while disconnected.
+    paused_peers: HashSet<PeerId>,
+    connections: HashSet<(PeerId, ConnectionId)>,
+    /// Connections yet to be told of a change of [`Self::paused`].
+    notify: VecDeque<(PeerId, ConnectionId)>,
+    /// Results of outbound pings, to be reported.
+    events: VecDeque<ping::Event>,
+}
+
+impl Behaviour {
+    /// Creates the behaviour pinging every `interval` and waiting up to
+    /// `timeout` for each answer; with `adaptive`, `interval` should be
+    /// [`AdaptiveInterval::min`], which the handlers lengthen. If `interval`
+    /// is shorter than that of peers without an interval of their own,
+    /// `default_interval` is theirs. With `keep_alive`, the handlers keep
+    /// their connections open while idle.
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        interval: Duration,
+        timeout: Duration,
+        limit: Option<PingLimit>,
+        impairment: Option<Impairment>,
+        adaptive: Option<AdaptiveInterval>,
+        default_interval: Option<Duration>,
+        keep_alive: bool,
+    ) -> Self {
+        Self {
+            interval,
+            timeout,
+            buckets: limit.map(Buckets::new),
+            impairer: impairment.map(Impairer::new),
+            adaptive,
+            default_interval,
+            intervals: HashMap::new(),
+            keep_alive,
+            counters: PingCounters::default(),
+            exceeded: VecDeque::new(),
+            disconnecting: HashSet::new(),
+            paused: false,
+            paused_peers: HashSet::new(),
+            connections: HashSet::new(),
+            notify: VecDeque::new(),
+            events: VecDeque::new(),
+        }
+    }
+
+    /// The buckets inbound pings take their tokens from, if there is a limit.
+    pub(crate) fn buckets(&self) -> Option<Buckets> {
+        self.buckets.clone()
+    }
+
+    /// Stops sending pings on every connection, or starts again; inbound pings
+    /// are answered either way.
+    pub fn set_paused(&mut self, paused: bool) {
+        if paused != self.paused {
+            self.paused = paused;
+            self.notify = self.connections.iter().copied().collect();
+        }
+    }
+
+    /// Whether outbound pings are paused.
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    /// Stops sending pings to `peer`, or starts again unless paused for all.
+    pub fn set_peer_paused(&mut self, peer: PeerId, paused: bool) {
+        let changed = if paused { self.paused_peers.insert(peer) } else { self.paused_peers.remove(&peer) };
+        if changed {
+            self.notify.extend(self.connections.iter().filter(|(peer_id, _)| *peer_id == peer));
+        }
+    }
+
+    /// Pings `peer` every `interval` rather than at the interval of every
+    /// peer, or again at that with `None`; `interval` can't be shorter than
+    /// the one the behaviour was created with.
+    pub fn set_peer_interval(&mut self, peer: PeerId, interval: Option<Duration>) {
+        let changed = match interval {
+            Some(interval) => self.intervals.insert(peer, interval) != Some(interval),
+            None => self.intervals.remove(&peer).is_some(),
+        };
+        if changed {
+            self.notify.extend(self.connections.iter().filter(|(peer_id, _)| *peer_id == peer));
+        }
+    }
+
+    /// Whether outbound pings to `peer` are paused by [`Self::set_peer_paused`].
+    pub fn is_peer_paused(&self, peer: &PeerId) -> bool {
+        self.paused_peers.contains(peer)
+    }
+
+    fn paused_for(&self, peer: &PeerId) -> bool {
+        self.paused || self.paused_peers.contains(peer)
+    }
+
+    /// What to tell the handlers of `peer`.
+    fn update_for(&self, peer: &PeerId) -> Update {
+        Update { paused: self.paused_for(peer), interval: self.intervals.get(peer).copied() }
+    }
+
+    pub(crate) fn counters(&self) -> &PingCounters {
+        &self.counters
+    }
+
+    fn handler(&self, peer: PeerId) -> Handler {
+        Handler {
+            peer,
+            interval: self.interval,
+            timeout: self.timeout,
+            buckets: self.buckets.clone(),
+            impairer: self.impairer.clone(),
+            counters: self.counters.clone(),
+            inbound: None,
+            pacer: self.adaptive.map(Pacer::new),
+            peer_interval: self.intervals.get(&peer).copied(),
+            default_interval: self.default_interval,
+            keep_alive: self.keep_alive,
+            outbound: None,
+            // The first ping goes out right away.
+            next_ping: Box::pin(tokio::time::sleep(Duration::ZERO)),
+            unsupported: None,
+            failure: None,
+            paused: self.paused_for(&peer),
+        }
+    }
+}
+
+impl NetworkBehaviour for Behaviour {
+    type ConnectionHandler = Handler;
+    type ToSwarm = ping::Event;
+
+    fn handle_established_inbound_connection(
+        &mut self,
+        _: ConnectionId,
+        peer: PeerId,
+        _: &Multiaddr,
+        _: &Multiaddr,
+    ) -> Result<THandler<Self>, ConnectionDenied> {
+        Ok(self.handler(peer))
+    }
+
+    fn handle_established_outbound_connection(
+        &mut self,
+        _: ConnectionId,
+        peer: PeerId,
+        _: &Multiaddr,
+        _: Endpoint,
+    ) -> Result<THandler<Self>, ConnectionDenied> {
+        Ok(self.handler(peer))
+    }
+
+    fn on_swarm_event(&mut self, event: FromSwarm) {
+        match event {
+            FromSwarm::ConnectionEstablished(established) => {
+                self.connections.insert((established.peer_id, established.connection_id));
+            }
+            FromSwarm::ConnectionClosed(closed) => {
+                self.connections.remove(&(closed.peer_id, closed.connection_id));
+                if closed.remaining_established == 0 {
+                    self.disconnecting.remove(&closed.peer_id);
+                    if let Some(buckets) = &self.buckets {
+                        buckets.remove(&closed.peer_id);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn on_connection_handler_event(&mut self, peer: PeerId, connection_id: ConnectionId, event: THandlerOutEvent<Self>) {
+        match event {
+            Either::Left(result) => self.events.push_back(ping::Event { peer, connection: connection_id, result }),
+            // Every connection of the peer may report it before they are closed.
+            Either::Right(Exceeded) => {
+                if self.disconnecting.insert(peer) {
+                    self.exceeded.push_back(peer);
+                }
+            }
+        }
+    }
+
+    fn poll(&mut self, _: &mut Context<'_>) -> Poll<ToSwarm<Self::ToSwarm, THandlerInEvent<Self>>> {
+        if let Some(peer_id) = self.exceeded.pop_front() {
+            let rate = self.buckets.as_ref().map_or(0, |buckets| buckets.limit.rate);
+            tracing::warn!(%peer_id, "disconnecting peer sending more than {rate} pings per second");
+            self.counters.disconnects.inc();
+            return Poll::Ready(ToSwarm::CloseConnection { peer_id, connection: CloseConnection::All });
+        }
+        while let Some((peer_id, connection)) = self.notify.pop_front() {
+            // The connection may have closed since.
+            if self.connections.contains(&(peer_id, connection)) {
+                return Poll::Ready(ToSwarm::NotifyHandler {
+                    peer_id,
+                    handler: NotifyHandler::One(connection),
+                    event: self.update_for(&peer_id),
+                });
+            }
+        }
+        match self.events.pop_front() {
+            Some(event) => Poll::Ready(ToSwarm::GenerateEvent(event)),
+            None => Poll::Pending,
+        }
+    }
+}
+
+/// Pings the peer of a connection, and answers its pings.
+pub struct Handler {
+    peer: PeerId,
+    /// How often the peer is pinged, unless a longer interval applies.
+    interval: Duration,
+    /// How long to wait for the answer to a ping.
+    timeout: Duration,
+    buckets: Option<Buckets>,
+    impairer: Option<Impairer>,
+    counters: PingCounters,
+    /// Answering the pings on the latest inbound stream; a new stream replaces
+    /// the previous one.
+    inbound: Option<BoxFuture<'static, io::Result<Exceeded>>>,
+    /// Picks the interval after each outbound ping in adaptive mode.
+    pacer: Option<Pacer>,
+    /// The interval of the peer, taking precedence over `pacer`.
+    peer_interval: Option<Duration>,
+    /// The interval without either, if `interval` is shorter.
+    default_interval: Option<Duration>,
+    /// Whether to keep the connection open while idle.
+    keep_alive: bool,
+    /// The outbound stream, `None` until one is opened or after a failure.
+    outbound: Option<Outbound>,
+    /// Until when the next outbound ping waits.
+    next_ping: Pin<Box<Sleep>>,
+    /// Set once the peer turned out not to support ping, to whether that was
+    /// reported; no more pings are sent then.
+    unsupported: Option<bool>,
+    /// Why the latest outbound stream couldn't be opened, to be reported.
+    failure: Option<ping::Failure>,
+    /// Whether no more pings are sent once the one in flight is done.
+    paused: bool,
+}
+
+/// The state of the outbound stream of a [`Handler`].
+enum Outbound {
+    /// The stream is being opened.
+    Opening,
+    /// The stream waits for the next ping.
+    Idle(Stream),
+    /// A ping was sent on the stream and waits for its answer.
+    Pinging(BoxFuture<'static, Result<(Stream, Duration), ping::Failure>>),
+}
+
+/// Tells a [`Handler`] whether to pause its outbound pings, and the interval
+/// of its peer, if it has one of its own.
+#[derive(Debug, Clone, Copy)]
+pub struct Update {
+    pub paused: bool,
+    pub interval: Option<Duration>,
+}
+
+impl Handler {
+    /// Waits for the next ping after `result`, and reports it.
+    fn finished(
+        &mut self,
+        result: Result<Duration, ping::Failure>,
+    ) -> ConnectionHandlerEvent<ReadyUpgrade<StreamProtocol>, (), <Self as ConnectionHandler>::ToBehaviour> {
+        let paced = self.pacer.as_mut().map(|pacer| (pacer.next(&result), pacer.min()));
+        let interval = match (self.peer_interval, paced) {
+            (Some(interval), _) => interval,
+            (None, Some((interval, min))) if interval > min => interval,
+            _ => self.default_interval.unwrap_or(self.interval),
+        };
+        let interval = interval.max(self.interval);
+        tracing::trace!(peer_id = %self.peer, ?interval, "next ping");
+        self.next_ping.as_mut().reset(tokio::time::Instant::now() + interval);
+        ConnectionHandlerEvent::NotifyBehaviour(Either::Left(result))
+    }
+
+    /// Whether the next outbound ping is due.
+    fn ping_due(&mut self, cx: &mut Context<'_>) -> bool {
+        !self.paused && self.unsupported.is_none() && self.next_ping.poll_unpin(cx).is_ready()
+    }
+}
+
+impl ConnectionHandler for Handler {
+    type FromBehaviour = Update;
+    type ToBehaviour = Either<Result<Duration, ping::Failure>, Exceeded>;
+    type InboundProtocol = ReadyUpgrade<StreamProtocol>;
+    type OutboundProtocol = ReadyUpgrade<StreamProtocol>;
+    type InboundOpenInfo = ();
+    type OutboundOpenInfo = ();
+
+    fn listen_protocol(&self) -> SubstreamProtocol<Self::InboundProtocol, Self::InboundOpenInfo> {
+        SubstreamProtocol::new(ReadyUpgrade::new(ping::PROTOCOL_NAME), ())
+    }
+
+    fn connection_keep_alive(&self) -> bool {
+        self.keep_alive
+    }
+
+    fn poll(
+        &mut self,
+        cx: &mut Context<'_>,
+    ) -> Poll<ConnectionHandlerEvent<Self::OutboundProtocol, Self::OutboundOpenInfo, Self::ToBehaviour>> {
+        if let Some(inbound) = self.inbound.as_mut() {
+            match inbound.poll_unpin(cx) {
+                Poll::Ready(Ok(Exceeded)) => {
+                    self.inbound = None;
+                    return Poll::Ready(ConnectionHandlerEvent::NotifyBehaviour(Either::Right(Exceeded)));
+                }
+                Poll::Ready(Err(e)) => {
+                    tracing::debug!("inbound ping stream ended: {e}");
+                    self.inbound = None;
+                }
+                Poll::Pending => {}
+            }
+        }
+        if self.unsupported == Some(false) {
+            self.unsupported = Some(true);
+            return Poll::Ready(ConnectionHandlerEvent::NotifyBehaviour(Either::Left(Err(ping::Failure::Unsupported))));
+        }
+        if let Some(failure) = self.failure.take() {
+            return Poll::Ready(self.finished(Err(failure)));
+        }
+        loop {
+            match self.outbound.take() {
+                Some(Outbound::Pinging(mut ping)) => match ping.poll_unpin(cx) {
+                    Poll::Ready(Ok((stream, rtt))) => {
+                        self.outbound = Some(Outbound::Idle(stream));
+                        return Poll::Ready(self.finished(Ok(rtt)));
+                    }
+                    // The next ping opens a new stream.
+                    Poll::Ready(Err(failure)) => return Poll::Ready(self.finished(Err(failure))),
+                    Poll::Pending => {
+                        self.outbound = Some(Outbound::Pinging(ping));
+                        return Poll::Pending;
+                    }
+                },
+                Some(Outbound::Idle(stream)) if self.ping_due(cx) => {
+                    self.outbound = Some(Outbound::Pinging(send_ping(stream, self.timeout).boxed()));
+                }
+                None if self.ping_due(cx) => {
+                    self.outbound = Some(Outbound::Opening);
+                    let protocol = SubstreamProtocol::new(ReadyUpgrade::new(ping::PROTOCOL_NAME), ());
+                    return Poll::Ready(ConnectionHandlerEvent::OutboundSubstreamRequest { protocol });
+                }
+                outbound => {
+                    self.outbound = outbound;
+                    return Poll::Pending;
+                }
+            }
+        }
+    }
+
+    fn on_behaviour_event(&mut self, Update { paused, interval }: Update) {
+        self.paused = paused;
+        self.peer_interval = interval;
+    }
+
+    fn on_connection_event(
+        &mut self,
+        event: ConnectionEvent<Self::InboundProtocol, Self::OutboundProtocol, Self::InboundOpenInfo, Self::OutboundOpenInfo>,
+    ) {
+        match event {
+            ConnectionEvent::FullyNegotiatedInbound(FullyNegotiatedInbound { protocol: mut stream, .. }) => {
+                // Pings don't keep the connection open, answered or sent.
+                stream.ignore_for_keep_alive();
+                let answering =
+                    answer(stream, self.peer, self.buckets.clone(), self.impairer.clone(), self.counters.clone());
+                self.inbound = Some(answering.boxed());
+            }
+            ConnectionEvent::FullyNegotiatedOutbound(FullyNegotiatedOutbound { protocol: mut stream, .. }) => {
+                stream.ignore_for_keep_alive();
+                self.outbound = Some(Outbound::Pinging(send_ping(stream, self.timeout).boxed()));
+            }
+            ConnectionEvent::DialUpgradeError(DialUpgradeError { error, .. }) => {
+                self.outbound = None;
+                match error {
+                    StreamUpgradeError::NegotiationFailed => self.unsupported = Some(false),
+                    StreamUpgradeError::Timeout => {
+                        let e = io::Error::new(io::ErrorKind::TimedOut, "ping protocol negotiation timed out");
+                        self.failure = Some(ping::Failure::Other { error: Box::new(e) });
+                    }
+                    StreamUpgradeError::Io(e) => self.failure = Some(ping::Failure::Other { error: Box::new(e) }),
+                    StreamUpgradeError::Apply(never) => match never {},
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Sends a ping on `stream` and waits up to `timeout` for the answer,
+/// returning the stream for the next ping and the round-trip time.
+async fn send_ping(mut stream: Stream, timeout: Duration) -> Result<(Stream, Duration), ping::Failure> {
+    let ping = async {
+        let payload: [u8; PING_SIZE] = rand::random();
+        stream.write_all(&payload).await?;
+        stream.flush().await?;
+        let sent = Instant::now();
+        let mut answer = [0; PING_SIZE];
+        stream.read_exact(&mut answer).await?;
+        if answer != payload {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "ping answered with another payload"));
+        }
+        Ok(sent.elapsed())
+    };
+    match tokio::time::timeout(timeout, ping).await {
+        Ok(Ok(rtt)) => Ok((stream, rtt)),
+        Ok(Err(e)) => Err(ping::Failure::Other { error: Box::new(e) }),
+        Err(_) => Err(ping::Failure::Timeout),
+    }
+}
+
+/// Sends each ping on `stream` back until the stream fails or closes, which
+/// ends it with an error, or until `peer` goes over the limit with
+/// [`PingLimitAction::Disconnect`].
+async fn answer(
+    mut stream: Stream,
+    peer: PeerId,
+    buckets: Option<Buckets>,
+    impairer: Option<Impairer>,
+    counters: PingCounters,
+) -> io::Result<Exceeded> {
+    let mut payload = [0; PING_SIZE];
+    loop {
+        stream.read_exact(&mut payload).await?;
+        if let Some(buckets) = &buckets {
+            let Some(wait) = buckets.take(peer) else {
+                return Ok(Exceeded);
+            };
+            if !wait.is_zero() {
+                counters.throttled.inc();
+                tokio::time::sleep(wait).await;
+            }
+        }
+        if let Some(impairer) = &impairer {
+            match impairer.next() {
+                Some(delay) => tokio::time::sleep(delay).await,
+                None => continue,
+            }
+        }
+        stream.write_all(&payload).await?;
+        stream.flush().await?;
+        counters.answered.inc();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limit(rate: u32, action: PingLimitAction) -> PingLimit {
+        PingLimit { rate, action }
+    }
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn full_bucket_answers_a_burst_right_away() {
+        let (limit, now) = (limit(3, PingLimitAction::Throttle), Instant::now());
+        let mut bucket = Bucket::full(limit, now);
+        for _ in 0..3 {
+            assert_eq!(bucket.take(limit, now), Some(Duration::ZERO));
+        }
+    }
+
+    #[test]
+    fn empty_bucket_throttles_to_the_rate() {
+        let (limit, now) = (limit(2, PingLimitAction::Throttle), Instant::now());
+        let mut bucket = Bucket::full(limit, now);
+        bucket.take(limit, now);
+        bucket.take(limit, now);
+        // Each ping over the limit waits another 500ms for its token.
+        assert_eq!(bucket.take(limit, now), Some(ms(500)));
+        assert_eq!(bucket.take(limit, now), Some(ms(1000)));
+        // The tokens owed are refilled as time passes.
+        assert_eq!(bucket.take(limit, now + ms(1000)), Some(ms(500)));
+    }
+
+    #[test]
+    fn refill_is_capped_at_the_rate() {
+        let (limit, now) = (limit(2, PingLimitAction::Throttle), Instant::now());
+        let mut bucket = Bucket::full(limit, now);
+        bucket.take(limit, now);
+        let later = now + Duration::from_secs(60);
+        assert_eq!(bucket.take(limit, later), Some(Duration::ZERO));
+        assert_eq!(bucket.take(limit, later), Some(Duration::ZERO));
+        assert_eq!(bucket.take(limit, later), Some(ms(500)));
+    }
+
+    #[test]
+    fn empty_bucket_disconnects() {
+        let (limit, now) = (limit(2, PingLimitAction::Disconnect), Instant::now());
+        let mut bucket = Bucket::full(limit, now);
+        bucket.take(limit, now);
+        bucket.take(limit, now);
+        assert_eq!(bucket.take(limit, now + ms(250)), None, "half a token");
+        assert_eq!(bucket.take(limit, now + ms(500)), Some(Duration::ZERO));
+        assert_eq!(bucket.take(limit, now + ms(500)), None);
+    }
+
+    #[test]
+    fn requests_take_no_tokens_in_advance() {
+        let (limit, now) = (limit(2, PingLimitAction::Throttle), Instant::now());
+        let mut bucket = Bucket::full(limit, now);
+        assert!(bucket.try_take(limit, now));
+        assert!(bucket.try_take(limit, now));
+        assert!(!bucket.try_take(limit, now));
+        assert!(!bucket.try_take(limit, now + ms(250)), "half a token");
+        assert!(bucket.try_take(limit, now + ms(500)), "the refused requests took nothing");
+    }
+
+    #[test]
+    fn requests_wait_for_the_pings_taken_in_advance() {
+        let (limit, now) = (limit(2, PingLimitAction::Throttle), Instant::now());
+        let mut bucket = Bucket::full(limit, now);
+        for _ in 0..3 {
+            bucket.take(limit, now);
+        }
+        assert!(!bucket.try_take(limit, now + ms(500)), "the throttled ping gets the first token");
+        assert!(bucket.try_take(limit, now + ms(1000)));
+    }
+
+    #[test]
+    fn buckets_are_per_peer() {
+        let buckets = Buckets::new(limit(1, PingLimitAction::Disconnect));
+        let (a, b) = (PeerId::random(), PeerId::random());
+        assert_eq!(buckets.take(a), Some(Duration::ZERO));
+        assert_eq!(buckets.take(a), None);
+        assert_eq!(buckets.take(b), Some(Duration::ZERO));
+        buckets.remove(&a);
+        assert_eq!(buckets.take(a), Some(Duration::ZERO), "full again once forgotten");
+    }
+}
@@ -0,0 +1,185 @@
+//! Protocol compatibility of a peer, for the `probe` subcommand: the security
+//! handshakes it accepts, the multiplexer, its agent version and protocols,
+//! and what to change where it and we can't agree.
+//!
+//! A mismatched handshake otherwise only shows as an opaque upgrade error, so
+//! on TCP and WebSocket each security protocol is offered on a connection of
+//! its own to tell which ones the peer accepts.
+
+use libp2p::identity::Keypair;
+use libp2p::multiaddr::Protocol;
+use libp2p::swarm::{DialError, SwarmEvent};
+use libp2p::{autonat, dcutr, identify, ping, relay, Multiaddr, PeerId, StreamProtocol};
+use libp2p_ping_tut::{clock, echo, exchange, BehaviourEvent, NodeConfig, PingNode, SecurityChoice};
+use std::error::Error;
+use std::time::Duration;
+use tokio::time::Instant;
+
+use crate::doctor;
+
+/// How long a connection gets to close before the next one is opened.
+const CLOSE_GRACE: Duration = Duration::from_millis(200);
+
+/// The protocols of the features of this tool, by the name of the feature.
+const FEATURES: [(&str, StreamProtocol); 7] = [
+    ("ping", ping::PROTOCOL_NAME),
+    ("echo", echo::PROTOCOL_NAME),
+    ("clock", clock::PROTOCOL_NAME),
+    ("peer-exchange", exchange::PROTOCOL_NAME),
+    ("autonat", autonat::DEFAULT_PROTOCOL_NAME),
+    ("relay", relay::HOP_PROTOCOL_NAME),
+    ("hole-punch", dcutr::PROTOCOL_NAME),
+];
+
+/// What probing a peer found out.
+#[derive(Debug, Default)]
+pub struct Findings {
+    pub peer_id: Option<PeerId>,
+    /// Whether the peer accepted each security protocol offered on its own,
+    /// with the time to connect or why it didn't; empty for QUIC, which
+    /// always uses its built-in TLS.
+    pub security: Vec<(SecurityChoice, Result<Duration, String>)>,
+    /// The stream multiplexer connections to the peer use.
+    pub muxer: Option<&'static str>,
+    pub agent_version: Option<String>,
+    pub protocol_version: Option<String>,
+    pub protocols: Vec<String>,
+    /// Whether the peer speaks the protocol of each feature of this tool;
+    /// empty without identify info.
+    pub features: Vec<(&'static str, bool)>,
+    /// Incompatibilities, each with what to do about it.
+    pub problems: Vec<String>,
+}
+
+/// How a connection offering one security protocol went.
+enum Attempt {
+    Connected { peer_id: PeerId, time: Duration, info: Option<Box<identify::Info>> },
+    /// The connection opened, but a handshake on it failed.
+    Rejected(String),
+}
+
+/// Probes the peer at `addr` with nodes of `keypair` and `config`.
+///
+/// Fails if the peer can't be reached at all, as there is nothing to probe.
+pub async fn probe(keypair: Keypair, config: NodeConfig, addr: &Multiaddr) -> Result<Findings, Box<dyn Error>> {
+    let mut findings = Findings::default();
+    let mut info = None;
+    if addr.iter().any(|protocol| matches!(protocol, Protocol::QuicV1)) {
+        match attempt(keypair, config, addr).await? {
+            Attempt::Connected { peer_id, info: identified, .. } => {
+                findings.peer_id = Some(peer_id);
+                findings.muxer = Some("quic");
+                info = identified;
+            }
+            Attempt::Rejected(e) => findings.problems.push(format!("the QUIC handshake failed: {e}")),
+        }
+    } else {
+        for security in [SecurityChoice::Tls, SecurityChoice::Noise] {
+            let config = NodeConfig { security, ..config.clone() };
+            let result = match attempt(keypair.clone(), config, addr).await? {
+                Attempt::Connected { peer_id, time, info: identified } => {
+                    findings.peer_id = Some(peer_id);
+                    findings.muxer = Some("yamux");
+                    info = info.or(identified);
+                    Ok(time)
+                }
+                Attempt::Rejected(e) => Err(e),
+            };
+            findings.security.push((security, result));
+        }
+        findings.problems.extend(security_problem(&findings.security, config.security, config.psk.is_some()));
+    }
+
+    match info {
+        Some(info) => {
+            let info = *info;
+            findings.features =
+                FEATURES.iter().map(|(name, protocol)| (*name, info.protocols.contains(protocol))).collect();
+            if !info.protocols.contains(&ping::PROTOCOL_NAME) {
+                findings.problems.push(format!("the peer doesn't speak {}, so it can't be pinged", ping::PROTOCOL_NAME));
+            }
+            findings.agent_version = Some(info.agent_version);
+            findings.protocol_version = Some(info.protocol_version);
+            findings.protocols = info.protocols.iter().map(ToString::to_string).collect();
+            findings.protocols.sort();
+        }
+        None if findings.peer_id.is_some() => {
+            findings.problems.push("the peer sent no identify info in time, so its protocols are unknown".to_owned());
+        }
+        None => {}
+    }
+    Ok(findings)
+}
+
+/// Connects to `addr` once, waiting for the identify info of the peer.
+async fn attempt(keypair: Keypair, config: NodeConfig, addr: &Multiaddr) -> Result<Attempt, Box<dyn Error>> {
+    let (dial_timeout, ping_timeout) = (config.dial_timeout, config.ping_timeout);
+    let mut node = PingNode::with_keypair(keypair, config)?;
+    let started = Instant::now();
+    let dial = node.dial(addr.clone())?;
+    let connected = loop {
+        let Ok(event) = tokio::time::timeout_at(started + dial_timeout, node.next_event()).await else {
+            return Err(format!("{addr}: not connected within {}", humantime::format_duration(dial_timeout)).into());
+        };
+        match event {
+            SwarmEvent::ConnectionEstablished { peer_id, connection_id, .. } if connection_id == dial => {
+                break (peer_id, started.elapsed());
+            }
+            SwarmEvent::OutgoingConnectionError { connection_id, error, .. } if connection_id == dial => {
+                node.shutdown(CLOSE_GRACE).await;
+                return match &error {
+                    DialError::WrongPeerId { obtained, .. } => {
+                        Err(format!("{addr} is the address of {obtained}, not of the PeerId it ends with").into())
+                    }
+                    error if doctor::failed_in_handshake(error) => Ok(Attempt::Rejected(error.to_string())),
+                    error => Err(format!("{addr}: {error}").into()),
+                };
+            }
+            _ => {}
+        }
+    };
+
+    let (peer_id, time) = connected;
+    let deadline = Instant::now() + ping_timeout;
+    let info = loop {
+        let Ok(event) = tokio::time::timeout_at(deadline, node.next_event()).await else {
+            break None;
+        };
+        match event {
+            SwarmEvent::Behaviour(BehaviourEvent::Identify(identify::Event::Received { peer_id: from, info })) if from == peer_id => {
+                break Some(Box::new(info));
+            }
+            SwarmEvent::ConnectionClosed { connection_id, .. } if connection_id == dial => break None,
+            _ => {}
+        }
+    };
+    node.shutdown(CLOSE_GRACE).await;
+    Ok(Attempt::Connected { peer_id, time, info })
+}
+
+/// What to change, if the security protocols the peer accepts and the one we
+/// are configured with don't match.
+fn security_problem(
+    accepted: &[(SecurityChoice, Result<Duration, String>)],
+    configured: SecurityChoice,
+    psk: bool,
+) -> Option<String> {
+    let accepts = |security| accepted.iter().any(|(choice, result)| *choice == security && result.is_ok());
+    match (accepts(SecurityChoice::Tls), accepts(SecurityChoice::Noise)) {
+        (false, false) => {
+            let error = accepted.iter().find_map(|(_, result)| result.as_ref().err()).map_or("", String::as_str);
+            let hint = match psk {
+                true => "does it use the same --psk?",
+                false => "does it multiplex with Yamux, or need a --psk?",
+            };
+            Some(format!("the peer accepts neither TLS nor Noise: {error}; {hint}"))
+        }
+        (true, false) if configured == SecurityChoice::Noise => {
+            Some("the peer only accepts TLS, but --security is noise; pass --security tls or both".to_owned())
+        }
+        (false, true) if configured == SecurityChoice::Tls => {
+            Some("the peer only accepts Noise, but --security is tls; pass --security noise or both".to_owned())
+        }
+        _ => None,
+    }
+}
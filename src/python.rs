@@ -0,0 +1,193 @@
+//! Python bindings of the [`PingNode`], built with the `python` feature as the
+//! `p2p_ping` module, e.g. by `maturin develop`:
+//!
+//! ```text
+//! import asyncio, p2p_ping
+//!
+//! async def main():
+//!     node = p2p_ping.Node()
+//!     events = node.events()
+//!     await node.dial("/ip4/192.0.2.1/tcp/4001")
+//!     async for event in events:
+//!         if event["event"] == "ping_success":
+//!             print(event["peer_id"], event["rtt_us"])
+//!
+//! asyncio.run(main())
+//! ```
+//!
+//! Events are dicts shaped like the JSON of [`PingEvent`]. The node runs in a
+//! task of its own on the Tokio runtime of `pyo3_async_runtimes`, until the
+//! `Node` is garbage collected.
+
+// The code generated for `#[pymethods]` converts every error into a `PyErr`,
+// including those that already are one.
+#![allow(clippy::useless_conversion)]
+
+use libp2p::Multiaddr;
+use pyo3::exceptions::{PyRuntimeError, PyStopAsyncIteration, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::{PyDict, PyList};
+use serde_json::Value;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::{broadcast, mpsc, oneshot, Mutex};
+
+use crate::{PingEvent, PingNode};
+
+/// How many events an iterator of `Node.events()` may fall behind by before
+/// it misses the oldest ones.
+const EVENT_BUFFER: usize = 1024;
+
+/// How long connections get to close when the node is dropped.
+const SHUTDOWN_GRACE: Duration = Duration::from_secs(1);
+
+/// How long the interpreter waits on exit for the runtime's threads to hand
+/// over the results of awaitables that just completed.
+const EXIT_GRACE: Duration = Duration::from_millis(100);
+
+/// What the task running the node is asked to do.
+enum Command {
+    Dial { addr: Multiaddr, reply: oneshot::Sender<Result<String, String>> },
+}
+
+/// A ping node, `p2p_ping.Node` in Python.
+#[pyclass(module = "p2p_ping", name = "Node")]
+struct Node {
+    peer_id: String,
+    commands: mpsc::Sender<Command>,
+    events: broadcast::Sender<PingEvent>,
+}
+
+#[pymethods]
+impl Node {
+    /// Starts a node with a random identity and the default settings.
+    #[new]
+    fn new() -> PyResult<Self> {
+        let runtime = pyo3_async_runtimes::tokio::get_runtime();
+        let _runtime = runtime.enter();
+        let node = PingNode::new().map_err(|e| PyRuntimeError::new_err(e.to_string()))?;
+        let peer_id = node.local_peer_id().to_string();
+        let (commands, receiver) = mpsc::channel(16);
+        let (events, _) = broadcast::channel(EVENT_BUFFER);
+        runtime.spawn(run(node, receiver, events.clone()));
+        Ok(Self { peer_id, commands, events })
+    }
+
+    /// The PeerId of the node.
+    #[getter]
+    fn peer_id(&self) -> &str {
+        &self.peer_id
+    }
+
+    /// Dials the peer at `multiaddr`, to ping it once connected; awaits the id
+    /// of the new connection.
+    fn dial<'py>(&self, py: Python<'py>, multiaddr: &str) -> PyResult<Bound<'py, PyAny>> {
+        let addr: Multiaddr = multiaddr.parse().map_err(|e| PyValueError::new_err(format!("{e}")))?;
+        let commands = self.commands.clone();
+        pyo3_async_runtimes::tokio::future_into_py(py, async move {
+            let (reply, result) = oneshot::channel();
+            commands.send(Command::Dial { addr, reply }).await.map_err(|_| stopped())?;
+            result.await.map_err(|_| stopped())?.map_err(PyRuntimeError::new_err)
+        })
+    }
+
+    /// Returns an async iterator over the events from now on.
+    fn events(&self) -> Events {
+        Events { receiver: Arc::new(Mutex::new(self.events.subscribe())) }
+    }
+}
+
+/// Async iterator over the events of a node.
+#[pyclass(module = "p2p_ping", name = "Events")]
+struct Events {
+    receiver: Arc<Mutex<broadcast::Receiver<PingEvent>>>,
+}
+
+#[pymethods]
+impl Events {
+    fn __aiter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    fn __anext__<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyAny>> {
+        let receiver = self.receiver.clone();
+        pyo3_async_runtimes::tokio::future_into_py(py, async move {
+            let event = loop {
+                match receiver.lock().await.recv().await {
+                    Ok(event) => break event,
+                    Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => return Err(PyStopAsyncIteration::new_err(())),
+                }
+            };
+            let value = serde_json::to_value(&event).expect("events serialize");
+            Python::with_gil(|py| to_python(py, &value))
+        })
+    }
+}
+
+/// Runs `node` until the `Node` it belongs to is dropped, carrying out its
+/// commands and publishing its events.
+async fn run(mut node: PingNode, mut commands: mpsc::Receiver<Command>, events: broadcast::Sender<PingEvent>) {
+    loop {
+        tokio::select! {
+            command = commands.recv() => match command {
+                Some(Command::Dial { addr, reply }) => {
+                    let _ = reply.send(node.dial(addr).map(|id| id.to_string()).map_err(|e| e.to_string()));
+                }
+                None => break,
+            },
+            event = node.next_event() => {
+                for event in PingEvent::from_swarm(&event) {
+                    let _ = events.send(event);
+                }
+            }
+        }
+    }
+    node.shutdown(SHUTDOWN_GRACE).await;
+}
+
+/// Lets the runtime's threads finish setting the results of awaitables; a
+/// thread holding the GIL while the interpreter finalizes crashes it.
+#[pyfunction]
+fn settle(py: Python<'_>) {
+    py.allow_threads(|| std::thread::sleep(EXIT_GRACE));
+}
+
+fn stopped() -> PyErr {
+    PyRuntimeError::new_err("the node has stopped")
+}
+
+/// Converts a JSON value into the equivalent Python object.
+fn to_python(py: Python<'_>, value: &Value) -> PyResult<PyObject> {
+    Ok(match value {
+        Value::Null => py.None(),
+        Value::Bool(b) => b.into_py(py),
+        Value::Number(n) => match n.as_u64() {
+            Some(n) => n.into_py(py),
+            None => n.as_f64().into_py(py),
+        },
+        Value::String(s) => s.into_py(py),
+        Value::Array(values) => {
+            let values = values.iter().map(|value| to_python(py, value)).collect::<PyResult<Vec<_>>>()?;
+            PyList::new_bound(py, values).into_py(py)
+        }
+        Value::Object(fields) => {
+            let dict = PyDict::new_bound(py);
+            for (key, value) in fields {
+                dict.set_item(key, to_python(py, value)?)?;
+            }
+            dict.into_py(py)
+        }
+    })
+}
+
+/// The `p2p_ping` module.
+#[pymodule]
+fn p2p_ping(module: &Bound<'_, PyModule>) -> PyResult<()> {
+    module.add_class::<Node>()?;
+    module.add_class::<Events>()?;
+    let settle = wrap_pyfunction!(settle, module)?;
+    module.py().import_bound("atexit")?.call_method1("register", (settle,))?;
+    Ok(())
+}
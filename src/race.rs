@@ -0,0 +1,397 @@
+//! Racing the addresses of a peer against each other ("happy eyeballs", RFC
+//! 8305), so that dead addresses don't hold up the dial until they time out.
+//!
+//! Each address gets a head start of [`NodeConfig::race_delay`] over the next
+//! one, or less if the previous attempts failed sooner; the first connection
+//! established wins and the other attempts are dropped. This covers both the
+//! addresses a DNS name resolves to, which [`Racing`] resolves itself, and the
+//! addresses of a peer dialed by PeerId, e.g. those learned via identify or
+//! the DHT, which the swarm dials at once and [`Racing`] staggers.
+//!
+//! [`NodeConfig::race_delay`]: crate::NodeConfig::race_delay
+
+use futures::future::{self, BoxFuture};
+use futures::stream::FuturesUnordered;
+use futures::{FutureExt, StreamExt, TryFutureExt};
+use hickory_resolver::system_conf;
+use hickory_resolver::TokioAsyncResolver;
+use libp2p::core::transport::{ListenerId, TransportError, TransportEvent};
+use libp2p::core::ConnectedPoint;
+use libp2p::dns::{self, ResolveError};
+use libp2p::multiaddr::Protocol;
+use libp2p::swarm::dial_opts::DialOpts;
+use libp2p::swarm::{ConnectionId, DialError, SwarmEvent};
+use libp2p::{Multiaddr, Swarm, Transport};
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::time::Instant;
+
+use crate::timing::DialTimer;
+use crate::{Behaviour, BehaviourEvent};
+
+/// DNS lookups after which resolving an address gives up, as nested
+/// `/dnsaddr` records may refer to each other.
+const MAX_LOOKUPS: usize = 32;
+
+/// Addresses of one `/dnsaddr` record set that are tried at most.
+const MAX_TXT_RECORDS: usize = 16;
+
+/// The outcome of dialing a peer at several addresses at once, as reported by
+/// [`PingNode::take_race`](crate::PingNode::take_race).
+#[derive(Debug, Clone)]
+pub struct Race {
+    /// The address the connection was established at.
+    pub winner: Multiaddr,
+    /// How many addresses took part.
+    pub candidates: usize,
+}
+
+/// What the [`Racing`] transports and the node share.
+#[derive(Debug, Default)]
+struct Shared {
+    /// Number of dials the swarm made since [`Races::stagger`] started.
+    staggered: Option<usize>,
+    /// The resolved address that won, and how many there were, by the
+    /// address with DNS names that was dialed.
+    resolved: HashMap<Multiaddr, (Multiaddr, usize)>,
+}
+
+/// Races between addresses, shared between the [`Racing`] transports and the
+/// node.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Races {
+    shared: Arc<Mutex<Shared>>,
+}
+
+impl Races {
+    /// Runs `dial`, a swarm dial, staggering the addresses it dials; returns
+    /// its result and how many addresses were dialed.
+    pub(crate) fn stagger<R>(&self, dial: impl FnOnce() -> R) -> (R, usize) {
+        self.lock().staggered = Some(0);
+        let result = dial();
+        let dialed = self.lock().staggered.take().unwrap_or_default();
+        (result, dialed)
+    }
+
+    /// Returns how many addresses of the current swarm dial came before this
+    /// one, or 0 outside of [`Self::stagger`].
+    fn next_in_line(&self) -> usize {
+        match &mut self.lock().staggered {
+            Some(dialed) => {
+                *dialed += 1;
+                *dialed - 1
+            }
+            None => 0,
+        }
+    }
+
+    fn resolved(&self, dialed: Multiaddr, winner: Multiaddr, candidates: usize) {
+        self.lock().resolved.insert(dialed, (winner, candidates));
+    }
+
+    fn take_resolved(&self, dialed: &Multiaddr) -> Option<(Multiaddr, usize)> {
+        self.lock().resolved.remove(dialed)
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Shared> {
+        self.shared.lock().expect("no panics while locked")
+    }
+}
+
+/// Wraps a transport to resolve the DNS names in an address, e.g. `/dns4` or
+/// `/dnsaddr`, anew on every dial and race the resolved addresses, and to
+/// stagger the addresses of swarm dials.
+///
+/// Like a DNS transport it takes every address and only fails those its inner
+/// transport refuses once the dial runs, so it has to be tried last.
+pub(crate) struct Racing<T> {
+    inner: Arc<Mutex<T>>,
+    resolver: TokioAsyncResolver,
+    delay: Duration,
+    races: Races,
+    timer: DialTimer,
+}
+
+impl<T> Racing<T> {
+    /// Uses the system's resolver configuration, or public resolvers on hosts
+    /// without one.
+    pub(crate) fn new(inner: T, delay: Duration, races: Races, timer: DialTimer) -> Self {
+        let (config, options) = system_conf::read_system_conf().unwrap_or_else(|e| {
+            tracing::warn!("no system DNS configuration ({e}), using public resolvers");
+            (dns::ResolverConfig::default(), dns::ResolverOpts::default())
+        });
+        Self {
+            inner: Arc::new(Mutex::new(inner)),
+            resolver: TokioAsyncResolver::tokio(config, options),
+            delay,
+            races,
+            timer,
+        }
+    }
+}
+
+type Error<T> = dns::Error<<T as Transport>::Error>;
+
+impl<T> Racing<T>
+where
+    T: Transport + Send + Unpin + 'static,
+    T::Error: Send,
+    T::Dial: Send,
+{
+    fn start(&self, addr: Multiaddr, as_listener: bool) -> BoxFuture<'static, Result<T::Output, Error<T>>> {
+        let head_start = self.delay * self.races.next_in_line() as u32;
+        let (inner, resolver, delay) = (self.inner.clone(), self.resolver.clone(), self.delay);
+        let (races, timer) = (self.races.clone(), self.timer.clone());
+        async move {
+            if !head_start.is_zero() {
+                tokio::time::sleep(head_start).await;
+            }
+            if !addr.iter().any(is_dns) {
+                return dial(&inner, addr, as_listener)?.await.map_err(dns::Error::Transport);
+            }
+            let started = std::time::Instant::now();
+            let candidates = interleave(resolve(&resolver, addr.clone()).await?);
+            let count = candidates.len();
+            let (output, winner) = race(&inner, candidates.clone(), delay, as_listener).await?;
+            // The timings and the winner are looked up by the address the swarm dialed.
+            for candidate in candidates.iter().filter(|&candidate| *candidate != winner) {
+                timer.forget(candidate);
+            }
+            timer.resolved(&winner, &addr, started);
+            if count > 1 {
+                races.resolved(addr, winner, count);
+            }
+            Ok(output)
+        }
+        .boxed()
+    }
+}
+
+impl<T> Transport for Racing<T>
+where
+    T: Transport + Send + Unpin + 'static,
+    T::Error: Send,
+    T::Dial: Send,
+{
+    type Output = T::Output;
+    type Error = Error<T>;
+    type ListenerUpgrade = future::MapErr<T::ListenerUpgrade, fn(T::Error) -> Self::Error>;
+    type Dial = BoxFuture<'static, Result<Self::Output, Self::Error>>;
+
+    fn listen_on(&mut self, id: ListenerId, addr: Multiaddr) -> Result<(), TransportError<Self::Error>> {
+        self.inner.lock().expect("no panics while locked").listen_on(id, addr).map_err(|e| e.map(dns::Error::Transport))
+    }
+
+    fn remove_listener(&mut self, id: ListenerId) -> bool {
+        self.inner.lock().expect("no panics while locked").remove_listener(id)
+    }
+
+    fn dial(&mut self, addr: Multiaddr) -> Result<Self::Dial, TransportError<Self::Error>> {
+        Ok(self.start(addr, false))
+    }
+
+    fn dial_as_listener(&mut self, addr: Multiaddr) -> Result<Self::Dial, TransportError<Self::Error>> {
+        Ok(self.start(addr, true))
+    }
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<TransportEvent<Self::ListenerUpgrade, Self::Error>> {
+        let mut inner = self.inner.lock().expect("no panics while locked");
+        Pin::new(&mut *inner).poll(cx).map(|event| {
+            event
+                .map_upgrade(|upgrade| upgrade.map_err(dns::Error::Transport as fn(_) -> _))
+                .map_err(dns::Error::Transport)
+        })
+    }
+
+    fn address_translation(&self, listen: &Multiaddr, observed: &Multiaddr) -> Option<Multiaddr> {
+        self.inner.lock().expect("no panics while locked").address_translation(listen, observed)
+    }
+}
+
+fn dial<T: Transport>(inner: &Mutex<T>, addr: Multiaddr, as_listener: bool) -> Result<T::Dial, Error<T>> {
+    let mut inner = inner.lock().expect("no panics while locked");
+    let dial = if as_listener { inner.dial_as_listener(addr) } else { inner.dial(addr) };
+    dial.map_err(|e| match e {
+        TransportError::MultiaddrNotSupported(addr) => dns::Error::MultiaddrNotSupported(addr),
+        TransportError::Other(e) => dns::Error::Transport(e),
+    })
+}
+
+/// Dials `candidates` one after the other, each `delay` after the previous
+/// one or as soon as all previous ones failed, until the first succeeds;
+/// returns its connection and address, or the last error.
+async fn race<T: Transport>(
+    inner: &Mutex<T>,
+    candidates: Vec<Multiaddr>,
+    delay: Duration,
+    as_listener: bool,
+) -> Result<(T::Output, Multiaddr), Error<T>> {
+    let mut waiting = candidates.into_iter();
+    let mut attempts = FuturesUnordered::new();
+    let mut next_at = Instant::now();
+    let mut error = None;
+    loop {
+        let more = waiting.len() > 0;
+        if !more && attempts.is_empty() {
+            return Err(error.expect("an address resolves to at least one"));
+        }
+        tokio::select! {
+            Some((addr, result)) = attempts.next() => match result {
+                Ok(output) => return Ok((output, addr)),
+                Err(e) => {
+                    error = Some(dns::Error::Transport(e));
+                    if attempts.is_empty() {
+                        next_at = Instant::now();
+                    }
+                }
+            },
+            () = tokio::time::sleep_until(next_at), if more => {
+                let addr = waiting.next().expect("there are more addresses");
+                match dial(inner, addr.clone(), as_listener) {
+                    Ok(dial) => attempts.push(dial.map(move |result| (addr, result))),
+                    Err(e) => error = Some(e),
+                }
+                next_at = if attempts.is_empty() { Instant::now() } else { Instant::now() + delay };
+            }
+        }
+    }
+}
+
+fn is_dns(protocol: Protocol<'_>) -> bool {
+    matches!(protocol, Protocol::Dns(_) | Protocol::Dns4(_) | Protocol::Dns6(_) | Protocol::Dnsaddr(_))
+}
+
+/// Replaces the DNS names in `addr` by what they resolve to, which may be
+/// many addresses.
+async fn resolve<E>(resolver: &TokioAsyncResolver, addr: Multiaddr) -> Result<Vec<Multiaddr>, dns::Error<E>> {
+    let mut unresolved = vec![addr];
+    let mut resolved = Vec::new();
+    let mut lookups = 0;
+    let mut error = None;
+    while let Some(addr) = unresolved.pop() {
+        let Some((index, name)) = addr.iter().enumerate().find(|(_, protocol)| is_dns(protocol.clone())) else {
+            resolved.push(addr);
+            continue;
+        };
+        if lookups == MAX_LOOKUPS {
+            error = Some(dns::Error::TooManyLookups);
+            continue;
+        }
+        lookups += 1;
+        let ips = |ips: Vec<IpAddr>| {
+            let addr = &addr;
+            ips.into_iter()
+                .map(move |ip| addr.replace(index, |_| Some(Protocol::from(ip))).expect("the index is valid"))
+                .collect::<Vec<_>>()
+        };
+        let addrs: Result<Vec<_>, ResolveError> = match name {
+            Protocol::Dns(name) => resolver.lookup_ip(name.as_ref()).await.map(|found| ips(found.iter().collect())),
+            Protocol::Dns4(name) => {
+                resolver.ipv4_lookup(name.as_ref()).await.map(|found| ips(found.iter().map(|a| IpAddr::V4(a.0)).collect()))
+            }
+            Protocol::Dns6(name) => {
+                resolver.ipv6_lookup(name.as_ref()).await.map(|found| ips(found.iter().map(|a| IpAddr::V6(a.0)).collect()))
+            }
+            Protocol::Dnsaddr(name) => {
+                let prefix: Multiaddr = addr.iter().take(index).collect();
+                let suffix: Multiaddr = addr.iter().skip(index + 1).collect();
+                resolver.txt_lookup(format!("_dnsaddr.{name}")).await.map(|found| {
+                    found
+                        .iter()
+                        .filter_map(|txt| {
+                            let record = std::str::from_utf8(txt.txt_data().first()?).ok()?;
+                            record.strip_prefix("dnsaddr=")?.parse::<Multiaddr>().ok()
+                        })
+                        .filter(|found| found.ends_with(&suffix))
+                        .take(MAX_TXT_RECORDS)
+                        .map(|found| prefix.iter().chain(found.iter()).collect())
+                        .collect()
+                })
+            }
+            _ => unreachable!("only DNS names are looked up"),
+        };
+        match addrs {
+            // Keep the order of the records, as `pop` takes from the end.
+            Ok(addrs) => unresolved.extend(addrs.into_iter().rev()),
+            Err(e) => error = Some(dns::Error::ResolveError(e)),
+        }
+    }
+    match error {
+        Some(e) if resolved.is_empty() => Err(e),
+        _ => Ok(resolved),
+    }
+}
+
+/// Alternates between IPv6 and IPv4 addresses, starting with the family of
+/// the first one, so that a broken family only costs one head start.
+fn interleave(addrs: Vec<Multiaddr>) -> Vec<Multiaddr> {
+    let is_ipv6 = |addr: &Multiaddr| matches!(addr.iter().next(), Some(Protocol::Ip6(_)));
+    let first_ipv6 = addrs.first().is_some_and(is_ipv6);
+    let (mut first, mut second): (Vec<_>, Vec<_>) = addrs.into_iter().partition(|addr| is_ipv6(addr) == first_ipv6);
+    let mut interleaved = Vec::with_capacity(first.len() + second.len());
+    first.reverse();
+    second.reverse();
+    loop {
+        match (first.pop(), second.pop()) {
+            (None, None) => return interleaved,
+            (a, b) => interleaved.extend(a.into_iter().chain(b)),
+        }
+    }
+}
+
+/// The [`Race`]s won by outgoing connections, until taken.
+#[derive(Debug, Default)]
+pub(crate) struct RaceResults {
+    races: Races,
+    /// Number of addresses of each swarm dial that had several.
+    dialing: HashMap<ConnectionId, usize>,
+    done: HashMap<ConnectionId, Race>,
+}
+
+impl RaceResults {
+    pub(crate) fn new(races: Races) -> Self {
+        Self { races, dialing: HashMap::new(), done: HashMap::new() }
+    }
+
+    /// Dials `opts` on `swarm`, staggering its addresses; returns the result
+    /// and how many addresses were dialed.
+    pub(crate) fn stagger(&self, swarm: &mut Swarm<Behaviour>, opts: DialOpts) -> (Result<(), DialError>, usize) {
+        self.races.stagger(|| swarm.dial(opts))
+    }
+
+    /// Notes that the swarm dialed `candidates` addresses for `connection_id`.
+    pub(crate) fn dialing(&mut self, connection_id: ConnectionId, candidates: usize) {
+        if candidates > 1 {
+            self.dialing.insert(connection_id, candidates);
+        }
+    }
+
+    pub(crate) fn observe(&mut self, event: &SwarmEvent<BehaviourEvent>) {
+        match event {
+            SwarmEvent::ConnectionEstablished { connection_id, endpoint: ConnectedPoint::Dialer { address, .. }, .. } => {
+                let dialed = self.dialing.remove(connection_id);
+                let race = match (self.races.take_resolved(address), dialed) {
+                    (Some((winner, candidates)), _) => Race { winner, candidates },
+                    (None, Some(candidates)) => Race { winner: address.clone(), candidates },
+                    (None, None) => return,
+                };
+                self.done.insert(*connection_id, race);
+            }
+            SwarmEvent::OutgoingConnectionError { connection_id, .. } => {
+                self.dialing.remove(connection_id);
+            }
+            SwarmEvent::ConnectionClosed { connection_id, .. } => {
+                self.done.remove(connection_id);
+            }
+            _ => {}
+        }
+    }
+
+    pub(crate) fn take(&mut self, connection_id: ConnectionId) -> Option<Race> {
+        self.done.remove(&connection_id)
+    }
+}
@@ -0,0 +1,134 @@
+//! Discovery of peers through a rendezvous point, where nodes register under
+//! a namespace and look up the others registered under it.
+
+use libp2p::multiaddr::Protocol;
+use libp2p::rendezvous::{self, Cookie, Namespace};
+use libp2p::swarm::SwarmEvent;
+use libp2p::{Multiaddr, PeerId, Swarm};
+use std::time::Duration;
+use tokio::time::{Instant, Interval, MissedTickBehavior};
+
+use crate::{Behaviour, BehaviourEvent, PingError};
+
+/// How often the namespace is looked up again for peers that registered since,
+/// and a lost connection to the point is redialed.
+const DISCOVER_INTERVAL: Duration = Duration::from_secs(30);
+
+/// A rendezvous point and the namespace to meet other peers under.
+#[derive(Debug, Clone)]
+pub struct Rendezvous {
+    /// Address of the point; must end with `/p2p/<peer id>`.
+    pub point: Multiaddr,
+    /// Namespace to register and discover under, at most
+    /// [`rendezvous::MAX_NAMESPACE`] bytes.
+    pub namespace: String,
+}
+
+/// Registration and discovery state of a node meeting others at a point.
+pub(crate) struct Meeting {
+    point: Multiaddr,
+    point_id: PeerId,
+    namespace: Namespace,
+    /// Picks up discovery where the last one left off, so that only newly
+    /// registered peers are returned.
+    cookie: Option<Cookie>,
+    /// When the registration has to be renewed; `None` while not registered.
+    renew_at: Option<Instant>,
+    refresh: Interval,
+}
+
+impl Meeting {
+    pub(crate) fn new(rendezvous: &Rendezvous) -> Result<Self, PingError> {
+        let Some(Protocol::P2p(point_id)) = rendezvous.point.iter().last() else {
+            let reason = "a rendezvous point must end with /p2p/<peer id>".to_owned();
+            return Err(PingError::InvalidMultiaddr { addr: rendezvous.point.to_string(), reason });
+        };
+        let namespace = Namespace::new(rendezvous.namespace.clone()).map_err(|_| {
+            PingError::InvalidConfig(format!("rendezvous namespace is longer than {} bytes", rendezvous::MAX_NAMESPACE))
+        })?;
+        let mut refresh = tokio::time::interval_at(Instant::now() + DISCOVER_INTERVAL, DISCOVER_INTERVAL);
+        refresh.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        Ok(Self { point: rendezvous.point.clone(), point_id, namespace, cookie: None, renew_at: None, refresh })
+    }
+
+    /// Dials the point.
+    pub(crate) fn start(&self, swarm: &mut Swarm<Behaviour>) -> Result<(), PingError> {
+        swarm.dial(self.point.clone()).map_err(PingError::dial)
+    }
+
+    /// Waits until it is time to look up the namespace again.
+    pub(crate) async fn tick(&mut self) {
+        self.refresh.tick().await;
+    }
+
+    /// Looks up the namespace again and renews the registration if it is due,
+    /// or redials the point if the connection to it was lost.
+    pub(crate) fn refresh(&mut self, swarm: &mut Swarm<Behaviour>) {
+        if !swarm.is_connected(&self.point_id) {
+            self.renew_at = None;
+            let _ = swarm.dial(self.point.clone());
+            return;
+        }
+        if self.renew_at.is_none_or(|at| at <= Instant::now()) {
+            self.register(swarm);
+        }
+        self.discover(swarm);
+    }
+
+    /// Registers and discovers once connected to the point, and registers again
+    /// when the node learns an external address, without which registering
+    /// fails. Returns the addresses of the peers discovered under the
+    /// namespace, except our own.
+    pub(crate) fn observe(&mut self, swarm: &mut Swarm<Behaviour>, event: &SwarmEvent<BehaviourEvent>) -> Vec<(PeerId, Multiaddr)> {
+        match event {
+            SwarmEvent::ConnectionEstablished { peer_id, num_established, .. }
+                if *peer_id == self.point_id && num_established.get() == 1 =>
+            {
+                self.register(swarm);
+                self.discover(swarm);
+            }
+            SwarmEvent::ExternalAddrConfirmed { .. } if self.renew_at.is_none() && swarm.is_connected(&self.point_id) => {
+                self.register(swarm);
+            }
+            SwarmEvent::Behaviour(BehaviourEvent::RendezvousClient(rendezvous::client::Event::Registered { ttl, .. })) => {
+                // Renew halfway through, well before the point forgets us.
+                self.renew_at = Some(Instant::now() + Duration::from_secs(*ttl) / 2);
+            }
+            SwarmEvent::Behaviour(BehaviourEvent::RendezvousClient(rendezvous::client::Event::Discovered {
+                registrations,
+                cookie,
+                ..
+            })) => {
+                self.cookie = Some(cookie.clone());
+                let local_peer_id = *swarm.local_peer_id();
+                return registrations
+                    .iter()
+                    .filter(|registration| registration.record.peer_id() != local_peer_id)
+                    .flat_map(|registration| {
+                        let peer_id = registration.record.peer_id();
+                        registration.record.addresses().iter().map(move |addr| (peer_id, addr.clone()))
+                    })
+                    .collect();
+            }
+            _ => {}
+        }
+        Vec::new()
+    }
+
+    fn register(&mut self, swarm: &mut Swarm<Behaviour>) {
+        let Some(client) = swarm.behaviour_mut().rendezvous_client.as_mut() else {
+            return;
+        };
+        // Without an external address there is nothing to register yet; we try
+        // again once we have one.
+        if let Err(e) = client.register(self.namespace.clone(), self.point_id, None) {
+            tracing::debug!(point = %self.point_id, "not registering yet: {e}");
+        }
+    }
+
+    fn discover(&mut self, swarm: &mut Swarm<Behaviour>) {
+        if let Some(client) = swarm.behaviour_mut().rendezvous_client.as_mut() {
+            client.discover(Some(self.namespace.clone()), self.cookie.clone(), None, self.point_id);
+        }
+    }
+}
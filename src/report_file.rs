@@ -0,0 +1,229 @@
+//! The final report of a run (`--report-file`), written as one JSON document
+//! on exit whatever the `--output` format: the versions and configuration
+//! used, the statistics of each peer and its health changes along the way.
+//!
+//! CI jobs archive it as a single artifact and diff it between runs.
+
+use libp2p::autonat::NatStatus;
+use libp2p::PeerId;
+use libp2p_ping_tut::labels::Labels;
+use libp2p_ping_tut::{PingNode, SecurityChoice, TransportChoice};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::io;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+use crate::config::Settings;
+use crate::targets::{Targets, Thresholds, Transition};
+
+/// What a run did so far, written to a file on [`RunReport::write`].
+pub struct RunReport {
+    path: PathBuf,
+    started: SystemTime,
+    config_file: Option<PathBuf>,
+    config: ConfigUsed,
+    transitions: Vec<TransitionRecord>,
+    /// The agent version each peer identified with.
+    agent_versions: HashMap<PeerId, String>,
+}
+
+/// The resolved settings that shape the results.
+#[derive(Debug, Serialize)]
+struct ConfigUsed {
+    transports: Vec<TransportChoice>,
+    security: SecurityChoice,
+    listen: Vec<String>,
+    peers: Vec<String>,
+    via_relay: Option<String>,
+    interval_ms: u64,
+    adaptive: bool,
+    timeout_ms: u64,
+    dial_timeout_ms: u64,
+    idle_timeout_ms: u64,
+    keep_alive: bool,
+    count: Option<u64>,
+    warmup: u64,
+    deadline_ms: Option<u64>,
+    size: Option<usize>,
+    fail_under: Option<f64>,
+    max_rtt_us: Option<u64>,
+}
+
+#[derive(Debug, Serialize)]
+struct TransitionRecord {
+    timestamp: String,
+    target: String,
+    peer_id: Option<String>,
+    from: Option<String>,
+    to: String,
+}
+
+#[derive(Debug, Serialize)]
+struct Report<'a> {
+    version: &'static str,
+    peer_id: String,
+    started: String,
+    finished: String,
+    success: bool,
+    command_line: Vec<String>,
+    config_file: Option<String>,
+    config: &'a ConfigUsed,
+    peers: Vec<PeerReport>,
+    transitions: &'a [TransitionRecord],
+    reachability: &'static str,
+    public_address: Option<String>,
+    sent_bytes: u64,
+    received_bytes: u64,
+}
+
+#[derive(Debug, Serialize)]
+struct PeerReport {
+    target: String,
+    address: String,
+    peer_id: Option<String>,
+    agent_version: Option<String>,
+    labels: Labels,
+    health: Option<String>,
+    transmitted: u64,
+    received: u64,
+    loss_percent: f64,
+    min_us: Option<u64>,
+    avg_us: Option<u64>,
+    max_us: Option<u64>,
+    mdev_us: Option<u64>,
+    jitter_us: Option<u64>,
+    p50_us: Option<u64>,
+    p90_us: Option<u64>,
+    p99_us: Option<u64>,
+    sent_bytes: u64,
+    received_bytes: u64,
+    /// Failed dials of the peer, by why they failed.
+    dial_failures: BTreeMap<&'static str, u64>,
+    /// Thresholds the peer violates, making the run fail.
+    violations: Vec<String>,
+}
+
+impl RunReport {
+    /// Starts the report of a run with `settings`, to be written to `path`.
+    pub fn new(path: PathBuf, settings: &Settings) -> Self {
+        let node = &settings.node;
+        let config = ConfigUsed {
+            transports: node.transports.clone(),
+            security: node.security,
+            listen: settings.listen.iter().map(ToString::to_string).collect(),
+            peers: settings.peers.iter().map(|peer| peer.addr.to_string()).collect(),
+            via_relay: settings.via_relay.as_ref().map(ToString::to_string),
+            interval_ms: millis(node.ping_interval),
+            adaptive: node.adaptive_interval.is_some(),
+            timeout_ms: millis(node.ping_timeout),
+            dial_timeout_ms: millis(node.dial_timeout),
+            idle_timeout_ms: millis(node.idle_timeout),
+            keep_alive: node.keep_alive,
+            count: settings.count,
+            warmup: settings.warmup,
+            deadline_ms: settings.deadline.map(millis),
+            size: node.echo_size,
+            fail_under: settings.thresholds.fail_under,
+            max_rtt_us: settings.thresholds.max_rtt.as_ref().map(micros),
+        };
+        Self {
+            path,
+            started: SystemTime::now(),
+            config_file: settings.config_file.clone(),
+            config,
+            transitions: Vec::new(),
+            agent_versions: HashMap::new(),
+        }
+    }
+
+    /// Records that `target` went up, degraded or down.
+    pub fn health_changed(&mut self, target: String, peer_id: Option<&PeerId>, transition: &Transition) {
+        self.transitions.push(TransitionRecord {
+            timestamp: humantime::format_rfc3339_micros(SystemTime::now()).to_string(),
+            target,
+            peer_id: peer_id.map(ToString::to_string),
+            from: transition.from.map(|from| from.to_string()),
+            to: transition.to.to_string(),
+        });
+    }
+
+    /// Records the agent version `peer_id` identified with.
+    pub fn identified(&mut self, peer_id: PeerId, agent_version: &str) {
+        self.agent_versions.insert(peer_id, agent_version.to_owned());
+    }
+
+    /// Writes the report of the finished run, replacing the file if it exists.
+    pub fn write(
+        &self,
+        node: &PingNode,
+        targets: &Targets,
+        thresholds: &Thresholds,
+        nat_status: &NatStatus,
+        success: bool,
+    ) -> io::Result<()> {
+        let peers = targets
+            .iter()
+            .map(|target| {
+                let stats = &target.stats;
+                let traffic = target.peer_id.map(|peer_id| node.traffic(&peer_id)).unwrap_or_default();
+                PeerReport {
+                    target: target.label(),
+                    address: target.addr.to_string(),
+                    peer_id: target.peer_id.map(|peer_id| peer_id.to_string()),
+                    agent_version: target.peer_id.and_then(|peer_id| self.agent_versions.get(&peer_id).cloned()),
+                    labels: target.labels.clone(),
+                    health: target.health().map(|health| health.to_string()),
+                    transmitted: stats.transmitted(),
+                    received: stats.received(),
+                    loss_percent: stats.loss_percent(),
+                    min_us: stats.min().as_ref().map(micros),
+                    avg_us: stats.avg().as_ref().map(micros),
+                    max_us: stats.max().as_ref().map(micros),
+                    mdev_us: stats.mdev().as_ref().map(micros),
+                    jitter_us: stats.jitter().as_ref().map(micros),
+                    p50_us: stats.percentile(50.0).as_ref().map(micros),
+                    p90_us: stats.percentile(90.0).as_ref().map(micros),
+                    p99_us: stats.percentile(99.0).as_ref().map(micros),
+                    sent_bytes: traffic.sent,
+                    received_bytes: traffic.received,
+                    dial_failures: target.dial_failures.iter().map(|(kind, count)| (kind.as_str(), *count)).collect(),
+                    violations: target.thresholds(thresholds).violations(stats),
+                }
+            })
+            .collect();
+        let (reachability, public_address) = match nat_status {
+            NatStatus::Public(address) => ("public", Some(address.to_string())),
+            NatStatus::Private => ("private", None),
+            NatStatus::Unknown => ("unknown", None),
+        };
+        let total = node.total_traffic();
+        let report = Report {
+            version: env!("CARGO_PKG_VERSION"),
+            peer_id: node.local_peer_id().to_string(),
+            started: humantime::format_rfc3339_micros(self.started).to_string(),
+            finished: humantime::format_rfc3339_micros(SystemTime::now()).to_string(),
+            success,
+            command_line: std::env::args().collect(),
+            config_file: self.config_file.as_ref().map(|path| path.display().to_string()),
+            config: &self.config,
+            peers,
+            transitions: &self.transitions,
+            reachability,
+            public_address,
+            sent_bytes: total.sent,
+            received_bytes: total.received,
+        };
+        let mut json = serde_json::to_vec_pretty(&report).map_err(io::Error::other)?;
+        json.push(b'\n');
+        std::fs::write(&self.path, json).map_err(|e| io::Error::new(e.kind(), format!("{}: {e}", self.path.display())))
+    }
+}
+
+fn millis(d: Duration) -> u64 {
+    d.as_millis() as u64
+}
+
+fn micros(d: &Duration) -> u64 {
+    d.as_micros() as u64
+}
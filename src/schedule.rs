@@ -0,0 +1,337 @@
+//! Measurement windows (`ping --schedule`), opened at the minutes a cron
+//! expression matches, outside of which the node only listens.
+
+use std::fmt;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const MINUTE: u64 = 60;
+const DAY: u64 = 24 * 60 * MINUTE;
+
+/// How many days ahead a match is looked for; a schedule matching less often,
+/// e.g. only on February 30, never opens a window.
+const HORIZON_DAYS: u64 = 5 * 366;
+
+/// Windows merged with the ones after them are cut off this long after they
+/// opened, so that a schedule matching every minute still reports on each day.
+const MAX_WINDOW: Duration = Duration::from_secs(DAY);
+
+/// A cron expression of five fields, `minute hour day-of-month month
+/// day-of-week`, in UTC.
+///
+/// Each field is `*`, a number, a range `a-b`, any of them stepped with `/n`,
+/// or a comma-separated list of those. Sunday is day 0 or 7 of the week. As in
+/// cron, a day matches if either day field does when both are restricted.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Schedule {
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    any_day: bool,
+    any_weekday: bool,
+    source: String,
+}
+
+impl Schedule {
+    /// Returns the start of the first minute matching the schedule at or after
+    /// `time`, if there is one within five years.
+    pub fn next_start(&self, time: SystemTime) -> Option<SystemTime> {
+        let secs = time.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        // Round up to the start of the next minute, unless at one already.
+        let from = secs.div_ceil(MINUTE) * MINUTE;
+        let first_day = from / DAY;
+        for day in first_day..first_day + HORIZON_DAYS {
+            if !self.matches_day(day) {
+                continue;
+            }
+            let earliest = if day == first_day { (from % DAY) / MINUTE } else { 0 };
+            for minute_of_day in earliest..DAY / MINUTE {
+                let (hour, minute) = (minute_of_day / 60, minute_of_day % 60);
+                if bit(self.hours, hour) && bit(self.minutes, minute) {
+                    return Some(UNIX_EPOCH + Duration::from_secs(day * DAY + minute_of_day * MINUTE));
+                }
+            }
+        }
+        None
+    }
+
+    fn matches_day(&self, days_since_epoch: u64) -> bool {
+        let (_, month, day) = civil_from_days(days_since_epoch);
+        // January 1, 1970 was a Thursday.
+        let weekday = (days_since_epoch + 4) % 7;
+        let day_matches = match (self.any_day, self.any_weekday) {
+            (false, false) => bit(self.days, day) || bit(self.weekdays, weekday),
+            _ => bit(self.days, day) && bit(self.weekdays, weekday),
+        };
+        day_matches && bit(self.months, month)
+    }
+}
+
+impl FromStr for Schedule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        let fields: Vec<&str> = s.split_whitespace().collect();
+        let [minutes, hours, days, months, weekdays] = fields[..] else {
+            return Err(format!("schedule `{s}` must have 5 fields: minute hour day-of-month month day-of-week"));
+        };
+        let mut weekday_bits = field(weekdays, 0, 7, "day-of-week")?;
+        // Both 0 and 7 are Sunday.
+        if bit(weekday_bits, 7) {
+            weekday_bits |= 1;
+        }
+        Ok(Self {
+            minutes: field(minutes, 0, 59, "minute")?,
+            hours: field(hours, 0, 23, "hour")?,
+            days: field(days, 1, 31, "day-of-month")?,
+            months: field(months, 1, 12, "month")?,
+            weekdays: weekday_bits,
+            any_day: days.starts_with('*'),
+            any_weekday: weekdays.starts_with('*'),
+            source: s.to_owned(),
+        })
+    }
+}
+
+impl fmt::Display for Schedule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+/// Parses a field with values from `min` to `max` into a bit per value.
+fn field(s: &str, min: u64, max: u64, name: &str) -> Result<u64, String> {
+    let number = |n: &str| -> Result<u64, String> {
+        match n.parse() {
+            Ok(n) if (min..=max).contains(&n) => Ok(n),
+            _ => Err(format!("schedule {name} `{n}` must be a number from {min} to {max}")),
+        }
+    };
+    let mut bits = 0;
+    for part in s.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => match step.parse() {
+                Ok(step) if step > 0 => (range, step),
+                _ => return Err(format!("schedule {name} step `{step}` must be a positive number")),
+            },
+            None => (part, 1),
+        };
+        let (first, last) = match range.split_once('-') {
+            _ if range == "*" => (min, max),
+            Some((first, last)) => (number(first)?, number(last)?),
+            // `a/n` steps from `a` to the end, as in cron.
+            None if step > 1 => (number(range)?, max),
+            None => {
+                let n = number(range)?;
+                (n, n)
+            }
+        };
+        if first > last {
+            return Err(format!("schedule {name} range `{range}` is reversed"));
+        }
+        for n in (first..=last).step_by(step) {
+            bits |= 1 << n;
+        }
+    }
+    Ok(bits)
+}
+
+fn bit(bits: u64, n: u64) -> bool {
+    bits & (1 << n) != 0
+}
+
+/// Returns the year, month and day of a day since the Unix epoch, after
+/// Howard Hinnant's `civil_from_days`.
+fn civil_from_days(days: u64) -> (u64, u64, u64) {
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let day_of_era = z % 146_097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + u64::from(month <= 2);
+    (year, month, day)
+}
+
+/// Whether a measurement window is open.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum State {
+    /// Open until the given time.
+    Open(SystemTime),
+    /// Closed until the given time, or for good.
+    Closed(Option<SystemTime>),
+}
+
+/// The windows of a schedule: each matching minute opens one lasting
+/// `length`, merged with the next ones where they meet or overlap.
+#[derive(Debug)]
+pub struct Windows {
+    schedule: Schedule,
+    length: Duration,
+}
+
+impl Windows {
+    pub fn new(schedule: Schedule, length: Duration) -> Self {
+        Self { schedule, length }
+    }
+
+    /// Returns whether a window is open at `now`.
+    pub fn state(&self, now: SystemTime) -> State {
+        // A window open now started at most its length ago.
+        let Some(start) = self.schedule.next_start(now.checked_sub(self.length).unwrap_or(UNIX_EPOCH) + Duration::from_secs(1))
+        else {
+            return State::Closed(None);
+        };
+        if start > now {
+            return State::Closed(Some(start));
+        }
+        let (mut latest, mut end) = (start, start + self.length);
+        while let Some(next) = self.schedule.next_start(latest + Duration::from_secs(1)).filter(|next| *next <= end) {
+            if next.duration_since(start).unwrap_or_default() >= MAX_WINDOW {
+                break;
+            }
+            (latest, end) = (next, end.max(next + self.length));
+        }
+        State::Open(end)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(time: &str) -> SystemTime {
+        humantime::parse_rfc3339(time).unwrap()
+    }
+
+    fn next(schedule: &str, from: &str) -> Option<SystemTime> {
+        schedule.parse::<Schedule>().unwrap().next_start(at(from))
+    }
+
+    fn windows(schedule: &str, length_mins: u64) -> Windows {
+        Windows::new(schedule.parse().unwrap(), Duration::from_secs(length_mins * MINUTE))
+    }
+
+    #[test]
+    fn rejects_malformed_expressions() {
+        for (schedule, error) in [
+            ("* * * *", "must have 5 fields"),
+            ("* * * * * *", "must have 5 fields"),
+            ("60 * * * *", "minute `60` must be a number from 0 to 59"),
+            ("* 24 * * *", "hour `24`"),
+            ("* * 0 * *", "day-of-month `0` must be a number from 1 to 31"),
+            ("* * * 13 *", "month `13`"),
+            ("* * * * 8", "day-of-week `8` must be a number from 0 to 7"),
+            ("x * * * *", "minute `x`"),
+            ("30-10 * * * *", "range `30-10` is reversed"),
+            ("*/0 * * * *", "step `0` must be a positive number"),
+            ("*/x * * * *", "step `x`"),
+        ] {
+            let result = schedule.parse::<Schedule>();
+            assert!(result.as_ref().is_err_and(|e| e.contains(error)), "{schedule}: {result:?}");
+        }
+    }
+
+    #[test]
+    fn displays_as_given() {
+        assert_eq!("0  2 * * 1-5".parse::<Schedule>().unwrap().to_string(), "0  2 * * 1-5");
+    }
+
+    #[test]
+    fn starts_at_the_next_whole_minute() {
+        assert_eq!(next("* * * * *", "2024-01-01T00:05:00Z"), Some(at("2024-01-01T00:05:00Z")));
+        assert_eq!(next("* * * * *", "2024-01-01T00:05:30Z"), Some(at("2024-01-01T00:06:00Z")));
+        assert_eq!(next("* * * * *", "2024-01-01T23:59:01Z"), Some(at("2024-01-02T00:00:00Z")));
+    }
+
+    #[test]
+    fn lists_ranges_and_steps() {
+        assert_eq!(next("15,45 * * * *", "2024-01-01T00:16:00Z"), Some(at("2024-01-01T00:45:00Z")));
+        assert_eq!(next("0 9-17 * * *", "2024-01-01T17:01:00Z"), Some(at("2024-01-02T09:00:00Z")));
+        assert_eq!(next("*/20 * * * *", "2024-01-01T00:41:00Z"), Some(at("2024-01-01T01:00:00Z")));
+        assert_eq!(next("10-30/10 * * * *", "2024-01-01T00:21:00Z"), Some(at("2024-01-01T00:30:00Z")));
+        assert_eq!(next("10-30/10 * * * *", "2024-01-01T00:31:00Z"), Some(at("2024-01-01T01:10:00Z")));
+    }
+
+    #[test]
+    fn a_single_value_with_a_step_runs_to_the_end_of_the_field() {
+        // 5, 25 and 45 past each hour.
+        assert_eq!(next("5/20 * * * *", "2024-01-01T00:00:00Z"), Some(at("2024-01-01T00:05:00Z")));
+        assert_eq!(next("5/20 * * * *", "2024-01-01T00:06:00Z"), Some(at("2024-01-01T00:25:00Z")));
+        assert_eq!(next("5/20 * * * *", "2024-01-01T00:46:00Z"), Some(at("2024-01-01T01:05:00Z")));
+    }
+
+    #[test]
+    fn sunday_is_both_0_and_7() {
+        // January 1, 2024 was a Monday.
+        for schedule in ["0 0 * * 0", "0 0 * * 7", "0 0 * * 6-7"] {
+            assert_eq!(next(schedule, "2024-01-07T00:00:00Z"), Some(at("2024-01-07T00:00:00Z")), "{schedule}");
+        }
+        assert_eq!(next("0 0 * * 7", "2024-01-01T00:00:00Z"), Some(at("2024-01-07T00:00:00Z")));
+        assert_eq!(next("0 0 * * 1-5", "2024-01-06T00:00:00Z"), Some(at("2024-01-08T00:00:00Z")));
+    }
+
+    #[test]
+    fn either_day_field_matches_when_both_are_restricted() {
+        // Every Friday and every 13th, as in cron; the first Friday is the 5th.
+        assert_eq!(next("0 0 13 * 5", "2024-01-01T00:00:00Z"), Some(at("2024-01-05T00:00:00Z")));
+        assert_eq!(next("0 0 13 * 5", "2024-01-12T00:01:00Z"), Some(at("2024-01-13T00:00:00Z")));
+        // Only one restricted, the other is ignored.
+        assert_eq!(next("0 0 13 * *", "2024-01-01T00:00:00Z"), Some(at("2024-01-13T00:00:00Z")));
+        assert_eq!(next("0 0 * * 5", "2024-01-06T00:00:00Z"), Some(at("2024-01-12T00:00:00Z")));
+        // A stepped `*` still counts as unrestricted: odd days of the month
+        // that also are Fridays.
+        assert_eq!(next("0 0 */2 * 5", "2024-01-01T00:00:00Z"), Some(at("2024-01-05T00:00:00Z")));
+        assert_eq!(next("0 0 */2 * 5", "2024-01-06T00:00:00Z"), Some(at("2024-01-19T00:00:00Z")));
+    }
+
+    #[test]
+    fn months_and_leap_days() {
+        assert_eq!(next("0 0 1 3 *", "2024-03-01T00:01:00Z"), Some(at("2025-03-01T00:00:00Z")));
+        assert_eq!(next("0 0 29 2 *", "2024-03-01T00:00:00Z"), Some(at("2028-02-29T00:00:00Z")));
+        assert_eq!(next("0 0 31 * *", "2024-04-01T00:00:00Z"), Some(at("2024-05-31T00:00:00Z")));
+    }
+
+    #[test]
+    fn impossible_dates_never_start() {
+        assert_eq!(next("0 0 30 2 *", "2024-01-01T00:00:00Z"), None);
+        assert_eq!(windows("0 0 31 4 *", 1).state(at("2024-01-01T00:00:00Z")), State::Closed(None));
+    }
+
+    #[test]
+    fn window_open_from_its_start_until_its_length_passed() {
+        let windows = windows("0 2 * * *", 60);
+        assert_eq!(windows.state(at("2024-01-01T01:00:00Z")), State::Closed(Some(at("2024-01-01T02:00:00Z"))));
+        assert_eq!(windows.state(at("2024-01-01T02:00:00Z")), State::Open(at("2024-01-01T03:00:00Z")));
+        assert_eq!(windows.state(at("2024-01-01T02:59:59Z")), State::Open(at("2024-01-01T03:00:00Z")));
+        assert_eq!(windows.state(at("2024-01-01T03:00:00Z")), State::Closed(Some(at("2024-01-02T02:00:00Z"))));
+    }
+
+    #[test]
+    fn windows_only_merge_where_they_meet() {
+        // 10 minutes each half hour stay apart.
+        let apart = windows("0,30 * * * *", 10);
+        assert_eq!(apart.state(at("2024-01-01T00:05:00Z")), State::Open(at("2024-01-01T00:10:00Z")));
+        assert_eq!(apart.state(at("2024-01-01T00:15:00Z")), State::Closed(Some(at("2024-01-01T00:30:00Z"))));
+        // Windows ending as the next opens are one.
+        let meeting = windows("0 2,3 * * *", 60);
+        assert_eq!(meeting.state(at("2024-01-01T02:30:00Z")), State::Open(at("2024-01-01T04:00:00Z")));
+        assert_eq!(meeting.state(at("2024-01-01T03:30:00Z")), State::Open(at("2024-01-01T04:00:00Z")));
+    }
+
+    #[test]
+    fn merged_windows_are_cut_after_a_day() {
+        // Every minute, each window meeting the next: the last one merged
+        // opens just before a day has passed.
+        let every_minute = windows("* * * * *", 1);
+        assert_eq!(every_minute.state(at("2024-01-01T00:00:30Z")), State::Open(at("2024-01-02T00:00:00Z")));
+        // Overlapping windows of 45 minutes every half hour: the one open at
+        // 00:10 opened at 23:30 the day before, the last merged at 23:00.
+        let overlapping = windows("0,30 * * * *", 45);
+        assert_eq!(overlapping.state(at("2024-01-01T00:10:00Z")), State::Open(at("2024-01-01T23:45:00Z")));
+    }
+}
@@ -0,0 +1,110 @@
+//! Security protocol selection for stream-based transports.
+
+use either::Either;
+use futures::future::{self, MapOk};
+use futures::TryFutureExt;
+use libp2p::core::either::EitherFuture;
+use libp2p::core::upgrade::{InboundConnectionUpgrade, OutboundConnectionUpgrade, UpgradeInfo};
+use libp2p::PeerId;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::iter::{Chain, Map};
+use std::str::FromStr;
+
+/// The security handshake(s) offered on TCP connections.
+///
+/// QUIC always uses its built-in TLS 1.3 and ignores this setting.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SecurityChoice {
+    /// TLS 1.3, the default.
+    #[default]
+    Tls,
+    /// Noise XX, the default of many other libp2p implementations.
+    Noise,
+    /// Offer both, preferring TLS.
+    Both,
+}
+
+impl fmt::Display for SecurityChoice {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SecurityChoice::Tls => f.write_str("tls"),
+            SecurityChoice::Noise => f.write_str("noise"),
+            SecurityChoice::Both => f.write_str("both"),
+        }
+    }
+}
+
+impl FromStr for SecurityChoice {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "tls" => Ok(SecurityChoice::Tls),
+            "noise" => Ok(SecurityChoice::Noise),
+            "both" => Ok(SecurityChoice::Both),
+            other => Err(format!("unknown security `{other}`, expected `tls`, `noise` or `both`")),
+        }
+    }
+}
+
+/// Offers the protocols of two security upgrades, preferring the first one.
+///
+/// Unlike [`libp2p::core::upgrade::SelectUpgrade`] the output keeps the
+/// `(PeerId, stream)` shape required by `authenticate`.
+#[derive(Debug, Clone)]
+pub(crate) struct SelectSecurity<A, B>(pub A, pub B);
+
+impl<A: UpgradeInfo, B: UpgradeInfo> UpgradeInfo for SelectSecurity<A, B> {
+    type Info = Either<A::Info, B::Info>;
+    type InfoIter = Chain<
+        Map<<A::InfoIter as IntoIterator>::IntoIter, fn(A::Info) -> Self::Info>,
+        Map<<B::InfoIter as IntoIterator>::IntoIter, fn(B::Info) -> Self::Info>,
+    >;
+
+    fn protocol_info(&self) -> Self::InfoIter {
+        let a = self.0.protocol_info().into_iter().map(Either::Left as fn(A::Info) -> _);
+        let b = self.1.protocol_info().into_iter().map(Either::Right as fn(B::Info) -> _);
+        a.chain(b)
+    }
+}
+
+/// Flattens the peer id out of whichever handshake completed.
+type Factored<TA, TB> = fn(future::Either<(PeerId, TA), (PeerId, TB)>) -> (PeerId, future::Either<TA, TB>);
+
+impl<C, A, B, TA, TB> InboundConnectionUpgrade<C> for SelectSecurity<A, B>
+where
+    A: InboundConnectionUpgrade<C, Output = (PeerId, TA)>,
+    B: InboundConnectionUpgrade<C, Output = (PeerId, TB)>,
+{
+    type Output = (PeerId, future::Either<TA, TB>);
+    type Error = Either<A::Error, B::Error>;
+    type Future = MapOk<EitherFuture<A::Future, B::Future>, Factored<TA, TB>>;
+
+    fn upgrade_inbound(self, socket: C, info: Self::Info) -> Self::Future {
+        match info {
+            Either::Left(info) => EitherFuture::First(self.0.upgrade_inbound(socket, info)),
+            Either::Right(info) => EitherFuture::Second(self.1.upgrade_inbound(socket, info)),
+        }
+        .map_ok(future::Either::factor_first as Factored<TA, TB>)
+    }
+}
+
+impl<C, A, B, TA, TB> OutboundConnectionUpgrade<C> for SelectSecurity<A, B>
+where
+    A: OutboundConnectionUpgrade<C, Output = (PeerId, TA)>,
+    B: OutboundConnectionUpgrade<C, Output = (PeerId, TB)>,
+{
+    type Output = (PeerId, future::Either<TA, TB>);
+    type Error = Either<A::Error, B::Error>;
+    type Future = MapOk<EitherFuture<A::Future, B::Future>, Factored<TA, TB>>;
+
+    fn upgrade_outbound(self, socket: C, info: Self::Info) -> Self::Future {
+        match info {
+            Either::Left(info) => EitherFuture::First(self.0.upgrade_outbound(socket, info)),
+            Either::Right(info) => EitherFuture::Second(self.1.upgrade_outbound(socket, info)),
+        }
+        .map_ok(future::Either::factor_first as Factored<TA, TB>)
+    }
+}
@@ -0,0 +1,121 @@
+//! Virtual peers within this process that answer pings with artificial
+//! latency, jitter and loss, for the `simulate` subcommand, which checks that
+//! the statistics report them.
+
+use libp2p::swarm::SwarmEvent;
+use libp2p::{ping, PeerId};
+use libp2p_ping_tut::testing::{Impairment, Network};
+use libp2p_ping_tut::{BehaviourEvent, NodeConfig, PingStats};
+use std::collections::HashMap;
+use std::error::Error;
+use std::time::Duration;
+
+/// Ping interval unless `--interval` is given, short to keep runs quick.
+pub const INTERVAL: Duration = Duration::from_millis(50);
+
+/// Time added to each RTT by the memory transport and the runtime, which the
+/// checks of the RTT allow for.
+const OVERHEAD_MS: f64 = 5.0;
+
+/// A statistic compared with the value the virtual peers simulate.
+pub struct Check {
+    /// `loss_percent`, `rtt_avg_ms` or `rtt_mdev_ms`.
+    pub metric: &'static str,
+    pub expected: f64,
+    pub measured: f64,
+    /// How far `measured` may be from `expected`.
+    pub tolerance: f64,
+}
+
+impl Check {
+    pub fn passed(&self) -> bool {
+        (self.measured - self.expected).abs() <= self.tolerance
+    }
+}
+
+/// The results of pinging one virtual peer.
+pub struct Outcome {
+    pub peer_id: PeerId,
+    pub stats: PingStats,
+    pub checks: Vec<Check>,
+}
+
+/// Starts `peers` virtual peers answering pings under `impairment`, each with
+/// its own seed derived from that of `impairment`, and a node with `config`
+/// pinging each of them `count` times.
+pub async fn run(config: NodeConfig, impairment: Impairment, peers: usize, count: u64) -> Result<Vec<Outcome>, Box<dyn Error>> {
+    let impaired = (0..peers).map(|index| NodeConfig {
+        impairment: Some(Impairment { seed: impairment.seed.wrapping_add(index as u64), ..impairment }),
+        ..config.clone()
+    });
+    let mut network = Network::with_configs(std::iter::once(config.clone()).chain(impaired)).await?;
+    for index in 1..=peers {
+        network.connect(0, index)?;
+    }
+
+    let mut results: HashMap<PeerId, PingStats> =
+        (1..=peers).map(|index| (network.peer_id(index), PingStats::default())).collect();
+    while results.values().any(|stats| stats.transmitted() < count) {
+        let (peer, rtt) = network
+            .wait_for(0, |event| match event {
+                SwarmEvent::Behaviour(BehaviourEvent::Ping(ping::Event { peer, result, .. })) => {
+                    Some((*peer, result.as_ref().ok().copied()))
+                }
+                _ => None,
+            })
+            .await?;
+        if let Some(stats) = results.get_mut(&peer).filter(|stats| stats.transmitted() < count) {
+            match rtt {
+                Some(rtt) => stats.record_success(rtt),
+                None => stats.record_failure(),
+            }
+        }
+    }
+
+    Ok((1..=peers)
+        .map(|index| {
+            let peer_id = network.peer_id(index);
+            let stats = results.remove(&peer_id).expect("every peer has results");
+            Outcome { peer_id, checks: checks(&impairment, &stats), stats }
+        })
+        .collect())
+}
+
+/// Compares the loss and RTTs in `stats` with those `impairment` simulates.
+///
+/// The tolerances allow for three standard deviations of each statistic,
+/// assuming a latency of at least the jitter so that no delays are cut off at
+/// zero.
+fn checks(impairment: &Impairment, stats: &PingStats) -> Vec<Check> {
+    let transmitted = stats.transmitted() as f64;
+    let loss = impairment.loss;
+    let mut checks = vec![Check {
+        metric: "loss_percent",
+        expected: loss * 100.0,
+        measured: stats.loss_percent(),
+        // Plus a single ping, for the rounding of small counts.
+        tolerance: 300.0 * (loss * (1.0 - loss) / transmitted).sqrt() + 100.0 / transmitted,
+    }];
+    if let (Some(avg), Some(mdev)) = (stats.avg(), stats.mdev()) {
+        let received = stats.received() as f64;
+        // Delays uniform within ±jitter have a standard deviation of jitter/√3.
+        let deviation = millis(impairment.jitter) / 3f64.sqrt();
+        checks.push(Check {
+            metric: "rtt_avg_ms",
+            expected: millis(impairment.latency),
+            measured: millis(avg),
+            tolerance: 3.0 * deviation / received.sqrt() + OVERHEAD_MS,
+        });
+        checks.push(Check {
+            metric: "rtt_mdev_ms",
+            expected: deviation,
+            measured: millis(mdev),
+            tolerance: 3.0 * deviation / (2.0 * received).sqrt() + OVERHEAD_MS,
+        });
+    }
+    checks
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
@@ -0,0 +1,172 @@
+//! Tunneling TCP dials through a SOCKS5 proxy (RFC 1928), e.g. Tor, to ping
+//! from behind networks that only let a proxy out.
+
+use std::fmt;
+use std::io;
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::str::FromStr;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+const VERSION: u8 = 5;
+const NO_AUTHENTICATION: u8 = 0;
+const USERNAME_PASSWORD: u8 = 2;
+const NO_ACCEPTABLE_METHOD: u8 = 0xff;
+const CONNECT: u8 = 1;
+const IPV4: u8 = 1;
+const DOMAIN_NAME: u8 = 3;
+const IPV6: u8 = 4;
+
+/// A SOCKS5 proxy all TCP and WebSocket dials go through, given as
+/// `socks5://[user:password@]host:port`, or as `socks5h://...` to have the
+/// proxy resolve DNS names, which Tor needs for `.onion` addresses and to
+/// keep lookups from leaking.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Socks5Proxy {
+    pub addr: SocketAddr,
+    /// Whether DNS names are sent to the proxy rather than resolved locally.
+    pub remote_dns: bool,
+    /// User name and password to authenticate with (RFC 1929).
+    pub credentials: Option<(String, String)>,
+}
+
+impl FromStr for Socks5Proxy {
+    type Err = String;
+
+    /// Parses the proxy URL, resolving the host of the proxy right away.
+    fn from_str(s: &str) -> Result<Self, String> {
+        let (remote_dns, rest) = match s.split_once("://") {
+            Some(("socks5", rest)) => (false, rest),
+            Some(("socks5h", rest)) => (true, rest),
+            _ => return Err(format!("proxy `{s}` must be a socks5:// or socks5h:// URL")),
+        };
+        let rest = rest.trim_end_matches('/');
+        let (credentials, host) = match rest.rsplit_once('@') {
+            Some((credentials, host)) => {
+                let (user, password) = credentials.split_once(':').unwrap_or((credentials, ""));
+                if user.len() > 255 || password.len() > 255 {
+                    return Err("proxy user name and password must be at most 255 bytes each".into());
+                }
+                (Some((user.to_owned(), password.to_owned())), host)
+            }
+            None => (None, rest),
+        };
+        let addr = host
+            .to_socket_addrs()
+            .map_err(|e| format!("proxy `{host}` must be HOST:PORT: {e}"))?
+            .next()
+            .ok_or_else(|| format!("proxy `{host}` resolves to no address"))?;
+        Ok(Self { addr, remote_dns, credentials })
+    }
+}
+
+impl fmt::Debug for Socks5Proxy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Socks5Proxy")
+            .field("addr", &self.addr)
+            .field("remote_dns", &self.remote_dns)
+            .field("user", &self.credentials.as_ref().map(|(user, _)| user))
+            .finish_non_exhaustive()
+    }
+}
+
+/// Where a proxy is asked to connect to.
+#[derive(Debug, Clone)]
+pub(crate) enum Destination {
+    Addr(SocketAddr),
+    /// A DNS name for the proxy to resolve, and a port.
+    Name(String, u16),
+}
+
+/// Asks the proxy at the other end of `stream` to connect to `destination`,
+/// after which the stream carries the connection.
+pub(crate) async fn connect(stream: &mut TcpStream, proxy: &Socks5Proxy, destination: &Destination) -> io::Result<()> {
+    let method = if proxy.credentials.is_some() { USERNAME_PASSWORD } else { NO_AUTHENTICATION };
+    stream.write_all(&[VERSION, 1, method]).await?;
+    let mut choice = [0; 2];
+    stream.read_exact(&mut choice).await?;
+    match choice {
+        [VERSION, chosen] if chosen == method => {}
+        [VERSION, NO_ACCEPTABLE_METHOD] if proxy.credentials.is_some() => {
+            return Err(refused("proxy doesn't accept user name and password"));
+        }
+        [VERSION, NO_ACCEPTABLE_METHOD] => return Err(refused("proxy requires authentication")),
+        _ => return Err(invalid("not a SOCKS5 proxy")),
+    }
+    if let Some((user, password)) = &proxy.credentials {
+        let mut request = vec![1, user.len() as u8];
+        request.extend_from_slice(user.as_bytes());
+        request.push(password.len() as u8);
+        request.extend_from_slice(password.as_bytes());
+        stream.write_all(&request).await?;
+        let mut status = [0; 2];
+        stream.read_exact(&mut status).await?;
+        if status[1] != 0 {
+            return Err(refused("proxy rejected the user name and password"));
+        }
+    }
+
+    let mut request = vec![VERSION, CONNECT, 0];
+    let port = match destination {
+        Destination::Addr(SocketAddr::V4(addr)) => {
+            request.push(IPV4);
+            request.extend_from_slice(&addr.ip().octets());
+            addr.port()
+        }
+        Destination::Addr(SocketAddr::V6(addr)) => {
+            request.push(IPV6);
+            request.extend_from_slice(&addr.ip().octets());
+            addr.port()
+        }
+        Destination::Name(name, port) => {
+            let len = u8::try_from(name.len()).map_err(|_| invalid("DNS name too long for the proxy"))?;
+            request.extend_from_slice(&[DOMAIN_NAME, len]);
+            request.extend_from_slice(name.as_bytes());
+            *port
+        }
+    };
+    request.extend_from_slice(&port.to_be_bytes());
+    stream.write_all(&request).await?;
+
+    let mut reply = [0; 4];
+    stream.read_exact(&mut reply).await?;
+    if reply[0] != VERSION {
+        return Err(invalid("not a SOCKS5 proxy"));
+    }
+    if reply[1] != 0 {
+        return Err(reply_error(reply[1]));
+    }
+    // The address the proxy connected from, which we don't need.
+    let len = match reply[3] {
+        IPV4 => 4,
+        IPV6 => 16,
+        DOMAIN_NAME => stream.read_u8().await? as usize,
+        _ => return Err(invalid("proxy answered with an unknown address type")),
+    };
+    let mut bound = vec![0; len + 2];
+    stream.read_exact(&mut bound).await?;
+    Ok(())
+}
+
+/// The error for a reply code other than success.
+fn reply_error(code: u8) -> io::Error {
+    let (kind, message) = match code {
+        2 => (io::ErrorKind::PermissionDenied, "connection not allowed by the proxy's rules"),
+        3 => (io::ErrorKind::NetworkUnreachable, "network unreachable from the proxy"),
+        4 => (io::ErrorKind::HostUnreachable, "host unreachable from the proxy"),
+        5 => (io::ErrorKind::ConnectionRefused, "connection refused by the destination"),
+        6 => (io::ErrorKind::TimedOut, "proxy timed out connecting"),
+        7 => (io::ErrorKind::Unsupported, "proxy doesn't support connecting"),
+        8 => (io::ErrorKind::Unsupported, "proxy doesn't support the address type"),
+        _ => (io::ErrorKind::Other, "proxy failed to connect"),
+    };
+    io::Error::new(kind, format!("SOCKS5: {message}"))
+}
+
+fn refused(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::PermissionDenied, format!("SOCKS5: {message}"))
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("SOCKS5: {message}"))
+}
@@ -0,0 +1,163 @@
+//! Tracing spans following each connection from dial to close, with a child
+//! span per ping.
+//!
+//! Run with `RUST_LOG=libp2p_ping_tut=debug` (or `trace` for libp2p's own
+//! diagnostics as well) to correlate every ping with the dial and upgrade of the
+//! connection it was sent on. Each event names what happened in an `event`
+//! field, for filtering the JSON logs of `--log-format json`.
+
+use libp2p::multiaddr::Protocol;
+use libp2p::swarm::{ConnectionId, SwarmEvent};
+use libp2p::{ping, Multiaddr, PeerId};
+use std::collections::HashMap;
+use tracing::field::{display, Empty};
+use tracing::Span;
+
+use crate::BehaviourEvent;
+
+/// The span of one connection and the number of pings sent on it so far.
+struct ConnectionSpan {
+    span: Span,
+    /// Whether the remote address has been recorded already; formatters
+    /// repeat fields recorded twice.
+    has_address: bool,
+    transport: &'static str,
+    pings: u64,
+}
+
+/// Spans of all pending and established connections.
+#[derive(Default)]
+pub(crate) struct ConnectionSpans {
+    connections: HashMap<ConnectionId, ConnectionSpan>,
+}
+
+impl ConnectionSpans {
+    /// Opens the span of a connection being dialed.
+    ///
+    /// The swarm only reports dials started by a behaviour, so the node calls
+    /// this for its own dials.
+    pub(crate) fn dialing(&mut self, connection_id: ConnectionId, peer_id: Option<PeerId>, address: Option<&Multiaddr>) {
+        let connection = self.open(connection_id, "outbound");
+        if let Some(peer_id) = peer_id {
+            connection.span.record("peer_id", display(peer_id));
+        }
+        if let Some(address) = address {
+            connection.record_address(address);
+        }
+        tracing::debug!(parent: &connection.span, event = "dialing", "dialing");
+    }
+
+    /// Opens, updates or closes spans according to a swarm event.
+    pub(crate) fn observe(&mut self, event: &SwarmEvent<BehaviourEvent>) {
+        match event {
+            SwarmEvent::Dialing { peer_id, connection_id } => self.dialing(*connection_id, *peer_id, None),
+            SwarmEvent::IncomingConnection { connection_id, send_back_addr, .. } => {
+                let connection = self.open(*connection_id, "inbound");
+                connection.record_address(send_back_addr);
+                tracing::debug!(parent: &connection.span, event = "incoming_connection", "incoming connection");
+            }
+            SwarmEvent::ConnectionEstablished { peer_id, connection_id, endpoint, established_in, .. } => {
+                let direction = if endpoint.is_dialer() { "outbound" } else { "inbound" };
+                let address = endpoint.get_remote_address();
+                let connection = self.open(*connection_id, direction);
+                connection.transport = transport(address);
+                connection.span.record("peer_id", display(peer_id));
+                connection.record_address(address);
+                connection.span.record("transport", connection.transport);
+                tracing::debug!(
+                    parent: &connection.span,
+                    event = "connection_established",
+                    established_in_us = established_in.as_micros() as u64,
+                    "connection established"
+                );
+            }
+            SwarmEvent::OutgoingConnectionError { connection_id, error, .. } => {
+                if let Some(connection) = self.connections.remove(connection_id) {
+                    tracing::debug!(parent: &connection.span, event = "dial_failed", %error, "dial failed");
+                }
+            }
+            SwarmEvent::IncomingConnectionError { connection_id, error, .. } => {
+                if let Some(connection) = self.connections.remove(connection_id) {
+                    tracing::debug!(parent: &connection.span, event = "upgrade_failed", %error, "upgrade failed");
+                }
+            }
+            SwarmEvent::ConnectionClosed { connection_id, cause, .. } => {
+                if let Some(connection) = self.connections.remove(connection_id) {
+                    match cause {
+                        Some(cause) => {
+                            tracing::debug!(parent: &connection.span, event = "connection_closed", %cause, "connection closed");
+                        }
+                        None => tracing::debug!(parent: &connection.span, event = "connection_closed", "connection closed"),
+                    }
+                }
+            }
+            SwarmEvent::Behaviour(BehaviourEvent::Ping(ping::Event { peer, connection, result })) => {
+                let Some(connection) = self.connections.get_mut(connection) else {
+                    return;
+                };
+                connection.pings += 1;
+                let span = tracing::debug_span!(
+                    parent: &connection.span,
+                    "ping",
+                    peer_id = %peer,
+                    seq = connection.pings,
+                    transport = connection.transport,
+                    outcome = Empty,
+                );
+                match result {
+                    Ok(rtt) => {
+                        span.record("outcome", "success");
+                        tracing::debug!(parent: &span, event = "pong", rtt_us = rtt.as_micros() as u64, "pong received");
+                    }
+                    Err(error) => {
+                        span.record("outcome", "failure");
+                        tracing::debug!(parent: &span, event = "ping_failed", %error, "ping failed");
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Returns the span of a connection, opening it if it is not known yet.
+    fn open(&mut self, connection_id: ConnectionId, direction: &'static str) -> &mut ConnectionSpan {
+        self.connections.entry(connection_id).or_insert_with(|| ConnectionSpan {
+            span: tracing::debug_span!(
+                "connection",
+                id = %connection_id,
+                direction,
+                peer_id = Empty,
+                address = Empty,
+                transport = Empty,
+            ),
+            has_address: false,
+            transport: "unknown",
+            pings: 0,
+        })
+    }
+}
+
+impl ConnectionSpan {
+    fn record_address(&mut self, address: &Multiaddr) {
+        if !self.has_address {
+            self.span.record("address", display(address));
+            self.has_address = true;
+        }
+    }
+}
+
+/// Names the transport of a connection to `addr` for span fields.
+fn transport(addr: &Multiaddr) -> &'static str {
+    let mut transport = "unknown";
+    for protocol in addr.iter() {
+        transport = match protocol {
+            Protocol::P2pCircuit => return "relay",
+            Protocol::Tcp(_) => "tcp",
+            Protocol::QuicV1 => "quic",
+            Protocol::Ws(_) => "ws",
+            Protocol::Wss(_) => "wss",
+            _ => continue,
+        };
+    }
+    transport
+}
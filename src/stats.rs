@@ -0,0 +1,329 @@
+//! Round-trip time statistics.
+
+use hdrhistogram::Histogram;
+use std::fmt::{Display, Write};
+use std::time::Duration;
+
+/// Significant decimal digits kept of each RTT in the histogram.
+const HISTOGRAM_PRECISION: u8 = 2;
+
+/// Upper bound of the first bucket of [`PingStats::histogram`]; each further
+/// bucket doubles it.
+const FIRST_BUCKET: Duration = Duration::from_micros(100);
+
+/// Percentiles shown in the report.
+const REPORTED_PERCENTILES: [f64; 4] = [50.0, 90.0, 99.0, 99.9];
+
+/// Aggregated results of the pings sent to a single target.
+///
+/// Besides running sums, RTTs are counted in an HDR histogram with buckets
+/// growing logarithmically, so memory use stays bounded no matter how long
+/// the node runs.
+#[derive(Debug, Clone)]
+pub struct PingStats {
+    transmitted: u64,
+    received: u64,
+    /// Answered warm-up pings, received but left out of the RTTs.
+    warmups: u64,
+    min: Option<Duration>,
+    max: Option<Duration>,
+    /// Sum of all RTTs in seconds.
+    sum: f64,
+    /// Sum of all squared RTTs in seconds², used for the standard deviation.
+    sum_sq: f64,
+    /// RTTs in microseconds.
+    histogram: Histogram<u64>,
+    /// RTT of the last answered ping, to compare the next one with.
+    last: Option<Duration>,
+    /// Sum of the differences between consecutive RTTs in seconds.
+    jitter_sum: f64,
+}
+
+impl Default for PingStats {
+    fn default() -> Self {
+        Self {
+            transmitted: 0,
+            received: 0,
+            warmups: 0,
+            min: None,
+            max: None,
+            sum: 0.0,
+            sum_sq: 0.0,
+            histogram: Histogram::new(HISTOGRAM_PRECISION).expect("precision is between 0 and 5"),
+            last: None,
+            jitter_sum: 0.0,
+        }
+    }
+}
+
+impl PingStats {
+    /// Records a successful round-trip.
+    pub fn record_success(&mut self, rtt: Duration) {
+        self.transmitted += 1;
+        self.received += 1;
+        self.min = Some(self.min.map_or(rtt, |min| min.min(rtt)));
+        self.max = Some(self.max.map_or(rtt, |max| max.max(rtt)));
+        let secs = rtt.as_secs_f64();
+        self.sum += secs;
+        self.sum_sq += secs * secs;
+        self.histogram
+            .record(rtt.as_micros() as u64)
+            .expect("the histogram grows to fit every RTT");
+        if let Some(last) = self.last.replace(rtt) {
+            self.jitter_sum += rtt.abs_diff(last).as_secs_f64();
+        }
+    }
+
+    /// Records a successful warm-up round-trip: it counts as received, but its
+    /// RTT is left out of min/avg/max, the percentiles and the jitter.
+    pub fn record_warmup(&mut self) {
+        self.transmitted += 1;
+        self.received += 1;
+        self.warmups += 1;
+    }
+
+    /// Records a ping that failed or timed out.
+    pub fn record_failure(&mut self) {
+        self.transmitted += 1;
+    }
+
+    /// Number of pings sent.
+    pub fn transmitted(&self) -> u64 {
+        self.transmitted
+    }
+
+    /// Number of pings answered.
+    pub fn received(&self) -> u64 {
+        self.received
+    }
+
+    /// Number of answered pings whose RTTs are in the statistics, i.e. not
+    /// warm-ups.
+    pub fn sampled(&self) -> u64 {
+        self.received - self.warmups
+    }
+
+    /// Percentage of pings that were not answered; `0.0` if none were sent.
+    pub fn loss_percent(&self) -> f64 {
+        if self.transmitted == 0 {
+            return 0.0;
+        }
+        (self.transmitted - self.received) as f64 * 100.0 / self.transmitted as f64
+    }
+
+    /// Fastest round-trip, if any ping succeeded.
+    pub fn min(&self) -> Option<Duration> {
+        self.min
+    }
+
+    /// Slowest round-trip, if any ping succeeded.
+    pub fn max(&self) -> Option<Duration> {
+        self.max
+    }
+
+    /// Mean round-trip, if any ping succeeded.
+    pub fn avg(&self) -> Option<Duration> {
+        (self.sampled() > 0).then(|| Duration::from_secs_f64(self.sum / self.sampled() as f64))
+    }
+
+    /// Standard deviation of the round-trips (`mdev` in classic ping), if any
+    /// ping succeeded.
+    pub fn mdev(&self) -> Option<Duration> {
+        (self.sampled() > 0).then(|| {
+            let n = self.sampled() as f64;
+            let mean = self.sum / n;
+            Duration::from_secs_f64((self.sum_sq / n - mean * mean).max(0.0).sqrt())
+        })
+    }
+
+    /// Mean difference between the RTTs of consecutive answered pings, as
+    /// shown by MTR, if at least two pings succeeded.
+    pub fn jitter(&self) -> Option<Duration> {
+        (self.sampled() > 1).then(|| Duration::from_secs_f64(self.jitter_sum / (self.sampled() - 1) as f64))
+    }
+
+    /// RTT below which `percent` of the answered pings fall, e.g. `99.9`, if
+    /// any ping succeeded. Accurate to 1%.
+    pub fn percentile(&self, percent: f64) -> Option<Duration> {
+        // Values are rounded up to their bucket, which may overshoot the maximum.
+        let rtt = Duration::from_micros(self.histogram.value_at_quantile(percent / 100.0));
+        self.max.map(|max| rtt.min(max))
+    }
+
+    /// Counts of answered pings in buckets up to just below 100 µs and each
+    /// doubling of it, e.g. 99 µs and 199 µs inclusive, up to the slowest RTT.
+    pub fn histogram(&self) -> Vec<(Duration, u64)> {
+        if self.sampled() == 0 {
+            return Vec::new();
+        }
+        self.histogram
+            .iter_log(FIRST_BUCKET.as_micros() as u64, 2.0)
+            .map(|bucket| (Duration::from_micros(bucket.value_iterated_to()), bucket.count_since_last_iteration()))
+            .collect()
+    }
+
+    /// Formats a classic `--- <target> ping statistics ---` block.
+    pub fn report(&self, target: impl Display) -> String {
+        let mut out = format!(
+            "--- {target} ping statistics ---\n{} packets transmitted, {} received, {}% packet loss",
+            self.transmitted,
+            self.received,
+            round(self.loss_percent()),
+        );
+        if let Some(rtt) = self.rtt_summary() {
+            let _ = write!(out, "\nrtt {rtt}");
+        }
+        if let Some(percentiles) = self.percentile_summary() {
+            let _ = write!(out, "\nrtt {percentiles}");
+        }
+        if let Some(jitter) = self.jitter() {
+            let _ = write!(out, "\njitter avg = {:.3} ms", millis(jitter));
+        }
+        out
+    }
+
+    /// Formats the `min/avg/max/mdev = ... ms` part of the report, if any ping
+    /// succeeded.
+    pub fn rtt_summary(&self) -> Option<String> {
+        let (Some(min), Some(avg), Some(max), Some(mdev)) = (self.min, self.avg(), self.max, self.mdev()) else {
+            return None;
+        };
+        Some(format!(
+            "min/avg/max/mdev = {:.3}/{:.3}/{:.3}/{:.3} ms",
+            millis(min),
+            millis(avg),
+            millis(max),
+            millis(mdev),
+        ))
+    }
+
+    /// Formats the `p50/p90/p99/p99.9 = ... ms` line of the report, if any
+    /// ping succeeded.
+    pub fn percentile_summary(&self) -> Option<String> {
+        let values = REPORTED_PERCENTILES
+            .iter()
+            .map(|percent| self.percentile(*percent).map(|rtt| format!("{:.3}", millis(rtt))))
+            .collect::<Option<Vec<_>>>()?;
+        Some(format!("p50/p90/p99/p99.9 = {} ms", values.join("/")))
+    }
+}
+
+/// Converts a duration to fractional milliseconds.
+fn millis(d: Duration) -> f64 {
+    d.as_secs_f64() * 1000.0
+}
+
+/// Rounds a percentage to at most one decimal place for display.
+fn round(percent: f64) -> f64 {
+    (percent * 10.0).round() / 10.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    fn stats(rtts: &[Option<u64>]) -> PingStats {
+        let mut stats = PingStats::default();
+        for rtt in rtts {
+            match rtt {
+                Some(rtt) => stats.record_success(ms(*rtt)),
+                None => stats.record_failure(),
+            }
+        }
+        stats
+    }
+
+    #[test]
+    fn empty() {
+        let stats = PingStats::default();
+        assert_eq!((stats.transmitted(), stats.received()), (0, 0));
+        assert_eq!(stats.loss_percent(), 0.0);
+        assert_eq!((stats.min(), stats.avg(), stats.max(), stats.mdev()), (None, None, None, None));
+        assert_eq!(stats.percentile(50.0), None);
+        assert!(stats.histogram().is_empty());
+        assert_eq!(stats.report("peer"), "--- peer ping statistics ---\n0 packets transmitted, 0 received, 0% packet loss");
+    }
+
+    #[test]
+    fn counts_and_loss() {
+        let stats = stats(&[Some(10), None, Some(30), None, None, Some(20)]);
+        assert_eq!((stats.transmitted(), stats.received(), stats.sampled()), (6, 3, 3));
+        assert_eq!(stats.loss_percent(), 50.0);
+    }
+
+    #[test]
+    fn report() {
+        let report = stats(&[Some(10), None, None]).report("berlin");
+        let mut lines = report.lines();
+        assert_eq!(lines.next(), Some("--- berlin ping statistics ---"));
+        assert_eq!(lines.next(), Some("3 packets transmitted, 1 received, 66.7% packet loss"));
+        assert_eq!(lines.next(), Some("rtt min/avg/max/mdev = 10.000/10.000/10.000/0.000 ms"));
+        assert_eq!(lines.next(), Some("rtt p50/p90/p99/p99.9 = 10.000/10.000/10.000/10.000 ms"));
+        assert_eq!(lines.next(), None);
+    }
+
+    #[test]
+    fn min_avg_max_mdev() {
+        let stats = stats(&[Some(10), Some(30), Some(20)]);
+        assert_eq!((stats.min(), stats.max()), (Some(ms(10)), Some(ms(30))));
+        assert_eq!(stats.avg(), Some(ms(20)));
+        // The population standard deviation of 10, 20 and 30 is √(200/3).
+        let mdev = stats.mdev().unwrap().as_secs_f64() * 1000.0;
+        assert!((mdev - (200.0f64 / 3.0).sqrt()).abs() < 1e-6, "{mdev}");
+        assert_eq!(stats.rtt_summary().unwrap(), "min/avg/max/mdev = 10.000/20.000/30.000/8.165 ms");
+    }
+
+    #[test]
+    fn jitter_between_consecutive_answers() {
+        assert_eq!(stats(&[Some(10)]).jitter(), None);
+        // |30 - 10| and |20 - 30|; failures in between don't reset the last RTT.
+        assert_eq!(stats(&[Some(10), None, Some(30), Some(20)]).jitter(), Some(ms(15)));
+    }
+
+    #[test]
+    fn percentiles() {
+        let stats = stats(&(1..=100).map(Some).collect::<Vec<_>>());
+        // Accurate to the histogram's precision, and never above the maximum.
+        let p50 = stats.percentile(50.0).unwrap();
+        assert!(p50.abs_diff(ms(50)) <= ms(1), "{p50:?}");
+        assert_eq!(stats.percentile(100.0), Some(ms(100)));
+        assert_eq!(stats.percentile(99.9), Some(ms(100)));
+        assert!(stats.percentile_summary().unwrap().starts_with("p50/p90/p99/p99.9 = "));
+    }
+
+    #[test]
+    fn histogram_buckets_double() {
+        let stats = stats(&[Some(1), Some(1), Some(3)]);
+        let buckets = stats.histogram();
+        let bounds: Vec<_> = buckets.iter().map(|(bound, _)| bound.as_micros()).collect();
+        assert_eq!(bounds, [99, 199, 399, 799, 1599, 3199]);
+        assert_eq!(buckets.iter().map(|(_, count)| *count).collect::<Vec<_>>(), [0, 0, 0, 0, 2, 1]);
+    }
+
+    #[test]
+    fn warmups_count_as_received_without_their_rtts() {
+        let mut stats = stats(&[None]);
+        stats.record_warmup();
+        stats.record_success(ms(10));
+        stats.record_success(ms(20));
+        assert_eq!((stats.transmitted(), stats.received(), stats.sampled()), (4, 3, 2));
+        assert_eq!(stats.loss_percent(), 25.0);
+        assert_eq!((stats.min(), stats.avg(), stats.max()), (Some(ms(10)), Some(ms(15)), Some(ms(20))));
+        assert_eq!(stats.jitter(), Some(ms(10)));
+        assert_eq!(stats.histogram().iter().map(|(_, count)| count).sum::<u64>(), 2);
+    }
+
+    #[test]
+    fn only_warmups() {
+        let mut stats = PingStats::default();
+        stats.record_warmup();
+        assert_eq!((stats.received(), stats.sampled()), (1, 0));
+        assert_eq!((stats.avg(), stats.mdev(), stats.percentile(50.0)), (None, None, None));
+        assert!(stats.histogram().is_empty());
+        assert_eq!(stats.rtt_summary(), None);
+    }
+}
@@ -0,0 +1,239 @@
+//! History of ping results in SQLite (`--store`), and the `report` computed
+//! from it, including the uptime of each peer for `report --sla`.
+
+use libp2p::{ping, PeerId};
+use rusqlite::{params, Connection, OpenFlags};
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Tables and indexes, created when a store is first opened.
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS samples (
+    timestamp_us INTEGER NOT NULL, -- microseconds since the Unix epoch
+    peer_id TEXT NOT NULL,
+    relayed INTEGER NOT NULL,
+    outcome TEXT NOT NULL,         -- 'success', 'failure', or 'unreachable' if not even connected
+    rtt_us INTEGER,                -- NULL for failures
+    error TEXT                     -- NULL for successes
+);
+CREATE INDEX IF NOT EXISTS samples_by_time ON samples (timestamp_us);
+";
+
+/// Gaps this long without results, e.g. while no node was running, count as
+/// neither up nor down.
+const MAX_GAP: Duration = Duration::from_secs(60 * 60);
+
+/// A SQLite database holding every ping result.
+pub struct Store {
+    conn: Connection,
+    path: PathBuf,
+}
+
+impl Store {
+    /// Opens the store at `path`, creating it if it doesn't exist.
+    pub fn open(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let conn = Connection::open(path).map_err(|e| format!("{}: {e}", path.display()))?;
+        // Write-ahead logging lets `report` read while a node is writing.
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        conn.execute_batch(SCHEMA)?;
+        Ok(Self { conn, path: path.to_owned() })
+    }
+
+    /// Opens an existing store at `path` for reading.
+    pub fn open_existing(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let conn = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)
+            .map_err(|e| format!("{}: {e}", path.display()))?;
+        Ok(Self { conn, path: path.to_owned() })
+    }
+
+    /// The file the store is kept in.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Stores the result of a ping to `peer` over a relayed or direct connection.
+    pub fn record(&self, peer: &PeerId, relayed: bool, result: &Result<Duration, ping::Failure>) -> rusqlite::Result<()> {
+        let (outcome, rtt_us, error) = match result {
+            Ok(rtt) => ("success", Some(rtt.as_micros() as i64), None),
+            Err(e) => ("failure", None, Some(e.to_string())),
+        };
+        self.conn.execute(
+            "INSERT INTO samples (timestamp_us, peer_id, relayed, outcome, rtt_us, error) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![micros_since_epoch(SystemTime::now()), peer.to_string(), relayed, outcome, rtt_us, error],
+        )?;
+        Ok(())
+    }
+
+    /// Stores that `peer` couldn't be pinged at all because the connection to
+    /// it was lost or couldn't be established.
+    pub fn record_unreachable(&self, peer: &PeerId, error: &str) -> rusqlite::Result<()> {
+        self.conn.execute(
+            "INSERT INTO samples (timestamp_us, peer_id, relayed, outcome, error) VALUES (?1, ?2, 0, 'unreachable', ?3)",
+            params![micros_since_epoch(SystemTime::now()), peer.to_string(), error],
+        )?;
+        Ok(())
+    }
+
+    /// Summarizes the results of each peer between `from` and `to`, or over the
+    /// whole history if unset.
+    pub fn report(&self, from: Option<SystemTime>, to: Option<SystemTime>) -> rusqlite::Result<Vec<PeerReport>> {
+        let mut statement = self.conn.prepare(
+            "SELECT peer_id, rtt_us FROM samples
+             WHERE timestamp_us >= ?1 AND timestamp_us <= ?2 AND outcome != 'unreachable' ORDER BY peer_id",
+        )?;
+        let from = from.map_or(i64::MIN, micros_since_epoch);
+        let to = to.map_or(i64::MAX, micros_since_epoch);
+        let mut reports: BTreeMap<String, PeerReport> = BTreeMap::new();
+        let mut rows = statement.query(params![from, to])?;
+        while let Some(row) = rows.next()? {
+            let peer_id: String = row.get(0)?;
+            let rtt_us: Option<i64> = row.get(1)?;
+            let report = reports.entry(peer_id.clone()).or_insert_with(|| PeerReport {
+                peer_id,
+                pings: 0,
+                rtts: Vec::new(),
+            });
+            report.pings += 1;
+            if let Some(us) = rtt_us {
+                report.rtts.push(Duration::from_micros(us as u64));
+            }
+        }
+        Ok(reports
+            .into_values()
+            .map(|mut report| {
+                report.rtts.sort();
+                report
+            })
+            .collect())
+    }
+
+    /// Works out how long each peer was up and down between `from` and `to`,
+    /// or over the whole history if unset.
+    ///
+    /// A peer is down from a failed ping, or from losing the connection to it,
+    /// until the next answered ping.
+    pub fn sla(&self, from: Option<SystemTime>, to: Option<SystemTime>) -> rusqlite::Result<Vec<PeerSla>> {
+        let mut statement = self.conn.prepare(
+            "SELECT peer_id, timestamp_us, outcome = 'success' FROM samples
+             WHERE timestamp_us >= ?1 AND timestamp_us <= ?2 ORDER BY peer_id, timestamp_us",
+        )?;
+        let from = from.map_or(i64::MIN, micros_since_epoch);
+        let to = to.map_or(i64::MAX, micros_since_epoch);
+        let mut slas: Vec<PeerSla> = Vec::new();
+        // Time and outcome of the previous sample of the current peer.
+        let mut previous: Option<(i64, bool)> = None;
+        let mut rows = statement.query(params![from, to])?;
+        while let Some(row) = rows.next()? {
+            let peer_id: String = row.get(0)?;
+            let timestamp_us: i64 = row.get(1)?;
+            let success: bool = row.get(2)?;
+            if slas.last().is_none_or(|sla| sla.peer_id != peer_id) {
+                slas.push(PeerSla::new(peer_id));
+                previous = None;
+            }
+            let sla = slas.last_mut().expect("pushed above");
+            if let Some((previous_us, previous_success)) = previous {
+                let gap = Duration::from_micros(timestamp_us.saturating_sub(previous_us) as u64);
+                if gap <= MAX_GAP {
+                    sla.observed += gap;
+                    if !previous_success {
+                        sla.downtime += gap;
+                        *sla.outage.get_or_insert(Duration::ZERO) += gap;
+                    }
+                }
+                if success || gap > MAX_GAP {
+                    sla.outages.extend(sla.outage.take());
+                }
+            }
+            if !success {
+                sla.outage.get_or_insert(Duration::ZERO);
+            }
+            previous = Some((timestamp_us, success));
+        }
+        Ok(slas)
+    }
+}
+
+/// Uptime of one peer over a time window.
+#[derive(Debug)]
+pub struct PeerSla {
+    pub peer_id: String,
+    /// Time covered by results.
+    pub observed: Duration,
+    /// Part of `observed` the peer was down for.
+    pub downtime: Duration,
+    /// Durations of the outages that ended.
+    outages: Vec<Duration>,
+    /// The outage still going on at the end of the window, if any.
+    outage: Option<Duration>,
+}
+
+impl PeerSla {
+    fn new(peer_id: String) -> Self {
+        Self { peer_id, observed: Duration::ZERO, downtime: Duration::ZERO, outages: Vec::new(), outage: None }
+    }
+
+    /// Percentage of the observed time the peer was up.
+    pub fn availability_percent(&self) -> f64 {
+        if self.observed.is_zero() {
+            return if self.outage.is_some() { 0.0 } else { 100.0 };
+        }
+        100.0 - self.downtime.as_secs_f64() * 100.0 / self.observed.as_secs_f64()
+    }
+
+    /// Number of outages, including one still going on.
+    pub fn outages(&self) -> usize {
+        self.outages.len() + usize::from(self.outage.is_some())
+    }
+
+    /// Mean time to repair: the average duration of the outages that ended.
+    pub fn mttr(&self) -> Option<Duration> {
+        let repaired = u32::try_from(self.outages.len()).ok().filter(|&n| n > 0)?;
+        Some(self.outages.iter().sum::<Duration>() / repaired)
+    }
+
+    /// The longest outage, including one still going on.
+    pub fn longest_outage(&self) -> Option<Duration> {
+        self.outages.iter().chain(&self.outage).max().copied()
+    }
+
+    /// Whether the peer was down at the end of the window.
+    pub fn ongoing(&self) -> bool {
+        self.outage.is_some()
+    }
+}
+
+/// Availability and latency of one peer over a time window.
+#[derive(Debug)]
+pub struct PeerReport {
+    pub peer_id: String,
+    /// Number of pings sent.
+    pub pings: u64,
+    /// RTTs of the answered pings, fastest first.
+    rtts: Vec<Duration>,
+}
+
+impl PeerReport {
+    /// Number of pings answered.
+    pub fn answered(&self) -> u64 {
+        self.rtts.len() as u64
+    }
+
+    /// Percentage of pings answered.
+    pub fn availability_percent(&self) -> f64 {
+        self.answered() as f64 * 100.0 / self.pings as f64
+    }
+
+    /// The RTT that `percent` of the answered pings stayed within, by the
+    /// nearest-rank method.
+    pub fn percentile(&self, percent: f64) -> Option<Duration> {
+        let rank = (percent / 100.0 * self.rtts.len() as f64).ceil() as usize;
+        self.rtts.get(rank.saturating_sub(1)).copied()
+    }
+}
+
+fn micros_since_epoch(time: SystemTime) -> i64 {
+    time.duration_since(UNIX_EPOCH).unwrap_or_default().as_micros() as i64
+}
@@ -0,0 +1,126 @@
+//! Latency survey of the peers closest to a key in the DHT, for the `sweep`
+//! subcommand.
+
+use libp2p::core::ConnectedPoint;
+use libp2p::kad::{self, GetClosestPeersError, QueryResult};
+use libp2p::swarm::SwarmEvent;
+use libp2p::{ping, Multiaddr, PeerId};
+use libp2p_ping_tut::{BehaviourEvent, PingNode, PingStats};
+use std::collections::HashMap;
+use std::time::Duration;
+
+/// One of the peers closest to the key, with its ping results.
+pub struct Neighbor {
+    pub peer_id: PeerId,
+    /// The address of the first connection to the peer, if any.
+    pub address: Option<Multiaddr>,
+    pub stats: PingStats,
+    /// Why the peer couldn't be pinged, if it couldn't.
+    pub error: Option<String>,
+}
+
+impl Neighbor {
+    fn done(&self, count: u64) -> bool {
+        self.error.is_some() || self.stats.transmitted() >= count
+    }
+}
+
+/// Looks up the peers closest to `key` in the DHT `node` bootstraps into,
+/// pings each of them `count` times and returns them ranked by average RTT,
+/// those that never answered last.
+///
+/// Peers still missing pings after `wait` from the end of the lookup are
+/// returned with what they answered so far.
+pub async fn run(node: &mut PingNode, key: PeerId, count: u64, wait: Duration) -> Result<Vec<Neighbor>, String> {
+    let query = node.find_peer(key).map_err(|e| e.to_string())?;
+    // Connections are opened by the lookup itself, so note their addresses
+    // and pings until then as well.
+    let mut addresses: HashMap<PeerId, Multiaddr> = HashMap::new();
+    let mut early: HashMap<PeerId, PingStats> = HashMap::new();
+    let peers = loop {
+        match node.next_event().await {
+            SwarmEvent::Behaviour(BehaviourEvent::Kademlia(kad::Event::OutboundQueryProgressed { id, result, step, .. }))
+                if id == query && step.last =>
+            {
+                break match result {
+                    QueryResult::GetClosestPeers(Ok(ok)) => ok.peers,
+                    QueryResult::GetClosestPeers(Err(GetClosestPeersError::Timeout { peers, .. })) => peers,
+                    other => return Err(format!("unexpected lookup result {other:?}")),
+                };
+            }
+            SwarmEvent::ConnectionEstablished { peer_id, endpoint, .. } => {
+                addresses.entry(peer_id).or_insert_with(|| remote(&endpoint));
+            }
+            SwarmEvent::Behaviour(BehaviourEvent::Ping(ping::Event { peer, result, .. })) => {
+                let stats = early.entry(peer).or_default();
+                if stats.transmitted() < count {
+                    record(stats, &result);
+                }
+            }
+            _ => {}
+        }
+    };
+    if peers.is_empty() {
+        return Err(format!("the DHT knows no peers close to {key}"));
+    }
+
+    let mut neighbors: Vec<Neighbor> = peers
+        .into_iter()
+        .map(|peer_id| Neighbor {
+            peer_id,
+            address: addresses.remove(&peer_id),
+            stats: early.remove(&peer_id).unwrap_or_default(),
+            error: None,
+        })
+        .collect();
+    for neighbor in &mut neighbors {
+        if !node.is_connected(&neighbor.peer_id) {
+            if let Err(e) = node.dial_peer(neighbor.peer_id) {
+                neighbor.error = Some(e.to_string());
+            }
+        }
+    }
+
+    // Unlike an `Instant`, a `Sleep` copes with waits too long to add to now.
+    let deadline = tokio::time::sleep(wait);
+    tokio::pin!(deadline);
+    while !neighbors.iter().all(|neighbor| neighbor.done(count)) {
+        let event = tokio::select! {
+            event = node.next_event() => event,
+            () = &mut deadline => break,
+        };
+        match event {
+            SwarmEvent::ConnectionEstablished { peer_id, endpoint, .. } => {
+                if let Some(neighbor) = neighbors.iter_mut().find(|neighbor| neighbor.peer_id == peer_id) {
+                    neighbor.address.get_or_insert_with(|| remote(&endpoint));
+                }
+            }
+            // Other connections to the peer may still answer.
+            SwarmEvent::OutgoingConnectionError { peer_id: Some(peer_id), error, .. } if !node.is_connected(&peer_id) => {
+                if let Some(neighbor) = neighbors.iter_mut().find(|neighbor| neighbor.peer_id == peer_id) {
+                    neighbor.error.get_or_insert_with(|| error.to_string());
+                }
+            }
+            SwarmEvent::Behaviour(BehaviourEvent::Ping(ping::Event { peer, result, .. })) => {
+                if let Some(neighbor) = neighbors.iter_mut().find(|neighbor| neighbor.peer_id == peer && !neighbor.done(count)) {
+                    record(&mut neighbor.stats, &result);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    neighbors.sort_by_key(|neighbor| (neighbor.stats.avg().is_none(), neighbor.stats.avg()));
+    Ok(neighbors)
+}
+
+fn record(stats: &mut PingStats, result: &Result<Duration, ping::Failure>) {
+    match result {
+        Ok(rtt) => stats.record_success(*rtt),
+        Err(_) => stats.record_failure(),
+    }
+}
+
+fn remote(endpoint: &ConnectedPoint) -> Multiaddr {
+    endpoint.get_remote_address().clone()
+}
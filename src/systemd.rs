@@ -0,0 +1,86 @@
+//! Notifications to systemd for services of `Type=notify` or
+//! `Type=notify-reload`, as described in sd_notify(3): readiness once the node
+//! listens, reloads on SIGHUP, the watchdog, and shutdown.
+
+use std::os::unix::net::{SocketAddr, UnixDatagram};
+use std::time::Duration;
+
+/// The notification socket systemd passed in `$NOTIFY_SOCKET`.
+pub struct Systemd {
+    socket: UnixDatagram,
+    addr: SocketAddr,
+}
+
+impl Systemd {
+    /// Returns `None` if not started by systemd, or if the socket can't be
+    /// used.
+    pub fn from_env() -> Option<Self> {
+        let path = std::env::var_os("NOTIFY_SOCKET")?;
+        let addr = match path.to_str().and_then(|path| path.strip_prefix('@')) {
+            Some(name) => abstract_addr(name)?,
+            None => SocketAddr::from_pathname(&path).ok()?,
+        };
+        let socket = UnixDatagram::unbound().ok()?;
+        Some(Self { socket, addr })
+    }
+
+    /// Tells systemd that the node is up, after starting or reloading.
+    pub fn ready(&self, status: &str) {
+        self.notify(&format!("READY=1\nSTATUS={status}"));
+    }
+
+    /// Tells systemd that a reload started; [`Self::ready`] ends it.
+    pub fn reloading(&self) {
+        self.notify(&format!("RELOADING=1\nMONOTONIC_USEC={}", monotonic_usec()));
+    }
+
+    /// Tells systemd that the node is shutting down.
+    pub fn stopping(&self) {
+        self.notify("STOPPING=1");
+    }
+
+    /// Returns how often to call [`Self::pet_watchdog`], half the timeout set
+    /// with `WatchdogSec=`, if the watchdog is enabled for this process.
+    pub fn watchdog_interval(&self) -> Option<Duration> {
+        if let Some(pid) = std::env::var_os("WATCHDOG_PID") {
+            if pid.to_str()?.parse::<u32>().ok()? != std::process::id() {
+                return None;
+            }
+        }
+        let usec: u64 = std::env::var("WATCHDOG_USEC").ok()?.parse().ok()?;
+        Some(Duration::from_micros(usec / 2)).filter(|interval| !interval.is_zero())
+    }
+
+    /// Tells systemd that the node is still alive.
+    pub fn pet_watchdog(&self) {
+        self.notify("WATCHDOG=1");
+    }
+
+    fn notify(&self, state: &str) {
+        if let Err(e) = self.socket.send_to_addr(state.as_bytes(), &self.addr) {
+            tracing::warn!("failed to notify systemd: {e}");
+        }
+    }
+}
+
+/// Returns the address of a socket in the abstract namespace, which only
+/// Linux has.
+#[cfg(target_os = "linux")]
+fn abstract_addr(name: &str) -> Option<SocketAddr> {
+    use std::os::linux::net::SocketAddrExt;
+    SocketAddr::from_abstract_name(name).ok()
+}
+
+#[cfg(not(target_os = "linux"))]
+fn abstract_addr(_: &str) -> Option<SocketAddr> {
+    None
+}
+
+/// Returns the time of `CLOCK_MONOTONIC` in microseconds, which systemd
+/// compares reloads with.
+fn monotonic_usec() -> u64 {
+    let mut now = libc::timespec { tv_sec: 0, tv_nsec: 0 };
+    // SAFETY: `now` is a valid timespec to write the time to.
+    unsafe { libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut now) };
+    now.tv_sec as u64 * 1_000_000 + now.tv_nsec as u64 / 1000
+}
@@ -0,0 +1,843 @@
+//! Bookkeeping for the peers the user asked us to ping.
+
+use libp2p::core::ConnectedPoint;
+use libp2p::multiaddr::Protocol;
+use libp2p::swarm::ConnectionId;
+use libp2p::{kad, ping, Multiaddr, PeerId};
+use libp2p_ping_tut::labels::Labels;
+use libp2p_ping_tut::{clock, echo, Backoff, DialErrorKind, PingStats, Traffic};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::time::Duration;
+
+/// First delay before re-dialing a lost target.
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+
+/// Consecutive failed pings after which a target is down rather than degraded.
+const DOWN_AFTER: u32 = 3;
+
+/// How a target is doing, judged by its latest pings and its connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Health {
+    /// The latest ping was answered.
+    Up,
+    /// Fewer than [`DOWN_AFTER`] pings in a row failed.
+    Degraded,
+    /// [`DOWN_AFTER`] pings in a row failed, or the connection is lost.
+    Down,
+}
+
+impl fmt::Display for Health {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Health::Up => write!(f, "up"),
+            Health::Degraded => write!(f, "degraded"),
+            Health::Down => write!(f, "down"),
+        }
+    }
+}
+
+/// A change of the [`Health`] of a target.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Transition {
+    /// `None` before the first ping result or connection loss.
+    pub from: Option<Health>,
+    pub to: Health,
+}
+
+/// When and how often lost targets are re-dialed.
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    /// Give up on a target after this many consecutive failed re-dials;
+    /// `None` retries forever.
+    pub max_retries: Option<u32>,
+    /// Upper bound for the delay between re-dials.
+    pub backoff_max: Duration,
+    /// Mark a target dead and close its connections after this many
+    /// consecutive failed pings; `None` keeps pinging.
+    pub max_failures: Option<u32>,
+    /// Stop re-dialing targets marked dead.
+    pub evict: bool,
+}
+
+/// Limits the ping results of every target must stay within for a run to
+/// succeed.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Thresholds {
+    /// Minimum percentage of pings answered.
+    pub fail_under: Option<f64>,
+    /// Maximum average round-trip time.
+    pub max_rtt: Option<Duration>,
+}
+
+impl Thresholds {
+    /// Describes each threshold `stats` violate.
+    pub fn violations(&self, stats: &PingStats) -> Vec<String> {
+        let mut violations = Vec::new();
+        if let Some(min) = self.fail_under {
+            let success = 100.0 - if stats.transmitted() == 0 { 100.0 } else { stats.loss_percent() };
+            if success < min {
+                violations.push(format!("{success:.1}% of pings answered, below {min}%"));
+            }
+        }
+        if let (Some(max), Some(avg)) = (self.max_rtt, stats.avg()) {
+            if avg > max {
+                violations.push(format!(
+                    "average rtt {:.3} ms above {:.3} ms",
+                    avg.as_secs_f64() * 1000.0,
+                    max.as_secs_f64() * 1000.0
+                ));
+            }
+        }
+        violations
+    }
+}
+
+/// Settings of a target that replace the global ones, given in its table in
+/// the `peers` of the configuration file; `None` keeps the global one.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Overrides {
+    pub interval: Option<Duration>,
+    pub timeout: Option<Duration>,
+    pub count: Option<u64>,
+    /// Echo payload size.
+    pub size: Option<usize>,
+    pub max_rtt: Option<Duration>,
+}
+
+/// An open or closed connection, to a target or another peer.
+#[derive(Debug, Clone)]
+pub struct Connection {
+    pub id: ConnectionId,
+    /// The local address of an incoming connection; that of outgoing ones is
+    /// not known.
+    pub local: Option<Multiaddr>,
+    pub remote: Multiaddr,
+    /// Whether the connection goes through a relay.
+    pub relayed: bool,
+}
+
+impl fmt::Display for Connection {
+    /// Formats the connection as `#<id> <local> -> <remote>`, with `?` for an
+    /// unknown local address.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.local {
+            Some(local) => write!(f, "#{} {local} -> {}", self.id, self.remote),
+            None => write!(f, "#{} ? -> {}", self.id, self.remote),
+        }
+    }
+}
+
+/// A peer given on the command line, with its accumulated ping results.
+#[derive(Debug)]
+pub struct Target {
+    /// The address the peer was dialed at, or just `/p2p/<peer id>` for peers
+    /// looked up in the DHT.
+    pub addr: Multiaddr,
+    /// Human-readable name given to the peer, shown instead of its PeerId.
+    pub name: Option<String>,
+    /// The peer behind `addr`, known once a connection has been established or
+    /// if `addr` ends with `/p2p/<peer id>`.
+    pub peer_id: Option<PeerId>,
+    /// Results of the pings sent to this peer.
+    pub stats: PingStats,
+    /// Results of the pings sent over relayed connections only.
+    pub relayed: PingStats,
+    /// Results of the pings sent over direct connections only.
+    pub direct: PingStats,
+    /// Results of the pings sent over each connection to the peer, in the
+    /// order they were established, including closed ones.
+    pub connections: Vec<(Connection, PingStats)>,
+    /// Results of the echo requests sent to this peer, if enabled.
+    pub echo: PingStats,
+    /// The clock sample of this peer with the lowest delay, which queueing
+    /// distorted the least, if clock probing is enabled.
+    pub clock: Option<clock::Sample>,
+    /// Clock samples taken of this peer.
+    pub clock_samples: u64,
+    /// Results of the pings since the last periodic summary.
+    pub recent: PingStats,
+    /// Results of the pings in the current measurement window.
+    pub window: PingStats,
+    /// Bytes exchanged with the peer up to the last periodic summary.
+    pub reported_traffic: Traffic,
+    /// Labels the peer advertised via identify.
+    pub labels: Labels,
+    /// For the path through the `--via-relay` of a target also pinged
+    /// directly, the index of the direct one.
+    pub detour_of: Option<usize>,
+    /// Whether pinging the peer was paused through the control socket.
+    pub paused: bool,
+    /// Settings of the target replacing the global ones.
+    pub overrides: Overrides,
+    /// Failed dials of the peer, by why they failed.
+    pub dial_failures: BTreeMap<DialErrorKind, u64>,
+    /// Pings still to be left out of the statistics, as the first ones are
+    /// slowed down by the handshakes.
+    warmup: u64,
+    /// `None` until the first ping result or connection loss.
+    health: Option<Health>,
+    /// Pings failed since the last answered one.
+    failures: u32,
+    /// Whether `stats` violated the thresholds when last checked.
+    breached: bool,
+    /// Delay tracking for re-dials.
+    backoff: Backoff,
+    /// Set once the retry budget is exhausted.
+    gave_up: bool,
+    /// Set once the target has been removed; it is then neither dialed nor
+    /// reported anymore.
+    removed: bool,
+}
+
+impl Target {
+    /// Returns `true` once the target has been removed with [`Targets::remove`].
+    pub fn is_removed(&self) -> bool {
+        self.removed
+    }
+
+    /// Returns the peer to look up in the DHT if the target has no address.
+    pub fn lookup_peer_id(&self) -> Option<PeerId> {
+        let mut protocols = self.addr.iter();
+        match (protocols.next(), protocols.next()) {
+            (Some(Protocol::P2p(peer_id)), None) => Some(peer_id),
+            _ => None,
+        }
+    }
+
+    /// Returns the name of the target, or its address if it has none.
+    pub fn label(&self) -> String {
+        self.name.clone().unwrap_or_else(|| self.addr.to_string())
+    }
+
+    /// Records the result of an echo request.
+    pub fn record_echo(&mut self, result: &Result<Duration, echo::Failure>) {
+        match result {
+            Ok(rtt) => self.echo.record_success(*rtt),
+            Err(_) => self.echo.record_failure(),
+        }
+    }
+
+    /// Records a clock sample, keeping it if it has the lowest delay so far.
+    pub fn record_clock(&mut self, sample: clock::Sample) {
+        self.clock_samples += 1;
+        if self.clock.is_none_or(|best| sample.delay_us < best.delay_us) {
+            self.clock = Some(sample);
+        }
+    }
+
+    /// Returns `true` while the next ping result is a warm-up one, whose RTT
+    /// [`Self::record`] leaves out of the statistics.
+    pub fn warming_up(&self) -> bool {
+        self.warmup > 0
+    }
+
+    /// Records the result of a ping over one of the target's connections, and
+    /// returns the change of health it causes, if any.
+    pub fn record(&mut self, connection: &Connection, result: &Result<Duration, ping::Failure>) -> Option<Transition> {
+        let warmup = self.warming_up();
+        if warmup {
+            self.warmup -= 1;
+        }
+        let index = match self.connections.iter().position(|(c, _)| c.id == connection.id) {
+            Some(index) => index,
+            None => {
+                self.connections.push((connection.clone(), PingStats::default()));
+                self.connections.len() - 1
+            }
+        };
+        let path = if connection.relayed { &mut self.relayed } else { &mut self.direct };
+        for stats in [&mut self.stats, &mut self.recent, &mut self.window, path, &mut self.connections[index].1] {
+            match result {
+                Ok(_) if warmup => stats.record_warmup(),
+                Ok(rtt) => stats.record_success(*rtt),
+                Err(_) => stats.record_failure(),
+            }
+        }
+        match result {
+            Ok(_) => self.failures = 0,
+            Err(_) => self.failures += 1,
+        }
+        self.set_health(match self.failures {
+            0 => Health::Up,
+            failures if failures < DOWN_AFTER => Health::Degraded,
+            _ => Health::Down,
+        })
+    }
+
+    /// Returns the global `thresholds` with the expected maximum RTT of the
+    /// target, if it has one.
+    pub fn thresholds(&self, thresholds: &Thresholds) -> Thresholds {
+        Thresholds { max_rtt: self.overrides.max_rtt.or(thresholds.max_rtt), ..*thresholds }
+    }
+
+    /// Returns `true` once the target received its own count of replies after
+    /// the warm-up or, without one, the global `count`.
+    pub fn done(&self, count: Option<u64>) -> bool {
+        self.overrides.count.or(count).is_some_and(|count| self.stats.sampled() >= count)
+    }
+
+    /// Returns the thresholds the results violate if they didn't when last
+    /// checked, so that each breach is reported once. The target's expected
+    /// maximum RTT replaces that of `thresholds`.
+    pub fn check(&mut self, thresholds: &Thresholds) -> Option<Vec<String>> {
+        let violations = self.thresholds(thresholds).violations(&self.stats);
+        let breached = !violations.is_empty();
+        let new = breached && !self.breached;
+        self.breached = breached;
+        new.then_some(violations)
+    }
+
+    /// Returns the current health, `None` before the first ping result.
+    pub fn health(&self) -> Option<Health> {
+        self.health
+    }
+
+    fn set_health(&mut self, health: Health) -> Option<Transition> {
+        let from = self.health.replace(health);
+        (from != Some(health)).then_some(Transition { from, to: health })
+    }
+}
+
+/// All ping targets, indexed by their dials and by their peer id.
+#[derive(Debug)]
+pub struct Targets {
+    policy: RetryPolicy,
+    /// Warm-up pings of each new target.
+    warmup: u64,
+    /// Relay to also ping the targets with a known peer through.
+    via_relay: Option<Multiaddr>,
+    targets: Vec<Target>,
+    by_connection: HashMap<ConnectionId, usize>,
+    by_peer: HashMap<PeerId, usize>,
+    by_query: HashMap<kad::QueryId, usize>,
+    /// Each open connection, to any peer, with the peer and the target it
+    /// belongs to.
+    connections: HashMap<ConnectionId, (Connection, PeerId, Option<usize>)>,
+    /// Whether pinging every peer was paused through the control socket.
+    pub all_paused: bool,
+}
+
+/// What to do after a target lost its connection or failed to connect.
+pub enum Retry {
+    /// Re-dial the target at `index` after `delay`.
+    After { index: usize, delay: Duration, attempt: u32 },
+    /// The retry budget is exhausted; the target will not be dialed again.
+    GiveUp { index: usize },
+}
+
+impl Targets {
+    /// Creates an empty list whose targets leave their first `warmup` pings
+    /// out of the statistics, and are also pinged through `via_relay`.
+    pub fn new(policy: RetryPolicy, warmup: u64, via_relay: Option<Multiaddr>) -> Self {
+        Self {
+            policy,
+            warmup,
+            via_relay,
+            targets: Vec::new(),
+            by_connection: HashMap::new(),
+            by_peer: HashMap::new(),
+            by_query: HashMap::new(),
+            connections: HashMap::new(),
+            all_paused: false,
+        }
+    }
+
+    /// Adds a target whose dial produces the given connection; returns its
+    /// index.
+    pub fn add(&mut self, addr: Multiaddr, name: Option<String>, connection_id: ConnectionId) -> usize {
+        let index = self.push(addr, name, None);
+        self.by_connection.insert(connection_id, index);
+        index
+    }
+
+    /// Adds a target known only by its peer id, to be looked up in the DHT.
+    pub fn add_lookup(&mut self, peer_id: PeerId) -> usize {
+        self.push(Multiaddr::empty().with(Protocol::P2p(peer_id)), None, None)
+    }
+
+    /// Returns the address to also ping the target at `index` at through the
+    /// `--via-relay`, if there is one and the target is a direct address
+    /// ending with its PeerId.
+    pub fn detour_addr(&self, index: usize) -> Option<Multiaddr> {
+        let target = &self.targets[index];
+        let relay = self.via_relay.as_ref().filter(|_| target.detour_of.is_none())?;
+        if target.addr.iter().any(|protocol| protocol == Protocol::P2pCircuit) || target.lookup_peer_id().is_some() {
+            return None;
+        }
+        let Some(Protocol::P2p(peer_id)) = target.addr.iter().last() else {
+            return None;
+        };
+        Some(relay.clone().with(Protocol::P2pCircuit).with(Protocol::P2p(peer_id)))
+    }
+
+    /// Adds the relayed path at `addr` of the target at `index`, whose dial
+    /// produces the given connection. Connections the peer opens itself, e.g.
+    /// by hole punching, still count for the direct target.
+    pub fn add_detour(&mut self, index: usize, addr: Multiaddr, connection_id: ConnectionId) {
+        let name = self.targets[index].name.as_ref().map(|name| format!("{name} via relay"));
+        let detour = self.push(addr, name, Some(index));
+        self.by_connection.insert(connection_id, detour);
+    }
+
+    fn push(&mut self, addr: Multiaddr, name: Option<String>, detour_of: Option<usize>) -> usize {
+        let index = self.targets.len();
+        let peer_id = match addr.iter().last() {
+            Some(Protocol::P2p(peer_id)) => Some(peer_id),
+            _ => None,
+        };
+        if let Some(peer_id) = peer_id.filter(|_| detour_of.is_none()) {
+            self.by_peer.insert(peer_id, index);
+        }
+        self.targets.push(Target {
+            addr,
+            name,
+            peer_id,
+            stats: PingStats::default(),
+            relayed: PingStats::default(),
+            direct: PingStats::default(),
+            connections: Vec::new(),
+            echo: PingStats::default(),
+            clock: None,
+            clock_samples: 0,
+            recent: PingStats::default(),
+            window: PingStats::default(),
+            paused: false,
+            overrides: Overrides::default(),
+            dial_failures: BTreeMap::new(),
+            reported_traffic: Traffic::default(),
+            labels: Labels::new(),
+            detour_of,
+            warmup: self.warmup,
+            health: None,
+            failures: 0,
+            breached: false,
+            backoff: Backoff::new(INITIAL_BACKOFF, self.policy.backoff_max),
+            gave_up: false,
+            removed: false,
+        });
+        index
+    }
+
+    /// Returns the target whose name, address or PeerId is `target`.
+    pub fn find(&self, target: &str) -> Option<usize> {
+        self.targets.iter().position(|t| {
+            !t.removed
+                && (t.name.as_deref() == Some(target)
+                    || t.addr.to_string() == target
+                    || t.peer_id.is_some_and(|peer_id| peer_id.to_string() == target))
+        })
+    }
+
+    /// Stops tracking the target at `index`, and its relayed path if any;
+    /// returns its peer, if known, to disconnect from.
+    pub fn remove(&mut self, index: usize) -> Option<PeerId> {
+        let detours = self.targets.iter().enumerate().filter(|(_, t)| t.detour_of == Some(index)).map(|(i, _)| i);
+        let removed: Vec<usize> = std::iter::once(index).chain(detours).collect();
+        for &index in &removed {
+            self.targets[index].removed = true;
+        }
+        self.by_connection.retain(|_, i| !removed.contains(i));
+        self.by_peer.retain(|_, i| !removed.contains(i));
+        self.by_query.retain(|_, i| !removed.contains(i));
+        for (_, _, target) in self.connections.values_mut() {
+            if target.is_some_and(|i| removed.contains(&i)) {
+                *target = None;
+            }
+        }
+        self.targets[index].peer_id
+    }
+
+    /// Records that the target at `index` has been dialed again.
+    pub fn redialed(&mut self, index: usize, connection_id: ConnectionId) {
+        self.by_connection.insert(connection_id, index);
+    }
+
+    /// Records that the DHT lookup of the target at `index` has been started.
+    pub fn lookup_started(&mut self, index: usize, query_id: kad::QueryId) {
+        self.by_query.insert(query_id, index);
+    }
+
+    /// Returns the target whose DHT lookup has finished, if any.
+    pub fn lookup_finished(&mut self, query_id: kad::QueryId) -> Option<usize> {
+        self.by_query.remove(&query_id)
+    }
+
+    /// Handles a DHT lookup that did not lead to a connection.
+    pub fn lookup_failed(&mut self, index: usize) -> Retry {
+        self.retry(index)
+    }
+
+    /// Associates an established connection and the peer behind it with a
+    /// target, if the connection came from dialing one or goes to a target's
+    /// known peer, and resets its backoff.
+    ///
+    /// Several targets may be the same peer at different addresses; the pings
+    /// over each connection count for the target that dialed it.
+    pub fn connection_established(&mut self, connection_id: ConnectionId, peer_id: PeerId, endpoint: &ConnectedPoint) {
+        let connection = Connection {
+            id: connection_id,
+            local: match endpoint {
+                ConnectedPoint::Listener { local_addr, .. } => Some(local_addr.clone()),
+                ConnectedPoint::Dialer { .. } => None,
+            },
+            remote: endpoint.get_remote_address().clone(),
+            relayed: endpoint.is_relayed(),
+        };
+        let dialed = self.by_connection.remove(&connection_id);
+        let index = dialed.or_else(|| self.by_peer.get(&peer_id).copied());
+        if let Some(index) = index {
+            let target = &mut self.targets[index];
+            target.peer_id = Some(peer_id);
+            target.backoff.reset();
+            self.by_peer.entry(peer_id).or_insert(index);
+        }
+        self.connections.insert(connection_id, (connection, peer_id, index));
+    }
+
+    /// Handles a dial that failed as `kind`; returns how to retry if it
+    /// belonged to a target.
+    pub fn dial_failed(&mut self, connection_id: ConnectionId, kind: DialErrorKind) -> Option<Retry> {
+        let index = self.by_connection.remove(&connection_id)?;
+        *self.targets[index].dial_failures.entry(kind).or_default() += 1;
+        Some(self.retry(index))
+    }
+
+    /// Handles a dial refused by the allow or deny list; the target, if any, is
+    /// given up on as re-dialing would be refused too.
+    pub fn dial_denied(&mut self, connection_id: ConnectionId) -> Option<Retry> {
+        let index = self.by_connection.remove(&connection_id)?;
+        *self.targets[index].dial_failures.entry(DialErrorKind::Denied).or_default() += 1;
+        self.targets[index].gave_up = true;
+        Some(Retry::GiveUp { index })
+    }
+
+    /// Handles a re-dial of the target at `index` that failed to start as
+    /// `kind`; a denied one is given up on like [`Self::dial_denied`].
+    pub fn redial_failed(&mut self, index: usize, kind: DialErrorKind) -> Retry {
+        *self.targets[index].dial_failures.entry(kind).or_default() += 1;
+        if kind == DialErrorKind::Denied {
+            self.targets[index].gave_up = true;
+            return Retry::GiveUp { index };
+        }
+        self.retry(index)
+    }
+
+    /// Forgets a closed connection; returns how to retry if it was the last
+    /// one of a target.
+    pub fn connection_closed(&mut self, connection_id: ConnectionId) -> Option<Retry> {
+        let (_, _, index) = self.connections.remove(&connection_id)?;
+        let index = index?;
+        let open = self.connections.values().any(|(_, _, target)| *target == Some(index));
+        (!open).then(|| self.retry(index))
+    }
+
+    /// Returns an open connection.
+    pub fn connection(&self, connection_id: ConnectionId) -> Option<&Connection> {
+        self.connections.get(&connection_id).map(|(connection, _, _)| connection)
+    }
+
+    /// Returns the number of open connections to `peer_id`.
+    pub fn connections_to(&self, peer_id: &PeerId) -> usize {
+        self.connections.values().filter(|(_, peer, _)| peer == peer_id).count()
+    }
+
+    /// Returns the relayed and the direct target if an open connection is the
+    /// relayed path of a target pinged both ways.
+    pub fn detour(&self, connection_id: ConnectionId) -> Option<(&Target, &Target)> {
+        let (_, _, index) = self.connections.get(&connection_id)?;
+        let relayed = &self.targets[(*index)?];
+        Some((relayed, &self.targets[relayed.detour_of?]))
+    }
+
+    /// Returns the ping timeout of the target an open connection belongs to,
+    /// if it has one of its own.
+    pub fn timeout_of(&self, connection_id: ConnectionId) -> Option<Duration> {
+        let (_, _, index) = self.connections.get(&connection_id)?;
+        self.targets[(*index)?].overrides.timeout
+    }
+
+    /// Returns the target an open connection belongs to, if any.
+    pub fn get_by_connection(&mut self, connection_id: ConnectionId) -> Option<&mut Target> {
+        let (_, _, index) = self.connections.get(&connection_id)?;
+        index.map(|index| &mut self.targets[index])
+    }
+
+    /// Marks the target of the connection a ping failed on dead if it failed
+    /// the configured number of pings in a row, returning its index to close
+    /// its connections. Its failures are counted anew once re-dialed, unless
+    /// it is evicted for good and given up on.
+    pub fn dead(&mut self, connection_id: ConnectionId) -> Option<usize> {
+        let max_failures = self.policy.max_failures?;
+        let (_, _, index) = self.connections.get(&connection_id)?;
+        let index = (*index)?;
+        let target = &mut self.targets[index];
+        if target.failures < max_failures {
+            return None;
+        }
+        target.failures = 0;
+        target.gave_up |= self.policy.evict;
+        Some(index)
+    }
+
+    /// Marks the target at `index` down after it lost its connection or
+    /// failed to connect, returning the change of health, if any.
+    pub fn lost(&mut self, index: usize) -> Option<Transition> {
+        self.targets[index].set_health(Health::Down)
+    }
+
+    fn retry(&mut self, index: usize) -> Retry {
+        let target = &mut self.targets[index];
+        // Evicted with `gave_up` set by `dead`.
+        if target.gave_up || self.policy.max_retries.is_some_and(|max| target.backoff.attempts() >= max) {
+            target.gave_up = true;
+            return Retry::GiveUp { index };
+        }
+        let delay = target.backoff.next_delay();
+        Retry::After { index, delay, attempt: target.backoff.attempts() }
+    }
+
+    /// Returns the target at `index`.
+    pub fn get(&self, index: usize) -> &Target {
+        &self.targets[index]
+    }
+
+    /// Replaces global settings for the target at `index`.
+    pub fn set_overrides(&mut self, index: usize, overrides: Overrides) {
+        self.targets[index].overrides = overrides;
+    }
+
+    /// Pauses pinging the target at `index`, or resumes it; returns its peer,
+    /// if known yet.
+    pub fn set_paused(&mut self, index: usize, paused: bool) -> Option<PeerId> {
+        let target = &mut self.targets[index];
+        target.paused = paused;
+        target.peer_id
+    }
+
+    /// Returns the name given to `peer_id`, if it is a named target.
+    pub fn name(&self, peer_id: &PeerId) -> Option<&str> {
+        self.by_peer.get(peer_id).and_then(|&index| self.targets[index].name.as_deref())
+    }
+
+    /// Returns the target for `peer_id`, if that peer is one we dialed.
+    pub fn get_mut(&mut self, peer_id: &PeerId) -> Option<&mut Target> {
+        self.by_peer.get(peer_id).map(|&index| &mut self.targets[index])
+    }
+
+    /// Returns `true` if there are targets and each of them has either been
+    /// given up on or received as many replies as its count or, without one,
+    /// `count`, if set.
+    ///
+    /// Always `false` when there are no targets, so listeners keep running.
+    pub fn all_done(&self, count: Option<u64>) -> bool {
+        self.iter().next().is_some() && self.iter().all(|t| t.gave_up || t.done(count))
+    }
+
+    /// Returns `true` if every target that is `peer_id` is [`Target::done`].
+    pub fn peer_done(&self, peer_id: &PeerId, count: Option<u64>) -> bool {
+        let mut of_peer = self.iter().filter(|t| t.peer_id.as_ref() == Some(peer_id)).peekable();
+        of_peer.peek().is_some() && of_peer.all(|t| t.done(count))
+    }
+
+    /// Returns `true` if every target answered at least one ping.
+    pub fn all_answered(&self) -> bool {
+        self.iter().all(|t| t.stats.received() > 0)
+    }
+
+    /// Iterates over all targets not removed, in the order they were added.
+    pub fn iter(&self) -> impl Iterator<Item = &Target> {
+        self.targets.iter().filter(|t| !t.removed)
+    }
+
+    /// Like [`Self::iter`], but mutably.
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut Target> {
+        self.targets.iter_mut().filter(|t| !t.removed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libp2p::core::Endpoint;
+
+    const TIMEOUT: Result<Duration, ping::Failure> = Err(ping::Failure::Timeout);
+
+    fn policy() -> RetryPolicy {
+        RetryPolicy { max_retries: None, backoff_max: Duration::from_secs(60), max_failures: None, evict: false }
+    }
+
+    fn rtt(ms: u64) -> Result<Duration, ping::Failure> {
+        Ok(Duration::from_millis(ms))
+    }
+
+    fn transition(from: Option<Health>, to: Health) -> Option<Transition> {
+        Some(Transition { from, to })
+    }
+
+    /// Adds a target and connects to it, returning its index and connection.
+    fn connected(targets: &mut Targets) -> (usize, Connection) {
+        let peer_id = PeerId::random();
+        let addr: Multiaddr = "/ip4/127.0.0.1/tcp/4001".parse().unwrap();
+        let connection_id = ConnectionId::new_unchecked(targets.targets.len());
+        let index = targets.add(addr.clone().with(Protocol::P2p(peer_id)), None, connection_id);
+        let endpoint = ConnectedPoint::Dialer { address: addr, role_override: Endpoint::Dialer };
+        targets.connection_established(connection_id, peer_id, &endpoint);
+        (index, targets.connection(connection_id).unwrap().clone())
+    }
+
+    #[test]
+    fn answered_ping_is_up() {
+        let mut targets = Targets::new(policy(), 0, None);
+        let (index, connection) = connected(&mut targets);
+        let target = &mut targets.targets[index];
+        assert_eq!(target.health(), None);
+        assert_eq!(target.record(&connection, &rtt(10)), transition(None, Health::Up));
+        assert_eq!(target.record(&connection, &rtt(10)), None, "reported once");
+        assert_eq!(target.health(), Some(Health::Up));
+    }
+
+    #[test]
+    fn failed_pings_degrade_then_take_down() {
+        let mut targets = Targets::new(policy(), 0, None);
+        let (index, connection) = connected(&mut targets);
+        let target = &mut targets.targets[index];
+        target.record(&connection, &rtt(10));
+        assert_eq!(target.record(&connection, &TIMEOUT), transition(Some(Health::Up), Health::Degraded));
+        for _ in 1..DOWN_AFTER - 1 {
+            assert_eq!(target.record(&connection, &TIMEOUT), None);
+        }
+        assert_eq!(target.record(&connection, &TIMEOUT), transition(Some(Health::Degraded), Health::Down));
+        assert_eq!(target.record(&connection, &TIMEOUT), None, "reported once");
+    }
+
+    #[test]
+    fn first_failure_is_degraded() {
+        let mut targets = Targets::new(policy(), 0, None);
+        let (index, connection) = connected(&mut targets);
+        let target = &mut targets.targets[index];
+        assert_eq!(target.record(&connection, &TIMEOUT), transition(None, Health::Degraded));
+    }
+
+    #[test]
+    fn answered_ping_recovers() {
+        let mut targets = Targets::new(policy(), 0, None);
+        let (index, connection) = connected(&mut targets);
+        let target = &mut targets.targets[index];
+        for _ in 0..DOWN_AFTER {
+            target.record(&connection, &TIMEOUT);
+        }
+        assert_eq!(target.record(&connection, &rtt(10)), transition(Some(Health::Down), Health::Up));
+        // The failures before the answer don't count anymore.
+        assert_eq!(target.record(&connection, &TIMEOUT), transition(Some(Health::Up), Health::Degraded));
+    }
+
+    #[test]
+    fn lost_connection_is_down() {
+        let mut targets = Targets::new(policy(), 0, None);
+        let (index, connection) = connected(&mut targets);
+        targets.targets[index].record(&connection, &rtt(10));
+        assert_eq!(targets.lost(index), transition(Some(Health::Up), Health::Down));
+        assert_eq!(targets.lost(index), None, "reported once");
+        let (never_answered, _) = connected(&mut targets);
+        assert_eq!(targets.lost(never_answered), transition(None, Health::Down));
+    }
+
+    #[test]
+    fn warmup_pings_leave_the_statistics() {
+        let mut targets = Targets::new(policy(), 2, None);
+        let (index, connection) = connected(&mut targets);
+        let target = &mut targets.targets[index];
+        for _ in 0..2 {
+            assert!(target.warming_up());
+            target.record(&connection, &rtt(500));
+        }
+        assert!(!target.warming_up());
+        target.record(&connection, &rtt(10));
+        assert_eq!((target.stats.received(), target.stats.sampled()), (3, 1));
+        assert_eq!(target.stats.max(), Some(Duration::from_millis(10)));
+        assert_eq!(target.connections[0].1.sampled(), 1);
+        assert!(target.done(Some(1)));
+        assert!(!target.done(Some(2)));
+    }
+
+    fn dying(max_failures: u32, evict: bool) -> Targets {
+        Targets::new(RetryPolicy { max_failures: Some(max_failures), evict, ..policy() }, 0, None)
+    }
+
+    #[test]
+    fn dead_after_max_failures_in_a_row() {
+        let mut targets = dying(2, false);
+        let (index, connection) = connected(&mut targets);
+        targets.targets[index].record(&connection, &TIMEOUT);
+        assert_eq!(targets.dead(connection.id), None);
+        targets.targets[index].record(&connection, &rtt(10));
+        targets.targets[index].record(&connection, &TIMEOUT);
+        assert_eq!(targets.dead(connection.id), None, "the answer reset the failures");
+        targets.targets[index].record(&connection, &TIMEOUT);
+        assert_eq!(targets.dead(connection.id), Some(index));
+        // Counted anew for the next connection.
+        targets.targets[index].record(&connection, &TIMEOUT);
+        assert_eq!(targets.dead(connection.id), None);
+    }
+
+    #[test]
+    fn dead_targets_are_redialed() {
+        let mut targets = dying(1, false);
+        let (index, connection) = connected(&mut targets);
+        targets.targets[index].record(&connection, &TIMEOUT);
+        assert_eq!(targets.dead(connection.id), Some(index));
+        let retry = targets.connection_closed(connection.id);
+        assert!(matches!(retry, Some(Retry::After { index: i, attempt: 1, .. }) if i == index));
+        assert!(!targets.all_done(None));
+    }
+
+    #[test]
+    fn evicted_targets_are_given_up_on() {
+        let mut targets = dying(1, true);
+        let (index, connection) = connected(&mut targets);
+        targets.targets[index].record(&connection, &TIMEOUT);
+        assert_eq!(targets.dead(connection.id), Some(index));
+        let retry = targets.connection_closed(connection.id);
+        assert!(matches!(retry, Some(Retry::GiveUp { index: i }) if i == index));
+        assert!(targets.all_done(None));
+    }
+
+    #[test]
+    fn no_max_failures_keeps_pinging() {
+        let mut targets = Targets::new(policy(), 0, None);
+        let (index, connection) = connected(&mut targets);
+        for _ in 0..10 {
+            targets.targets[index].record(&connection, &TIMEOUT);
+        }
+        assert_eq!(targets.dead(connection.id), None);
+    }
+
+    #[test]
+    fn gives_up_after_max_retries() {
+        let mut targets = Targets::new(RetryPolicy { max_retries: Some(2), ..policy() }, 0, None);
+        let (index, connection) = connected(&mut targets);
+        assert!(matches!(targets.connection_closed(connection.id), Some(Retry::After { attempt: 1, .. })));
+        let redial = |targets: &mut Targets, id| {
+            let connection_id = ConnectionId::new_unchecked(id);
+            targets.redialed(index, connection_id);
+            targets.dial_failed(connection_id, DialErrorKind::Refused)
+        };
+        assert!(matches!(redial(&mut targets, 100), Some(Retry::After { attempt: 2, .. })));
+        assert!(matches!(redial(&mut targets, 101), Some(Retry::GiveUp { index: i }) if i == index));
+        assert_eq!(targets.get(index).dial_failures.get(&DialErrorKind::Refused), Some(&2));
+        assert!(targets.all_done(None));
+    }
+
+    #[test]
+    fn connecting_resets_the_retries() {
+        let mut targets = Targets::new(RetryPolicy { max_retries: Some(1), ..policy() }, 0, None);
+        let (index, connection) = connected(&mut targets);
+        assert!(matches!(targets.connection_closed(connection.id), Some(Retry::After { attempt: 1, .. })));
+        let connection_id = ConnectionId::new_unchecked(100);
+        targets.redialed(index, connection_id);
+        let endpoint = ConnectedPoint::Dialer { address: connection.remote, role_override: Endpoint::Dialer };
+        targets.connection_established(connection_id, targets.get(index).peer_id.unwrap(), &endpoint);
+        assert!(matches!(targets.connection_closed(connection_id), Some(Retry::After { attempt: 1, .. })));
+    }
+}
@@ -0,0 +1,183 @@
+//! In-process nodes connected over the memory transport, to test code built
+//! on [`PingNode`] without real sockets. Nodes with an [`Impairment`] answer
+//! pings late or not at all, like peers behind a bad network path.
+//!
+//! ```
+//! use libp2p_ping_tut::testing::Network;
+//! use libp2p_ping_tut::NodeConfig;
+//!
+//! # #[tokio::main(flavor = "current_thread")]
+//! # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! let mut network = Network::new(2, NodeConfig::default()).await?;
+//! network.connect(0, 1)?;
+//! let rtt = network.next_ping(0, 1).await?;
+//! println!("RTT between the nodes: {rtt:?}");
+//! # Ok(())
+//! # }
+//! ```
+
+use futures::future;
+use libp2p::multiaddr::Protocol;
+use libp2p::swarm::{ConnectionId, SwarmEvent};
+use libp2p::{ping, Multiaddr, PeerId};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::{BehaviourEvent, NodeConfig, PingError, PingNode, TransportChoice};
+
+/// How long the helpers of [`Network`] wait for an event before failing.
+pub const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Latency and loss a node adds to the pings it answers, set with
+/// [`NodeConfig::impairment`].
+#[derive(Debug, Clone, Copy)]
+pub struct Impairment {
+    /// Delay before answering a ping, which adds to its RTT.
+    pub latency: Duration,
+    /// Each delay differs from `latency` by up to this much, uniformly
+    /// distributed; delays that would be negative are zero.
+    pub jitter: Duration,
+    /// Fraction of pings left unanswered, between 0 and 1.
+    pub loss: f64,
+    /// Seed of the delays and losses, which are the same on every run with the
+    /// same seed.
+    pub seed: u64,
+}
+
+/// Draws the delay or loss of each ping answered under an [`Impairment`].
+#[derive(Debug, Clone)]
+pub(crate) struct Impairer {
+    impairment: Impairment,
+    /// Shared by all connections of the node.
+    rng: Arc<Mutex<StdRng>>,
+}
+
+impl Impairer {
+    pub(crate) fn new(impairment: Impairment) -> Self {
+        let rng = StdRng::seed_from_u64(impairment.seed);
+        Self { impairment, rng: Arc::new(Mutex::new(rng)) }
+    }
+
+    /// Returns how long to wait before answering the next ping, or `None` if
+    /// it is lost.
+    pub(crate) fn next(&self) -> Option<Duration> {
+        let mut rng = self.rng.lock().expect("no panics while locked");
+        if rng.gen_bool(self.impairment.loss.clamp(0.0, 1.0)) {
+            return None;
+        }
+        let jitter = self.impairment.jitter.as_secs_f64();
+        let delay = self.impairment.latency.as_secs_f64() + rng.gen_range(-jitter..=jitter);
+        Some(Duration::from_secs_f64(delay.max(0.0)))
+    }
+}
+
+/// Builds a node with `config` that only uses the memory transport.
+pub fn memory_node(mut config: NodeConfig) -> Result<PingNode, PingError> {
+    config.transports = vec![TransportChoice::Memory];
+    config.mdns = false;
+    PingNode::with_config(config)
+}
+
+/// Nodes listening on memory addresses, driven together so that they answer
+/// each other while one of them is awaited.
+pub struct Network {
+    nodes: Vec<PingNode>,
+    /// The listen address of each node, ending with `/p2p/<peer id>`.
+    addrs: Vec<Multiaddr>,
+}
+
+impl Network {
+    /// Starts `count` nodes with `config`, each listening on its own memory
+    /// address.
+    pub async fn new(count: usize, config: NodeConfig) -> Result<Self, PingError> {
+        Self::with_configs(std::iter::repeat_n(config, count)).await
+    }
+
+    /// Starts a node with each of `configs`, e.g. to impair only some of them.
+    pub async fn with_configs(configs: impl IntoIterator<Item = NodeConfig>) -> Result<Self, PingError> {
+        let (mut nodes, mut addrs) = (Vec::new(), Vec::new());
+        for config in configs {
+            let mut node = memory_node(config)?;
+            node.listen(Multiaddr::empty().with(Protocol::Memory(0)))?;
+            let addr = within_timeout(async {
+                loop {
+                    if let SwarmEvent::NewListenAddr { address, .. } = node.next_event().await {
+                        break address;
+                    }
+                }
+            })
+            .await?;
+            addrs.push(addr.with(Protocol::P2p(node.local_peer_id())));
+            nodes.push(node);
+        }
+        Ok(Self { nodes, addrs })
+    }
+
+    /// Returns node `index`, e.g. to call [`PingNode::disconnect`] on it.
+    pub fn node(&mut self, index: usize) -> &mut PingNode {
+        &mut self.nodes[index]
+    }
+
+    /// Returns the [`PeerId`] of node `index`.
+    pub fn peer_id(&self, index: usize) -> PeerId {
+        self.nodes[index].local_peer_id()
+    }
+
+    /// Returns the listen address of node `index`, ending with its PeerId.
+    pub fn addr(&self, index: usize) -> &Multiaddr {
+        &self.addrs[index]
+    }
+
+    /// Dials node `to` from node `from`; await [`Self::wait_for`] or
+    /// [`Self::next_ping`] for the connection to be established.
+    pub fn connect(&mut self, from: usize, to: usize) -> Result<ConnectionId, PingError> {
+        let addr = self.addrs[to].clone();
+        self.nodes[from].dial(addr)
+    }
+
+    /// Drives all nodes until `matches` returns something for an event of
+    /// node `index`, for at most [`TIMEOUT`]. The events of the other nodes
+    /// are dropped.
+    pub async fn wait_for<T>(
+        &mut self,
+        index: usize,
+        mut matches: impl FnMut(&SwarmEvent<BehaviourEvent>) -> Option<T>,
+    ) -> Result<T, PingError> {
+        within_timeout(async {
+            loop {
+                let events = self.nodes.iter_mut().map(|node| Box::pin(node.next_event()));
+                let (event, from, _) = future::select_all(events).await;
+                if let Some(found) = (from == index).then(|| matches(&event)).flatten() {
+                    break found;
+                }
+            }
+        })
+        .await
+    }
+
+    /// Drives all nodes until node `from` receives the result of its next ping
+    /// to node `to`.
+    pub async fn next_ping(&mut self, from: usize, to: usize) -> Result<Duration, PingError> {
+        let peer_id = self.peer_id(to);
+        let result = self
+            .wait_for(from, |event| match event {
+                SwarmEvent::Behaviour(BehaviourEvent::Ping(ping::Event { peer, result, .. })) if *peer == peer_id => {
+                    Some(result.as_ref().copied().map_err(|failure| match failure {
+                        ping::Failure::Timeout => PingError::Timeout,
+                        failure => PingError::PingFailure(failure.to_string()),
+                    }))
+                }
+                _ => None,
+            })
+            .await?;
+        result
+    }
+}
+
+/// Fails if `wait` doesn't finish within [`TIMEOUT`].
+async fn within_timeout<T>(wait: impl Future<Output = T>) -> Result<T, PingError> {
+    tokio::time::timeout(TIMEOUT, wait).await.map_err(|_| PingError::Timeout)
+}
@@ -0,0 +1,193 @@
+//! Timing of the phases of outgoing connections, from the first packet to the
+//! first answered ping.
+
+use libp2p::core::transport::{ListenerId, TransportError, TransportEvent};
+use libp2p::core::ConnectedPoint;
+use libp2p::swarm::{ConnectionId, SwarmEvent};
+use libp2p::{ping, Multiaddr, Transport};
+use std::collections::HashMap;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use crate::BehaviourEvent;
+
+/// How long each phase of setting up an outgoing connection took, as reported
+/// by [`PingNode::take_connection_timing`](crate::PingNode::take_connection_timing).
+#[derive(Debug, Clone, Copy)]
+pub struct ConnectionTiming {
+    /// Resolving DNS addresses and opening the TCP connection, WebSocket or
+    /// relayed circuit; for QUIC the whole handshake, which includes security
+    /// and multiplexing.
+    pub connect: Duration,
+    /// Negotiating and running the security handshake (TLS or Noise),
+    /// including that of a private network; `None` for QUIC.
+    pub security: Option<Duration>,
+    /// Negotiating the stream multiplexer (Yamux); `None` for QUIC.
+    pub muxer: Option<Duration>,
+    /// From the established connection to the first answered ping.
+    pub first_ping: Duration,
+}
+
+/// When each phase of a dial finished.
+#[derive(Debug, Clone, Copy)]
+struct Phases {
+    started: Instant,
+    connected: Option<Instant>,
+    secured: Option<Instant>,
+    muxed: Option<Instant>,
+}
+
+/// Phase timestamps of pending dials by address, filled in by the transports.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct DialTimer {
+    dials: Arc<Mutex<HashMap<Multiaddr, Phases>>>,
+}
+
+impl DialTimer {
+    fn started(&self, addr: &Multiaddr) {
+        let phases = Phases { started: Instant::now(), connected: None, secured: None, muxed: None };
+        self.dials.lock().expect("no panics while locked").insert(addr.clone(), phases);
+    }
+
+    /// Notes that the transport connection to the dialed address is open.
+    pub(crate) fn connected(&self, endpoint: &ConnectedPoint) {
+        self.update(endpoint, |phases| phases.connected = Some(Instant::now()));
+    }
+
+    /// Notes that the security handshake on the connection finished.
+    pub(crate) fn secured(&self, endpoint: &ConnectedPoint) {
+        self.update(endpoint, |phases| phases.secured = Some(Instant::now()));
+    }
+
+    /// Notes that the stream multiplexer on the connection was negotiated.
+    pub(crate) fn muxed(&self, endpoint: &ConnectedPoint) {
+        self.update(endpoint, |phases| phases.muxed = Some(Instant::now()));
+    }
+
+    /// Updates the phases of an outgoing connection; inbound ones aren't timed.
+    fn update(&self, endpoint: &ConnectedPoint, update: impl FnOnce(&mut Phases)) {
+        if let ConnectedPoint::Dialer { address, .. } = endpoint {
+            if let Some(phases) = self.dials.lock().expect("no panics while locked").get_mut(address) {
+                update(phases);
+            }
+        }
+    }
+
+    /// Files the phases of a dial of `winner`, the fastest of the addresses
+    /// `dialed` resolved to, under `dialed` as started at `started`, which is
+    /// the lookup of the event establishing it.
+    pub(crate) fn resolved(&self, winner: &Multiaddr, dialed: &Multiaddr, started: Instant) {
+        let mut dials = self.dials.lock().expect("no panics while locked");
+        if let Some(phases) = dials.remove(winner) {
+            dials.insert(dialed.clone(), Phases { started, ..phases });
+        }
+    }
+
+    /// Forgets the dial of `addr`, e.g. one that lost a race.
+    pub(crate) fn forget(&self, addr: &Multiaddr) {
+        self.take(addr);
+    }
+
+    fn take(&self, addr: &Multiaddr) -> Option<Phases> {
+        self.dials.lock().expect("no panics while locked").remove(addr)
+    }
+}
+
+/// Wraps a transport to note when each dial starts.
+pub(crate) struct Timed<T> {
+    inner: T,
+    timer: DialTimer,
+}
+
+impl<T> Timed<T> {
+    pub(crate) fn new(inner: T, timer: DialTimer) -> Self {
+        Self { inner, timer }
+    }
+}
+
+impl<T: Transport + Unpin> Transport for Timed<T> {
+    type Output = T::Output;
+    type Error = T::Error;
+    type ListenerUpgrade = T::ListenerUpgrade;
+    type Dial = T::Dial;
+
+    fn listen_on(&mut self, id: ListenerId, addr: Multiaddr) -> Result<(), TransportError<Self::Error>> {
+        self.inner.listen_on(id, addr)
+    }
+
+    fn remove_listener(&mut self, id: ListenerId) -> bool {
+        self.inner.remove_listener(id)
+    }
+
+    fn dial(&mut self, addr: Multiaddr) -> Result<Self::Dial, TransportError<Self::Error>> {
+        self.timer.started(&addr);
+        self.inner.dial(addr)
+    }
+
+    fn dial_as_listener(&mut self, addr: Multiaddr) -> Result<Self::Dial, TransportError<Self::Error>> {
+        self.timer.started(&addr);
+        self.inner.dial_as_listener(addr)
+    }
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<TransportEvent<Self::ListenerUpgrade, Self::Error>> {
+        Pin::new(&mut self.inner).poll(cx)
+    }
+
+    fn address_translation(&self, listen: &Multiaddr, observed: &Multiaddr) -> Option<Multiaddr> {
+        self.inner.address_translation(listen, observed)
+    }
+}
+
+/// Turns the phase timestamps of established connections into
+/// [`ConnectionTiming`]s once their first ping is answered.
+#[derive(Debug, Default)]
+pub(crate) struct ConnectionTimings {
+    timer: DialTimer,
+    /// Phases and establishment time of connections awaiting their first pong.
+    pending: HashMap<ConnectionId, (Phases, Instant)>,
+    /// Timings not yet taken.
+    done: HashMap<ConnectionId, ConnectionTiming>,
+}
+
+impl ConnectionTimings {
+    pub(crate) fn new(timer: DialTimer) -> Self {
+        Self { timer, pending: HashMap::new(), done: HashMap::new() }
+    }
+
+    pub(crate) fn observe(&mut self, event: &SwarmEvent<BehaviourEvent>) {
+        match event {
+            SwarmEvent::ConnectionEstablished { connection_id, endpoint: endpoint @ ConnectedPoint::Dialer { .. }, .. } => {
+                if let Some(phases) = self.timer.take(endpoint.get_remote_address()) {
+                    self.pending.insert(*connection_id, (phases, Instant::now()));
+                }
+            }
+            SwarmEvent::Behaviour(BehaviourEvent::Ping(ping::Event { connection, result: Ok(_), .. })) => {
+                if let Some((phases, established)) = self.pending.remove(connection) {
+                    self.done.insert(*connection, timing(&phases, established));
+                }
+            }
+            SwarmEvent::ConnectionClosed { connection_id, .. } => {
+                self.pending.remove(connection_id);
+                self.done.remove(connection_id);
+            }
+            _ => {}
+        }
+    }
+
+    pub(crate) fn take(&mut self, connection_id: ConnectionId) -> Option<ConnectionTiming> {
+        self.done.remove(&connection_id)
+    }
+}
+
+/// Splits the time from starting the dial to the first pong into phases.
+fn timing(phases: &Phases, established: Instant) -> ConnectionTiming {
+    let connected = phases.connected.unwrap_or(established);
+    ConnectionTiming {
+        connect: connected - phases.started,
+        security: phases.secured.map(|secured| secured - connected),
+        muxer: phases.muxed.zip(phases.secured).map(|(muxed, secured)| muxed - secured),
+        first_ping: established.elapsed(),
+    }
+}
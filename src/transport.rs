@@ -0,0 +1,400 @@
+//! Transport selection and construction.
+
+use either::Either;
+use futures::{future, AsyncRead, AsyncWrite};
+use libp2p::core::muxing::{StreamMuxer, StreamMuxerBox};
+use libp2p::core::transport::timeout::TransportTimeout;
+use libp2p::core::transport::{Boxed, MemoryTransport};
+use libp2p::core::upgrade;
+use libp2p::core::ConnectedPoint;
+use libp2p::identity::Keypair;
+use libp2p::multiaddr::Protocol;
+use libp2p::pnet::PnetConfig;
+use libp2p::websocket::{self, tls as ws_tls};
+use libp2p::{noise, quic, relay, tls, yamux, Multiaddr, PeerId, Transport};
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fmt;
+use std::io::{self, BufReader};
+use std::net::IpAddr;
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::bandwidth::Bandwidth;
+use crate::bind::BoundTcp;
+use crate::race::{Racing, Races};
+use crate::security::{SecurityChoice, SelectSecurity};
+use crate::timing::{DialTimer, Timed};
+use crate::webtransport::WebTransport;
+use crate::{NodeConfig, PingError};
+
+/// A fully upgraded transport yielding authenticated, multiplexed connections.
+pub(crate) type BoxedTransport = Boxed<(PeerId, StreamMuxerBox)>;
+
+/// The transports a [`PingNode`](crate::PingNode) can be built with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TransportChoice {
+    /// TCP secured with the configured [`SecurityChoice`](crate::SecurityChoice) and
+    /// multiplexed with Yamux.
+    Tcp,
+    /// QUIC, which brings its own encryption and multiplexing.
+    Quic,
+    /// WebSocket over TCP, secured and multiplexed like TCP; reachable from
+    /// browsers. `/wss` addresses add TLS at the WebSocket layer.
+    Ws,
+    /// In-process connections between nodes of the same program at
+    /// `/memory/<port>` addresses, secured and multiplexed like TCP; see
+    /// [`testing`](crate::testing).
+    Memory,
+    /// WebTransport over QUIC, which browsers dial directly. Always secured
+    /// with Noise; listen addresses end with the `/certhash` of the
+    /// self-signed certificates the browsers check.
+    ///
+    /// Experimental: only tried between nodes of this crate so far, not with
+    /// browsers running js-libp2p.
+    WebTransport,
+}
+
+impl TransportChoice {
+    /// Returns the address to listen on for this transport at `ip` with a
+    /// random OS-assigned port, e.g. `/ip6/::/tcp/0` for all IPv6 interfaces;
+    /// memory addresses have no IP, just a random port.
+    pub fn listen_addr(self, ip: IpAddr) -> Multiaddr {
+        let addr = Multiaddr::from(ip);
+        match self {
+            TransportChoice::Tcp => addr.with(Protocol::Tcp(0)),
+            TransportChoice::Quic => addr.with(Protocol::Udp(0)).with(Protocol::QuicV1),
+            TransportChoice::Ws => addr.with(Protocol::Tcp(0)).with(Protocol::Ws("/".into())),
+            TransportChoice::Memory => Multiaddr::empty().with(Protocol::Memory(0)),
+            TransportChoice::WebTransport => {
+                addr.with(Protocol::Udp(0)).with(Protocol::QuicV1).with(Protocol::WebTransport)
+            }
+        }
+    }
+
+    /// Returns the transport that dials `addr`, or `None` for `/p2p-circuit`
+    /// and other addresses none of them can dial.
+    pub fn for_addr(addr: &Multiaddr) -> Option<Self> {
+        let mut transport = None;
+        for protocol in addr.iter() {
+            transport = match protocol {
+                Protocol::P2pCircuit => return None,
+                Protocol::Tcp(_) => Some(TransportChoice::Tcp),
+                Protocol::QuicV1 => Some(TransportChoice::Quic),
+                Protocol::Ws(_) | Protocol::Wss(_) => Some(TransportChoice::Ws),
+                Protocol::Memory(_) => Some(TransportChoice::Memory),
+                Protocol::WebTransport => Some(TransportChoice::WebTransport),
+                _ => continue,
+            };
+        }
+        transport
+    }
+}
+
+impl fmt::Display for TransportChoice {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TransportChoice::Tcp => f.write_str("tcp"),
+            TransportChoice::Quic => f.write_str("quic"),
+            TransportChoice::Ws => f.write_str("ws"),
+            TransportChoice::Memory => f.write_str("memory"),
+            TransportChoice::WebTransport => f.write_str("webtransport"),
+        }
+    }
+}
+
+/// Certificate and private key for listening on `/wss` addresses.
+#[derive(Clone)]
+pub struct WsTls {
+    /// DER-encoded certificate chain, leaf first.
+    cert_chain: Vec<Vec<u8>>,
+    /// DER-encoded PKCS#8, PKCS#1 or SEC1 private key.
+    key: Vec<u8>,
+}
+
+impl WsTls {
+    /// Reads a PEM certificate chain and private key, e.g. as issued by Let's Encrypt.
+    pub fn from_pem_files(cert: &Path, key: &Path) -> Result<Self, PingError> {
+        let open = |path: &Path| {
+            std::fs::File::open(path)
+                .map(BufReader::new)
+                .map_err(|e| PingError::identity(path, e))
+        };
+        let cert_chain = rustls_pemfile::certs(&mut open(cert)?)
+            .map(|cert| cert.map(|cert| cert.to_vec()))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| PingError::identity(cert, e))?;
+        if cert_chain.is_empty() {
+            return Err(PingError::identity(cert, "no certificates found"));
+        }
+        let key = rustls_pemfile::private_key(&mut open(key)?)
+            .map_err(|e| PingError::identity(key, e))?
+            .ok_or_else(|| PingError::identity(key, "no private key found"))?;
+        Ok(Self {
+            cert_chain,
+            key: key.secret_der().to_vec(),
+        })
+    }
+}
+
+impl fmt::Debug for WsTls {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WsTls")
+            .field("cert_chain", &format!("[{} certificates]", self.cert_chain.len()))
+            .finish_non_exhaustive()
+    }
+}
+
+impl FromStr for TransportChoice {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "tcp" => Ok(TransportChoice::Tcp),
+            "quic" => Ok(TransportChoice::Quic),
+            "ws" => Ok(TransportChoice::Ws),
+            "memory" => Ok(TransportChoice::Memory),
+            "webtransport" => Ok(TransportChoice::WebTransport),
+            other => Err(format!(
+                "unknown transport `{other}`, expected `tcp`, `quic`, `ws`, `webtransport` or `memory`"
+            )),
+        }
+    }
+}
+
+/// Builds a transport combining every transport enabled in `config` with the
+/// relay client transport, which handles `/p2p-circuit` addresses.
+///
+/// Dials fail after [`NodeConfig::dial_timeout`], and their phases are noted
+/// in `timer`. The addresses of a dial race each other as set up in `races`.
+/// The bytes of every connection are counted in `bandwidth`.
+pub(crate) fn build(
+    keypair: &Keypair,
+    config: &NodeConfig,
+    relay: relay::client::Transport,
+    timer: DialTimer,
+    races: Races,
+    bandwidth: Bandwidth,
+) -> Result<BoxedTransport, Box<dyn Error + Send + Sync>> {
+    if config.transports.is_empty() {
+        return Err("at least one transport must be enabled".into());
+    }
+    let udp = config
+        .transports
+        .iter()
+        .any(|choice| matches!(choice, TransportChoice::Quic | TransportChoice::WebTransport));
+    if config.psk.is_some() && udp {
+        return Err("QUIC and WebTransport can't be used in a private network, \
+                    the pre-shared key only protects TCP and WebSocket"
+            .into());
+    }
+    if config.proxy.is_some() && udp {
+        return Err("QUIC and WebTransport can't be used with a proxy, which only carries TCP and WebSocket".into());
+    }
+    // TCP, QUIC and WebTransport share one racing DNS resolver, which takes
+    // every address and only fails dialing those its transport can't handle
+    // once resolved, so it comes after the transports that turn addresses
+    // down right away.
+    // A proxy resolving DNS names takes TCP out of it.
+    let remote_dns = config.proxy.as_ref().is_some_and(|proxy| proxy.remote_dns);
+    let (mut resolved, mut others) = (Vec::new(), Vec::new());
+    for choice in &config.transports {
+        match choice {
+            TransportChoice::Tcp if remote_dns => others.push(build_tcp(keypair, config, timer.clone())?),
+            TransportChoice::Tcp => resolved.push(build_tcp(keypair, config, timer.clone())?),
+            TransportChoice::Quic => resolved.push(build_quic(keypair, timer.clone())),
+            TransportChoice::WebTransport => resolved.push(build_webtransport(keypair, timer.clone())),
+            TransportChoice::Ws => others.push(build_ws(keypair, config, timer.clone())?),
+            TransportChoice::Memory => others.push(secure(MemoryTransport::default(), keypair, config, timer.clone())?),
+        }
+    }
+    // Circuits are tried first as the other transports can't dial them anyway.
+    let first = secure(relay, keypair, config, timer.clone())?;
+    let mut combined = others.into_iter().fold(first, either);
+    let mut resolved = resolved.into_iter();
+    if let Some(first) = resolved.next() {
+        let racing = Racing::new(resolved.fold(first, either), config.race_delay, races, timer);
+        combined = either(combined, racing.boxed());
+    }
+    let metered = combined.map(move |(peer_id, muxer), _| (peer_id, bandwidth.meter(&peer_id, muxer)));
+    Ok(TransportTimeout::with_outgoing_timeout(metered, config.dial_timeout).boxed())
+}
+
+/// Tries `first`, then `second` for the addresses `first` doesn't support.
+fn either(first: BoxedTransport, second: BoxedTransport) -> BoxedTransport {
+    first.or_transport(second).map(|either, _| either.into_inner()).boxed()
+}
+
+/// Wraps the error of either of two steps of a transport into an I/O error,
+/// as an [`Either`] hides the error it holds from the `source` chain that
+/// [`DialErrorKind`](crate::DialErrorKind) walks.
+fn flatten<A, B>(error: Either<A, B>) -> io::Error
+where
+    A: Error + Send + Sync + 'static,
+    B: Error + Send + Sync + 'static,
+{
+    error.either(io::Error::other, io::Error::other)
+}
+
+/// Wraps the error of an upgraded transport like [`flatten`]: that of the
+/// transport, the security or the multiplexer handshake.
+fn flatten_upgrade<A, B, C>(error: Either<Either<A, B>, C>) -> io::Error
+where
+    A: Error + Send + Sync + 'static,
+    B: Error + Send + Sync + 'static,
+    C: Error + Send + Sync + 'static,
+{
+    flatten(error.map_left(flatten))
+}
+
+/// TCP dialing from [`NodeConfig::local_binding`] with the
+/// [`NodeConfig::tcp_options`], through [`NodeConfig::proxy`] if set, upgraded
+/// with the selected security protocol(s) and Yamux.
+fn build_tcp(keypair: &Keypair, config: &NodeConfig, timer: DialTimer) -> Result<BoxedTransport, Box<dyn Error + Send + Sync>> {
+    secure(BoundTcp::new(config), keypair, config, timer)
+}
+
+/// WebSocket over TCP with DNS resolution, upgraded like TCP.
+///
+/// Dialing `/wss` verifies the server against the web PKI roots; listening on
+/// `/wss` requires [`NodeConfig::ws_tls`].
+fn build_ws(keypair: &Keypair, config: &NodeConfig, timer: DialTimer) -> Result<BoxedTransport, Box<dyn Error + Send + Sync>> {
+    let tcp = BoundTcp::new(config);
+    // A proxy resolving DNS names gets them as they are.
+    if config.proxy.as_ref().is_some_and(|proxy| proxy.remote_dns) {
+        return secure(websocket_over(tcp, config)?, keypair, config, timer);
+    }
+    // Dials are timed by their `/ws` address, and the races of the addresses
+    // it resolves to aren't reported.
+    let racing = Racing::new(tcp, config.race_delay, Races::default(), DialTimer::default());
+    secure(websocket_over(racing, config)?, keypair, config, timer)
+}
+
+/// WebSocket over a TCP transport, with TLS for listening on `/wss` if configured.
+fn websocket_over<T>(tcp: T, config: &NodeConfig) -> Result<websocket::WsConfig<T>, Box<dyn Error + Send + Sync>>
+where
+    T: Transport + Send + Unpin + 'static,
+    T::Output: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    T::Error: Send + 'static,
+    T::Dial: Send + 'static,
+    T::ListenerUpgrade: Send + 'static,
+{
+    let mut ws = websocket::WsConfig::new(tcp);
+    if let Some(tls) = &config.ws_tls {
+        let certs = tls.cert_chain.iter().cloned().map(ws_tls::Certificate::new);
+        ws.set_tls_config(ws_tls::Config::new(ws_tls::PrivateKey::new(tls.key.clone()), certs)?);
+    }
+    Ok(ws)
+}
+
+/// Upgrades a stream-based transport with the selected security protocol(s) and
+/// Yamux, behind the private network protector if [`NodeConfig::psk`] is set.
+fn secure<T>(
+    transport: T,
+    keypair: &Keypair,
+    config: &NodeConfig,
+    timer: DialTimer,
+) -> Result<BoxedTransport, Box<dyn Error + Send + Sync>>
+where
+    T: Transport + Send + Unpin + 'static,
+    T::Output: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    T::Error: Send + Sync,
+    T::Dial: Send,
+    T::ListenerUpgrade: Send,
+{
+    let connected = timer.clone();
+    let transport = Timed::new(transport, timer.clone())
+        .and_then(move |socket, endpoint| {
+            connected.connected(&endpoint);
+            future::ready(Ok::<_, io::Error>(socket))
+        })
+        .map_err(flatten);
+    match config.psk {
+        Some(psk) => authenticate(
+            transport.and_then(move |socket, _| PnetConfig::new(psk).handshake(socket)).map_err(flatten),
+            keypair,
+            config.security,
+            timer,
+        ),
+        None => authenticate(transport, keypair, config.security, timer),
+    }
+}
+
+/// Upgrades a stream-based transport with the selected security protocol(s) and Yamux.
+fn authenticate<T>(
+    transport: T,
+    keypair: &Keypair,
+    security: SecurityChoice,
+    timer: DialTimer,
+) -> Result<BoxedTransport, Box<dyn Error + Send + Sync>>
+where
+    T: Transport + Send + Unpin + 'static,
+    T::Output: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    T::Error: Send + Sync,
+    T::Dial: Send,
+    T::ListenerUpgrade: Send,
+{
+    // The multiplexer is chosen once the security handshake is done.
+    let secured = timer.clone();
+    let multiplexer = move |_: &PeerId, endpoint: &ConnectedPoint| {
+        secured.secured(endpoint);
+        yamux::Config::default() // Use Yamux for stream multiplexing.
+    };
+    let upgraded = transport.upgrade(upgrade::Version::V1Lazy);
+    let transport = match security {
+        SecurityChoice::Tls => upgraded
+            .authenticate(tls::Config::new(keypair)?)
+            .multiplex_ext(multiplexer)
+            .map_err(flatten_upgrade)
+            .map(muxed(timer))
+            .boxed(),
+        SecurityChoice::Noise => upgraded
+            .authenticate(noise::Config::new(keypair)?)
+            .multiplex_ext(multiplexer)
+            .map_err(flatten_upgrade)
+            .map(muxed(timer))
+            .boxed(),
+        SecurityChoice::Both => upgraded
+            .authenticate(SelectSecurity(tls::Config::new(keypair)?, noise::Config::new(keypair)?))
+            .multiplex_ext(multiplexer)
+            .map_err(flatten_upgrade)
+            .map(muxed(timer))
+            .boxed(),
+    };
+    Ok(transport)
+}
+
+/// Boxes the muxer of each connection, noting in `timer` that it is ready.
+fn muxed<M>(timer: DialTimer) -> impl FnOnce((PeerId, M), ConnectedPoint) -> (PeerId, StreamMuxerBox) + Clone
+where
+    M: StreamMuxer + Send + 'static,
+    M::Substream: Send + 'static,
+    M::Error: Send + Sync + 'static,
+{
+    move |(peer_id, muxer), endpoint| {
+        timer.muxed(&endpoint);
+        (peer_id, StreamMuxerBox::new(muxer))
+    }
+}
+
+/// QUIC, with its built-in TLS 1.3 security and stream multiplexing.
+fn build_quic(keypair: &Keypair, timer: DialTimer) -> BoxedTransport {
+    let quic = quic::tokio::Transport::new(quic::Config::new(keypair));
+    Timed::new(quic, timer.clone())
+        .map(move |(peer_id, connection), endpoint| {
+            timer.connected(&endpoint);
+            (peer_id, StreamMuxerBox::new(connection))
+        })
+        .boxed()
+}
+
+/// WebTransport, secured with Noise and multiplexed by its own streams.
+fn build_webtransport(keypair: &Keypair, timer: DialTimer) -> BoxedTransport {
+    Timed::new(WebTransport::new(keypair), timer.clone())
+        .map(move |(peer_id, connection), endpoint| {
+            timer.connected(&endpoint);
+            (peer_id, StreamMuxerBox::new(connection))
+        })
+        .boxed()
+}
@@ -0,0 +1,312 @@
+//! Full-screen dashboard of peers and their round-trip times (`--tui`), with a
+//! plot of the RTTs over time (`--graph`).
+
+use crossterm::event::{Event, EventStream, KeyCode, KeyEventKind, KeyModifiers};
+use futures::StreamExt;
+use libp2p::swarm::{ConnectionId, SwarmEvent};
+use libp2p::{Multiaddr, PeerId};
+use libp2p_ping_tut::{BehaviourEvent, PingStats};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Color, Modifier, Style, Stylize};
+use ratatui::symbols::Marker;
+use ratatui::text::Line;
+use ratatui::widgets::{Axis, Block, Cell, Chart, Dataset, GraphType, Row, Table};
+use ratatui::DefaultTerminal;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::io;
+use std::time::{Duration, Instant};
+
+/// How often the dashboard is redrawn.
+const REDRAW_INTERVAL: Duration = Duration::from_millis(250);
+
+/// How many recent RTTs the sparkline of each peer shows.
+const HISTORY: usize = 40;
+
+/// Bars of increasing height used to draw sparklines.
+const BARS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+/// How far back the graph reaches.
+const GRAPH_WINDOW: Duration = Duration::from_secs(120);
+
+/// Colors of the graph's series, handed out to peers in turn.
+const SERIES_COLORS: [Color; 6] = [Color::Cyan, Color::Magenta, Color::Yellow, Color::Green, Color::Blue, Color::Red];
+
+/// What the dashboard knows about one peer.
+struct PeerRow {
+    /// Remote address of the most recent connection.
+    address: Option<Multiaddr>,
+    /// Open connections and whether each goes through a relay.
+    connections: HashMap<ConnectionId, bool>,
+    /// Recent RTTs, oldest first; `None` for failed pings.
+    history: VecDeque<Option<Duration>>,
+    /// Successful pings within the graph window, oldest first.
+    samples: VecDeque<(Instant, Duration)>,
+    stats: PingStats,
+    /// Color of the peer's series in the graph.
+    color: Color,
+}
+
+impl PeerRow {
+    fn new(color: Color) -> Self {
+        Self {
+            address: None,
+            connections: HashMap::new(),
+            history: VecDeque::new(),
+            samples: VecDeque::new(),
+            stats: PingStats::default(),
+            color,
+        }
+    }
+
+    /// Returns the samples as seconds before `now` and RTTs in milliseconds,
+    /// dropping those that left the graph window.
+    fn points(&mut self, now: Instant) -> Vec<(f64, f64)> {
+        while self.samples.front().is_some_and(|(at, _)| now - *at > GRAPH_WINDOW) {
+            self.samples.pop_front();
+        }
+        self.samples
+            .iter()
+            .map(|(at, rtt)| (-(now - *at).as_secs_f64(), rtt.as_secs_f64() * 1000.0))
+            .collect()
+    }
+
+    fn state(&self) -> (&'static str, Color) {
+        if self.connections.is_empty() {
+            ("disconnected", Color::Red)
+        } else if self.connections.values().all(|&relayed| relayed) {
+            ("relayed", Color::Yellow)
+        } else {
+            ("direct", Color::Green)
+        }
+    }
+
+    /// Draws the recent RTTs scaled to the slowest one, with `×` for
+    /// failed pings.
+    fn sparkline(&self) -> String {
+        let max = self.history.iter().flatten().max().copied().unwrap_or_default();
+        self.history
+            .iter()
+            .map(|rtt| match rtt {
+                Some(rtt) if !max.is_zero() => {
+                    let level = rtt.as_secs_f64() / max.as_secs_f64() * (BARS.len() - 1) as f64;
+                    BARS[level.round() as usize]
+                }
+                Some(_) => BARS[0],
+                None => '×',
+            })
+            .collect()
+    }
+}
+
+/// Live table of all peers the node is or was connected to.
+pub struct Dashboard {
+    terminal: DefaultTerminal,
+    keys: EventStream,
+    redraw: tokio::time::Interval,
+    local_peer_id: PeerId,
+    peers: BTreeMap<PeerId, PeerRow>,
+    /// Names given to peers, shown instead of their PeerIds.
+    names: HashMap<PeerId, String>,
+    /// Whether to plot the RTTs above the table.
+    graph: bool,
+}
+
+impl Dashboard {
+    /// Switches the terminal to the dashboard, with the RTT plot if `graph`
+    /// is set; the terminal is restored on drop.
+    pub fn new(local_peer_id: PeerId, graph: bool) -> Self {
+        Self {
+            terminal: ratatui::init(),
+            keys: EventStream::new(),
+            redraw: tokio::time::interval(REDRAW_INTERVAL),
+            local_peer_id,
+            peers: BTreeMap::new(),
+            names: HashMap::new(),
+            graph,
+        }
+    }
+
+    /// Returns the row of `peer_id`, adding it with the next series color.
+    fn row(&mut self, peer_id: PeerId) -> &mut PeerRow {
+        let color = SERIES_COLORS[self.peers.len() % SERIES_COLORS.len()];
+        self.peers.entry(peer_id).or_insert_with(|| PeerRow::new(color))
+    }
+
+    /// Shows `name` instead of the PeerId of `peer_id`.
+    pub fn name_peer(&mut self, peer_id: PeerId, name: &str) {
+        self.names.insert(peer_id, name.to_owned());
+    }
+
+    /// Updates the peer table from a swarm event.
+    pub fn observe(&mut self, event: &SwarmEvent<BehaviourEvent>) {
+        match event {
+            SwarmEvent::ConnectionEstablished { peer_id, connection_id, endpoint, .. } => {
+                let row = self.row(*peer_id);
+                row.address = Some(endpoint.get_remote_address().clone());
+                row.connections.insert(*connection_id, endpoint.is_relayed());
+            }
+            SwarmEvent::ConnectionClosed { peer_id, connection_id, .. } => {
+                if let Some(row) = self.peers.get_mut(peer_id) {
+                    row.connections.remove(connection_id);
+                }
+            }
+            SwarmEvent::Behaviour(BehaviourEvent::Ping(event)) => {
+                let row = self.row(event.peer);
+                match event.result {
+                    Ok(rtt) => {
+                        row.stats.record_success(rtt);
+                        row.samples.push_back((Instant::now(), rtt));
+                    }
+                    Err(_) => row.stats.record_failure(),
+                }
+                if row.history.len() == HISTORY {
+                    row.history.pop_front();
+                }
+                row.history.push_back(event.result.as_ref().ok().copied());
+            }
+            _ => {}
+        }
+    }
+
+    /// Redraws the dashboard when it is due, or handles a key press.
+    ///
+    /// Returns `true` once the user asks to quit with `q`, `Esc` or Ctrl-C;
+    /// the raw terminal doesn't turn Ctrl-C into SIGINT.
+    pub async fn update(&mut self) -> io::Result<bool> {
+        tokio::select! {
+            _ = self.redraw.tick() => {
+                self.draw()?;
+                Ok(false)
+            }
+            Some(event) = self.keys.next() => {
+                let Event::Key(key) = event? else {
+                    return Ok(false);
+                };
+                let ctrl_c = key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL);
+                Ok(key.kind == KeyEventKind::Press && (ctrl_c || matches!(key.code, KeyCode::Char('q') | KeyCode::Esc)))
+            }
+        }
+    }
+
+    fn draw(&mut self) -> io::Result<()> {
+        let header = Line::from(vec![
+            "libp2p ping ".bold(),
+            format!("— local peer {}", self.local_peer_id).into(),
+        ]);
+        let rows: Vec<Row> = self
+            .peers
+            .iter()
+            .map(|(peer_id, row)| {
+                let (state, color) = row.state();
+                let ms = |rtt: Option<Duration>| rtt.map_or("-".to_owned(), |rtt| format!("{:.1}", rtt.as_secs_f64() * 1000.0));
+                // Tie the rows to the series of the graph.
+                let name = Cell::from(self.names.get(peer_id).cloned().unwrap_or_else(|| peer_id.to_string()));
+                Row::new(vec![
+                    if self.graph { name.style(Style::new().fg(row.color)) } else { name },
+                    Cell::from(row.address.as_ref().map_or_else(String::new, ToString::to_string)),
+                    Cell::from(state),
+                    Cell::from(ms(row.history.back().copied().flatten())),
+                    Cell::from(ms(row.stats.avg())),
+                    Cell::from(format!("{:.1}%", row.stats.loss_percent())),
+                    Cell::from(row.sparkline()),
+                ])
+                .style(Style::new().fg(color))
+            })
+            .collect();
+        let widths = [
+            Constraint::Length(52),
+            Constraint::Fill(1),
+            Constraint::Length(12),
+            Constraint::Length(9),
+            Constraint::Length(9),
+            Constraint::Length(7),
+            Constraint::Length(HISTORY as u16),
+        ];
+        let table = Table::new(rows, widths)
+            .header(
+                Row::new(["Peer", "Address", "State", "RTT ms", "Avg ms", "Loss", "History"])
+                    .style(Style::new().add_modifier(Modifier::BOLD)),
+            )
+            .block(Block::bordered().title(header));
+        let footer = Line::from("q: quit").dim();
+
+        let now = Instant::now();
+        let series: Vec<Series> = if self.graph {
+            self.peers
+                .iter_mut()
+                .map(|(peer_id, row)| Series {
+                    name: self.names.get(peer_id).cloned().unwrap_or_else(|| peer_id.to_string()),
+                    color: row.color,
+                    points: row.points(now),
+                })
+                .collect()
+        } else {
+            Vec::new()
+        };
+        let table_height = self.peers.len() as u16 + 3;
+
+        self.terminal.draw(|frame| {
+            let [main, help] = Layout::vertical([Constraint::Fill(1), Constraint::Length(1)]).areas(frame.area());
+            if self.graph {
+                let [graph, main] = Layout::vertical([Constraint::Fill(1), Constraint::Length(table_height)]).areas(main);
+                frame.render_widget(chart(&series), graph);
+                frame.render_widget(table, main);
+            } else {
+                frame.render_widget(table, main);
+            }
+            frame.render_widget(footer, help);
+        })?;
+        Ok(())
+    }
+}
+
+/// The RTTs of one peer in the graph.
+struct Series {
+    name: String,
+    color: Color,
+    /// Seconds before now and RTT in milliseconds of each sample.
+    points: Vec<(f64, f64)>,
+}
+
+/// Plots each series of RTTs in milliseconds over the seconds before now,
+/// scaled to the slowest RTT shown.
+fn chart(series: &[Series]) -> Chart<'_> {
+    let max = series
+        .iter()
+        .flat_map(|series| series.points.iter().map(|(_, ms)| *ms))
+        .fold(0.0, f64::max)
+        .max(1.0)
+        * 1.1;
+    let datasets = series
+        .iter()
+        .map(|series| {
+            Dataset::default()
+                .name(series.name.as_str())
+                .marker(Marker::Braille)
+                .graph_type(GraphType::Line)
+                .style(Style::new().fg(series.color))
+                .data(&series.points)
+        })
+        .collect();
+    let window = GRAPH_WINDOW.as_secs_f64();
+    Chart::new(datasets)
+        .block(Block::bordered().title("RTT (ms)"))
+        .x_axis(
+            Axis::default()
+                .bounds([-window, 0.0])
+                .labels([format!("-{}s", window), format!("-{}s", window / 2.0), "now".to_owned()])
+                .style(Style::new().dim()),
+        )
+        .y_axis(
+            Axis::default()
+                .bounds([0.0, max])
+                .labels(["0".to_owned(), format!("{:.1}", max / 2.0), format!("{max:.1}")])
+                .style(Style::new().dim()),
+        )
+}
+
+impl Drop for Dashboard {
+    fn drop(&mut self) {
+        ratatui::restore();
+    }
+}
@@ -0,0 +1,70 @@
+//! Pinging peers from a browser, in builds for `wasm32-unknown-unknown`.
+//!
+//! Browsers can only open WebSocket and WebTransport connections, so the peer
+//! has to listen on a `/ws` or `/wss` address with Noise, such as a node run
+//! with `--transport ws --security noise`, or on a `/webtransport` address
+//! with its `/certhash`, such as one run with `--transport webtransport`.
+//! Build with `wasm-pack build --target web`, then from JavaScript:
+//!
+//! ```text
+//! import init, { pingPeer } from "./pkg/libp2p_ping_tut.js";
+//! await init();
+//! const rtt = await pingPeer("/ip4/127.0.0.1/tcp/4001/ws", 5);
+//! ```
+
+use futures::StreamExt;
+use libp2p::core::muxing::StreamMuxerBox;
+use libp2p::core::upgrade::Version;
+use libp2p::core::Transport;
+use libp2p::swarm::SwarmEvent;
+use libp2p::{noise, ping, websocket_websys, webtransport_websys, yamux, Multiaddr};
+use std::time::Duration;
+use wasm_bindgen::prelude::*;
+
+use crate::PingStats;
+
+/// Interval between the pings after the first one.
+const PING_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How long the connection is kept open between pings.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Dials `multiaddr` and resolves to the average RTT in milliseconds of
+/// `count` pings, one by default, or rejects on the first failure.
+#[wasm_bindgen(js_name = pingPeer)]
+pub async fn ping_peer(multiaddr: String, count: Option<u32>) -> Result<f64, JsError> {
+    let addr: Multiaddr = multiaddr.parse()?;
+    let count = count.unwrap_or(1).max(1) as u64;
+    let mut swarm = libp2p::SwarmBuilder::with_new_identity()
+        .with_wasm_bindgen()
+        .with_other_transport(|keypair| {
+            let websocket = websocket_websys::Transport::default()
+                .upgrade(Version::V1Lazy)
+                .authenticate(noise::Config::new(keypair)?)
+                .multiplex(yamux::Config::default())
+                .map(|(peer_id, muxer), _| (peer_id, StreamMuxerBox::new(muxer)));
+            let webtransport = webtransport_websys::Transport::new(webtransport_websys::Config::new(keypair))
+                .map(|(peer_id, connection), _| (peer_id, StreamMuxerBox::new(connection)));
+            Ok(websocket.or_transport(webtransport).map(|either, _| either.into_inner()))
+        })?
+        .with_behaviour(|_| ping::Behaviour::new(ping::Config::new().with_interval(PING_INTERVAL)))?
+        .with_swarm_config(|config| config.with_idle_connection_timeout(IDLE_TIMEOUT))
+        .build();
+    swarm.dial(addr.clone())?;
+
+    let mut stats = PingStats::default();
+    while stats.received() < count {
+        match swarm.select_next_some().await {
+            SwarmEvent::Behaviour(ping::Event { result: Ok(rtt), .. }) => stats.record_success(rtt),
+            SwarmEvent::Behaviour(ping::Event { result: Err(e), .. }) => {
+                return Err(JsError::new(&format!("ping to {addr} failed: {e}")));
+            }
+            SwarmEvent::OutgoingConnectionError { error, .. } => {
+                return Err(JsError::new(&format!("failed to dial {addr}: {error}")));
+            }
+            _ => {}
+        }
+    }
+    let avg = stats.avg().expect("answered pings have an average");
+    Ok(avg.as_secs_f64() * 1000.0)
+}
@@ -0,0 +1,117 @@
+//! Notifications of peer health changes and threshold breaches, POSTed as JSON
+//! to a webhook (`--webhook`).
+//!
+//! Every notification carries a `text` summary besides its fields, which is
+//! what chat webhooks such as Slack's display.
+
+use libp2p::PeerId;
+use reqwest::Url;
+use serde::Serialize;
+use std::time::{Duration, SystemTime};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+use crate::targets::Transition;
+
+/// How many notifications may wait for a slow webhook before new ones are
+/// dropped.
+const QUEUE_SIZE: usize = 64;
+
+/// How long a webhook gets to answer a notification.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The body of a notification.
+#[derive(Debug, Serialize)]
+struct Notification {
+    timestamp: String,
+    text: String,
+    #[serde(flatten)]
+    event: Event,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+enum Event {
+    HealthChanged {
+        target: String,
+        peer_id: Option<String>,
+        from: Option<String>,
+        to: String,
+    },
+    ThresholdBreached {
+        target: String,
+        peer_id: Option<String>,
+        reasons: Vec<String>,
+    },
+}
+
+/// Sends notifications to a webhook in the background, one at a time and in
+/// order; failures are logged.
+pub struct Webhook {
+    queue: mpsc::Sender<Notification>,
+    delivery: JoinHandle<()>,
+}
+
+impl Webhook {
+    pub fn new(url: Url) -> Self {
+        let (queue, notifications) = mpsc::channel(QUEUE_SIZE);
+        let delivery = tokio::spawn(deliver(url, notifications));
+        Self { queue, delivery }
+    }
+
+    /// Waits for the queued notifications to be sent, as long as the webhook
+    /// answers in time.
+    pub async fn flush(self) {
+        drop(self.queue);
+        let _ = tokio::time::timeout(REQUEST_TIMEOUT, self.delivery).await;
+    }
+
+    /// Notifies that `target` went up, degraded or down.
+    pub fn health_changed(&self, target: String, peer_id: Option<&PeerId>, transition: &Transition) {
+        let text = match transition.from {
+            Some(from) => format!("{target} is {} (was {from})", transition.to),
+            None => format!("{target} is {}", transition.to),
+        };
+        self.send(
+            text,
+            Event::HealthChanged {
+                target,
+                peer_id: peer_id.map(ToString::to_string),
+                from: transition.from.map(|from| from.to_string()),
+                to: transition.to.to_string(),
+            },
+        );
+    }
+
+    /// Notifies that the results of `target` started violating the thresholds.
+    pub fn threshold_breached(&self, target: String, peer_id: Option<&PeerId>, reasons: Vec<String>) {
+        let text = format!("{target} breaches its thresholds: {}", reasons.join("; "));
+        self.send(text, Event::ThresholdBreached { target, peer_id: peer_id.map(ToString::to_string), reasons });
+    }
+
+    fn send(&self, text: String, event: Event) {
+        let timestamp = humantime::format_rfc3339_micros(SystemTime::now()).to_string();
+        if self.queue.try_send(Notification { timestamp, text, event }).is_err() {
+            tracing::warn!("webhook is falling behind, dropping a notification");
+        }
+    }
+}
+
+/// POSTs each queued notification to `url`.
+async fn deliver(url: Url, mut notifications: mpsc::Receiver<Notification>) {
+    let client = reqwest::Client::builder()
+        .timeout(REQUEST_TIMEOUT)
+        .build()
+        .expect("the TLS backend initializes");
+    while let Some(notification) = notifications.recv().await {
+        let result = client
+            .post(url.clone())
+            .json(&notification)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status());
+        if let Err(e) = result {
+            tracing::warn!("webhook notification failed: {e}");
+        }
+    }
+}
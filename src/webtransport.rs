@@ -0,0 +1,595 @@
+//! WebTransport over QUIC, which browsers running js-libp2p dial directly,
+//! without a relay or a reverse proxy in front of a WebSocket listener.
+//!
+//! Browsers connect with an HTTP/3 request for [`PATH`] and run the Noise
+//! handshake as initiator on the first bidirectional stream of the session.
+//! Every stream they open after it is a substream, so there is no Yamux.
+//!
+//! Listeners serve self-signed certificates, which browsers accept by the
+//! hashes in the `/certhash` parts of the listen address rather than by a CA,
+//! and only for at most 14 days. Each listener therefore moves on to the next
+//! certificate every [`ROTATION`]; its addresses carry the hashes of the
+//! certificate served and of the next one, and the Noise handshake confirms
+//! those and the previous one, so addresses learned before a rotation still
+//! work after it.
+//!
+//! This is experimental: it follows the libp2p WebTransport spec, but has only
+//! been tried between nodes of this crate, not with js-libp2p in a browser.
+
+use futures::future::{BoxFuture, Fuse};
+use futures::{ready, AsyncRead, AsyncWrite, FutureExt};
+use libp2p::core::muxing::{StreamMuxer, StreamMuxerEvent};
+use libp2p::core::transport::{ListenerId, TransportError, TransportEvent};
+use libp2p::core::upgrade::{InboundConnectionUpgrade, OutboundConnectionUpgrade};
+use libp2p::identity::Keypair;
+use libp2p::multiaddr::Protocol;
+use libp2p::multihash::Multihash;
+use libp2p::{noise, Multiaddr, PeerId, Transport};
+use std::collections::{HashSet, VecDeque};
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV6};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll, Waker};
+use std::time::Duration;
+use tokio::io::ReadBuf;
+use tokio::time::{Instant, Sleep};
+use wtransport::config::Ipv6DualStackConfig;
+use wtransport::endpoint::endpoint_side::{Client, Server};
+use wtransport::endpoint::IncomingSession;
+use wtransport::error::ConnectionError;
+use wtransport::stream::BiStream;
+use wtransport::tls::client::{build_default_tls_config, NoServerVerification, ServerHashVerification};
+use wtransport::tls::rustls::client::danger::ServerCertVerifier;
+use wtransport::tls::rustls::client::WebPkiServerVerifier;
+use wtransport::tls::rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+use wtransport::tls::rustls::RootCertStore;
+use wtransport::tls::{build_native_cert_store, Sha256Digest};
+use wtransport::{ClientConfig, Endpoint, Identity, ServerConfig, VarInt};
+
+/// Path of the HTTP/3 requests opening libp2p sessions.
+const PATH: &str = "/.well-known/libp2p-webtransport?type=noise";
+
+/// Multihash code of SHA-256, the only hash of certificates browsers take.
+const SHA2_256: u64 = 0x12;
+
+/// How long a listener serves a certificate; each is valid for 14 days, and
+/// the next one is advertised a rotation ahead.
+const ROTATION: Duration = Duration::from_secs(6 * 24 * 60 * 60);
+
+/// Interval of QUIC keep-alive packets, so that QUIC doesn't close sessions
+/// that libp2p keeps open.
+const KEEP_ALIVE: Duration = Duration::from_secs(5);
+
+type Upgrade = BoxFuture<'static, io::Result<(PeerId, Connection)>>;
+
+/// Listens on and dials `/ip4|ip6/<ip>/udp/<port>/quic-v1/webtransport`
+/// addresses, always secured with Noise.
+pub(crate) struct WebTransport {
+    keypair: Keypair,
+    listeners: Vec<Listener>,
+    clients: Clients,
+    /// Events of listeners removed with [`Transport::remove_listener`].
+    closed: VecDeque<TransportEvent<Upgrade, io::Error>>,
+    /// Woken when a listener is added or removed.
+    waker: Option<Waker>,
+}
+
+impl WebTransport {
+    pub(crate) fn new(keypair: &Keypair) -> Self {
+        Self {
+            keypair: keypair.clone(),
+            listeners: Vec::new(),
+            clients: Clients::default(),
+            closed: VecDeque::new(),
+            waker: None,
+        }
+    }
+
+    fn noise(&self, certhashes: HashSet<Multihash<64>>) -> Result<noise::Config, TransportError<io::Error>> {
+        let noise = noise::Config::new(&self.keypair).map_err(|e| TransportError::Other(io::Error::other(e)))?;
+        Ok(noise.with_webtransport_certhashes(certhashes))
+    }
+}
+
+impl Transport for WebTransport {
+    type Output = (PeerId, Connection);
+    type Error = io::Error;
+    type ListenerUpgrade = Upgrade;
+    type Dial = Upgrade;
+
+    fn listen_on(&mut self, id: ListenerId, addr: Multiaddr) -> Result<(), TransportError<Self::Error>> {
+        // Listen addresses get the certhashes of the listener's own certificates.
+        let Some((local, _)) = socket_addr(&addr).filter(|(_, certhashes)| certhashes.is_empty()) else {
+            return Err(TransportError::MultiaddrNotSupported(addr));
+        };
+        let listener = Listener::bind(id, local).map_err(TransportError::Other)?;
+        tracing::debug!(address = %listener.bound, "listening for WebTransport sessions");
+        self.listeners.push(listener);
+        if let Some(waker) = self.waker.take() {
+            waker.wake();
+        }
+        Ok(())
+    }
+
+    fn remove_listener(&mut self, id: ListenerId) -> bool {
+        let Some(index) = self.listeners.iter().position(|listener| listener.id == id) else {
+            return false;
+        };
+        self.listeners.remove(index).endpoint.close(VarInt::from_u32(0), b"");
+        self.closed.push_back(TransportEvent::ListenerClosed { listener_id: id, reason: Ok(()) });
+        if let Some(waker) = self.waker.take() {
+            waker.wake();
+        }
+        true
+    }
+
+    fn dial(&mut self, addr: Multiaddr) -> Result<Self::Dial, TransportError<Self::Error>> {
+        let Some((remote, digests)) =
+            socket_addr(&addr).filter(|(remote, _)| remote.port() != 0 && !remote.ip().is_unspecified())
+        else {
+            return Err(TransportError::MultiaddrNotSupported(addr));
+        };
+        // Noise checks that the server confirms the certhashes it was dialed with.
+        let noise = self.noise(digests.iter().map(certhash).collect())?;
+        let endpoint = self.clients.endpoint(remote).map_err(TransportError::Other)?;
+        let verifier = self.clients.verifier(digests).map_err(TransportError::Other)?;
+        tracing::debug!(address = %remote, "dialing WebTransport session");
+        Ok(async move {
+            let session = endpoint.connect(format!("https://{remote}{PATH}")).await.map_err(io::Error::other)?;
+            verify(&session, &*verifier)?;
+            let stream = open_bi(session.clone()).await?;
+            let (peer_id, _) = noise.upgrade_outbound(stream, "/noise").await.map_err(io::Error::other)?;
+            Ok((peer_id, Connection::new(session)))
+        }
+        .boxed())
+    }
+
+    fn dial_as_listener(&mut self, addr: Multiaddr) -> Result<Self::Dial, TransportError<Self::Error>> {
+        // Hole punching needs the dial to go out through the listening socket,
+        // but wtransport only dials from client endpoints, which have sockets
+        // of their own. Refusing these dials leaves hole punching to TCP and
+        // QUIC rather than making it look like it went through.
+        Err(TransportError::MultiaddrNotSupported(addr))
+    }
+
+    fn poll(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<TransportEvent<Self::ListenerUpgrade, Self::Error>> {
+        if let Some(event) = self.closed.pop_front() {
+            return Poll::Ready(event);
+        }
+        let this = &mut *self;
+        for listener in &mut this.listeners {
+            if let Poll::Ready(event) = listener.poll(&this.keypair, cx) {
+                return Poll::Ready(event);
+            }
+        }
+        this.waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+
+    fn address_translation(&self, _listen: &Multiaddr, _observed: &Multiaddr) -> Option<Multiaddr> {
+        // Observed addresses lack the certhashes, which only the listener knows.
+        None
+    }
+}
+
+/// A server endpoint and the certificates it serves.
+struct Listener {
+    id: ListenerId,
+    endpoint: Arc<Endpoint<Server>>,
+    /// The address the endpoint is bound to, which rotations keep.
+    bound: SocketAddr,
+    /// The IPs of the listen addresses, those of every interface of the
+    /// family if bound to all of them.
+    ips: Vec<IpAddr>,
+    certificates: Certificates,
+    accept: BoxFuture<'static, IncomingSession>,
+    rotation: Pin<Box<Sleep>>,
+    /// Events about the listen addresses yet to be reported.
+    events: VecDeque<TransportEvent<Upgrade, io::Error>>,
+}
+
+impl Listener {
+    fn bind(id: ListenerId, local: SocketAddr) -> io::Result<Self> {
+        let certificates = Certificates::new()?;
+        let endpoint = Arc::new(Endpoint::server(server_config(local, &certificates.current))?);
+        let bound = endpoint.local_addr()?;
+        let ips = if bound.ip().is_unspecified() {
+            let interfaces = if_addrs::get_if_addrs()?;
+            interfaces.iter().map(|interface| interface.ip()).filter(|ip| ip.is_ipv4() == bound.is_ipv4()).collect()
+        } else {
+            vec![bound.ip()]
+        };
+        let mut listener = Self {
+            id,
+            accept: accept(endpoint.clone()),
+            endpoint,
+            bound,
+            ips,
+            certificates,
+            rotation: Box::pin(tokio::time::sleep(ROTATION)),
+            events: VecDeque::new(),
+        };
+        let new = listener.listen_addrs().into_iter();
+        listener.events.extend(new.map(|listen_addr| TransportEvent::NewAddress { listener_id: id, listen_addr }));
+        Ok(listener)
+    }
+
+    /// The listen addresses, with the certhashes advertised.
+    fn listen_addrs(&self) -> Vec<Multiaddr> {
+        let certhashes: Vec<_> = self.certificates.advertised().iter().map(certhash).collect();
+        let with_certhashes =
+            |addr: Multiaddr| certhashes.iter().fold(addr, |addr, hash| addr.with(Protocol::Certhash(*hash)));
+        let addr = |ip: &IpAddr| webtransport_addr(SocketAddr::new(*ip, self.bound.port()));
+        self.ips.iter().map(|ip| with_certhashes(addr(ip))).collect()
+    }
+
+    /// Serves the next certificate, replacing the listen addresses.
+    fn rotate(&mut self) -> io::Result<()> {
+        let expired = self.listen_addrs();
+        self.certificates.rotate()?;
+        self.endpoint.reload_config(server_config(self.bound, &self.certificates.current), false)?;
+        let listener_id = self.id;
+        let expired = expired.into_iter();
+        self.events.extend(expired.map(|listen_addr| TransportEvent::AddressExpired { listener_id, listen_addr }));
+        let new = self.listen_addrs().into_iter();
+        self.events.extend(new.map(|listen_addr| TransportEvent::NewAddress { listener_id, listen_addr }));
+        Ok(())
+    }
+
+    fn poll(&mut self, keypair: &Keypair, cx: &mut Context<'_>) -> Poll<TransportEvent<Upgrade, io::Error>> {
+        if let Some(event) = self.events.pop_front() {
+            return Poll::Ready(event);
+        }
+        if self.rotation.poll_unpin(cx).is_ready() {
+            self.rotation.as_mut().reset(Instant::now() + ROTATION);
+            if let Err(error) = self.rotate() {
+                return Poll::Ready(TransportEvent::ListenerError { listener_id: self.id, error });
+            }
+            return self.poll(keypair, cx);
+        }
+        let Poll::Ready(incoming) = self.accept.poll_unpin(cx) else {
+            return Poll::Pending;
+        };
+        self.accept = accept(self.endpoint.clone());
+        let send_back_addr = webtransport_addr(incoming.remote_address());
+        let noise = match noise::Config::new(keypair) {
+            Ok(noise) => noise.with_webtransport_certhashes(self.certificates.confirmed()),
+            Err(e) => {
+                let error = io::Error::other(e);
+                return Poll::Ready(TransportEvent::ListenerError { listener_id: self.id, error });
+            }
+        };
+        Poll::Ready(TransportEvent::Incoming {
+            listener_id: self.id,
+            upgrade: accept_session(incoming, noise).boxed(),
+            local_addr: webtransport_addr(self.bound),
+            send_back_addr,
+        })
+    }
+}
+
+/// The self-signed certificates of a listener.
+struct Certificates {
+    /// Hash of the certificate served before the last rotation, which Noise
+    /// still confirms for clients that learned the address before it.
+    previous: Option<Sha256Digest>,
+    /// The certificate served.
+    current: Identity,
+    /// The certificate served after the next rotation, advertised already.
+    next: Identity,
+}
+
+impl Certificates {
+    fn new() -> io::Result<Self> {
+        Ok(Self { previous: None, current: self_signed()?, next: self_signed()? })
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        let next = self_signed()?;
+        self.previous = Some(digest(&self.current));
+        self.current = std::mem::replace(&mut self.next, next);
+        Ok(())
+    }
+
+    /// The hashes in the listen addresses.
+    fn advertised(&self) -> [Sha256Digest; 2] {
+        [digest(&self.current), digest(&self.next)]
+    }
+
+    /// The hashes Noise confirms to clients.
+    fn confirmed(&self) -> HashSet<Multihash<64>> {
+        self.previous.iter().chain(&self.advertised()).map(certhash).collect()
+    }
+}
+
+/// A certificate valid for 14 days from now, with a P-256 ECDSA key as
+/// browsers require for certificates accepted by their hash.
+fn self_signed() -> io::Result<Identity> {
+    Identity::self_signed(["localhost"]).map_err(io::Error::other)
+}
+
+fn digest(identity: &Identity) -> Sha256Digest {
+    identity.certificate_chain().as_slice()[0].hash()
+}
+
+fn certhash(digest: &Sha256Digest) -> Multihash<64> {
+    Multihash::wrap(SHA2_256, digest.as_ref()).expect("a SHA-256 digest fits in 64 bytes")
+}
+
+fn server_config(bound: SocketAddr, identity: &Identity) -> ServerConfig {
+    let builder = match bound {
+        SocketAddr::V4(_) => ServerConfig::builder().with_bind_address(bound),
+        SocketAddr::V6(v6) => ServerConfig::builder().with_bind_address_v6(v6, Ipv6DualStackConfig::Deny),
+    };
+    builder.with_identity(identity.clone_identity()).keep_alive_interval(Some(KEEP_ALIVE)).build()
+}
+
+/// The client endpoints dials go out from, one per IP family, each bound on
+/// the first dial to its family.
+#[derive(Default)]
+struct Clients {
+    v4: Option<Arc<Endpoint<Client>>>,
+    v6: Option<Arc<Endpoint<Client>>>,
+    /// Verifies the certificates of servers dialed without certhashes.
+    cas: Option<Arc<WebPkiServerVerifier>>,
+}
+
+impl Clients {
+    fn endpoint(&mut self, remote: SocketAddr) -> io::Result<Arc<Endpoint<Client>>> {
+        let endpoint = if remote.is_ipv4() { &mut self.v4 } else { &mut self.v6 };
+        match endpoint {
+            Some(endpoint) => Ok(endpoint.clone()),
+            None => Ok(endpoint.insert(Arc::new(Endpoint::client(client_config(remote))?)).clone()),
+        }
+    }
+
+    /// A verifier trusting the certificates with `digests`, or those signed by
+    /// a CA of the system if there are none.
+    fn verifier(&mut self, digests: Vec<Sha256Digest>) -> io::Result<Arc<dyn ServerCertVerifier>> {
+        if !digests.is_empty() {
+            return Ok(Arc::new(ServerHashVerification::new(digests)));
+        }
+        if let Some(cas) = &self.cas {
+            return Ok(cas.clone());
+        }
+        let provider = tls_config().crypto_provider().clone();
+        let cas = WebPkiServerVerifier::builder_with_provider(Arc::new(build_native_cert_store()), provider)
+            .build()
+            .map_err(io::Error::other)?;
+        Ok(self.cas.insert(cas).clone())
+    }
+}
+
+/// A client config for the endpoint of the family of `remote`.
+///
+/// The certificates to trust differ from dial to dial, but the config of an
+/// endpoint is the same for all of them, so it takes any certificate as long
+/// as the server holds its key, and each dial [`verify`]s the certificate of
+/// its session before using it.
+fn client_config(remote: SocketAddr) -> ClientConfig {
+    let builder = match remote {
+        SocketAddr::V4(_) => ClientConfig::builder().with_bind_address((Ipv4Addr::UNSPECIFIED, 0).into()),
+        SocketAddr::V6(_) => ClientConfig::builder()
+            .with_bind_address_v6(SocketAddrV6::new(Ipv6Addr::UNSPECIFIED, 0, 0, 0), Ipv6DualStackConfig::Deny),
+    };
+    builder.with_custom_tls(tls_config()).keep_alive_interval(Some(KEEP_ALIVE)).build()
+}
+
+fn tls_config() -> wtransport::tls::rustls::ClientConfig {
+    build_default_tls_config(Arc::new(RootCertStore::empty()), Some(Arc::new(NoServerVerification::new())))
+}
+
+/// Checks the certificate of a dialed `session` with `verifier`. Only the
+/// request for [`PATH`] has gone out at this point.
+fn verify(session: &wtransport::Connection, verifier: &dyn ServerCertVerifier) -> io::Result<()> {
+    let invalid = |error| io::Error::new(io::ErrorKind::InvalidData, error);
+    let no_certificate = || invalid(wtransport::tls::rustls::Error::NoCertificatesPresented);
+    let chain = session.peer_identity().ok_or_else(no_certificate)?;
+    let (end_entity, intermediates) = chain.as_slice().split_first().ok_or_else(no_certificate)?;
+    let intermediates: Vec<_> = intermediates.iter().map(|certificate| CertificateDer::from(certificate.der())).collect();
+    let name = ServerName::IpAddress(session.remote_address().ip().into());
+    let end_entity = CertificateDer::from(end_entity.der());
+    verifier.verify_server_cert(&end_entity, &intermediates, &name, &[], UnixTime::now()).map_err(invalid)?;
+    Ok(())
+}
+
+fn accept(endpoint: Arc<Endpoint<Server>>) -> BoxFuture<'static, IncomingSession> {
+    async move { endpoint.accept().await }.boxed()
+}
+
+/// Accepts the session of an incoming connection if it asks for [`PATH`],
+/// and answers the Noise handshake on its first stream.
+async fn accept_session(incoming: IncomingSession, noise: noise::Config) -> io::Result<(PeerId, Connection)> {
+    let request = incoming.await.map_err(io::Error::other)?;
+    if request.path() != PATH {
+        let path = request.path().to_owned();
+        request.not_found().await;
+        return Err(io::Error::new(io::ErrorKind::NotFound, format!("no libp2p session at `{path}`")));
+    }
+    let session = request.accept().await.map_err(io::Error::other)?;
+    let stream = session.accept_bi().await.map_err(io::Error::other)?;
+    let (peer_id, _) = noise.upgrade_inbound(Stream(BiStream::join(stream)), "/noise").await.map_err(io::Error::other)?;
+    Ok((peer_id, Connection::new(session)))
+}
+
+async fn open_bi(session: wtransport::Connection) -> io::Result<Stream> {
+    let opening = session.open_bi().await.map_err(io::Error::other)?;
+    Ok(Stream(BiStream::join(opening.await.map_err(io::Error::other)?)))
+}
+
+/// Returns the socket address and the certhashes of
+/// `/ip4|ip6/<ip>/udp/<port>/quic-v1/webtransport[/certhash/<hash>...]`
+/// addresses, optionally ending with `/p2p/<peer id>`; certhashes other than
+/// SHA-256 are skipped.
+fn socket_addr(addr: &Multiaddr) -> Option<(SocketAddr, Vec<Sha256Digest>)> {
+    let mut protocols = addr.iter();
+    let ip = match protocols.next()? {
+        Protocol::Ip4(ip) => IpAddr::from(ip),
+        Protocol::Ip6(ip) => IpAddr::from(ip),
+        _ => return None,
+    };
+    let Protocol::Udp(port) = protocols.next()? else {
+        return None;
+    };
+    let (Protocol::QuicV1, Protocol::WebTransport) = (protocols.next()?, protocols.next()?) else {
+        return None;
+    };
+    let mut digests = Vec::new();
+    for protocol in protocols {
+        match protocol {
+            Protocol::Certhash(hash) if hash.code() == SHA2_256 => {
+                digests.extend(<[u8; 32]>::try_from(hash.digest()).ok().map(Sha256Digest::new));
+            }
+            Protocol::Certhash(_) => {}
+            Protocol::P2p(_) => break,
+            _ => return None,
+        }
+    }
+    Some((SocketAddr::new(ip, port), digests))
+}
+
+fn webtransport_addr(addr: SocketAddr) -> Multiaddr {
+    Multiaddr::from(addr.ip())
+        .with(Protocol::Udp(addr.port()))
+        .with(Protocol::QuicV1)
+        .with(Protocol::WebTransport)
+}
+
+/// A WebTransport session, whose bidirectional streams are substreams.
+pub(crate) struct Connection {
+    session: wtransport::Connection,
+    /// Resolves once the session is closed, by either side or for being idle.
+    closed: Fuse<BoxFuture<'static, ConnectionError>>,
+    inbound: Option<BoxFuture<'static, io::Result<Stream>>>,
+    outbound: Option<BoxFuture<'static, io::Result<Stream>>>,
+}
+
+impl Connection {
+    fn new(session: wtransport::Connection) -> Self {
+        let closing = session.clone();
+        let closed = async move { closing.closed().await }.boxed().fuse();
+        Self { session, closed, inbound: None, outbound: None }
+    }
+}
+
+impl StreamMuxer for Connection {
+    type Substream = Stream;
+    type Error = io::Error;
+
+    fn poll_inbound(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<Self::Substream, Self::Error>> {
+        let this = self.get_mut();
+        let accepting = this.inbound.get_or_insert_with(|| {
+            let session = this.session.clone();
+            async move { Ok(Stream(BiStream::join(session.accept_bi().await.map_err(io::Error::other)?))) }.boxed()
+        });
+        let stream = ready!(accepting.poll_unpin(cx));
+        this.inbound = None;
+        Poll::Ready(stream)
+    }
+
+    fn poll_outbound(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<Self::Substream, Self::Error>> {
+        let this = self.get_mut();
+        let opening = this.outbound.get_or_insert_with(|| open_bi(this.session.clone()).boxed());
+        let stream = ready!(opening.poll_unpin(cx));
+        this.outbound = None;
+        Poll::Ready(stream)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.session.close(VarInt::from_u32(0), b"");
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<StreamMuxerEvent, Self::Error>> {
+        let error = ready!(self.get_mut().closed.poll_unpin(cx));
+        Poll::Ready(Err(io::Error::other(error)))
+    }
+}
+
+/// A bidirectional WebTransport stream, with the IO traits of `futures`.
+pub(crate) struct Stream(BiStream);
+
+impl AsyncRead for Stream {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+        let mut buf = ReadBuf::new(buf);
+        ready!(tokio::io::AsyncRead::poll_read(Pin::new(&mut self.0), cx, &mut buf))?;
+        Poll::Ready(Ok(buf.filled().len()))
+    }
+}
+
+impl AsyncWrite for Stream {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        tokio::io::AsyncWrite::poll_write(Pin::new(&mut self.0), cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        tokio::io::AsyncWrite::poll_flush(Pin::new(&mut self.0), cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        tokio::io::AsyncWrite::poll_shutdown(Pin::new(&mut self.0), cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addr(s: &str) -> Multiaddr {
+        s.parse().expect("a valid address")
+    }
+
+    #[test]
+    fn socket_addrs() {
+        let certhash = certhash(&Sha256Digest::new([7; 32]));
+        let with_certhash = addr("/ip6/::1/udp/4001/quic-v1/webtransport").with(Protocol::Certhash(certhash));
+        let (remote, digests) = socket_addr(&with_certhash).expect("a WebTransport address");
+        assert_eq!(remote, "[::1]:4001".parse().unwrap());
+        assert_eq!(digests, vec![Sha256Digest::new([7; 32])]);
+
+        let peer_id = PeerId::random();
+        let with_peer_id = addr("/ip4/127.0.0.1/udp/4001/quic-v1/webtransport").with(Protocol::P2p(peer_id));
+        assert_eq!(socket_addr(&with_peer_id), Some(("127.0.0.1:4001".parse().unwrap(), Vec::new())));
+
+        for other in [
+            "/ip4/127.0.0.1/udp/4001/quic-v1",
+            "/ip4/127.0.0.1/tcp/4001/ws",
+            "/dns4/example.com/udp/4001/quic-v1/webtransport",
+            "/ip4/127.0.0.1/udp/4001/quic-v1/webtransport/p2p-circuit",
+        ] {
+            assert_eq!(socket_addr(&addr(other)), None, "{other}");
+        }
+    }
+
+    #[tokio::test]
+    async fn dials_of_a_family_share_an_endpoint() {
+        let mut clients = Clients::default();
+        let v4 = clients.endpoint("127.0.0.1:4001".parse().unwrap()).unwrap();
+        let again = clients.endpoint("127.0.0.2:4002".parse().unwrap()).unwrap();
+        assert!(Arc::ptr_eq(&v4, &again));
+        let v6 = clients.endpoint("[::1]:4001".parse().unwrap()).unwrap();
+        assert!(!Arc::ptr_eq(&v4, &v6));
+        assert!(v6.local_addr().unwrap().is_ipv6());
+    }
+
+    #[test]
+    fn rotations_keep_confirming_advertised_certhashes() {
+        let mut certificates = Certificates::new().unwrap();
+        let advertised: HashSet<_> = certificates.advertised().iter().map(certhash).collect();
+        assert_eq!(advertised.len(), 2);
+        assert_eq!(certificates.confirmed(), advertised);
+
+        let [_, next] = certificates.advertised();
+        certificates.rotate().unwrap();
+        assert!(advertised.is_subset(&certificates.confirmed()));
+        assert_eq!(certificates.confirmed().len(), 3);
+        assert_eq!(digest(&certificates.current), next, "the next certificate is served");
+
+        certificates.rotate().unwrap();
+        assert!(!advertised.is_subset(&certificates.confirmed()));
+    }
+}
@@ -0,0 +1,194 @@
+//! Nodes of a [`Network`] pinging each other over the memory transport.
+
+use libp2p::ping;
+use libp2p::swarm::SwarmEvent;
+use libp2p_ping_tut::ping_limit::{PingLimit, PingLimitAction};
+use libp2p_ping_tut::testing::{Impairment, Network};
+use libp2p_ping_tut::{BehaviourEvent, NodeConfig, PingError};
+use prometheus_client::encoding::text::encode;
+use std::time::Duration;
+
+/// A config pinging often enough to keep the tests short.
+fn config() -> NodeConfig {
+    NodeConfig { ping_interval: Duration::from_millis(100), ..NodeConfig::default() }
+}
+
+/// Node 0 with `config()` connected to node 1 with `answering`.
+async fn pair(answering: NodeConfig) -> Result<Network, PingError> {
+    let mut network = Network::with_configs([config(), answering]).await?;
+    network.connect(0, 1)?;
+    Ok(network)
+}
+
+fn impaired(impairment: Impairment) -> NodeConfig {
+    NodeConfig { impairment: Some(impairment), ..config() }
+}
+
+fn limited(action: PingLimitAction) -> NodeConfig {
+    NodeConfig { inbound_ping_limit: Some(PingLimit { rate: 2, action }), ..config() }
+}
+
+#[tokio::test]
+async fn pings_are_answered() -> Result<(), PingError> {
+    let mut network = pair(config()).await?;
+    for _ in 0..3 {
+        network.next_ping(0, 1).await?;
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn impaired_nodes_answer_late() -> Result<(), PingError> {
+    let latency = Duration::from_millis(200);
+    let mut network = pair(impaired(Impairment { latency, jitter: Duration::ZERO, loss: 0.0, seed: 1 })).await?;
+    for _ in 0..3 {
+        let rtt = network.next_ping(0, 1).await?;
+        assert!(rtt >= latency, "RTT {rtt:?} below the latency");
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn impaired_nodes_lose_pings() -> Result<(), PingError> {
+    let lossy = impaired(Impairment { latency: Duration::ZERO, jitter: Duration::ZERO, loss: 1.0, seed: 1 });
+    let pinging = NodeConfig { ping_timeout: Duration::from_millis(300), ..config() };
+    let mut network = Network::with_configs([pinging, lossy]).await?;
+    network.connect(0, 1)?;
+    assert!(matches!(network.next_ping(0, 1).await, Err(PingError::Timeout)));
+    Ok(())
+}
+
+#[tokio::test]
+async fn pings_over_the_limit_are_throttled() -> Result<(), PingError> {
+    let mut network = pair(limited(PingLimitAction::Throttle)).await?;
+    // The bucket holds two tokens and refills one every 500ms, while pings
+    // come every 100ms after the previous answer.
+    let mut rtts = Vec::new();
+    for _ in 0..4 {
+        rtts.push(network.next_ping(0, 1).await?);
+    }
+    assert!(rtts[..2].iter().all(|rtt| *rtt < Duration::from_millis(250)), "{rtts:?}");
+    assert!(rtts[2..].iter().all(|rtt| *rtt >= Duration::from_millis(250)), "{rtts:?}");
+    let peer_id = network.peer_id(1);
+    assert!(network.node(0).is_connected(&peer_id));
+    Ok(())
+}
+
+#[tokio::test]
+async fn peers_over_the_limit_are_disconnected() -> Result<(), PingError> {
+    let mut network = pair(limited(PingLimitAction::Disconnect)).await?;
+    let peer_id = network.peer_id(1);
+    network
+        .wait_for(0, |event| match event {
+            SwarmEvent::ConnectionClosed { peer_id: closed, .. } if *closed == peer_id => Some(()),
+            _ => None,
+        })
+        .await?;
+    assert!(!network.node(0).is_connected(&peer_id));
+    Ok(())
+}
+
+#[tokio::test]
+async fn peers_over_the_limit_are_disconnected_once() -> Result<(), PingError> {
+    let limiting = NodeConfig { metrics: true, ..limited(PingLimitAction::Disconnect) };
+    let mut network = Network::with_configs([config(), limiting]).await?;
+    // Both connections go over the limit of the peer they share.
+    network.connect(0, 1)?;
+    network.connect(0, 1)?;
+    let peer_id = network.peer_id(0);
+    network
+        .wait_for(1, |event| match event {
+            SwarmEvent::ConnectionClosed { peer_id: closed, num_established: 0, .. } if *closed == peer_id => Some(()),
+            _ => None,
+        })
+        .await?;
+
+    let registry = network.node(1).metrics_registry().expect("metrics are enabled");
+    let mut text = String::new();
+    encode(&mut text, &registry).expect("encoding into a string doesn't fail");
+    assert!(text.lines().any(|line| line == "libp2p_ping_tut_inbound_ping_disconnects_total 1"), "{text}");
+    Ok(())
+}
+
+#[tokio::test]
+async fn echoes_over_the_limit_are_refused() -> Result<(), PingError> {
+    let echoing = NodeConfig { echo_size: Some(64), ..config() };
+    let mut network = Network::with_configs([echoing, limited(PingLimitAction::Throttle)]).await?;
+    network.connect(0, 1)?;
+    // The pings and echoes of node 0 share the two tokens a second of node 1,
+    // so the echoes soon find none left.
+    network
+        .wait_for(0, |event| match event {
+            SwarmEvent::Behaviour(BehaviourEvent::Echo(event)) => event.result.is_err().then_some(()),
+            _ => None,
+        })
+        .await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn evictions_are_counted_once() -> Result<(), PingError> {
+    let mut network = Network::with_configs([NodeConfig { metrics: true, ..config() }, config()]).await?;
+    network.connect(0, 1)?;
+    network.next_ping(0, 1).await?;
+    let peer_id = network.peer_id(1);
+    assert!(network.node(0).evict(peer_id));
+    // The connection is still closing, so this is the same eviction.
+    assert!(network.node(0).evict(peer_id));
+    network
+        .wait_for(0, |event| matches!(event, SwarmEvent::ConnectionClosed { .. }).then_some(()))
+        .await?;
+    assert!(!network.node(0).evict(peer_id), "no connection is left to evict");
+
+    let registry = network.node(0).metrics_registry().expect("metrics are enabled");
+    let mut text = String::new();
+    encode(&mut text, &registry).expect("encoding into a string doesn't fail");
+    assert!(text.lines().any(|line| line == "libp2p_ping_tut_evicted_peers_total 1"), "{text}");
+    Ok(())
+}
+
+/// Pings node 1 until its connection closes or it answers `pings` pings,
+/// returning whether it stayed open.
+async fn stays_open(network: &mut Network, pings: usize) -> Result<bool, PingError> {
+    let peer_id = network.peer_id(1);
+    let mut answered = 0;
+    network
+        .wait_for(0, |event| match event {
+            SwarmEvent::ConnectionClosed { peer_id: closed, .. } if *closed == peer_id => Some(false),
+            SwarmEvent::Behaviour(BehaviourEvent::Ping(ping::Event { peer, result: Ok(_), .. }))
+                if *peer == peer_id =>
+            {
+                answered += 1;
+                (answered == pings).then_some(true)
+            }
+            _ => None,
+        })
+        .await
+}
+
+#[tokio::test]
+async fn idle_connections_close_between_pings() -> Result<(), PingError> {
+    let idle = NodeConfig {
+        ping_interval: Duration::from_secs(1),
+        idle_timeout: Duration::from_millis(200),
+        ..config()
+    };
+    let mut network = Network::with_configs([idle.clone(), idle]).await?;
+    network.connect(0, 1)?;
+    assert!(!stays_open(&mut network, 3).await?);
+    Ok(())
+}
+
+#[tokio::test]
+async fn keep_alive_keeps_idle_connections_open() -> Result<(), PingError> {
+    let kept = NodeConfig {
+        ping_interval: Duration::from_secs(1),
+        idle_timeout: Duration::from_millis(200),
+        keep_alive: true,
+        ..config()
+    };
+    let mut network = Network::with_configs([kept.clone(), kept]).await?;
+    network.connect(0, 1)?;
+    assert!(stays_open(&mut network, 3).await?);
+    Ok(())
+}
@@ -0,0 +1,107 @@
+//! Nodes pinging each other over WebTransport on the loopback interface.
+
+use libp2p::multiaddr::Protocol;
+use libp2p::swarm::SwarmEvent;
+use libp2p::{ping, Multiaddr};
+use libp2p_ping_tut::testing::TIMEOUT;
+use libp2p_ping_tut::{BehaviourEvent, NodeConfig, PingError, PingNode, TransportChoice};
+
+fn node() -> Result<PingNode, PingError> {
+    let transports = vec![TransportChoice::WebTransport];
+    PingNode::with_config(NodeConfig { transports, mdns: false, ..NodeConfig::default() })
+}
+
+async fn listen(node: &mut PingNode) -> Result<Multiaddr, PingError> {
+    node.listen("/ip4/127.0.0.1/udp/0/quic-v1/webtransport".parse().expect("a valid address"))?;
+    let wait = async {
+        loop {
+            if let SwarmEvent::NewListenAddr { address, .. } = node.next_event().await {
+                break address;
+            }
+        }
+    };
+    tokio::time::timeout(TIMEOUT, wait).await.map_err(|_| PingError::Timeout)
+}
+
+#[tokio::test]
+async fn listen_addresses_carry_two_certhashes() -> Result<(), PingError> {
+    let addr = listen(&mut node()?).await?;
+    assert_eq!(TransportChoice::for_addr(&addr), Some(TransportChoice::WebTransport));
+    let certhashes = addr.iter().filter(|protocol| matches!(protocol, Protocol::Certhash(_))).count();
+    assert_eq!(certhashes, 2, "{addr}");
+    Ok(())
+}
+
+#[tokio::test]
+async fn nodes_ping_over_webtransport() -> Result<(), PingError> {
+    let (mut listener, mut dialer) = (node()?, node()?);
+    let addr = listen(&mut listener).await?.with(Protocol::P2p(listener.local_peer_id()));
+    dialer.dial(addr)?;
+    let pinged = async {
+        loop {
+            tokio::select! {
+                event = dialer.next_event() => {
+                    if let SwarmEvent::Behaviour(BehaviourEvent::Ping(ping::Event { result, .. })) = event {
+                        break result;
+                    }
+                }
+                _ = listener.next_event() => {}
+            }
+        }
+    };
+    let rtt = tokio::time::timeout(TIMEOUT, pinged).await.map_err(|_| PingError::Timeout)?;
+    assert!(rtt.is_ok(), "{rtt:?}");
+    Ok(())
+}
+
+#[tokio::test]
+async fn dials_need_the_certhash_of_the_listener() -> Result<(), PingError> {
+    let (mut listener, mut dialer) = (node()?, node()?);
+    let addr = listen(&mut listener).await?;
+    // The certhashes of another listener's certificates.
+    let other = listen(&mut node()?).await?;
+    let wrong = addr
+        .iter()
+        .filter(|protocol| !matches!(protocol, Protocol::Certhash(_)))
+        .chain(other.iter().filter(|protocol| matches!(protocol, Protocol::Certhash(_))))
+        .collect();
+    dialer.dial(wrong)?;
+    let failed = async {
+        loop {
+            tokio::select! {
+                event = dialer.next_event() => match event {
+                    SwarmEvent::OutgoingConnectionError { .. } => break true,
+                    SwarmEvent::ConnectionEstablished { .. } => break false,
+                    _ => {}
+                },
+                _ = listener.next_event() => {}
+            }
+        }
+    };
+    assert!(tokio::time::timeout(TIMEOUT, failed).await.map_err(|_| PingError::Timeout)?);
+    Ok(())
+}
+
+#[tokio::test]
+async fn closed_sessions_close_the_connection() -> Result<(), PingError> {
+    let (mut listener, mut dialer) = (node()?, node()?);
+    let addr = listen(&mut listener).await?;
+    dialer.dial(addr)?;
+    let closed = async {
+        loop {
+            tokio::select! {
+                event = dialer.next_event() => {
+                    if let SwarmEvent::ConnectionClosed { .. } = event {
+                        break;
+                    }
+                }
+                event = listener.next_event() => {
+                    if let SwarmEvent::ConnectionEstablished { peer_id, .. } = event {
+                        listener.disconnect(peer_id);
+                    }
+                }
+            }
+        }
+    };
+    tokio::time::timeout(TIMEOUT, closed).await.map_err(|_| PingError::Timeout)
+}